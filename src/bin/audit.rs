@@ -0,0 +1,76 @@
+//! Audits the stored daily bars of tickers and prints one JSON report per ticker.
+//!
+//! Usage: `audit <start_date> <end_date> <ticker>... [--calendar <closed_days.csv>]`
+//!
+//! Bars are read from the CSV files in `PRICE_CSV_DIR` when it is set, otherwise from
+//! QuestDB, configured as described in `trade_stack::config`. Sessions are checked against the NASDAQ calendar unless `--calendar` names a
+//! file in the `nasdaq_closed_days.csv` format.
+
+use std::error::Error;
+use std::sync::Arc;
+use trade_stack::config::DatabaseConfig;
+use trade_stack::market::audit::{audit_ticker, AuditOptions};
+use trade_stack::market::calendar::TradingCalendar;
+use trade_stack::market::execution_date::ExecutionDate;
+use trade_stack::market::price_store::PriceStore;
+use trade_stack::market::provider::{MarketDataProvider, QuestDbProvider};
+use trade_stack::market::resilience::ResilientProvider;
+
+const USAGE: &str =
+    "usage: audit <start_date> <end_date> <ticker>... [--calendar <closed_days.csv>]";
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let (database, args) = DatabaseConfig::load(std::env::args().skip(1))?;
+    let mut args = args.into_iter();
+    let (start, end): (ExecutionDate, ExecutionDate) = match (args.next(), args.next()) {
+        (Some(start), Some(end)) => (start.parse()?, end.parse()?),
+        _ => return Err(USAGE.into()),
+    };
+
+    let mut tickers = Vec::new();
+    let mut calendar = TradingCalendar::nasdaq();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--calendar" => {
+                calendar = Arc::new(TradingCalendar::from_csv(args.next().ok_or(USAGE)?)?)
+            }
+            option if option.starts_with("--") => return Err(USAGE.into()),
+            _ => tickers.push(arg),
+        }
+    }
+    if tickers.is_empty() {
+        return Err(USAGE.into());
+    }
+
+    let market_data: Arc<dyn MarketDataProvider> = match std::env::var("PRICE_CSV_DIR") {
+        Ok(dir) => Arc::new(PriceStore::from_csv_dir(dir)?),
+        Err(_) => Arc::new(ResilientProvider::from_config(
+            Arc::new(QuestDbProvider::new(database.create_pool()?)),
+            &database,
+        )),
+    };
+
+    let options = AuditOptions::default();
+    let mut reports = Vec::with_capacity(tickers.len());
+    for ticker in &tickers {
+        let report = audit_ticker(
+            market_data.as_ref(),
+            &calendar,
+            ticker,
+            start,
+            end,
+            &options,
+        )
+        .await?;
+        eprintln!(
+            "{}: {} bars, {} issues",
+            ticker,
+            report.bars,
+            report.issues.len()
+        );
+        reports.push(report);
+    }
+    println!("{}", serde_json::to_string_pretty(&reports)?);
+    Ok(())
+}
@@ -0,0 +1,57 @@
+//! Generates the `nasdaq_closed_days` data from the US exchange holiday rules.
+//!
+//! Usage: `generate_calendar <first_year> <last_year> [--csv <path>] [--upsert]`
+//!
+//! Without options the rows are written to stdout as CSV. `--upsert` writes them into
+//! the QuestDB `nasdaq_closed_days` table, replacing rows for the same dates, connecting
+//! with the settings described in `trade_stack::config`.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use trade_stack::config::DatabaseConfig;
+use trade_stack::market::holidays::{closed_days, upsert_closed_days, write_csv};
+
+const USAGE: &str = "usage: generate_calendar <first_year> <last_year> [--csv <path>] [--upsert]";
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let (database, args) = DatabaseConfig::load(std::env::args().skip(1))?;
+    let (first_year, last_year) = match (args.first(), args.get(1)) {
+        (Some(first), Some(last)) => (first.parse::<i32>()?, last.parse::<i32>()?),
+        _ => return Err(USAGE.into()),
+    };
+
+    let mut csv_path = None;
+    let mut upsert = false;
+    let mut options = args.iter().skip(2);
+    while let Some(option) = options.next() {
+        match option.as_str() {
+            "--csv" => csv_path = Some(options.next().ok_or(USAGE)?),
+            "--upsert" => upsert = true,
+            _ => return Err(USAGE.into()),
+        }
+    }
+
+    let rows = closed_days(first_year, last_year)?;
+    eprintln!(
+        "Generated {} closed days for {} to {}",
+        rows.len(),
+        first_year,
+        last_year
+    );
+
+    match csv_path {
+        Some(path) => write_csv(&rows, BufWriter::new(File::create(path)?))?,
+        None if !upsert => write_csv(&rows, BufWriter::new(io::stdout().lock()))?,
+        None => {}
+    }
+
+    if upsert {
+        let client = database.create_pool()?.get().await?;
+        let written = upsert_closed_days(&client, &rows).await?;
+        eprintln!("Upserted {} rows into nasdaq_closed_days", written);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,89 @@
+//! Loads Tiingo daily CSVs into QuestDB over the line protocol.
+//!
+//! Usage: `ingest <file or dir>... [--batch <lines>] [--checkpoint <path>]
+//! [--resume-from-db] [--create-tables]`
+//!
+//! Directories are read like `PRICE_CSV_DIR`: every `<TICKER>prices.csv` or
+//! `<TICKER>.csv` in them. `--checkpoint` keeps the last bar written per ticker in a
+//! JSON file so an interrupted run continues where it stopped; `--resume-from-db` also
+//! skips bars up to the latest of every ticker in `stock_data_daily`. `--create-tables`
+//! creates the tables with deduplication on `(time, ticker)` before writing.
+//!
+//! Lines go to the `ilp` endpoint of the settings described in `trade_stack::config`,
+//! e.g. `--questdb-ilp http://localhost:9000`.
+
+use std::error::Error;
+use std::path::Path;
+use trade_stack::config::DatabaseConfig;
+use trade_stack::ingest::ilp::{IlpSender, IlpTransport};
+use trade_stack::ingest::{create_tables, Checkpoint, Ingestor, DEFAULT_BATCH_SIZE};
+
+const USAGE: &str = "usage: ingest <file or dir>... [--batch <lines>] [--checkpoint <path>] \
+                     [--resume-from-db] [--create-tables]";
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    tracing_subscriber::fmt().init();
+
+    let mut inputs = Vec::new();
+    let mut batch_size = DEFAULT_BATCH_SIZE;
+    let mut checkpoint_path = None;
+    let mut resume_from_db = false;
+    let mut create = false;
+    let (database, args) = DatabaseConfig::load(std::env::args().skip(1))?;
+    let transport: IlpTransport = database.ilp.parse()?;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--batch" => batch_size = args.next().ok_or(USAGE)?.parse()?,
+            "--checkpoint" => checkpoint_path = Some(args.next().ok_or(USAGE)?),
+            "--resume-from-db" => resume_from_db = true,
+            "--create-tables" => create = true,
+            option if option.starts_with("--") => return Err(USAGE.into()),
+            _ => inputs.push(arg),
+        }
+    }
+    if inputs.is_empty() {
+        return Err(USAGE.into());
+    }
+
+    let mut checkpoint = match &checkpoint_path {
+        Some(path) => Checkpoint::load(path)?,
+        None => Checkpoint::default(),
+    };
+    if create || resume_from_db {
+        let client = database.create_pool()?.get().await?;
+        if create {
+            create_tables(&client).await?;
+        }
+        if resume_from_db {
+            checkpoint.merge(Checkpoint::from_database(&client).await?);
+        }
+    }
+
+    let mut ingestor =
+        Ingestor::new(IlpSender::new(transport), checkpoint).with_batch_size(batch_size);
+    if let Some(path) = checkpoint_path {
+        ingestor = ingestor.with_checkpoint_file(path);
+    }
+    for input in &inputs {
+        if Path::new(input).is_dir() {
+            ingestor.ingest_dir(input).await?;
+        } else {
+            ingestor.ingest_file(input).await?;
+        }
+    }
+
+    let (summary, _) = ingestor.finish().await?;
+    eprintln!(
+        "Wrote {} bars and {} corporate actions from {} files \
+         ({} already written, {} duplicates, {} invalid)",
+        summary.bars,
+        summary.corporate_actions,
+        summary.files,
+        summary.already_written,
+        summary.duplicates,
+        summary.invalid
+    );
+    Ok(())
+}
@@ -0,0 +1,269 @@
+//! InfluxDB line protocol (ILP) writer for QuestDB.
+//! Rows are encoded as `stock_data_daily,ticker=AAPL open=1.5,...,volume=100i <nanos>`
+//! and sent in batches over TCP (QuestDB listens on 9009) or HTTP (`POST /write` on
+//! 9000). Only HTTP reports whether a batch was accepted; over TCP QuestDB closes the
+//! connection on a bad line, which surfaces as an error on a later write.
+
+use crate::market::adjustments::CorporateAction;
+use crate::market::database_functions::{Bar, DatabaseError};
+use chrono::NaiveDateTime;
+use std::fmt::Write as _;
+use std::io;
+use std::str::FromStr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Table the daily bars are written to
+pub const BARS_TABLE: &str = "stock_data_daily";
+/// Table splits and dividends are written to
+pub const CORPORATE_ACTIONS_TABLE: &str = "corporate_actions";
+
+/// Escapes a tag value: commas, spaces and equals signs are preceded by a backslash
+fn escape_tag(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, ',' | ' ' | '=' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn timestamp_nanos(time: NaiveDateTime) -> i64 {
+    // Bar times are written as UTC, see `Exchange::bar_timestamp`
+    time.and_utc()
+        .timestamp_nanos_opt()
+        .expect("bar time within the nanosecond range")
+}
+
+/// Appends the line of `bar` to `buffer`
+pub fn write_bar(buffer: &mut String, ticker: &str, bar: &Bar) {
+    // Writing to a String cannot fail
+    let _ = writeln!(
+        buffer,
+        "{},ticker={} open={:?},high={:?},low={:?},close={:?},volume={}i {}",
+        BARS_TABLE,
+        escape_tag(ticker),
+        bar.open,
+        bar.high,
+        bar.low,
+        bar.close,
+        bar.volume,
+        timestamp_nanos(bar.time)
+    );
+}
+
+/// Appends the line of `action` to `buffer`
+pub fn write_corporate_action(buffer: &mut String, ticker: &str, action: &CorporateAction) {
+    let _ = writeln!(
+        buffer,
+        "{},ticker={} split_factor={:?},dividend={:?} {}",
+        CORPORATE_ACTIONS_TABLE,
+        escape_tag(ticker),
+        action.split_factor,
+        action.dividend,
+        timestamp_nanos(action.time)
+    );
+}
+
+/// Where lines are sent, as `tcp://host:port` or `http://host:port`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IlpTransport {
+    Tcp(String),
+    Http(String),
+}
+
+impl FromStr for IlpTransport {
+    type Err = DatabaseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(address) = s.strip_prefix("tcp://") {
+            Ok(IlpTransport::Tcp(address.to_string()))
+        } else if let Some(address) = s.strip_prefix("http://") {
+            Ok(IlpTransport::Http(
+                address.trim_end_matches('/').to_string(),
+            ))
+        } else {
+            Err(DatabaseError::InvalidInput(format!(
+                "Unsupported ILP address '{}', expected tcp://host:port or http://host:port",
+                s
+            )))
+        }
+    }
+}
+
+/// Sends batches of lines to QuestDB. A TCP connection is kept open across batches and
+/// re-established after a failed write; every HTTP batch is its own request.
+pub struct IlpSender {
+    transport: IlpTransport,
+    connection: Option<TcpStream>,
+}
+
+impl IlpSender {
+    pub fn new(transport: IlpTransport) -> Self {
+        Self {
+            transport,
+            connection: None,
+        }
+    }
+
+    /// Sends newline-terminated `lines`
+    pub async fn send(&mut self, lines: &str) -> Result<(), DatabaseError> {
+        if lines.is_empty() {
+            return Ok(());
+        }
+        match &self.transport {
+            IlpTransport::Tcp(address) => {
+                let connection = match &mut self.connection {
+                    Some(connection) => connection,
+                    None => self.connection.insert(TcpStream::connect(address).await?),
+                };
+                let written = async {
+                    connection.write_all(lines.as_bytes()).await?;
+                    connection.flush().await
+                }
+                .await;
+                if written.is_err() {
+                    self.connection = None;
+                }
+                Ok(written?)
+            }
+            IlpTransport::Http(address) => post_lines(address, lines).await,
+        }
+    }
+
+    /// Flushes and closes the TCP connection, if any
+    pub async fn close(&mut self) -> Result<(), DatabaseError> {
+        if let Some(mut connection) = self.connection.take() {
+            connection.shutdown().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Posts `lines` to `/write` and checks the status; QuestDB answers 204 on success
+async fn post_lines(address: &str, lines: &str) -> Result<(), DatabaseError> {
+    let mut connection = TcpStream::connect(address).await?;
+    let head = format!(
+        "POST /write HTTP/1.1\r\nHost: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n",
+        address,
+        lines.len()
+    );
+    connection.write_all(head.as_bytes()).await?;
+    connection.write_all(lines.as_bytes()).await?;
+    connection.flush().await?;
+
+    let mut response = Vec::new();
+    connection.read_to_end(&mut response).await?;
+    let response = String::from_utf8_lossy(&response);
+    let status: u16 = response
+        .lines()
+        .next()
+        .and_then(|status_line| status_line.split_whitespace().nth(1))
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid HTTP response from {}", address),
+            )
+        })?;
+    let body = response
+        .split_once("\r\n\r\n")
+        .map(|(_, body)| body.trim())
+        .unwrap_or_default();
+
+    match status {
+        200..=299 => Ok(()),
+        // Rejected lines will be rejected again
+        400..=499 => Err(DatabaseError::InvalidInput(format!(
+            "ILP write rejected with {}: {}",
+            status, body
+        ))),
+        _ => Err(io::Error::other(format!("ILP write failed with {}: {}", status, body)).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market::price_store::bar_timestamp;
+    use chrono::NaiveDate;
+    use tokio::net::TcpListener;
+
+    /// Answers one HTTP request with `status` and returns the request body
+    async fn mock_http(listener: TcpListener, status: &'static str) -> String {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buffer = [0; 4096];
+        let body = loop {
+            let read = socket.read(&mut buffer).await.unwrap();
+            request.extend_from_slice(&buffer[..read]);
+            let text = String::from_utf8_lossy(&request).to_string();
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length: usize = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("Content-Length: "))
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+                if body.len() >= length {
+                    break body.to_string();
+                }
+            }
+        };
+        let response = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status);
+        socket.write_all(response.as_bytes()).await.unwrap();
+        body
+    }
+
+    #[tokio::test]
+    async fn test_lines_are_posted_over_http() {
+        let time = bar_timestamp(NaiveDate::from_ymd_opt(2020, 8, 31).unwrap());
+        let mut lines = String::new();
+        write_bar(
+            &mut lines,
+            "BRK B",
+            &Bar {
+                time,
+                open: 127.58,
+                high: 131.0,
+                low: 126.0,
+                close: 129.0,
+                volume: 225_702_700,
+            },
+        );
+        write_corporate_action(
+            &mut lines,
+            "AAPL",
+            &CorporateAction {
+                time,
+                split_factor: 4.0,
+                dividend: 0.0,
+            },
+        );
+        assert_eq!(
+            lines,
+            "stock_data_daily,ticker=BRK\\ B open=127.58,high=131.0,low=126.0,close=129.0,volume=225702700i 1598889600000000000\n\
+             corporate_actions,ticker=AAPL split_factor=4.0,dividend=0.0 1598889600000000000\n"
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(mock_http(listener, "204 No Content"));
+        let mut sender = IlpSender::new(address.parse().unwrap());
+        sender.send(&lines).await.unwrap();
+        assert_eq!(server.await.unwrap(), lines);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(mock_http(listener, "400 Bad Request"));
+        let mut sender = IlpSender::new(address.parse().unwrap());
+        assert!(matches!(
+            sender.send(&lines).await,
+            Err(DatabaseError::InvalidInput(_))
+        ));
+    }
+}
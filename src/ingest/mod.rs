@@ -0,0 +1,322 @@
+//! Native ingestion of daily bars into QuestDB.
+//! Tiingo exports are normalized into bars and corporate actions ([`tiingo`]) and
+//! written over the line protocol ([`ilp`]) in batches. A [`Checkpoint`] of the last
+//! bar written per ticker makes runs resumable: rows at or before it are skipped, so a
+//! rerun or an overlapping file does not write a bar twice. The tables created by
+//! [`create_tables`] also deduplicate on `(time, ticker)` on the server.
+
+pub mod ilp;
+pub mod tiingo;
+
+use crate::ingest::ilp::{write_bar, write_corporate_action, IlpSender};
+use crate::ingest::tiingo::{read_tiingo_file, TiingoBars};
+use crate::market::csv_prices::ticker_from_path;
+use crate::market::database_functions::DatabaseError;
+use chrono::NaiveDateTime;
+use deadpool_postgres::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Lines sent per batch unless configured otherwise
+pub const DEFAULT_BATCH_SIZE: usize = 5_000;
+
+/// Last bar written per ticker
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    last_written: BTreeMap<String, NaiveDateTime>,
+}
+
+impl Checkpoint {
+    /// Loads a checkpoint saved by [`Checkpoint::save`]; a missing file is empty
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, DatabaseError> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        serde_json::from_str(&fs::read_to_string(path)?).map_err(|e| {
+            DatabaseError::InvalidInput(format!("Invalid checkpoint {}: {}", path.display(), e))
+        })
+    }
+
+    /// Writes the checkpoint as JSON, replacing the file only once it is complete
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), DatabaseError> {
+        let path = path.as_ref();
+        let partial = path.with_extension("partial");
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| DatabaseError::InvalidInput(e.to_string()))?;
+        fs::write(&partial, json)?;
+        fs::rename(&partial, path)?;
+        Ok(())
+    }
+
+    /// Latest bar of every ticker in `stock_data_daily`
+    pub async fn from_database(client: &Client) -> Result<Self, DatabaseError> {
+        let rows = client
+            .query(
+                "SELECT ticker, max(time) AS time FROM stock_data_daily GROUP BY ticker",
+                &[],
+            )
+            .await?;
+        Ok(Self {
+            last_written: rows
+                .iter()
+                .map(|row| (row.get("ticker"), row.get("time")))
+                .collect(),
+        })
+    }
+
+    pub fn last_written(&self, ticker: &str) -> Option<NaiveDateTime> {
+        self.last_written.get(ticker).copied()
+    }
+
+    /// Records that the bars of `ticker` up to `time` are written
+    pub fn record(&mut self, ticker: &str, time: NaiveDateTime) {
+        let entry = self.last_written.entry(ticker.to_string()).or_insert(time);
+        *entry = (*entry).max(time);
+    }
+
+    /// Keeps the later bar of both checkpoints for every ticker
+    pub fn merge(&mut self, other: Checkpoint) {
+        for (ticker, time) in other.last_written {
+            self.record(&ticker, time);
+        }
+    }
+}
+
+/// Rows handled by an [`Ingestor`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IngestSummary {
+    pub files: usize,
+    pub bars: usize,
+    pub corporate_actions: usize,
+    /// Bars at or before the checkpoint of their ticker
+    pub already_written: usize,
+    pub duplicates: usize,
+    pub invalid: usize,
+}
+
+/// Writes Tiingo files to QuestDB in batches, advancing the checkpoint after each batch
+pub struct Ingestor {
+    sender: IlpSender,
+    checkpoint: Checkpoint,
+    checkpoint_path: Option<PathBuf>,
+    batch_size: usize,
+    buffer: String,
+    lines: usize,
+    /// Last bar per ticker in the unsent batch
+    pending: BTreeMap<String, NaiveDateTime>,
+    summary: IngestSummary,
+}
+
+impl Ingestor {
+    /// Ingests after the bars recorded in `checkpoint`
+    pub fn new(sender: IlpSender, checkpoint: Checkpoint) -> Self {
+        Self {
+            sender,
+            checkpoint,
+            checkpoint_path: None,
+            batch_size: DEFAULT_BATCH_SIZE,
+            buffer: String::new(),
+            lines: 0,
+            pending: BTreeMap::new(),
+            summary: IngestSummary::default(),
+        }
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Saves the checkpoint to `path` after every batch
+    pub fn with_checkpoint_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.checkpoint_path = Some(path.into());
+        self
+    }
+
+    /// Ingests every price file in `dir`, in file name order
+    pub async fn ingest_dir(&mut self, dir: impl AsRef<Path>) -> Result<(), DatabaseError> {
+        let mut paths: Vec<_> = fs::read_dir(dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<_, _>>()?;
+        paths.sort();
+
+        for path in paths {
+            if ticker_from_path(&path).is_none() {
+                tracing::debug!("Skipping non-price file {}", path.display());
+                continue;
+            }
+            self.ingest_file(&path).await?;
+        }
+        Ok(())
+    }
+
+    /// Ingests one file named like `AAPLprices.csv`
+    pub async fn ingest_file(&mut self, path: impl AsRef<Path>) -> Result<(), DatabaseError> {
+        let path = path.as_ref();
+        let (ticker, parsed) = read_tiingo_file(path)?;
+        self.summary.files += 1;
+        self.ingest(&ticker, parsed).await?;
+        tracing::info!("Ingested {} for {}", path.display(), ticker);
+        Ok(())
+    }
+
+    /// Queues the records of `ticker` after its checkpoint, sending full batches
+    pub async fn ingest(&mut self, ticker: &str, parsed: TiingoBars) -> Result<(), DatabaseError> {
+        self.summary.duplicates += parsed.duplicates;
+        self.summary.invalid += parsed.invalid;
+
+        let written = self.checkpoint.last_written(ticker);
+        for record in parsed.records {
+            if written.is_some_and(|written| record.bar.time <= written) {
+                self.summary.already_written += 1;
+                continue;
+            }
+
+            write_bar(&mut self.buffer, ticker, &record.bar);
+            self.lines += 1;
+            self.summary.bars += 1;
+            if let Some(action) = &record.action {
+                write_corporate_action(&mut self.buffer, ticker, action);
+                self.lines += 1;
+                self.summary.corporate_actions += 1;
+            }
+            self.pending.insert(ticker.to_string(), record.bar.time);
+
+            if self.lines >= self.batch_size {
+                self.flush().await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends the queued lines and records them in the checkpoint
+    pub async fn flush(&mut self) -> Result<(), DatabaseError> {
+        if self.lines == 0 {
+            return Ok(());
+        }
+        self.sender.send(&self.buffer).await?;
+        tracing::debug!("Sent {} lines", self.lines);
+        self.buffer.clear();
+        self.lines = 0;
+
+        for (ticker, time) in std::mem::take(&mut self.pending) {
+            self.checkpoint.record(&ticker, time);
+        }
+        if let Some(path) = &self.checkpoint_path {
+            self.checkpoint.save(path)?;
+        }
+        Ok(())
+    }
+
+    /// Sends the last batch and closes the connection
+    pub async fn finish(mut self) -> Result<(IngestSummary, Checkpoint), DatabaseError> {
+        self.flush().await?;
+        self.sender.close().await?;
+        Ok((self.summary, self.checkpoint))
+    }
+}
+
+/// Creates the tables written by the ingestion if needed and makes QuestDB replace rows
+/// with the same `time` and `ticker` instead of adding them
+pub async fn create_tables(client: &Client) -> Result<(), DatabaseError> {
+    client
+        .batch_execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS stock_data_daily (
+                time TIMESTAMP,
+                ticker SYMBOL,
+                open DOUBLE,
+                high DOUBLE,
+                low DOUBLE,
+                close DOUBLE,
+                volume LONG
+            ) TIMESTAMP(time) PARTITION BY YEAR WAL;
+            ALTER TABLE stock_data_daily DEDUP ENABLE UPSERT KEYS(time, ticker);
+            CREATE TABLE IF NOT EXISTS corporate_actions (
+                time TIMESTAMP,
+                ticker SYMBOL,
+                split_factor DOUBLE,
+                dividend DOUBLE
+            ) TIMESTAMP(time) PARTITION BY YEAR WAL;
+            ALTER TABLE corporate_actions DEDUP ENABLE UPSERT KEYS(time, ticker);
+            "#,
+        )
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ingest::ilp::IlpTransport;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    /// Accepts one line protocol connection and returns everything sent on it
+    async fn mock_tcp(listener: TcpListener) -> String {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut received = String::new();
+        socket.read_to_string(&mut received).await.unwrap();
+        received
+    }
+
+    async fn run(files: &[(&str, &str)], checkpoint: Checkpoint) -> (String, Checkpoint) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = tokio::spawn(mock_tcp(listener));
+
+        let mut ingestor = Ingestor::new(IlpSender::new(IlpTransport::Tcp(address)), checkpoint)
+            .with_batch_size(2);
+        for (ticker, contents) in files {
+            let parsed = tiingo::parse_tiingo_csv(contents, ticker).unwrap();
+            ingestor.ingest(ticker, parsed).await.unwrap();
+        }
+        let (_, checkpoint) = ingestor.finish().await.unwrap();
+        (server.await.unwrap(), checkpoint)
+    }
+
+    #[tokio::test]
+    async fn test_ingest_resumes_after_checkpoint() {
+        let header = "date,close,high,low,open,volume,divCash,splitFactor\n";
+        let first = format!(
+            "{}2024-01-02,185.64,188.44,183.89,187.15,82488700,0,1\n\
+             2024-01-03,184.25,185.88,183.43,184.22,58414500,0,1\n\
+             2024-01-02,185.64,188.44,183.89,187.15,82488700,0,1\n",
+            header
+        );
+        let (received, checkpoint) = run(&[("AAPL", &first)], Checkpoint::default()).await;
+        assert_eq!(received.lines().count(), 2);
+        assert!(received
+            .lines()
+            .all(|line| line.starts_with("stock_data_daily,ticker=AAPL ")));
+        let written = checkpoint.last_written("AAPL").unwrap();
+        assert_eq!(written.date().to_string(), "2024-01-03");
+
+        // A rerun with one more day only sends that day and its dividend
+        let second = format!(
+            "{}2024-01-04,181.91,183.09,180.88,182.15,71983600,0.24,1\n",
+            &first
+        );
+        let (received, checkpoint) = run(&[("AAPL", &second)], checkpoint).await;
+        let lines: Vec<_> = received.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("stock_data_daily,ticker=AAPL open=182.15,"));
+        assert!(
+            lines[1].starts_with("corporate_actions,ticker=AAPL split_factor=1.0,dividend=0.24 ")
+        );
+        assert_eq!(
+            checkpoint.last_written("AAPL").unwrap().date().to_string(),
+            "2024-01-04"
+        );
+
+        let path =
+            std::env::temp_dir().join(format!("ingest_checkpoint_{}.json", std::process::id()));
+        checkpoint.save(&path).unwrap();
+        let loaded = Checkpoint::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, checkpoint);
+    }
+}
@@ -0,0 +1,196 @@
+//! Reader for Tiingo daily exports such as `Python fetch data/testdata/AAPLprices.csv`.
+//! Rows are normalized into what QuestDB stores: the raw bar and, when `divCash` or
+//! `splitFactor` are set, a corporate action. Both are stamped with their date at
+//! [`BAR_TIME`](crate::market::exchange::BAR_TIME), the stamp the lookups of every
+//! exchange expect, so a file is ingested without knowing its listing exchange.
+//! Unlike [`load_price_file`](crate::market::csv_prices::load_price_file) the reader
+//! tolerates what raw downloads contain: repeated dates keep their last row, rows come
+//! back in date order, and bars QuestDB should not hold are dropped and counted.
+
+use crate::market::adjustments::CorporateAction;
+use crate::market::calendar::split_row;
+use crate::market::csv_prices::ticker_from_path;
+use crate::market::database_functions::{Bar, DatabaseError};
+use crate::market::price_store::bar_timestamp;
+use chrono::{NaiveDate, NaiveDateTime};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// One day of a Tiingo file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DailyRecord {
+    pub bar: Bar,
+    pub action: Option<CorporateAction>,
+}
+
+/// Records of one file, oldest first, with the rows that were dropped
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TiingoBars {
+    pub records: Vec<DailyRecord>,
+    /// Rows replaced by a later row for the same date
+    pub duplicates: usize,
+    /// Rows with non-positive or non-finite prices, a low above the high, a negative
+    /// volume or an invalid split factor or dividend
+    pub invalid: usize,
+}
+
+/// Parses a Tiingo export with at least `date`, `open`, `high`, `low`, `close` and
+/// `volume` columns; `divCash` and `splitFactor` are optional
+pub fn parse_tiingo_csv(contents: &str, source: &str) -> Result<TiingoBars, DatabaseError> {
+    let mut lines = contents.lines();
+    let header = split_row(
+        lines
+            .next()
+            .ok_or_else(|| DatabaseError::InvalidInput(format!("Empty file {}", source)))?,
+    );
+    let column = |name: &str| header.iter().position(|column| column.trim() == name);
+    let required = |name: &str| {
+        column(name).ok_or_else(|| {
+            DatabaseError::InvalidInput(format!("Missing column '{}' in {}", name, source))
+        })
+    };
+    let date_index = required("date")?;
+    let open_index = required("open")?;
+    let high_index = required("high")?;
+    let low_index = required("low")?;
+    let close_index = required("close")?;
+    let volume_index = required("volume")?;
+    let dividend_index = column("divCash");
+    let split_index = column("splitFactor");
+
+    let mut by_time: BTreeMap<NaiveDateTime, DailyRecord> = BTreeMap::new();
+    let mut parsed = TiingoBars::default();
+    for (line_number, line) in lines.enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let fields = split_row(line);
+        let invalid_row = |reason: String| {
+            DatabaseError::InvalidInput(format!("{}:{}: {}", source, line_number + 2, reason))
+        };
+        let field = |index: usize, name: &str| {
+            fields
+                .get(index)
+                .map(|field| field.trim())
+                .ok_or_else(|| invalid_row(format!("missing {}", name)))
+        };
+        let number = |index: usize, name: &str| -> Result<f64, DatabaseError> {
+            let value = field(index, name)?;
+            value
+                .parse()
+                .map_err(|_| invalid_row(format!("invalid {} '{}'", name, value)))
+        };
+
+        // Tiingo writes plain dates; some exports append a midnight time
+        let date_field = field(date_index, "date")?;
+        let date =
+            NaiveDate::parse_from_str(date_field.get(..10).unwrap_or(date_field), "%Y-%m-%d")
+                .map_err(|_| invalid_row(format!("invalid date '{}'", date_field)))?;
+        let time = bar_timestamp(date);
+        let volume = number(volume_index, "volume")?;
+        let bar = Bar {
+            time,
+            open: number(open_index, "open")?,
+            high: number(high_index, "high")?,
+            low: number(low_index, "low")?,
+            close: number(close_index, "close")?,
+            // Some exports write volumes as floats
+            volume: volume.round() as i64,
+        };
+        let dividend = dividend_index
+            .map(|index| number(index, "divCash"))
+            .transpose()?
+            .unwrap_or(0.0);
+        let split_factor = split_index
+            .map(|index| number(index, "splitFactor"))
+            .transpose()?
+            .unwrap_or(1.0);
+
+        let valid_prices = [bar.open, bar.high, bar.low, bar.close]
+            .iter()
+            .all(|price| price.is_finite() && *price > 0.0);
+        let valid_action = split_factor.is_finite()
+            && split_factor > 0.0
+            && dividend.is_finite()
+            && dividend >= 0.0;
+        if !valid_prices || bar.low > bar.high || volume.is_nan() || volume < 0.0 || !valid_action {
+            tracing::warn!("{}:{}: dropping invalid bar", source, line_number + 2);
+            parsed.invalid += 1;
+            continue;
+        }
+
+        let action = (dividend != 0.0 || split_factor != 1.0).then_some(CorporateAction {
+            time,
+            split_factor,
+            dividend,
+        });
+        if by_time.insert(time, DailyRecord { bar, action }).is_some() {
+            parsed.duplicates += 1;
+        }
+    }
+
+    parsed.records = by_time.into_values().collect();
+    Ok(parsed)
+}
+
+/// Reads a file named like `AAPLprices.csv` or `AAPL.csv`, returning its ticker
+pub fn read_tiingo_file(path: &Path) -> Result<(String, TiingoBars), DatabaseError> {
+    let ticker = ticker_from_path(path).ok_or_else(|| {
+        DatabaseError::InvalidInput(format!("No ticker in file name {}", path.display()))
+    })?;
+    let parsed = parse_tiingo_csv(&fs::read_to_string(path)?, &path.display().to_string())?;
+    Ok((ticker, parsed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market::exchange::Exchange;
+    use crate::market::execution_date::ExecutionDate;
+
+    #[test]
+    fn test_parse_normalizes_and_deduplicates() {
+        let parsed = parse_tiingo_csv(
+            "date,close,high,low,open,volume,adjClose,adjHigh,adjLow,adjOpen,adjVolume,divCash,splitFactor\n\
+             2020-08-31,129.04,131.0,126.0,127.58,225702688.0,127.2,129.1,124.2,125.7,225702688,0.0,4.0\n\
+             2020-08-28,499.23,505.64,494.81,504.05,46907479,122.9,124.5,121.8,124.1,187629916,0.0,1.0\n\
+             2020-08-31,129.04,131.0,126.0,127.58,225702700,127.2,129.1,124.2,125.7,225702700,0.0,4.0\n\
+             2020-09-01,134.18,134.8,130.53,132.76,151948100,132.3,132.9,128.7,130.9,151948100,0.0,1.0\n\
+             2020-09-02,131.4,137.98,126.0,0.0,200119000,129.5,136.0,124.2,0.0,200119000,0.0,1.0\n",
+            "AAPLprices.csv",
+        )
+        .unwrap();
+
+        assert_eq!(parsed.duplicates, 1);
+        assert_eq!(parsed.invalid, 1);
+        let times: Vec<_> = parsed
+            .records
+            .iter()
+            .map(|record| record.bar.time.date().to_string())
+            .collect();
+        assert_eq!(times, ["2020-08-28", "2020-08-31", "2020-09-01"]);
+
+        // The later row for the 31st wins, and the split comes with it
+        let split_day = parsed.records[1];
+        assert_eq!(split_day.bar.volume, 225_702_700);
+        assert_eq!(split_day.bar.time.time().to_string(), "16:00:00");
+        // The stamp the lookups use for a listing on any exchange
+        for exchange in [Exchange::Nasdaq, Exchange::Xetra, Exchange::Crypto] {
+            let date = split_day.bar.time.date();
+            assert_eq!(
+                ExecutionDate::at_close(date, exchange).bar_timestamp(),
+                split_day.bar.time
+            );
+        }
+        assert_eq!(
+            split_day.action.map(|action| action.split_factor),
+            Some(4.0)
+        );
+        assert_eq!(parsed.records[2].action, None);
+
+        let result = parse_tiingo_csv("date,close\n2020-08-31,129.04\n", "AAPLprices.csv");
+        assert!(matches!(result, Err(DatabaseError::InvalidInput(_))));
+    }
+}
@@ -1,2 +1,4 @@
+pub mod config;
+pub mod ingest;
+pub mod market;
 pub mod portfolio;
-pub mod market;
\ No newline at end of file
@@ -377,43 +377,32 @@
 //     }
 // }
 
-use trade_stack::market::database_functions::{self, DatabaseError};
+use chrono::NaiveDate;
+use trade_stack::config::DatabaseConfig;
+use trade_stack::market::adjustments::PriceAdjustment;
+use trade_stack::market::calendar::{CalendarRegistry, TradingCalendar};
+use trade_stack::market::execution_date::ExecutionDate;
+use trade_stack::market::missing_data::MissingDataPolicy;
+use trade_stack::market::price_store::PriceStore;
+use trade_stack::market::provider::{MarketDataProvider, QuestDbProvider};
+use trade_stack::market::resilience::ResilientProvider;
+use trade_stack::market::security_master::SecurityMaster;
+use trade_stack::market::symbols::{install_symbol_rules, SymbolRules};
 use trade_stack::portfolio::construction::validate_json;
+use trade_stack::portfolio::construction::validate_securities::validate_securities;
+use trade_stack::portfolio::execution::options::ExecutionOptions;
 use trade_stack::portfolio::execution::strategy_executor;
 
-use chrono::{NaiveDate, Utc};
-use deadpool_postgres::{Client, Config, Pool};
 use psutil::process::Process;
 use std::error::Error;
 use std::fs;
+use std::sync::Arc;
 use std::time::Instant;
-use tokio::time::Duration;
-use tracing::{error, info, warn};
 
-use trade_stack::portfolio::execution::sequential_execution::execute_strategy_over_time_span_sequential;
 use trade_stack::portfolio::execution::time_based_execution::{
     execute_strategy_over_time_span, ExecutionResult,
 };
 
-// Define `create_pool`
-fn create_pool() -> Pool {
-    let config = Config {
-        host: Some("questdb.orb.local".to_string()),
-        port: Some(8812),
-        user: Some("admin".to_string()),
-        password: Some("quest".to_string()),
-        dbname: Some("qdb".to_string()),
-        ..Default::default()
-    };
-
-    config
-        .create_pool(
-            Some(deadpool_postgres::Runtime::Tokio1),
-            tokio_postgres::NoTls,
-        )
-        .expect("Failed to create connection pool")
-}
-
 // Define `PerformanceMonitor`
 struct PerformanceMonitor {
     start_time: Instant,
@@ -478,8 +467,67 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // Initialize logging
     tracing_subscriber::fmt().init();
 
-    // Setup database connection
-    let pool = create_pool();
+    // Read prices from CSV files when PRICE_CSV_DIR is set, otherwise from QuestDB
+    // The pool only connects once a query needs it; see `config` for its settings
+    let (database, _) = DatabaseConfig::load(std::env::args().skip(1))?;
+    let pool = database.create_pool()?;
+    let price_csv_dir = std::env::var("PRICE_CSV_DIR").ok();
+    // Reference data from SECURITY_MASTER (a CSV file) or the security_master table, used
+    // to check tickers and by Screen blocks
+    let security_master = match std::env::var("SECURITY_MASTER") {
+        Ok(path) => Some(Arc::new(SecurityMaster::from_csv(path)?)),
+        Err(_) if price_csv_dir.is_none() => match SecurityMaster::from_database(&pool).await {
+            Ok(master) => Some(Arc::new(master)),
+            Err(e) => {
+                tracing::warn!(
+                    "Skipping security checks, security master unavailable: {}",
+                    e
+                );
+                None
+            }
+        },
+        Err(_) => None,
+    };
+    let market_data: Arc<dyn MarketDataProvider> = match &price_csv_dir {
+        Some(dir) => {
+            let mut store = PriceStore::from_csv_dir(dir)?.with_calendar(TradingCalendar::nasdaq());
+            if let Some(master) = &security_master {
+                store = store.with_security_master(Arc::clone(master));
+            }
+            Arc::new(store)
+        }
+        None => Arc::new(ResilientProvider::from_config(
+            Arc::new(QuestDbProvider::new(pool.clone())),
+            &database,
+        )),
+    };
+
+    // Built-in calendars plus any `<EXCHANGE>.csv` files in TRADING_CALENDAR_DIR
+    let mut calendars = CalendarRegistry::builtin();
+    if let Ok(dir) = std::env::var("TRADING_CALENDAR_DIR") {
+        calendars.load_csv_dir(dir)?;
+    }
+    let calendars = Arc::new(calendars);
+
+    // Functions without their own price_adjustment use PRICE_ADJUSTMENT (raw by default)
+    let price_adjustment = match std::env::var("PRICE_ADJUSTMENT") {
+        Ok(value) => value.parse()?,
+        Err(_) => PriceAdjustment::Raw,
+    };
+    // MISSING_DATA_POLICY: error (default), forward_fill:<days>, last_available or exclude_asset
+    let missing_data = match std::env::var("MISSING_DATA_POLICY") {
+        Ok(value) => value.parse()?,
+        Err(_) => MissingDataPolicy::Error,
+    };
+    let options = ExecutionOptions::default()
+        .with_calendars(calendars)
+        .with_price_adjustment(price_adjustment)
+        .with_missing_data(missing_data);
+
+    // Ticker symbol rules from SYMBOL_RULES (a JSON file), the built-in ones otherwise
+    if let Ok(path) = std::env::var("SYMBOL_RULES") {
+        install_symbol_rules(SymbolRules::from_json_file(path)?);
+    }
 
     // Load strategy from JSON
     let json_str = fs::read_to_string("printing.json")?;
@@ -488,10 +536,26 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let start_date = "2015-01-01";
     let end_date = Some("2025-01-01");
 
+    if let Some(master) = &security_master {
+        // Runs without an end date go up to today
+        let last_date = match end_date {
+            Some(date) => NaiveDate::parse_from_str(date, "%Y-%m-%d")?,
+            None => chrono::Local::now().date_naive(),
+        };
+        let issues = validate_securities(&strategy, master, last_date);
+        for issue in &issues {
+            tracing::warn!("{}", issue);
+        }
+        let errors = issues.iter().filter(|issue| issue.is_error()).count();
+        if errors > 0 {
+            return Err(format!("{} security master issues, see the log", errors).into());
+        }
+    }
+
     //Execute sequential version
     // let mut sequential_monitor = PerformanceMonitor::new()?;
     // let sequential_results = execute_strategy_over_time_span_sequential(
-    //     &pool, &strategy, start_date, end_date, "monthly",
+    //     &market_data, &options, &strategy, start_date, end_date, "monthly",
     // )
     // .await?;
     // let sequential_metrics = sequential_monitor.measure()?;
@@ -503,11 +567,20 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     // Execute parallel version
     let mut parallel_monitor = PerformanceMonitor::new()?;
-    let parallel_results =
-        execute_strategy_over_time_span(&pool, &strategy, start_date, end_date, "monthly").await?;
+    let parallel_results = execute_strategy_over_time_span(
+        &market_data,
+        &options,
+        &strategy,
+        start_date,
+        end_date,
+        "monthly",
+    )
+    .await?;
     let parallel_metrics = parallel_monitor.measure()?;
     parallel_metrics.log("Parallel");
 
+    print_missing_data(&parallel_results);
+
     // Convert parallel_results to the expected format
     let converted_parallel_results = convert_execution_results(parallel_results);
 
@@ -519,7 +592,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
 }
 
 /// Helper function to print results in a readable format
-fn print_results(results: &[(String, String, Vec<strategy_executor::Allocation>)]) {
+fn print_results(results: &[(NaiveDate, ExecutionDate, Vec<strategy_executor::Allocation>)]) {
     for (display_date, execution_date, allocations) in results {
         println!("Display Date: {}", display_date);
         println!("Execution Date: {}", execution_date);
@@ -530,10 +603,25 @@ fn print_results(results: &[(String, String, Vec<strategy_executor::Allocation>)
     }
 }
 
-/// Convert `Vec<ExecutionResult>` to `Vec<(String, String, Vec<Allocation>)>`
+/// Lists the assets the missing-data policy filled or excluded, per execution date
+fn print_missing_data(results: &[ExecutionResult]) {
+    for result in results {
+        for event in &result.missing_data {
+            println!(
+                "Missing data on {}: {} (session {}) {:?}",
+                result.execution_date.date(),
+                event.ticker,
+                event.session,
+                event.action
+            );
+        }
+    }
+}
+
+/// Convert `Vec<ExecutionResult>` to `Vec<(NaiveDate, ExecutionDate, Vec<Allocation>)>`
 fn convert_execution_results(
     results: Vec<ExecutionResult>,
-) -> Vec<(String, String, Vec<strategy_executor::Allocation>)> {
+) -> Vec<(NaiveDate, ExecutionDate, Vec<strategy_executor::Allocation>)> {
     results
         .into_iter()
         .map(|result| {
@@ -0,0 +1,365 @@
+//! Split and dividend adjusted prices.
+//! Adjustments are applied backwards from the last bar of each evaluation window, so the
+//! adjusted close at the execution date equals the raw close and no corporate action
+//! after the execution date leaks into a backtest.
+
+use crate::market::database_functions::{Bar, DatabaseError, PricePoint};
+use crate::market::exchange::Exchange;
+use crate::market::execution_date::ExecutionDate;
+use crate::market::market_cap::SharesOutstanding;
+use crate::market::provider::{BoxFuture, FunctionValues, MarketDataProvider};
+use crate::market::security_master::SecurityMaster;
+use crate::market::universe::MembershipChange;
+use crate::portfolio::blocks::models::FunctionDefinition;
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Which prices indicators are computed from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PriceAdjustment {
+    /// Closes as traded
+    #[default]
+    Raw,
+    /// Closes before a split scaled by the split ratio
+    SplitAdjusted,
+    /// Split adjusted, with dividends reinvested on the ex-date
+    TotalReturn,
+}
+
+impl fmt::Display for PriceAdjustment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PriceAdjustment::Raw => write!(f, "raw"),
+            PriceAdjustment::SplitAdjusted => write!(f, "split_adjusted"),
+            PriceAdjustment::TotalReturn => write!(f, "total_return"),
+        }
+    }
+}
+
+impl FromStr for PriceAdjustment {
+    type Err = DatabaseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "raw" => Ok(PriceAdjustment::Raw),
+            "split_adjusted" => Ok(PriceAdjustment::SplitAdjusted),
+            "total_return" => Ok(PriceAdjustment::TotalReturn),
+            other => Err(DatabaseError::InvalidInput(format!(
+                "Unsupported price adjustment: {}",
+                other
+            ))),
+        }
+    }
+}
+
+/// Split and/or cash dividend taking effect at the open of the bar stamped `time`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CorporateAction {
+    pub time: NaiveDateTime,
+    /// New shares per old share, e.g. 4.0 for a 4-for-1 split; 1.0 when there is none
+    pub split_factor: f64,
+    /// Cash dividend per share; 0.0 when there is none
+    pub dividend: f64,
+}
+
+impl CorporateAction {
+    /// Factor applied to closes before this action, given the last close before it
+    pub fn factor(&self, adjustment: PriceAdjustment, previous_close: f64) -> f64 {
+        let split = if self.split_factor > 0.0 {
+            1.0 / self.split_factor
+        } else {
+            1.0
+        };
+        match adjustment {
+            PriceAdjustment::Raw => 1.0,
+            PriceAdjustment::SplitAdjusted => split,
+            PriceAdjustment::TotalReturn if previous_close > 0.0 => {
+                split * (1.0 - self.dividend / previous_close)
+            }
+            PriceAdjustment::TotalReturn => split,
+        }
+    }
+}
+
+/// Closes in `range` adjusted for every action inside the window, relative to the last
+/// bar of the window. `actions` must be sorted by time.
+pub fn adjust_closes<'a>(
+    times: &[NaiveDateTime],
+    closes: &'a [f64],
+    actions: &[CorporateAction],
+    range: Range<usize>,
+    adjustment: PriceAdjustment,
+) -> Cow<'a, [f64]> {
+    let window = &closes[range.clone()];
+    if adjustment == PriceAdjustment::Raw || range.is_empty() {
+        return Cow::Borrowed(window);
+    }
+
+    // Actions effective on the first bar only affect bars before the window
+    let first = times[range.start];
+    let last = times[range.end - 1];
+    let first_action = actions.partition_point(|action| action.time <= first);
+    let last_action = actions.partition_point(|action| action.time <= last);
+    if first_action == last_action {
+        return Cow::Borrowed(window);
+    }
+
+    let mut adjusted = window.to_vec();
+    for action in &actions[first_action..last_action] {
+        let index = times.partition_point(|time| *time < action.time);
+        let factor = action.factor(adjustment, closes[index - 1]);
+        for close in &mut adjusted[..index - range.start] {
+            *close *= factor;
+        }
+    }
+    Cow::Owned(adjusted)
+}
+
+/// `points` of `ticker` adjusted as of their last bar, with the splits and dividends
+/// `source` has between their first and last bar on `exchange`'s sessions
+pub async fn adjust_points(
+    source: &dyn MarketDataProvider,
+    ticker: &str,
+    points: Vec<PricePoint>,
+    adjustment: PriceAdjustment,
+    exchange: Exchange,
+) -> Result<Vec<PricePoint>, DatabaseError> {
+    let (Some(first), Some(last)) = (points.first(), points.last()) else {
+        return Ok(points);
+    };
+    if adjustment == PriceAdjustment::Raw {
+        return Ok(points);
+    }
+    let first = ExecutionDate::from_bar_timestamp(first.time, exchange);
+    let last = ExecutionDate::from_bar_timestamp(last.time, exchange);
+    let actions = source.get_corporate_actions(ticker, first, last).await?;
+
+    let times: Vec<_> = points.iter().map(|point| point.time).collect();
+    let closes: Vec<_> = points.iter().map(|point| point.close).collect();
+    let adjusted = adjust_closes(&times, &closes, &actions, 0..points.len(), adjustment);
+    Ok(times
+        .into_iter()
+        .zip(adjusted.iter())
+        .map(|(time, close)| PricePoint {
+            time,
+            close: *close,
+        })
+        .collect())
+}
+
+/// `MarketDataProvider` that evaluates functions without an explicit `price_adjustment`
+/// with a run-wide default
+pub struct AdjustedProvider {
+    source: Arc<dyn MarketDataProvider>,
+    default: PriceAdjustment,
+}
+
+impl AdjustedProvider {
+    pub fn new(source: Arc<dyn MarketDataProvider>, default: PriceAdjustment) -> Self {
+        Self { source, default }
+    }
+}
+
+impl MarketDataProvider for AdjustedProvider {
+    fn get_price_series<'a>(
+        &'a self,
+        ticker: &'a str,
+        execution_date: ExecutionDate,
+        trading_days: i64,
+    ) -> BoxFuture<'a, Result<Vec<PricePoint>, DatabaseError>> {
+        self.source
+            .get_price_series(ticker, execution_date, trading_days)
+    }
+
+    fn get_price_history<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: ExecutionDate,
+        end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<PricePoint>, DatabaseError>> {
+        self.source.get_price_history(ticker, start_date, end_date)
+    }
+
+    fn get_bar_series<'a>(
+        &'a self,
+        ticker: &'a str,
+        execution_date: ExecutionDate,
+        trading_days: i64,
+    ) -> BoxFuture<'a, Result<Vec<Bar>, DatabaseError>> {
+        self.source
+            .get_bar_series(ticker, execution_date, trading_days)
+    }
+
+    fn get_bar_history<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: ExecutionDate,
+        end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<Bar>, DatabaseError>> {
+        self.source.get_bar_history(ticker, start_date, end_date)
+    }
+
+    fn get_corporate_actions<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: ExecutionDate,
+        end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<CorporateAction>, DatabaseError>> {
+        self.source
+            .get_corporate_actions(ticker, start_date, end_date)
+    }
+
+    fn get_shares_outstanding<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: ExecutionDate,
+        end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<SharesOutstanding>, DatabaseError>> {
+        self.source
+            .get_shares_outstanding(ticker, start_date, end_date)
+    }
+
+    fn get_index_membership<'a>(
+        &'a self,
+        universe: &'a str,
+        end_date: NaiveDate,
+    ) -> BoxFuture<'a, Result<Vec<MembershipChange>, DatabaseError>> {
+        self.source.get_index_membership(universe, end_date)
+    }
+
+    fn get_security_master(&self) -> BoxFuture<'_, Result<Arc<SecurityMaster>, DatabaseError>> {
+        self.source.get_security_master()
+    }
+
+    fn get_last_market_day(
+        &self,
+        date: NaiveDate,
+    ) -> BoxFuture<'_, Result<ExecutionDate, DatabaseError>> {
+        self.source.get_last_market_day(date)
+    }
+
+    fn evaluate_function<'a>(
+        &'a self,
+        function: &'a FunctionDefinition,
+        execution_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<f64, DatabaseError>> {
+        if function.price_adjustment.is_some() || self.default == PriceAdjustment::Raw {
+            return self.source.evaluate_function(function, execution_date);
+        }
+
+        Box::pin(async move {
+            let function = FunctionDefinition {
+                price_adjustment: Some(self.default),
+                ..function.clone()
+            };
+            self.source
+                .evaluate_function(&function, execution_date)
+                .await
+        })
+    }
+
+    fn evaluate_function_batch<'a>(
+        &'a self,
+        function: &'a FunctionDefinition,
+        tickers: &'a [String],
+        execution_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<FunctionValues, DatabaseError>> {
+        if function.price_adjustment.is_some() || self.default == PriceAdjustment::Raw {
+            return self
+                .source
+                .evaluate_function_batch(function, tickers, execution_date);
+        }
+
+        Box::pin(async move {
+            let function = FunctionDefinition {
+                price_adjustment: Some(self.default),
+                ..function.clone()
+            };
+            self.source
+                .evaluate_function_batch(&function, tickers, execution_date)
+                .await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(day: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_hms_opt(16, 0, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_split_and_dividend_adjustment() {
+        let times = [day(2), day(3), day(4), day(5)];
+        let closes = [200.0, 210.0, 105.0, 100.0];
+        let actions = [
+            // 2-for-1 split effective January 4th
+            CorporateAction {
+                time: day(4),
+                split_factor: 2.0,
+                dividend: 0.0,
+            },
+            // $1 dividend going ex on January 5th
+            CorporateAction {
+                time: day(5),
+                split_factor: 1.0,
+                dividend: 1.05,
+            },
+        ];
+
+        let raw = adjust_closes(&times, &closes, &actions, 0..4, PriceAdjustment::Raw);
+        assert_eq!(raw.as_ref(), closes);
+
+        let split = adjust_closes(
+            &times,
+            &closes,
+            &actions,
+            0..4,
+            PriceAdjustment::SplitAdjusted,
+        );
+        assert_eq!(split.as_ref(), [100.0, 105.0, 105.0, 100.0]);
+
+        let total = adjust_closes(
+            &times,
+            &closes,
+            &actions,
+            0..4,
+            PriceAdjustment::TotalReturn,
+        );
+        assert_eq!(total.as_ref(), [99.0, 103.95, 103.95, 100.0]);
+
+        // A window ending before the split does not see it
+        let before = adjust_closes(
+            &times,
+            &closes,
+            &actions,
+            0..2,
+            PriceAdjustment::TotalReturn,
+        );
+        assert_eq!(before.as_ref(), [200.0, 210.0]);
+    }
+
+    #[test]
+    fn test_parses_adjustment_names() {
+        assert_eq!(
+            "total_return".parse::<PriceAdjustment>().unwrap(),
+            PriceAdjustment::TotalReturn
+        );
+        assert_eq!(
+            serde_json::to_string(&PriceAdjustment::SplitAdjusted).unwrap(),
+            "\"split_adjusted\""
+        );
+        assert!("adjusted".parse::<PriceAdjustment>().is_err());
+    }
+}
@@ -0,0 +1,361 @@
+//! Data quality audit of stored daily bars.
+//! Checks the bars of a ticker over a date range against the trading calendar and its
+//! corporate actions, and reports every problem that would silently distort a
+//! backtest: missing sessions, bars on closed days, duplicate bars, non-positive prices,
+//! inconsistent OHLC values, split-like jumps without a recorded split and runs of
+//! unchanged closes.
+
+use crate::market::adjustments::CorporateAction;
+use crate::market::calendar::TradingCalendar;
+use crate::market::database_functions::{Bar, DatabaseError};
+use crate::market::execution_date::ExecutionDate;
+use crate::market::provider::MarketDataProvider;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Thresholds of the audit
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AuditOptions {
+    /// Close-to-close ratio (or its inverse) from which a move looks like a split;
+    /// 1.45 catches 3-for-2 splits
+    pub split_jump_ratio: f64,
+    /// Consecutive bars with the same close from which the series looks stale
+    pub stale_run_bars: usize,
+}
+
+impl Default for AuditOptions {
+    fn default() -> Self {
+        Self {
+            split_jump_ratio: 1.45,
+            stale_run_bars: 5,
+        }
+    }
+}
+
+/// What is wrong with a stretch of bars
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    /// Trading days without a bar
+    MissingTradingDays,
+    /// Bar on a day the exchange was closed
+    ClosedDayBar,
+    /// More than one bar for the same session
+    DuplicateBar,
+    /// Zero, negative or non-finite price
+    NonPositivePrice,
+    /// High below or low above another price, or a negative volume
+    OhlcInconsistency,
+    /// Close-to-close jump as large as a split without a matching split factor
+    UnexplainedJump,
+    /// Run of bars with the same close
+    StaleRun,
+}
+
+/// One problem, covering the sessions `start..=end`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditIssue {
+    pub kind: IssueKind,
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+    pub detail: String,
+}
+
+/// Audit of one ticker over `start..=end`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditReport {
+    pub ticker: String,
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+    pub bars: usize,
+    pub first_bar: Option<NaiveDate>,
+    pub last_bar: Option<NaiveDate>,
+    pub issues: Vec<AuditIssue>,
+}
+
+impl AuditReport {
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Number of issues of each kind
+    pub fn counts(&self) -> BTreeMap<IssueKind, usize> {
+        let mut counts = BTreeMap::new();
+        for issue in &self.issues {
+            *counts.entry(issue.kind).or_insert(0) += 1;
+        }
+        counts
+    }
+}
+
+/// Audits the bars and corporate actions of `ticker` between `start` and `end` as read
+/// from `market_data`, which should be the raw source rather than an adjusting provider
+pub async fn audit_ticker(
+    market_data: &dyn MarketDataProvider,
+    calendar: &TradingCalendar,
+    ticker: &str,
+    start: ExecutionDate,
+    end: ExecutionDate,
+    options: &AuditOptions,
+) -> Result<AuditReport, DatabaseError> {
+    let bars = market_data.get_bar_history(ticker, start, end).await?;
+    let actions = market_data
+        .get_corporate_actions(ticker, start, end)
+        .await?;
+    Ok(audit_bars(
+        ticker,
+        &bars,
+        &actions,
+        calendar,
+        start.date(),
+        end.date(),
+        options,
+    ))
+}
+
+/// Audits `bars` (ordered by time) of `ticker` between `start` and `end`. Sessions are
+/// expected from the first to the last bar, so listings and delistings inside the range
+/// are not reported as gaps; days the calendar does not cover are not checked.
+pub fn audit_bars(
+    ticker: &str,
+    bars: &[Bar],
+    actions: &[CorporateAction],
+    calendar: &TradingCalendar,
+    start: NaiveDate,
+    end: NaiveDate,
+    options: &AuditOptions,
+) -> AuditReport {
+    let mut issues = Vec::new();
+    let issue = |kind, start, end, detail: String| AuditIssue {
+        kind,
+        start,
+        end,
+        detail,
+    };
+
+    // One bar per session from here on; the first of duplicates is kept
+    let mut sessions: Vec<&Bar> = Vec::with_capacity(bars.len());
+    for bar in bars {
+        match sessions.last() {
+            Some(last) if last.time == bar.time => {
+                let date = bar.time.date();
+                issues.push(issue(
+                    IssueKind::DuplicateBar,
+                    date,
+                    date,
+                    format!("another bar at {}", bar.time),
+                ));
+            }
+            _ => sessions.push(bar),
+        }
+    }
+
+    let (calendar_start, calendar_end) = calendar.range();
+    for bar in &sessions {
+        let date = bar.time.date();
+        if calendar.covers(date) && !calendar.is_trading_day(date).unwrap_or(true) {
+            let reason = calendar.holiday_name(date).unwrap_or("weekend");
+            issues.push(issue(
+                IssueKind::ClosedDayBar,
+                date,
+                date,
+                format!("bar on a closed day ({})", reason),
+            ));
+        }
+
+        let prices = [bar.open, bar.high, bar.low, bar.close];
+        if prices
+            .iter()
+            .any(|price| !price.is_finite() || *price <= 0.0)
+        {
+            issues.push(issue(
+                IssueKind::NonPositivePrice,
+                date,
+                date,
+                format!(
+                    "open {} high {} low {} close {}",
+                    bar.open, bar.high, bar.low, bar.close
+                ),
+            ));
+            continue;
+        }
+        let highest = bar.open.max(bar.close).max(bar.low);
+        let lowest = bar.open.min(bar.close).min(bar.high);
+        if bar.high < highest || bar.low > lowest || bar.volume < 0 {
+            issues.push(issue(
+                IssueKind::OhlcInconsistency,
+                date,
+                date,
+                format!(
+                    "open {} high {} low {} close {} volume {}",
+                    bar.open, bar.high, bar.low, bar.close, bar.volume
+                ),
+            ));
+        }
+    }
+
+    if let (Some(first), Some(last)) = (sessions.first(), sessions.last()) {
+        let from = first.time.date().max(calendar_start);
+        let to = last.time.date().min(calendar_end);
+        if from <= to {
+            let stored: Vec<NaiveDate> = sessions.iter().map(|bar| bar.time.date()).collect();
+            let mut gap: Option<(NaiveDate, NaiveDate, usize)> = None;
+            let mut close_gap = |gap: &mut Option<(NaiveDate, NaiveDate, usize)>| {
+                if let Some((gap_start, gap_end, days)) = gap.take() {
+                    issues.push(issue(
+                        IssueKind::MissingTradingDays,
+                        gap_start,
+                        gap_end,
+                        format!("{} trading days without a bar", days),
+                    ));
+                }
+            };
+            // Both ranges are covered, so listing the sessions cannot fail
+            for date in calendar.trading_days(from, to).into_iter().flatten() {
+                if stored.binary_search(&date).is_ok() {
+                    close_gap(&mut gap);
+                } else {
+                    gap = Some(match gap {
+                        Some((gap_start, _, days)) => (gap_start, date, days + 1),
+                        None => (date, date, 1),
+                    });
+                }
+            }
+            close_gap(&mut gap);
+        }
+    }
+
+    let valid = |bar: &&&Bar| bar.close.is_finite() && bar.close > 0.0;
+    let closes: Vec<&&Bar> = sessions.iter().filter(valid).collect();
+    for pair in closes.windows(2) {
+        let (previous, bar) = (pair[0], pair[1]);
+        let ratio = bar.close / previous.close;
+        if ratio < options.split_jump_ratio && ratio > 1.0 / options.split_jump_ratio {
+            continue;
+        }
+        // A 4-for-1 split (factor 4) takes the close to about a quarter
+        let explained = actions.iter().any(|action| {
+            action.time > previous.time
+                && action.time <= bar.time
+                && action.split_factor != 1.0
+                && (0.75..=1.33).contains(&(ratio * action.split_factor))
+        });
+        if !explained {
+            let date = bar.time.date();
+            issues.push(issue(
+                IssueKind::UnexplainedJump,
+                date,
+                date,
+                format!(
+                    "close went from {} to {} (x{:.3}) without a split",
+                    previous.close, bar.close, ratio
+                ),
+            ));
+        }
+    }
+
+    let mut run_start = 0;
+    for index in 1..=closes.len() {
+        if index < closes.len() && closes[index].close == closes[run_start].close {
+            continue;
+        }
+        let length = index - run_start;
+        if length >= options.stale_run_bars.max(2) {
+            issues.push(issue(
+                IssueKind::StaleRun,
+                closes[run_start].time.date(),
+                closes[index - 1].time.date(),
+                format!("{} bars closing at {}", length, closes[run_start].close),
+            ));
+        }
+        run_start = index;
+    }
+
+    issues.sort_by_key(|issue| (issue.start, issue.kind));
+    AuditReport {
+        ticker: ticker.to_string(),
+        start,
+        end,
+        bars: sessions.len(),
+        first_bar: sessions.first().map(|bar| bar.time.date()),
+        last_bar: sessions.last().map(|bar| bar.time.date()),
+        issues,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market::price_store::bar_timestamp;
+
+    fn bar(month: u32, day: u32, close: f64) -> Bar {
+        Bar {
+            time: bar_timestamp(NaiveDate::from_ymd_opt(2024, month, day).unwrap()),
+            open: close,
+            high: close,
+            low: close,
+            close,
+            volume: 1_000,
+        }
+    }
+
+    #[test]
+    fn test_audit_reports_each_kind_of_issue() {
+        let date = |month, day| NaiveDate::from_ymd_opt(2024, month, day).unwrap();
+        let mut inconsistent = bar(6, 4, 101.0);
+        inconsistent.high = 99.0;
+        let bars = [
+            bar(6, 3, 100.0),
+            inconsistent,
+            bar(6, 4, 101.0),
+            // The 5th is missing; the 8th is a Saturday
+            bar(6, 6, 0.0),
+            bar(6, 7, 102.0),
+            bar(6, 8, 102.0),
+            // The 10th halves without a split, the 12th with a 2-for-1 split
+            bar(6, 10, 51.0),
+            bar(6, 11, 51.0),
+            bar(6, 12, 25.5),
+            bar(6, 13, 25.5),
+            bar(6, 14, 25.5),
+            bar(6, 17, 25.5),
+            bar(6, 18, 25.5),
+        ];
+        let actions = [CorporateAction {
+            time: bar_timestamp(date(6, 12)),
+            split_factor: 2.0,
+            dividend: 0.0,
+        }];
+        let report = audit_bars(
+            "AAPL",
+            &bars,
+            &actions,
+            &TradingCalendar::nasdaq(),
+            date(6, 1),
+            date(6, 30),
+            &AuditOptions::default(),
+        );
+
+        let found: Vec<_> = report
+            .issues
+            .iter()
+            .map(|issue| (issue.kind, issue.start.to_string()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (IssueKind::DuplicateBar, "2024-06-04".to_string()),
+                (IssueKind::OhlcInconsistency, "2024-06-04".to_string()),
+                (IssueKind::MissingTradingDays, "2024-06-05".to_string()),
+                (IssueKind::NonPositivePrice, "2024-06-06".to_string()),
+                (IssueKind::ClosedDayBar, "2024-06-08".to_string()),
+                (IssueKind::UnexplainedJump, "2024-06-10".to_string()),
+                (IssueKind::StaleRun, "2024-06-12".to_string()),
+            ]
+        );
+        assert_eq!(report.bars, 12);
+        assert_eq!(report.last_bar, Some(date(6, 18)));
+        assert_eq!(report.counts()[&IssueKind::StaleRun], 1);
+    }
+}
@@ -0,0 +1,609 @@
+//! In-process trading calendar.
+//! Built from `Python fetch data/nasdaq_closed_days.csv` (the same data that feeds the
+//! `nasdaq_closed_days` table), so calendar questions can be answered without a database
+//! round trip. The CSV lists every closed day (weekends and holidays) for whole years;
+//! every other day in that range is a trading day.
+//! A `CalendarRegistry` holds one calendar per `Exchange` and answers which session a
+//! strategy trading on several exchanges executes on.
+
+use crate::market::database_functions::DatabaseError;
+use crate::market::exchange::Exchange;
+use crate::market::execution_date::ExecutionDate;
+use chrono::{Datelike, NaiveDate};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+
+const NASDAQ_CLOSED_DAYS: &str = include_str!("../../Python fetch data/nasdaq_closed_days.csv");
+
+/// Splits one CSV row, honouring double-quoted fields such as `"Martin Luther King, Jr. Day"`
+pub(crate) fn split_row(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+pub(crate) fn parse_flag(value: &str) -> Option<bool> {
+    match value.trim() {
+        "True" | "true" | "1" => Some(true),
+        "False" | "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Trading days of one exchange over a contiguous range of whole years
+#[derive(Debug, Clone)]
+pub struct TradingCalendar {
+    first_day: NaiveDate,
+    last_day: NaiveDate,
+    closed_days: BTreeSet<NaiveDate>,
+    holidays: BTreeMap<NaiveDate, String>,
+}
+
+impl TradingCalendar {
+    /// Calendar from explicit closed days covering `first_day..=last_day`
+    pub fn new(
+        first_day: NaiveDate,
+        last_day: NaiveDate,
+        closed_days: BTreeSet<NaiveDate>,
+        holidays: BTreeMap<NaiveDate, String>,
+    ) -> Result<Self, DatabaseError> {
+        if first_day > last_day {
+            return Err(DatabaseError::InvalidDateRange);
+        }
+        Ok(Self {
+            first_day,
+            last_day,
+            closed_days,
+            holidays,
+        })
+    }
+
+    /// Calendar of a market that trades every day, such as crypto
+    pub fn always_open(first_day: NaiveDate, last_day: NaiveDate) -> Result<Self, DatabaseError> {
+        Self::new(first_day, last_day, BTreeSet::new(), BTreeMap::new())
+    }
+
+    /// The NASDAQ calendar bundled with the crate, parsed once per process
+    pub fn nasdaq() -> Arc<TradingCalendar> {
+        static NASDAQ: OnceLock<Arc<TradingCalendar>> = OnceLock::new();
+        NASDAQ
+            .get_or_init(|| {
+                Arc::new(
+                    Self::parse(NASDAQ_CLOSED_DAYS, "nasdaq_closed_days.csv")
+                        .expect("bundled NASDAQ calendar is valid"),
+                )
+            })
+            .clone()
+    }
+
+    /// Loads a calendar in the `nasdaq_closed_days.csv` format
+    pub fn from_csv(path: impl AsRef<Path>) -> Result<Self, DatabaseError> {
+        let path = path.as_ref();
+        Self::parse(&fs::read_to_string(path)?, &path.display().to_string())
+    }
+
+    /// Parses closed-day rows with `Date`, `Is Weekend`, `Is Holiday` and `Holiday Name`
+    /// columns. The calendar covers the whole years spanned by the listed dates.
+    pub fn parse(contents: &str, source: &str) -> Result<Self, DatabaseError> {
+        let mut lines = contents.lines();
+        let header =
+            split_row(lines.next().ok_or_else(|| {
+                DatabaseError::InvalidInput(format!("Empty calendar {}", source))
+            })?);
+        let column = |name: &str| {
+            header
+                .iter()
+                .position(|column| column.trim() == name)
+                .ok_or_else(|| {
+                    DatabaseError::InvalidInput(format!("Missing column '{}' in {}", name, source))
+                })
+        };
+        let date_index = column("Date")?;
+        let weekend_index = column("Is Weekend")?;
+        let holiday_index = column("Is Holiday")?;
+        let name_index = column("Holiday Name")?;
+
+        let mut closed_days = BTreeSet::new();
+        let mut holidays = BTreeMap::new();
+        for (line_number, line) in lines.enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let fields = split_row(line);
+            let invalid_row = |reason: &str| {
+                DatabaseError::InvalidInput(format!("{}:{}: {}", source, line_number + 2, reason))
+            };
+            let field = |index: usize| {
+                fields
+                    .get(index)
+                    .map(String::as_str)
+                    .ok_or_else(|| invalid_row("missing column"))
+            };
+
+            let date = NaiveDate::parse_from_str(field(date_index)?.trim(), "%Y-%m-%d")
+                .map_err(|_| invalid_row("invalid date"))?;
+            let is_weekend =
+                parse_flag(field(weekend_index)?).ok_or_else(|| invalid_row("invalid flag"))?;
+            let is_holiday =
+                parse_flag(field(holiday_index)?).ok_or_else(|| invalid_row("invalid flag"))?;
+
+            if is_weekend || is_holiday {
+                closed_days.insert(date);
+            }
+            if is_holiday {
+                holidays.insert(date, field(name_index)?.trim().to_string());
+            }
+        }
+
+        let (Some(first), Some(last)) = (closed_days.first(), closed_days.last()) else {
+            return Err(DatabaseError::InvalidInput(format!(
+                "No closed days in {}",
+                source
+            )));
+        };
+        let first_day = NaiveDate::from_ymd_opt(first.year(), 1, 1).unwrap();
+        let last_day = NaiveDate::from_ymd_opt(last.year(), 12, 31).unwrap();
+
+        Self::new(first_day, last_day, closed_days, holidays)
+    }
+
+    /// First and last day the calendar knows about
+    pub fn range(&self) -> (NaiveDate, NaiveDate) {
+        (self.first_day, self.last_day)
+    }
+
+    pub fn covers(&self, date: NaiveDate) -> bool {
+        (self.first_day..=self.last_day).contains(&date)
+    }
+
+    fn ensure_covered(&self, date: NaiveDate) -> Result<(), DatabaseError> {
+        if self.covers(date) {
+            Ok(())
+        } else {
+            Err(DatabaseError::InvalidInput(format!(
+                "{} is outside the trading calendar ({} to {})",
+                date, self.first_day, self.last_day
+            )))
+        }
+    }
+
+    fn is_open(&self, date: NaiveDate) -> bool {
+        !self.closed_days.contains(&date)
+    }
+
+    pub fn is_trading_day(&self, date: NaiveDate) -> Result<bool, DatabaseError> {
+        self.ensure_covered(date)?;
+        Ok(self.is_open(date))
+    }
+
+    /// Name of the holiday closing the exchange on `date`, if any
+    pub fn holiday_name(&self, date: NaiveDate) -> Option<&str> {
+        self.holidays.get(&date).map(String::as_str)
+    }
+
+    /// Holidays (weekday closures) in `start..=end`
+    pub fn holidays(
+        &self,
+        start: NaiveDate,
+        end: NaiveDate,
+    ) -> impl Iterator<Item = (NaiveDate, &str)> {
+        self.holidays
+            .range(start..=end.max(start))
+            .map(|(date, name)| (*date, name.as_str()))
+    }
+
+    /// Trading days in `start..=end`, oldest first
+    pub fn trading_days(
+        &self,
+        start: NaiveDate,
+        end: NaiveDate,
+    ) -> Result<impl Iterator<Item = NaiveDate> + '_, DatabaseError> {
+        self.ensure_covered(start)?;
+        self.ensure_covered(end)?;
+        Ok(start
+            .iter_days()
+            .take_while(move |date| *date <= end)
+            .filter(|date| self.is_open(*date)))
+    }
+
+    /// Latest trading day on or before `date`
+    pub fn trading_day_on_or_before(&self, date: NaiveDate) -> Result<NaiveDate, DatabaseError> {
+        self.ensure_covered(date)?;
+        let mut day = date;
+        while !self.is_open(day) {
+            day = day
+                .pred_opt()
+                .filter(|day| self.covers(*day))
+                .ok_or_else(|| {
+                    DatabaseError::InsufficientData(format!("No trading day on or before {}", date))
+                })?;
+        }
+        Ok(day)
+    }
+
+    /// Earliest trading day on or after `date`
+    pub fn trading_day_on_or_after(&self, date: NaiveDate) -> Result<NaiveDate, DatabaseError> {
+        self.ensure_covered(date)?;
+        let mut day = date;
+        while !self.is_open(day) {
+            day = day
+                .succ_opt()
+                .filter(|day| self.covers(*day))
+                .ok_or_else(|| {
+                    DatabaseError::InsufficientData(format!("No trading day on or after {}", date))
+                })?;
+        }
+        Ok(day)
+    }
+
+    /// Trading day strictly before `date`
+    pub fn previous_trading_day(&self, date: NaiveDate) -> Result<NaiveDate, DatabaseError> {
+        let day = date.pred_opt().ok_or(DatabaseError::InvalidDateRange)?;
+        self.trading_day_on_or_before(day)
+    }
+
+    /// Trading day strictly after `date`
+    pub fn next_trading_day(&self, date: NaiveDate) -> Result<NaiveDate, DatabaseError> {
+        let day = date.succ_opt().ok_or(DatabaseError::InvalidDateRange)?;
+        self.trading_day_on_or_after(day)
+    }
+
+    /// The `n`th (1-based) trading day of a month
+    pub fn nth_trading_day_of_month(
+        &self,
+        year: i32,
+        month: u32,
+        n: usize,
+    ) -> Result<NaiveDate, DatabaseError> {
+        let (first, last) = month_bounds(year, month)?;
+        if n == 0 {
+            return Err(DatabaseError::InvalidInput(
+                "Trading day index starts at 1".to_string(),
+            ));
+        }
+        self.trading_days(first, last)?.nth(n - 1).ok_or_else(|| {
+            DatabaseError::InsufficientData(format!(
+                "{}-{:02} has fewer than {} trading days",
+                year, month, n
+            ))
+        })
+    }
+
+    pub fn first_trading_day_of_month(
+        &self,
+        year: i32,
+        month: u32,
+    ) -> Result<NaiveDate, DatabaseError> {
+        self.nth_trading_day_of_month(year, month, 1)
+    }
+
+    pub fn last_trading_day_of_month(
+        &self,
+        year: i32,
+        month: u32,
+    ) -> Result<NaiveDate, DatabaseError> {
+        let (_, last) = month_bounds(year, month)?;
+        self.trading_day_on_or_before(last)
+    }
+
+    /// Last trading day of quarter `quarter` (1 to 4)
+    pub fn last_trading_day_of_quarter(
+        &self,
+        year: i32,
+        quarter: u32,
+    ) -> Result<NaiveDate, DatabaseError> {
+        if !(1..=4).contains(&quarter) {
+            return Err(DatabaseError::InvalidInput(format!(
+                "Invalid quarter: {}",
+                quarter
+            )));
+        }
+        self.last_trading_day_of_month(year, quarter * 3)
+    }
+
+    pub fn last_trading_day_of_year(&self, year: i32) -> Result<NaiveDate, DatabaseError> {
+        self.last_trading_day_of_month(year, 12)
+    }
+
+    /// Number of trading days in `start..=end`; zero when `end` is before `start`
+    pub fn count_trading_days(
+        &self,
+        start: NaiveDate,
+        end: NaiveDate,
+    ) -> Result<usize, DatabaseError> {
+        if end < start {
+            return Ok(0);
+        }
+        Ok(self.trading_days(start, end)?.count())
+    }
+}
+
+fn month_bounds(year: i32, month: u32) -> Result<(NaiveDate, NaiveDate), DatabaseError> {
+    let first = NaiveDate::from_ymd_opt(year, month, 1)
+        .ok_or_else(|| DatabaseError::InvalidInput(format!("Invalid month {}-{}", year, month)))?;
+    let last = first
+        .checked_add_months(chrono::Months::new(1))
+        .and_then(|next| next.pred_opt())
+        .ok_or(DatabaseError::InvalidDateRange)?;
+    Ok((first, last))
+}
+
+/// Trading calendars by exchange
+#[derive(Debug, Clone, Default)]
+pub struct CalendarRegistry {
+    calendars: BTreeMap<Exchange, Arc<TradingCalendar>>,
+}
+
+impl CalendarRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// NASDAQ and NYSE (which share their holiday schedule) from the bundled calendar,
+    /// plus an always-open crypto calendar over the same years
+    pub fn builtin() -> Self {
+        let nasdaq = TradingCalendar::nasdaq();
+        let (first_day, last_day) = nasdaq.range();
+        let crypto = TradingCalendar::always_open(first_day, last_day)
+            .expect("bundled calendar range is ordered");
+
+        let mut registry = Self::new();
+        registry.register(Exchange::Nasdaq, Arc::clone(&nasdaq));
+        registry.register(Exchange::Nyse, nasdaq);
+        registry.register(Exchange::Crypto, Arc::new(crypto));
+        registry
+    }
+
+    /// Adds or replaces the calendar of `exchange`
+    pub fn register(&mut self, exchange: Exchange, calendar: Arc<TradingCalendar>) {
+        self.calendars.insert(exchange, calendar);
+    }
+
+    /// Registers every `<EXCHANGE>.csv` file in `dir`, e.g. `XETRA.csv`, replacing any
+    /// calendar already registered for that exchange
+    pub fn load_csv_dir(&mut self, dir: impl AsRef<Path>) -> Result<(), DatabaseError> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("csv") {
+                continue;
+            }
+
+            let exchange: Exchange = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or_default()
+                .parse()?;
+            self.register(exchange, Arc::new(TradingCalendar::from_csv(&path)?));
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, exchange: Exchange) -> Result<&Arc<TradingCalendar>, DatabaseError> {
+        self.calendars.get(&exchange).ok_or_else(|| {
+            DatabaseError::InvalidInput(format!("No trading calendar for {}", exchange))
+        })
+    }
+
+    pub fn contains(&self, exchange: Exchange) -> bool {
+        self.calendars.contains_key(&exchange)
+    }
+
+    /// Last session of `exchange` on or before `date`
+    pub fn session_on_or_before(
+        &self,
+        exchange: Exchange,
+        date: NaiveDate,
+    ) -> Result<ExecutionDate, DatabaseError> {
+        let day = self.get(exchange)?.trading_day_on_or_before(date)?;
+        Ok(ExecutionDate::at_close(day, exchange))
+    }
+
+    /// Last day on or before `date` on which every exchange in `exchanges` is open.
+    /// The session returned is the one that closes last that day, so every close is
+    /// known when the strategy executes.
+    pub fn common_session_on_or_before(
+        &self,
+        exchanges: &BTreeSet<Exchange>,
+        date: NaiveDate,
+    ) -> Result<ExecutionDate, DatabaseError> {
+        let calendars = exchanges
+            .iter()
+            .map(|exchange| self.get(*exchange))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Each pass only moves backwards, so this stops at the first common trading day
+        let mut day = date;
+        loop {
+            let mut candidate = day;
+            for calendar in &calendars {
+                candidate = calendar.trading_day_on_or_before(candidate)?;
+            }
+            if candidate == day {
+                break;
+            }
+            day = candidate;
+        }
+
+        let exchange = exchanges
+            .iter()
+            .copied()
+            .max_by_key(|exchange| exchange.close_instant(day))
+            .ok_or_else(|| DatabaseError::InvalidInput("No exchanges to schedule".to_string()))?;
+
+        Ok(ExecutionDate::at_close(day, exchange))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn test_split_row_handles_quoted_commas() {
+        assert_eq!(
+            split_row(r#"1986-01-20,"Martin Luther King, Jr. Day",x"#),
+            vec!["1986-01-20", "Martin Luther King, Jr. Day", "x"]
+        );
+    }
+
+    #[test]
+    fn test_bundled_nasdaq_calendar() {
+        let calendar = TradingCalendar::nasdaq();
+        assert_eq!(calendar.range(), (date(1980, 1, 1), date(2050, 12, 31)));
+
+        assert!(calendar.is_trading_day(date(2024, 12, 31)).unwrap());
+        assert!(!calendar.is_trading_day(date(2024, 12, 25)).unwrap());
+        assert!(!calendar.is_trading_day(date(2024, 12, 28)).unwrap());
+        // National day of mourning for President Carter
+        assert!(!calendar.is_trading_day(date(2025, 1, 9)).unwrap());
+        assert_eq!(
+            calendar.holiday_name(date(2024, 7, 4)),
+            Some("Independence Day")
+        );
+        assert!(calendar.is_trading_day(date(2051, 1, 2)).is_err());
+    }
+
+    #[test]
+    fn test_previous_and_next_trading_day() {
+        let calendar = TradingCalendar::nasdaq();
+        // Markets were closed from September 11th to 14th, 2001
+        assert_eq!(
+            calendar.previous_trading_day(date(2001, 9, 17)).unwrap(),
+            date(2001, 9, 10)
+        );
+        assert_eq!(
+            calendar.next_trading_day(date(2001, 9, 10)).unwrap(),
+            date(2001, 9, 17)
+        );
+        assert_eq!(
+            calendar.trading_day_on_or_before(date(2020, 1, 1)).unwrap(),
+            date(2019, 12, 31)
+        );
+    }
+
+    #[test]
+    fn test_month_quarter_and_year_boundaries() {
+        let calendar = TradingCalendar::nasdaq();
+        // January 1st and 9th 2025 were closed
+        assert_eq!(
+            calendar.nth_trading_day_of_month(2025, 1, 1).unwrap(),
+            date(2025, 1, 2)
+        );
+        assert_eq!(
+            calendar.nth_trading_day_of_month(2025, 1, 6).unwrap(),
+            date(2025, 1, 10)
+        );
+        assert!(calendar.nth_trading_day_of_month(2025, 1, 30).is_err());
+        // March 29th 2024 was Good Friday
+        assert_eq!(
+            calendar.last_trading_day_of_quarter(2024, 1).unwrap(),
+            date(2024, 3, 28)
+        );
+        assert_eq!(
+            calendar.last_trading_day_of_month(2024, 6).unwrap(),
+            date(2024, 6, 28)
+        );
+        assert_eq!(
+            calendar.last_trading_day_of_year(2022).unwrap(),
+            date(2022, 12, 30)
+        );
+    }
+
+    #[test]
+    fn test_count_trading_days() {
+        let calendar = TradingCalendar::nasdaq();
+        assert_eq!(
+            calendar
+                .count_trading_days(date(2024, 1, 1), date(2024, 12, 31))
+                .unwrap(),
+            252
+        );
+        assert_eq!(
+            calendar
+                .count_trading_days(date(2024, 12, 23), date(2024, 12, 27))
+                .unwrap(),
+            4
+        );
+        assert_eq!(
+            calendar
+                .count_trading_days(date(2024, 12, 27), date(2024, 12, 23))
+                .unwrap(),
+            0
+        );
+    }
+
+    fn xetra_calendar() -> TradingCalendar {
+        let csv = "Date,Is Weekend,Is Holiday,Holiday Name\n\
+                   2024-12-24,False,True,Christmas Eve\n\
+                   2024-12-25,False,True,Christmas Day\n\
+                   2024-12-26,False,True,Boxing Day\n\
+                   2024-12-28,True,False,\n\
+                   2024-12-29,True,False,\n\
+                   2024-12-31,False,True,New Year's Eve\n";
+        TradingCalendar::parse(csv, "XETRA.csv").unwrap()
+    }
+
+    #[test]
+    fn test_common_session_across_exchanges() {
+        let mut registry = CalendarRegistry::builtin();
+        registry.register(Exchange::Xetra, Arc::new(xetra_calendar()));
+
+        let us_and_crypto = BTreeSet::from([Exchange::Nasdaq, Exchange::Crypto]);
+        let session = registry
+            .common_session_on_or_before(&us_and_crypto, date(2024, 12, 28))
+            .unwrap();
+        assert_eq!(session.date(), date(2024, 12, 27));
+        assert_eq!(session.exchange(), Exchange::Crypto);
+
+        // New Year's Eve is a holiday in Frankfurt and Christmas Eve/Boxing Day are
+        // closed too, so the last common day is December 30th
+        let us_and_germany = BTreeSet::from([Exchange::Nyse, Exchange::Xetra]);
+        let session = registry
+            .common_session_on_or_before(&us_and_germany, date(2024, 12, 31))
+            .unwrap();
+        assert_eq!(session.date(), date(2024, 12, 30));
+        assert_eq!(session.exchange(), Exchange::Nyse);
+
+        // A single exchange just rolls back on its own calendar
+        assert_eq!(
+            registry
+                .session_on_or_before(Exchange::Xetra, date(2024, 12, 26))
+                .unwrap()
+                .date(),
+            date(2024, 12, 23)
+        );
+        assert!(registry
+            .session_on_or_before(Exchange::Xetra, date(2030, 1, 2))
+            .is_err());
+    }
+
+    #[test]
+    fn test_missing_calendar_is_an_error() {
+        let registry = CalendarRegistry::builtin();
+        let result = registry
+            .common_session_on_or_before(&BTreeSet::from([Exchange::Xetra]), date(2024, 1, 2));
+        assert!(matches!(result, Err(DatabaseError::InvalidInput(_))));
+    }
+}
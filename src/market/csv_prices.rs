@@ -0,0 +1,443 @@
+//! Loader for Tiingo-style daily price CSVs such as `Python fetch data/testdata/*prices.csv`.
+//! Files are named `<TICKER>prices.csv` (or `<TICKER>.csv`) and start with a header row
+//! `date,close,high,low,open,volume,adjClose,...`; columns are looked up by name.
+//! Files with `open`, `high`, `low` and `volume` columns load as full OHLCV bars, files
+//! with only `date` and `close` as close series. `divCash` and `splitFactor` columns,
+//! when present, are loaded as corporate actions for adjusted prices. An optional
+//! `shares_outstanding.csv` with `ticker`, `date` and `shares` columns holds the share
+//! counts used for market-cap weighting, an optional `index_membership.csv` the dated
+//! constituents of universes.
+
+use crate::market::adjustments::CorporateAction;
+use crate::market::calendar::split_row;
+use crate::market::database_functions::{validate_ticker, Bar, DatabaseError};
+use crate::market::market_cap::SharesOutstanding;
+use crate::market::price_store::{bar_timestamp, PriceSeries, PriceStore};
+use crate::market::universe::{load_index_membership, INDEX_MEMBERSHIP_FILE};
+use chrono::NaiveDate;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Derives the ticker from a file name like `AAPLprices.csv` or `AAPL.csv`
+pub(crate) fn ticker_from_path(path: &Path) -> Option<String> {
+    let file_name = path.file_name()?.to_str()?;
+    let stem = file_name.strip_suffix(".csv")?;
+    let ticker = stem.strip_suffix("prices").unwrap_or(stem);
+    validate_ticker(ticker).ok()?;
+    Some(ticker.to_string())
+}
+
+fn column_index(header: &[&str], name: &str, path: &Path) -> Result<usize, DatabaseError> {
+    header
+        .iter()
+        .position(|column| column.trim() == name)
+        .ok_or_else(|| {
+            DatabaseError::InvalidInput(format!("Missing column '{}' in {}", name, path.display()))
+        })
+}
+
+/// Parses one price CSV into a series ordered by date
+pub fn load_price_file(path: &Path) -> Result<PriceSeries, DatabaseError> {
+    let contents = fs::read_to_string(path)?;
+    let mut lines = contents.lines();
+
+    let header: Vec<&str> = lines
+        .next()
+        .ok_or_else(|| DatabaseError::InvalidInput(format!("Empty file {}", path.display())))?
+        .split(',')
+        .collect();
+    let date_index = column_index(&header, "date", path)?;
+    let close_index = column_index(&header, "close", path)?;
+    let bar_indices = match (
+        column_index(&header, "open", path),
+        column_index(&header, "high", path),
+        column_index(&header, "low", path),
+        column_index(&header, "volume", path),
+    ) {
+        (Ok(open), Ok(high), Ok(low), Ok(volume)) => Some((open, high, low, volume)),
+        _ => None,
+    };
+    let action_indices = match (
+        column_index(&header, "divCash", path),
+        column_index(&header, "splitFactor", path),
+    ) {
+        (Ok(dividend), Ok(split_factor)) => Some((dividend, split_factor)),
+        _ => None,
+    };
+
+    let mut series = PriceSeries::new();
+    let mut actions = Vec::new();
+    for (line_number, line) in lines.enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split(',').collect();
+        let invalid_row = |reason: String| {
+            DatabaseError::InvalidInput(format!(
+                "{}:{}: {}",
+                path.display(),
+                line_number + 2,
+                reason
+            ))
+        };
+
+        let date_field = fields
+            .get(date_index)
+            .ok_or_else(|| invalid_row("missing date".to_string()))?;
+        let date = NaiveDate::parse_from_str(date_field.trim(), "%Y-%m-%d")
+            .map_err(|e| invalid_row(format!("invalid date '{}': {}", date_field, e)))?;
+
+        let price = |index: usize, name: &str| -> Result<f64, DatabaseError> {
+            let field = fields
+                .get(index)
+                .ok_or_else(|| invalid_row(format!("missing {}", name)))?;
+            field
+                .trim()
+                .parse()
+                .map_err(|e| invalid_row(format!("invalid {} '{}': {}", name, field, e)))
+        };
+        let close = price(close_index, "close")?;
+
+        let pushed = match bar_indices {
+            Some((open_index, high_index, low_index, volume_index)) => series.push_bar(Bar {
+                time: bar_timestamp(date),
+                open: price(open_index, "open")?,
+                high: price(high_index, "high")?,
+                low: price(low_index, "low")?,
+                close,
+                // Some exports write volumes as floats
+                volume: price(volume_index, "volume")?.round() as i64,
+            }),
+            None => series.push(bar_timestamp(date), close),
+        };
+        pushed.map_err(|e| invalid_row(e.to_string()))?;
+
+        if let Some((dividend_index, split_index)) = action_indices {
+            let dividend = price(dividend_index, "divCash")?;
+            let split_factor = price(split_index, "splitFactor")?;
+            if dividend != 0.0 || split_factor != 1.0 {
+                actions.push(CorporateAction {
+                    time: bar_timestamp(date),
+                    split_factor,
+                    dividend,
+                });
+            }
+        }
+    }
+
+    series.set_corporate_actions(actions);
+    Ok(series)
+}
+
+/// File in a price directory holding the share counts of its tickers
+pub const SHARES_OUTSTANDING_FILE: &str = "shares_outstanding.csv";
+
+/// Parses share counts from rows of `ticker`, `date` and `shares`, grouped by ticker
+pub fn load_shares_file(
+    path: &Path,
+) -> Result<HashMap<String, Vec<SharesOutstanding>>, DatabaseError> {
+    let contents = fs::read_to_string(path)?;
+    let mut lines = contents.lines();
+
+    let header =
+        split_row(lines.next().ok_or_else(|| {
+            DatabaseError::InvalidInput(format!("Empty file {}", path.display()))
+        })?);
+    let header: Vec<&str> = header.iter().map(String::as_str).collect();
+    let ticker_index = column_index(&header, "ticker", path)?;
+    let date_index = column_index(&header, "date", path)?;
+    let shares_index = column_index(&header, "shares", path)?;
+
+    let mut shares: HashMap<String, Vec<SharesOutstanding>> = HashMap::new();
+    for (line_number, line) in lines.enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let fields = split_row(line);
+        let invalid_row = |reason: &str| {
+            DatabaseError::InvalidInput(format!(
+                "{}:{}: {}",
+                path.display(),
+                line_number + 2,
+                reason
+            ))
+        };
+        let field = |index: usize| {
+            fields
+                .get(index)
+                .map(|field| field.trim())
+                .ok_or_else(|| invalid_row("missing column"))
+        };
+
+        let ticker = field(ticker_index)?;
+        validate_ticker(ticker).map_err(|_| invalid_row("invalid ticker"))?;
+        let date = NaiveDate::parse_from_str(field(date_index)?, "%Y-%m-%d")
+            .map_err(|_| invalid_row("invalid date"))?;
+        let count: f64 = field(shares_index)?
+            .parse()
+            .map_err(|_| invalid_row("invalid share count"))?;
+        if !count.is_finite() || count <= 0.0 {
+            return Err(invalid_row("share count must be positive"));
+        }
+
+        shares
+            .entry(ticker.to_string())
+            .or_default()
+            .push(SharesOutstanding {
+                time: bar_timestamp(date),
+                shares: count,
+            });
+    }
+
+    Ok(shares)
+}
+
+impl PriceStore {
+    /// Loads every `*.csv` price file in `dir`, keyed by the ticker in its file name,
+    /// with the share counts of [`SHARES_OUTSTANDING_FILE`] and the universes of
+    /// [`INDEX_MEMBERSHIP_FILE`] when present
+    pub fn from_csv_dir(dir: impl AsRef<Path>) -> Result<Self, DatabaseError> {
+        let dir = dir.as_ref();
+        let mut store = PriceStore::new();
+
+        let mut paths: Vec<_> = fs::read_dir(dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<_, _>>()?;
+        paths.sort();
+
+        let shares_path = dir.join(SHARES_OUTSTANDING_FILE);
+        let mut shares = if shares_path.is_file() {
+            load_shares_file(&shares_path)?
+        } else {
+            HashMap::new()
+        };
+
+        for path in paths {
+            let Some(ticker) = ticker_from_path(&path) else {
+                tracing::debug!("Skipping non-price file {}", path.display());
+                continue;
+            };
+
+            let mut series = load_price_file(&path)?;
+            if let Some(records) = shares.remove(&ticker) {
+                series.set_shares_outstanding(records);
+            }
+            tracing::info!(
+                "Loaded {} bars for {} from {}",
+                series.len(),
+                ticker,
+                path.display()
+            );
+            store.insert(&ticker, series);
+        }
+
+        for ticker in shares.keys() {
+            tracing::warn!(
+                "Ignoring shares outstanding of {} without a price file",
+                ticker
+            );
+        }
+
+        let membership_path = dir.join(INDEX_MEMBERSHIP_FILE);
+        if membership_path.is_file() {
+            for (universe, changes) in load_index_membership(&membership_path)? {
+                tracing::info!(
+                    "Loaded {} membership changes for {}",
+                    changes.len(),
+                    universe
+                );
+                store.set_index_membership(&universe, changes);
+            }
+        }
+
+        Ok(store)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market::adjustments::PriceAdjustment;
+    use crate::market::provider::MarketDataProvider;
+    use crate::portfolio::blocks::models::{FunctionDefinition, FunctionName};
+
+    fn testdata_dir() -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("Python fetch data/testdata")
+    }
+
+    #[test]
+    fn test_ticker_from_path() {
+        assert_eq!(
+            ticker_from_path(Path::new("data/AAPLprices.csv")),
+            Some("AAPL".to_string())
+        );
+        assert_eq!(
+            ticker_from_path(Path::new("QQQ.csv")),
+            Some("QQQ".to_string())
+        );
+        assert_eq!(ticker_from_path(Path::new("notes.txt")), None);
+    }
+
+    #[tokio::test]
+    async fn test_load_testdata_dir() {
+        let store = PriceStore::from_csv_dir(testdata_dir()).unwrap();
+        let mut tickers: Vec<&str> = store.tickers().collect();
+        tickers.sort();
+        assert_eq!(
+            tickers,
+            ["AAPL", "AMZN", "GOOG", "META", "MSFT", "NVDA", "QQQ", "TSLA"]
+        );
+
+        let close = store
+            .evaluate(
+                &FunctionDefinition {
+                    function_name: FunctionName::CurrentPrice,
+                    window_of_days: None,
+                    asset: "AAPL".to_string(),
+                    price_adjustment: None,
+                },
+                "2019-12-31T16:00:00.000000Z".parse().unwrap(),
+            )
+            .unwrap();
+        assert_eq!(close, 293.65);
+
+        // 2020-01-01 was a holiday
+        let holiday = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap();
+        assert_eq!(
+            store
+                .get_last_market_day(holiday)
+                .await
+                .unwrap()
+                .to_string(),
+            "2019-12-31T21:00:00.000000Z"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_loads_ohlcv_bars() {
+        let store = PriceStore::from_csv_dir(testdata_dir()).unwrap();
+        let start = "2019-12-31".parse().unwrap();
+        let end = "2020-01-02".parse().unwrap();
+
+        let bars = store.get_bar_history("AAPL", start, end).await.unwrap();
+        assert_eq!(bars.len(), 2);
+        assert_eq!(
+            bars[0],
+            Bar {
+                time: bar_timestamp(NaiveDate::from_ymd_opt(2019, 12, 31).unwrap()),
+                open: 289.93,
+                high: 293.68,
+                low: 289.52,
+                close: 293.65,
+                volume: 25247625,
+            }
+        );
+
+        // Close-only lookups see the same bars
+        let closes = store.get_price_history("AAPL", start, end).await.unwrap();
+        assert_eq!(closes[1].close, bars[1].close);
+        let series = store.get_bar_series("AAPL", end, 3).await.unwrap();
+        assert_eq!(series.last(), bars.last());
+    }
+
+    #[test]
+    fn test_total_return_matches_adjusted_close() {
+        let path = testdata_dir().join("AAPLprices.csv");
+        let series = load_price_file(&path).unwrap();
+        let split = CorporateAction {
+            time: bar_timestamp(NaiveDate::from_ymd_opt(2020, 8, 31).unwrap()),
+            split_factor: 4.0,
+            dividend: 0.0,
+        };
+        assert!(series.corporate_actions().contains(&split));
+
+        // July to September 2020 spans the August 7th dividend and the 4-for-1 split
+        let start = bar_timestamp(NaiveDate::from_ymd_opt(2020, 7, 1).unwrap());
+        let end = bar_timestamp(NaiveDate::from_ymd_opt(2020, 9, 30).unwrap());
+        let range = series.range_between(start, end);
+        let adjusted = series.adjusted_closes(range.clone(), PriceAdjustment::TotalReturn);
+
+        // Tiingo's adjClose column is the same series on a different scale, up to a
+        // slightly different dividend factor convention
+        let contents = fs::read_to_string(&path).unwrap();
+        let adj_closes: Vec<f64> = contents
+            .lines()
+            .skip(1 + range.start)
+            .take(range.len())
+            .map(|line| line.split(',').nth(6).unwrap().parse().unwrap())
+            .collect();
+        let scale = adj_closes.last().unwrap() / adjusted.last().unwrap();
+        for (adjusted, adj_close) in adjusted.iter().zip(&adj_closes) {
+            assert!((adjusted * scale - adj_close).abs() < 1e-4 * adj_close);
+        }
+
+        // The last close of the window is never adjusted, earlier ones drop below raw
+        assert_eq!(adjusted.last(), series.closes()[range.clone()].last());
+        let split_adjusted = series.adjusted_closes(range.clone(), PriceAdjustment::SplitAdjusted);
+        assert_eq!(split_adjusted[0], series.closes()[range.start] / 4.0);
+        assert!(adjusted[0] < split_adjusted[0]);
+    }
+
+    #[tokio::test]
+    async fn test_close_only_files_have_no_bars() {
+        let dir = std::env::temp_dir().join(format!("close_only_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("SPY.csv"),
+            "date,close\n2024-01-02,472.65\n2024-01-03,468.79\n",
+        )
+        .unwrap();
+
+        let store = PriceStore::from_csv_dir(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let execution_date = "2024-01-03".parse().unwrap();
+        assert_eq!(
+            store
+                .get_price_series("SPY", execution_date, 2)
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+        assert!(matches!(
+            store.get_bar_series("SPY", execution_date, 2).await,
+            Err(DatabaseError::InsufficientData(_))
+        ));
+    }
+
+    #[test]
+    fn test_every_function_evaluates_on_testdata() {
+        let store = PriceStore::from_csv_dir(testdata_dir()).unwrap();
+        let functions = [
+            (FunctionName::CurrentPrice, None),
+            (FunctionName::CumulativeReturn, Some(20)),
+            (FunctionName::SimpleMovingAverage, Some(20)),
+            (FunctionName::ExponentialMovingAverage, Some(200)),
+            (FunctionName::MovingAverageOfReturns, Some(20)),
+            (FunctionName::RelativeStrengthIndex, Some(14)),
+            (FunctionName::PriceStandardDeviation, Some(20)),
+            (FunctionName::ReturnsStandardDeviation, Some(20)),
+            (FunctionName::MaxDrawdown, Some(60)),
+        ];
+
+        for (function_name, window_of_days) in functions {
+            let function = FunctionDefinition {
+                function_name: function_name.clone(),
+                window_of_days,
+                asset: "QQQ".to_string(),
+                price_adjustment: None,
+            };
+            let value = store.evaluate(&function, "2024-12-31T16:00:00.000000Z".parse().unwrap());
+            assert!(
+                value.as_ref().is_ok_and(|v| v.is_finite()),
+                "{} failed: {:?}",
+                function_name,
+                value
+            );
+        }
+    }
+}
@@ -1,8 +1,17 @@
-use crate::portfolio::blocks::models::{FunctionDefinition, FunctionName};
-use chrono::{DateTime, NaiveDateTime, Utc};
+use crate::market::adjustments::CorporateAction;
+use crate::market::exchange::Exchange;
+use crate::market::execution_date::ExecutionDate;
+use crate::market::indicators;
+use crate::market::market_cap::SharesOutstanding;
+use crate::market::security_master::Security;
+use crate::market::symbols::symbol_rules;
+use crate::market::universe::MembershipChange;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
 use deadpool_postgres::Client;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use thiserror::Error;
+use tokio_postgres::types::ToSql;
 use tokio_postgres::Error as PgError;
 
 #[derive(Debug, Error)]
@@ -19,12 +28,73 @@ pub enum DatabaseError {
     InvalidPeriod(String),
     #[error("Insufficient data: {0}")]
     InsufficientData(String),
+    #[error("Asset excluded for missing data: {0}")]
+    AssetExcluded(String),
     #[error("Insufficient data for MA: {0}")]
     InsufficientDataForMA(String),
     #[error("Invalid calculation: {0}")]
     InvalidCalculation(String),
     #[error("Invalid input: {0}")]
     InvalidInput(String),
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Timed out: {0}")]
+    Timeout(String),
+    #[error("Database unavailable: {0}")]
+    CircuitOpen(String),
+}
+
+impl DatabaseError {
+    /// Whether the same request may succeed when sent again: lost or refused
+    /// connections, an exhausted pool, timeouts and server-side resource problems.
+    /// Everything else, a query rejected by the server or data that is not there,
+    /// fails the same way on every attempt.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            DatabaseError::PostgresError(e) => is_transient_pg(e),
+            DatabaseError::PoolError(e) => match e {
+                deadpool_postgres::PoolError::Timeout(_) => true,
+                deadpool_postgres::PoolError::Backend(e) => is_transient_pg(e),
+                _ => false,
+            },
+            DatabaseError::IoError(e) => is_transient_io(e),
+            DatabaseError::Timeout(_) => true,
+            _ => false,
+        }
+    }
+}
+
+fn is_transient_pg(error: &PgError) -> bool {
+    if error.is_closed() {
+        return true;
+    }
+    if let Some(code) = error.code() {
+        // Connection exceptions, insufficient resources, server shutdown or start-up,
+        // serialization failures and deadlocks. A statement cancelled by
+        // `statement_timeout` (57014) would be cancelled again.
+        let code = code.code();
+        return code.starts_with("08")
+            || code.starts_with("53")
+            || matches!(code, "57P01" | "57P02" | "57P03" | "40001" | "40P01");
+    }
+    std::error::Error::source(error)
+        .and_then(|source| source.downcast_ref::<std::io::Error>())
+        .is_some_and(is_transient_io)
+}
+
+fn is_transient_io(error: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+    matches!(
+        error.kind(),
+        ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::BrokenPipe
+            | ErrorKind::TimedOut
+            | ErrorKind::Interrupted
+            | ErrorKind::UnexpectedEof
+    )
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -35,76 +105,18 @@ pub struct StockDataPoint {
     pub sma: f64,
 }
 
-fn validate_price(price: f64, context: &str) -> Result<(), DatabaseError> {
-    if !price.is_finite() || price <= 0.0 {
-        return Err(DatabaseError::InvalidCalculation(format!(
-            "Invalid price in {}: {}",
-            context, price
-        )));
-    }
-    Ok(())
-}
-#[derive(Debug)]
-struct StartDateResult {
-    time: NaiveDateTime,
-}
 use chrono::SecondsFormat;
-// pub async fn get_start_date(
-//     client: &Client,
-//     ticker: &str,
-//     execution_date: &str,
-//     trading_days: i64,
-// ) -> Result<String, DatabaseError> {
-//     validate_ticker(ticker)?;
-//     validate_period(trading_days, "Trading days")?;
-//     tracing::debug!("Starting get_start_date");
-
-//     let query = format!(
-//         "SELECT min(time) as start_date
-//         FROM (
-//             SELECT time
-//             FROM stock_data_daily
-//             WHERE ticker = $1
-//             AND time <= '{}'
-//             ORDER BY time DESC
-//             LIMIT $2
-//         ) AS subquery",
-//         execution_date
-//     );
-
-//     let row = client.query_one(&query, &[&ticker, &trading_days]).await?;
-
-//     let time: NaiveDateTime = row.get("start_date");
-//     let start_date = DateTime::<Utc>::from_naive_utc_and_offset(time, Utc)
-//         .to_rfc3339_opts(SecondsFormat::Micros, true);
-
-//     print!("Found start date: {}", start_date);
-
-//     tracing::debug!(
-//         execution_date = %execution_date,
-//         start_date = %start_date,
-//         "Retrieved start date for historical data"
-//     );
-
-//     Ok(start_date)
-// }
-
 pub async fn get_start_date(
     client: &Client,
     ticker: &str,
-    execution_date: &str,
+    execution_date: ExecutionDate,
     trading_days: i64,
-) -> Result<String, DatabaseError> {
+) -> Result<ExecutionDate, DatabaseError> {
     // Input validation
     validate_ticker(ticker)?;
     validate_period(trading_days, "Trading days")?;
 
-    // Validate execution_date format
-    if !execution_date.contains('T') || !execution_date.contains('Z') {
-        return Err(DatabaseError::InvalidInput(
-            "Execution date must be in format YYYY-MM-DDT16:00:00.000000Z".to_string(),
-        ));
-    }
+    let execution_time = execution_date.bar_timestamp();
 
     tracing::debug!(
         "Starting get_start_date for ticker: {}, execution_date: {}",
@@ -112,28 +124,27 @@ pub async fn get_start_date(
         execution_date
     );
 
+    // The limit is a validated integer; QuestDB does not accept a bind variable there
     let query = format!(
         "SELECT min(time) as start_date
         FROM (
             SELECT time
             FROM stock_data_daily
             WHERE ticker = $1
-            AND time <= '{}'
+            AND time <= $2
             ORDER BY time DESC
             LIMIT {}
         ) AS subquery",
-        &execution_date,
-        trading_days // Interpolate the limit directly
+        trading_days
     );
 
-    // Now only passing one parameter (ticker)
     let row = client
-        .query_one(&query, &[&ticker])
+        .query_one(&query, &[&ticker, &execution_time])
         .await
         .map_err(|e| match e {
-            e if e.as_db_error().map_or(false, |dbe| {
-                dbe.code() == &tokio_postgres::error::SqlState::NO_DATA
-            }) =>
+            e if e
+                .as_db_error()
+                .is_some_and(|dbe| dbe.code() == &tokio_postgres::error::SqlState::NO_DATA) =>
             {
                 DatabaseError::InsufficientData(format!(
                     "No data found for ticker {} before {}",
@@ -152,9 +163,7 @@ pub async fn get_start_date(
         ))
     })?;
 
-    // Convert to UTC and format
-    let start_date = DateTime::<Utc>::from_naive_utc_and_offset(time, Utc)
-        .to_rfc3339_opts(SecondsFormat::Micros, true);
+    let start_date = ExecutionDate::from_bar_timestamp(time, execution_date.exchange());
 
     // print!(
     //     "Found start date: {} for ticker: {}, execution_date: {}",
@@ -172,35 +181,31 @@ pub async fn get_start_date(
     Ok(start_date)
 }
 
-// Validation functions remain unchanged
-fn validate_ticker(ticker: &str) -> Result<(), DatabaseError> {
-    if ticker.trim().is_empty() || ticker.len() > 10 {
-        return Err(DatabaseError::InvalidTicker);
-    }
-
-    if !ticker
-        .chars()
-        .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '.' || c == '-')
-    {
-        print!("\nError to validate ticker {}", ticker);
-        return Err(DatabaseError::InvalidTicker);
-    }
+/// Parses a timestamp argument into the UTC instant bound as a query parameter.
+/// Accepts RFC 3339 timestamps such as `2024-12-31T16:00:00.000000Z` and bare
+/// `YYYY-MM-DD` dates (midnight UTC); anything else is rejected before it reaches SQL.
+pub fn parse_timestamp(value: &str) -> Result<NaiveDateTime, DatabaseError> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.naive_utc())
+        .or_else(|_| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .map(|date| date.and_hms_opt(0, 0, 0).unwrap())
+        })
+        .map_err(|_| DatabaseError::InvalidInput(format!("Invalid timestamp: {:?}", value)))
+}
 
-    Ok(())
+/// Renders a bar time in the canonical `2024-12-31T16:00:00.000000Z` form
+pub fn format_timestamp(time: NaiveDateTime) -> String {
+    DateTime::<Utc>::from_naive_utc_and_offset(time, Utc)
+        .to_rfc3339_opts(SecondsFormat::Micros, true)
 }
 
-fn validate_date_range(
-    start_date: DateTime<Utc>,
-    end_date: DateTime<Utc>,
-) -> Result<(), DatabaseError> {
-    if start_date >= end_date {
-        Err(DatabaseError::InvalidDateRange)
-    } else {
-        Ok(())
-    }
+/// Checks `ticker` against the installed symbol rules of any exchange
+pub(crate) fn validate_ticker(ticker: &str) -> Result<(), DatabaseError> {
+    symbol_rules().validate_any(ticker)
 }
 
-fn validate_period(period: i64, context: &str) -> Result<(), DatabaseError> {
+pub(crate) fn validate_period(period: i64, context: &str) -> Result<(), DatabaseError> {
     if period <= 0 {
         return Err(DatabaseError::InvalidPeriod(format!(
             "{} must be positive",
@@ -223,69 +228,19 @@ fn validate_period(period: i64, context: &str) -> Result<(), DatabaseError> {
     Ok(())
 }
 
-#[derive(Debug)]
-struct SMAResult {
-    sma: f64,
-}
-
 pub async fn get_sma(
     client: &Client,
-    ticker: &str,         // Changed from &String to &str
-    execution_date: &str, // Changed from &String to &str
+    ticker: &str,
+    execution_date: ExecutionDate,
     period: i64,
 ) -> Result<f64, DatabaseError> {
     validate_ticker(ticker)?;
     validate_period(period, "SMA period")?;
 
-    tracing::debug!("Getting start date for SMA calculation"); // Better logging
-                                                               // print!("\nTry to get start date for SMA calculation\n");
-    let start_date = get_start_date(client, ticker, execution_date, period).await?;
-    // print!("\nReceived start date for SMA calculation\n");
-    // tracing::debug!("Retrieved start date for SMA calculation");
+    let closes = load_closes(client, ticker, execution_date, period).await?;
+    let sma = indicators::sma(&closes, period as usize)?;
 
-    let query = format!(
-        r#"
-        SELECT avg(close) OVER (
-            PARTITION BY ticker
-            ORDER BY time
-            ROWS BETWEEN {} PRECEDING AND CURRENT ROW
-        ) AS sma
-        FROM stock_data_daily
-        WHERE ticker = $1
-        AND time BETWEEN '{}'
-        AND '{}'
-        ORDER BY time DESC
-        LIMIT 1
-        "#,
-        period - 1,
-        start_date,
-        execution_date
-    );
-
-    let row = client
-        .query_one(&query, &[&ticker]) // Removed &start_date and &execution_date since they're interpolated
-        .await
-        .map_err(|e| match e {
-            e if e.as_db_error().map_or(false, |dbe| {
-                dbe.code() == &tokio_postgres::error::SqlState::NO_DATA
-            }) =>
-            {
-                DatabaseError::InsufficientData(format!(
-                    "No data found for {} between {} and {}",
-                    ticker, start_date, execution_date
-                ))
-            }
-            other => DatabaseError::PostgresError(other),
-        })?;
-
-    let sma: f64 = row.get("sma");
-    if !sma.is_finite() {
-        return Err(DatabaseError::InvalidCalculation(
-            "SMA calculation resulted in invalid value".to_string(),
-        ));
-    }
-
-    tracing::debug!(ticker, %start_date, %execution_date, %sma, "SMA calculation completed");
+    tracing::debug!(ticker, %execution_date, %sma, "SMA calculation completed");
     Ok(sma)
 }
 
@@ -299,9 +254,9 @@ pub struct CurrentPrice {
 pub async fn get_current_price(
     client: &Client,
     ticker: &str,
-    execution_date: &str,
+    execution_date: ExecutionDate,
 ) -> Result<CurrentPrice, DatabaseError> {
-    validate_ticker(&ticker)?;
+    validate_ticker(ticker)?;
 
     // Log the query parameters
     // print!(
@@ -309,29 +264,21 @@ pub async fn get_current_price(
     //     ticker, execution_date
     // );
 
-    // Interpolate execution_date into the query string
-    let query = format!(
-        "SELECT time, ticker, close
+    let execution_time = execution_date.bar_timestamp();
+
+    let query = "SELECT time, ticker, close
              FROM stock_data_daily
              WHERE ticker = $1
-             AND time = '{}'",
-        execution_date
-    );
+             AND time = $2";
 
     let row = client
-        .query_one(&query, &[&ticker])
-        .await
-        .map_err(|e| match e {
-            e if e.as_db_error().map_or(false, |dbe| {
-                dbe.code() == &tokio_postgres::error::SqlState::NO_DATA
-            }) =>
-            {
-                DatabaseError::InsufficientData(format!(
-                    "No price data found for {} at {}",
-                    ticker, execution_date
-                ))
-            }
-            other => DatabaseError::PostgresError(other),
+        .query_opt(query, &[&ticker, &execution_time])
+        .await?
+        .ok_or_else(|| {
+            DatabaseError::InsufficientData(format!(
+                "No price data found for {} at {}",
+                ticker, execution_date
+            ))
         })?;
 
     // Log the result
@@ -349,140 +296,529 @@ pub async fn get_current_price(
         close,
     })
 }
-#[derive(Debug)]
-struct CumulativeReturnResult {
-    return_percentage: f64,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PricePoint {
+    pub time: NaiveDateTime,
+    pub close: f64,
 }
 
-pub async fn get_cumulative_return(
+pub async fn get_price_series(
     client: &Client,
-    ticker: &str, // Changed from &String to &str for more flexibility
-    execution_date: &str,
-    period: i64,
-) -> Result<f64, DatabaseError> {
+    ticker: &str,
+    execution_date: ExecutionDate,
+    trading_days: i64,
+) -> Result<Vec<PricePoint>, DatabaseError> {
     validate_ticker(ticker)?;
-    validate_period(period, "Return period")?;
+    validate_period(trading_days, "Trading days")?;
+
+    let start_date = get_start_date(client, ticker, execution_date, trading_days).await?;
+
+    get_price_history(client, ticker, start_date, execution_date).await
+}
+
+/// Loads every close of `ticker` between `start_date` and `end_date` inclusive, oldest first
+pub async fn get_price_history(
+    client: &Client,
+    ticker: &str,
+    start_date: ExecutionDate,
+    end_date: ExecutionDate,
+) -> Result<Vec<PricePoint>, DatabaseError> {
+    validate_ticker(ticker)?;
+    let start_time = start_date.bar_timestamp();
+    let end_time = end_date.bar_timestamp();
+
+    let query = r#"
+        SELECT
+            time,
+            close
+        FROM stock_data_daily
+        WHERE ticker = $1
+        AND time BETWEEN $2
+        AND $3
+        ORDER BY time ASC
+        "#;
+
+    let rows = client
+        .query(query, &[&ticker, &start_time, &end_time])
+        .await?;
+
+    let series: Vec<PricePoint> = rows
+        .iter()
+        .map(|row| PricePoint {
+            time: row.get("time"),
+            close: row.get("close"),
+        })
+        .collect();
+
+    tracing::debug!(
+        %ticker,
+        %start_date,
+        %end_date,
+        points = series.len(),
+        "Price history loaded"
+    );
+
+    Ok(series)
+}
 
-    let start_date = get_start_date(client, ticker, execution_date, period).await?;
+/// `ticker IN (...)` over parameters `$first..` with one parameter per ticker
+fn ticker_list(tickers: &[String], first: usize) -> String {
+    let placeholders: Vec<String> = (first..first + tickers.len())
+        .map(|index| format!("${}", index))
+        .collect();
+    format!("ticker IN ({})", placeholders.join(", "))
+}
+
+/// Loads the closes of all `tickers` between `start_date` and `end_date` inclusive in
+/// one query, oldest first. Tickers without bars in the range have no entry.
+pub async fn get_price_history_batch(
+    client: &Client,
+    tickers: &[String],
+    start_date: ExecutionDate,
+    end_date: ExecutionDate,
+) -> Result<HashMap<String, Vec<PricePoint>>, DatabaseError> {
+    let mut series: HashMap<String, Vec<PricePoint>> = HashMap::new();
+    if tickers.is_empty() {
+        return Ok(series);
+    }
+    for ticker in tickers {
+        validate_ticker(ticker)?;
+    }
+    let start_time = start_date.bar_timestamp();
+    let end_time = end_date.bar_timestamp();
 
-    // Modified query to use string interpolation for dates with single quotes for QuestDB
     let query = format!(
         r#"
-        WITH period_prices AS (
-            SELECT
-                first(close) as start_price,
-                last(close) as end_price
-            FROM stock_data_daily
-            WHERE ticker = $1
-            AND time BETWEEN '{}'
-            AND '{}'
-        )
         SELECT
-            ((end_price - start_price) / start_price * 100) as return_percentage
-        FROM period_prices
+            ticker,
+            time,
+            close
+        FROM stock_data_daily
+        WHERE time BETWEEN $1
+        AND $2
+        AND {}
+        ORDER BY time ASC
         "#,
-        start_date, execution_date
+        ticker_list(tickers, 3)
     );
+    let mut params: Vec<&(dyn ToSql + Sync)> = vec![&start_time, &end_time];
+    params.extend(tickers.iter().map(|ticker| ticker as &(dyn ToSql + Sync)));
+
+    let rows = client.query(&query, &params).await?;
+    for row in &rows {
+        series
+            .entry(row.get("ticker"))
+            .or_default()
+            .push(PricePoint {
+                time: row.get("time"),
+                close: row.get("close"),
+            });
+    }
 
-    // Removed date parameters since they're now interpolated in the query
-    let row = client
-        .query_one(&query, &[&ticker])
-        .await
-        .map_err(|e| match e {
-            e if e.as_db_error().map_or(false, |dbe| {
-                dbe.code() == &tokio_postgres::error::SqlState::NO_DATA
-            }) =>
-            {
-                DatabaseError::InsufficientData(format!(
-                    "No price data found for {} between {} and {}",
-                    ticker, start_date, execution_date
-                ))
-            }
-            other => DatabaseError::PostgresError(other),
-        })?;
+    tracing::debug!(
+        tickers = tickers.len(),
+        %start_date,
+        %end_date,
+        points = rows.len(),
+        "Batched price history loaded"
+    );
 
-    let return_percentage: f64 = row.get("return_percentage");
+    Ok(series)
+}
 
-    // Added more descriptive error message
-    if !return_percentage.is_finite() {
-        return Err(DatabaseError::InvalidCalculation(
-            "Cumulative return calculation resulted in invalid value".to_string(),
-        ));
+/// Daily OHLCV bar as stored in `stock_data_daily`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Bar {
+    pub time: NaiveDateTime,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: i64,
+}
+
+impl From<Bar> for PricePoint {
+    fn from(bar: Bar) -> Self {
+        PricePoint {
+            time: bar.time,
+            close: bar.close,
+        }
     }
+}
+
+/// Returns the last `trading_days` bars for `ticker` up to and including `execution_date`
+pub async fn get_bar_series(
+    client: &Client,
+    ticker: &str,
+    execution_date: ExecutionDate,
+    trading_days: i64,
+) -> Result<Vec<Bar>, DatabaseError> {
+    validate_ticker(ticker)?;
+    validate_period(trading_days, "Trading days")?;
+
+    let start_date = get_start_date(client, ticker, execution_date, trading_days).await?;
+
+    get_bar_history(client, ticker, start_date, execution_date).await
+}
+
+/// Loads every bar of `ticker` between `start_date` and `end_date` inclusive, oldest first
+pub async fn get_bar_history(
+    client: &Client,
+    ticker: &str,
+    start_date: ExecutionDate,
+    end_date: ExecutionDate,
+) -> Result<Vec<Bar>, DatabaseError> {
+    validate_ticker(ticker)?;
+    let start_time = start_date.bar_timestamp();
+    let end_time = end_date.bar_timestamp();
+
+    let query = r#"
+        SELECT
+            time,
+            open,
+            high,
+            low,
+            close,
+            volume
+        FROM stock_data_daily
+        WHERE ticker = $1
+        AND time BETWEEN $2
+        AND $3
+        ORDER BY time ASC
+        "#;
+
+    let rows = client
+        .query(query, &[&ticker, &start_time, &end_time])
+        .await?;
+
+    let bars: Vec<Bar> = rows
+        .iter()
+        .map(|row| Bar {
+            time: row.get("time"),
+            open: row.get("open"),
+            high: row.get("high"),
+            low: row.get("low"),
+            close: row.get("close"),
+            volume: row.get("volume"),
+        })
+        .collect();
 
-    // Added debug logging for better observability
     tracing::debug!(
         %ticker,
         %start_date,
-        %execution_date,
-        %return_percentage,
-        "Cumulative return calculation completed"
+        %end_date,
+        bars = bars.len(),
+        "Bar history loaded"
     );
 
-    Ok(return_percentage)
-}
-#[derive(Debug)]
-struct EMAResult {
-    close: f64,
+    Ok(bars)
 }
 
-pub async fn get_ema(
+/// Loads the splits and dividends of `ticker` taking effect between `start_date` and
+/// `end_date` inclusive from the `corporate_actions` table, oldest first
+pub async fn get_corporate_actions(
     client: &Client,
-    ticker: &str, // Changed from &String to &str
-    execution_date: &str,
-    period: i64,
-) -> Result<f64, DatabaseError> {
+    ticker: &str,
+    start_date: ExecutionDate,
+    end_date: ExecutionDate,
+) -> Result<Vec<CorporateAction>, DatabaseError> {
     validate_ticker(ticker)?;
-    validate_period(period, "EMA period")?;
+    let start_time = start_date.bar_timestamp();
+    let end_time = end_date.bar_timestamp();
+
+    let query = r#"
+        SELECT
+            time,
+            split_factor,
+            dividend
+        FROM corporate_actions
+        WHERE ticker = $1
+        AND time BETWEEN $2
+        AND $3
+        ORDER BY time ASC
+        "#;
+
+    let rows = client
+        .query(query, &[&ticker, &start_time, &end_time])
+        .await?;
 
-    let start_date = get_start_date(client, ticker, execution_date, period).await?;
+    let actions: Vec<CorporateAction> = rows
+        .iter()
+        .map(|row| CorporateAction {
+            time: row.get("time"),
+            split_factor: row.get("split_factor"),
+            dividend: row.get("dividend"),
+        })
+        .collect();
+
+    tracing::debug!(
+        %ticker,
+        %start_date,
+        %end_date,
+        actions = actions.len(),
+        "Corporate actions loaded"
+    );
+
+    Ok(actions)
+}
+
+/// Loads the splits and dividends of all `tickers` between `start_date` and `end_date`
+/// inclusive in one query, oldest first. Tickers without actions have no entry.
+pub async fn get_corporate_actions_batch(
+    client: &Client,
+    tickers: &[String],
+    start_date: ExecutionDate,
+    end_date: ExecutionDate,
+) -> Result<HashMap<String, Vec<CorporateAction>>, DatabaseError> {
+    let mut actions: HashMap<String, Vec<CorporateAction>> = HashMap::new();
+    if tickers.is_empty() {
+        return Ok(actions);
+    }
+    for ticker in tickers {
+        validate_ticker(ticker)?;
+    }
+    let start_time = start_date.bar_timestamp();
+    let end_time = end_date.bar_timestamp();
 
     let query = format!(
         r#"
         SELECT
+            ticker,
             time,
-            close
-        FROM stock_data_daily
-        WHERE ticker = $1
-        AND time BETWEEN '{}'
-        AND '{}'
-        AND close > 0
+            split_factor,
+            dividend
+        FROM corporate_actions
+        WHERE time BETWEEN $1
+        AND $2
+        AND {}
         ORDER BY time ASC
         "#,
-        start_date, execution_date
+        ticker_list(tickers, 3)
     );
+    let mut params: Vec<&(dyn ToSql + Sync)> = vec![&start_time, &end_time];
+    params.extend(tickers.iter().map(|ticker| ticker as &(dyn ToSql + Sync)));
+
+    for row in client.query(&query, &params).await? {
+        actions
+            .entry(row.get("ticker"))
+            .or_default()
+            .push(CorporateAction {
+                time: row.get("time"),
+                split_factor: row.get("split_factor"),
+                dividend: row.get("dividend"),
+            });
+    }
+    Ok(actions)
+}
 
-    let rows = client
-        .query(&query, &[&ticker]) // Removed date parameters since they're interpolated
+/// Share counts of `ticker` in effect between `start_date` and `end_date`: the last one
+/// reported at or before `start_date`, then every change up to `end_date`
+pub async fn get_shares_outstanding(
+    client: &Client,
+    ticker: &str,
+    start_date: ExecutionDate,
+    end_date: ExecutionDate,
+) -> Result<Vec<SharesOutstanding>, DatabaseError> {
+    validate_ticker(ticker)?;
+    let start_time = start_date.bar_timestamp();
+    let end_time = end_date.bar_timestamp();
+
+    let in_effect_query = r#"
+        SELECT
+            time,
+            shares
+        FROM shares_outstanding
+        WHERE ticker = $1
+        AND time <= $2
+        ORDER BY time DESC
+        LIMIT 1
+        "#;
+    let changes_query = r#"
+        SELECT
+            time,
+            shares
+        FROM shares_outstanding
+        WHERE ticker = $1
+        AND time > $2
+        AND time <= $3
+        ORDER BY time ASC
+        "#;
+
+    let mut rows = client
+        .query(in_effect_query, &[&ticker, &start_time])
         .await?;
+    rows.extend(
+        client
+            .query(changes_query, &[&ticker, &start_time, &end_time])
+            .await?,
+    );
 
-    if rows.len() < period as usize {
-        return Err(DatabaseError::InsufficientData(format!(
-            "Need at least {} data points for {} between {} and {}",
-            period, ticker, start_date, execution_date
-        )));
-    }
+    let records: Vec<SharesOutstanding> = rows
+        .iter()
+        .map(|row| SharesOutstanding {
+            time: row.get("time"),
+            shares: row.get("shares"),
+        })
+        .collect();
 
-    let prices: Vec<f64> = rows.iter().map(|row| row.get("close")).collect();
+    tracing::debug!(
+        %ticker,
+        %start_date,
+        %end_date,
+        records = records.len(),
+        "Shares outstanding loaded"
+    );
 
-    let initial_sma = prices[..period as usize].iter().sum::<f64>() / period as f64;
-    let smoothing = 2.0;
-    let multiplier = smoothing / (period as f64 + 1.0);
+    Ok(records)
+}
 
-    let mut ema = initial_sma;
-    for price in prices[period as usize..].iter() {
-        ema = price * multiplier + ema * (1.0 - multiplier);
-    }
+/// Every row of the `security_master` table
+pub async fn get_security_master(client: &Client) -> Result<Vec<Security>, DatabaseError> {
+    let query = r#"
+        SELECT
+            ticker,
+            name,
+            exchange,
+            asset_class,
+            sector,
+            currency,
+            first_trading_date,
+            last_trading_date,
+            delisted
+        FROM security_master
+        ORDER BY ticker ASC
+        "#;
+
+    let rows = client.query(query, &[]).await?;
+
+    let securities = rows
+        .iter()
+        .map(|row| {
+            let first: Option<NaiveDateTime> = row.get("first_trading_date");
+            let last: Option<NaiveDateTime> = row.get("last_trading_date");
+            let asset_class: String = row.get("asset_class");
+            Ok(Security {
+                ticker: row.get("ticker"),
+                name: row.get("name"),
+                exchange: row.get("exchange"),
+                asset_class: asset_class.parse()?,
+                sector: row.get("sector"),
+                currency: row.get("currency"),
+                first_trading_date: first.map(|time| time.date()),
+                last_trading_date: last.map(|time| time.date()),
+                delisted: row.get("delisted"),
+            })
+        })
+        .collect::<Result<Vec<_>, DatabaseError>>()?;
+
+    tracing::debug!(securities = securities.len(), "Security master loaded");
 
-    if !ema.is_finite() {
-        return Err(DatabaseError::InvalidCalculation(
-            "EMA calculation resulted in invalid value".to_string(),
+    Ok(securities)
+}
+
+/// Additions to and removals from `universe` in the `index_membership` table dated on or
+/// before `end_date`, oldest first
+pub async fn get_index_membership(
+    client: &Client,
+    universe: &str,
+    end_date: NaiveDate,
+) -> Result<Vec<MembershipChange>, DatabaseError> {
+    if universe.trim().is_empty() {
+        return Err(DatabaseError::InvalidInput(
+            "Universe name cannot be empty".to_string(),
         ));
     }
+    let end_time = end_date
+        .succ_opt()
+        .ok_or_else(|| DatabaseError::InvalidInput(format!("Invalid end date {}", end_date)))?
+        .and_time(NaiveTime::MIN);
+
+    let query = r#"
+        SELECT
+            time,
+            ticker,
+            action
+        FROM index_membership
+        WHERE universe = $1
+        AND time < $2
+        ORDER BY time ASC
+        "#;
+
+    let rows = client.query(query, &[&universe, &end_time]).await?;
+
+    let changes = rows
+        .iter()
+        .map(|row| {
+            let time: NaiveDateTime = row.get("time");
+            let action: String = row.get("action");
+            Ok(MembershipChange {
+                ticker: row.get("ticker"),
+                date: time.date(),
+                action: action.parse()?,
+            })
+        })
+        .collect::<Result<Vec<_>, DatabaseError>>()?;
+
+    tracing::debug!(
+        %universe,
+        %end_date,
+        changes = changes.len(),
+        "Index membership loaded"
+    );
+
+    Ok(changes)
+}
+
+/// Closes of the last `rows` bars of `ticker` up to `execution_date`, oldest first
+async fn load_closes(
+    client: &Client,
+    ticker: &str,
+    execution_date: ExecutionDate,
+    rows: i64,
+) -> Result<Vec<f64>, DatabaseError> {
+    Ok(get_price_series(client, ticker, execution_date, rows)
+        .await?
+        .into_iter()
+        .map(|point| point.close)
+        .collect())
+}
+
+pub async fn get_cumulative_return(
+    client: &Client,
+    ticker: &str,
+    execution_date: ExecutionDate,
+    period: i64,
+) -> Result<f64, DatabaseError> {
+    validate_ticker(ticker)?;
+    validate_period(period, "Return period")?;
+
+    let closes = load_closes(client, ticker, execution_date, period).await?;
+    let return_percentage = indicators::cumulative_return(&closes)?;
+
+    tracing::debug!(
+        %ticker,
+        %execution_date,
+        %return_percentage,
+        "Cumulative return calculation completed"
+    );
+
+    Ok(return_percentage)
+}
+
+pub async fn get_ema(
+    client: &Client,
+    ticker: &str,
+    execution_date: ExecutionDate,
+    period: i64,
+) -> Result<f64, DatabaseError> {
+    validate_ticker(ticker)?;
+    validate_period(period, "EMA period")?;
+
+    let closes = load_closes(client, ticker, execution_date, period).await?;
+    let ema = indicators::ema(&closes, period as usize)?;
 
     tracing::debug!(
         %ticker,
-        %start_date,
         %execution_date,
         %period,
         %ema,
@@ -502,111 +838,30 @@ pub struct DrawdownResult {
     pub trough_time: NaiveDateTime,
 }
 
-#[derive(Debug)]
-struct PriceResult {
-    time: NaiveDateTime,
-    close: f64,
-}
-
 pub async fn get_max_drawdown(
     client: &Client,
-    ticker: &str, // Changed from &String to &str
-    execution_date: &str,
+    ticker: &str,
+    execution_date: ExecutionDate,
     period: i64,
 ) -> Result<DrawdownResult, DatabaseError> {
     validate_ticker(ticker)?;
     validate_period(period, "Drawdown period")?;
 
-    let start_date = get_start_date(client, ticker, execution_date, period).await?;
-
-    tracing::debug!("Starting max drawdown calculation"); // Replaced println
-
-    // Modified query to use string interpolation for dates with single quotes for QuestDB
-    let query = format!(
-        r#"
-        SELECT
-            time,
-            close
-        FROM stock_data_daily
-        WHERE ticker = $1
-        AND time BETWEEN '{}'
-        AND '{}'
-        ORDER BY time ASC
-        "#,
-        start_date, execution_date
-    );
-
-    // Removed date parameters since they're interpolated in the query
-    let rows = client.query(&query, &[&ticker]).await?;
-
-    tracing::debug!("Retrieved price data for drawdown calculation"); // Replaced println
-
-    if rows.len() < 2 {
-        return Err(DatabaseError::InsufficientData(format!(
-            "Need at least 2 data points for drawdown calculation for {}",
-            ticker
-        )));
-    }
-
-    let mut max_drawdown = 0.0;
-    let mut max_drawdown_value = 0.0;
-    let mut peak_price = f64::NEG_INFINITY;
-    let mut peak_time = rows[0].get::<_, NaiveDateTime>("time");
-    let mut max_drawdown_peak_time = peak_time;
-    let mut max_drawdown_trough_time = peak_time;
-    let mut max_drawdown_peak_price = 0.0;
-    let mut max_drawdown_trough_price = 0.0;
-
-    // Added logging for initialization values
-    tracing::debug!(
-        initial_time = %peak_time,
-        "Initialized drawdown calculation"
-    );
-
-    for row in rows.iter() {
-        let current_price: f64 = row.get("close");
-        let current_time: NaiveDateTime = row.get("time");
-
-        if current_price > peak_price {
-            peak_price = current_price;
-            peak_time = current_time;
-        }
-
-        let drawdown = (peak_price - current_price) / peak_price * 100.0;
-        let drawdown_value = peak_price - current_price;
-
-        if drawdown > max_drawdown {
-            max_drawdown = drawdown;
-            max_drawdown_value = drawdown_value;
-            max_drawdown_peak_time = peak_time;
-            max_drawdown_trough_time = current_time;
-            max_drawdown_peak_price = peak_price;
-            max_drawdown_trough_price = current_price;
-
-            // Added logging for new max drawdown
-            tracing::debug!(
-                %max_drawdown,
-                %max_drawdown_value,
-                peak_time = %max_drawdown_peak_time,
-                trough_time = %max_drawdown_trough_time,
-                "New maximum drawdown found"
-            );
-        }
-    }
+    let series = get_price_series(client, ticker, execution_date, period).await?;
+    let closes: Vec<f64> = series.iter().map(|point| point.close).collect();
+    let drawdown = indicators::max_drawdown(&closes)?;
 
     let result = DrawdownResult {
-        max_drawdown_percentage: max_drawdown,
-        max_drawdown_value,
-        peak_price: max_drawdown_peak_price,
-        trough_price: max_drawdown_trough_price,
-        peak_time: max_drawdown_peak_time,
-        trough_time: max_drawdown_trough_time,
+        max_drawdown_percentage: drawdown.percentage,
+        max_drawdown_value: drawdown.value,
+        peak_price: drawdown.peak_price,
+        trough_price: drawdown.trough_price,
+        peak_time: series[drawdown.peak_index].time,
+        trough_time: series[drawdown.trough_index].time,
     };
 
-    // Added final debug log with calculation results
     tracing::debug!(
         %ticker,
-        %start_date,
         %execution_date,
         max_drawdown = %result.max_drawdown_percentage,
         peak_time = %result.peak_time,
@@ -624,7 +879,7 @@ pub async fn get_max_drawdown(
 // pub async fn get_ma_of_price(
 //     client: &Client,
 //     ticker: &str, // Changed from &String to &str
-//     execution_date: &str,
+//     execution_date: ExecutionDate,
 //     period: i64,
 // ) -> Result<f64, DatabaseError> {
 //     validate_ticker(ticker)?;
@@ -697,104 +952,20 @@ pub async fn get_max_drawdown(
 //     Ok(ma)
 // }
 
-#[derive(Debug)]
-struct ReturnPriceResult {
-    close: f64,
-}
-
 pub async fn get_ma_of_returns(
     client: &Client,
     ticker: &str,
-    execution_date: &str,
+    execution_date: ExecutionDate,
     period: i64,
 ) -> Result<f64, DatabaseError> {
     validate_ticker(ticker)?;
     validate_period(period, "Moving average period")?;
 
-    let start_date = get_start_date(client, ticker, execution_date, period + 1).await?;
-
-    tracing::debug!("Starting MA of returns calculation");
-
-    // Ensure dates are properly sanitized
-    let query = format!(
-        r#"
-        SELECT time, close
-        FROM stock_data_daily
-        WHERE ticker = $1
-        AND time BETWEEN '{}'
-        AND '{}'
-        ORDER BY time ASC
-        "#,
-        start_date, execution_date
-    );
-
-    let rows = client.query(&query, &[&ticker]).await?;
-
-    tracing::debug!("Retrieved price data successfully");
-
-    if rows.len() < 2 {
-        return Err(DatabaseError::InsufficientData(format!(
-            "Need at least 2 price points to calculate returns for {}",
-            ticker
-        )));
-    }
-
-    let prices: Vec<f64> = rows.iter().map(|row| row.get("close")).collect();
-
-    let daily_returns: Vec<f64> = prices
-        .windows(2)
-        .map(|window| {
-            let previous_close = window[0];
-            let current_close = window[1];
-
-            if previous_close == 0.0 {
-                return Err(DatabaseError::InsufficientData(format!(
-                    "Invalid price data for {}: zero price encountered",
-                    ticker
-                )));
-            }
-
-            let daily_return = (current_close - previous_close) / previous_close * 100.0;
-
-            if daily_return.abs() > 100.0 {
-                return Err(DatabaseError::InsufficientData(format!(
-                    "Suspicious return value detected for {}: {}%",
-                    ticker, daily_return
-                )));
-            }
-
-            Ok(daily_return)
-        })
-        .collect::<Result<Vec<f64>, DatabaseError>>()?;
-
-    if daily_returns.len() < period as usize {
-        return Err(DatabaseError::InsufficientData(format!(
-            "Need at least {} data points for {}-day MA of returns for {}",
-            period, period, ticker
-        )));
-    }
-
-    let ma_return = daily_returns
-        .windows(period as usize)
-        .last()
-        .map(|window| window.iter().sum::<f64>() / window.len() as f64)
-        .ok_or_else(|| {
-            DatabaseError::InsufficientData(format!(
-                "Failed to calculate moving average of returns for {}",
-                ticker
-            ))
-        })?;
-
-    if !ma_return.is_finite() {
-        return Err(DatabaseError::InvalidCalculation(format!(
-            "MA of returns calculation for {} resulted in invalid value",
-            ticker
-        )));
-    }
+    let closes = load_closes(client, ticker, execution_date, period + 1).await?;
+    let ma_return = indicators::ma_of_returns(&closes, period as usize)?;
 
     tracing::debug!(
         %ticker,
-        %start_date,
         %execution_date,
         %period,
         %ma_return,
@@ -803,98 +974,21 @@ pub async fn get_ma_of_returns(
 
     Ok(ma_return)
 }
-#[derive(Debug)]
-struct RSIResult {
-    close: f64,
-}
+
 pub async fn get_rsi(
     client: &Client,
-    ticker: &str, // Changed from &String to &str
-    execution_date: &str,
+    ticker: &str,
+    execution_date: ExecutionDate,
     period: i64,
 ) -> Result<f64, DatabaseError> {
     validate_ticker(ticker)?;
     validate_period(period, "RSI period")?;
 
-    let start_date = get_start_date(client, ticker, execution_date, period + 1).await?;
-
-    // Modified query to use string interpolation for dates with single quotes for QuestDB
-    let query = format!(
-        r#"
-        SELECT
-            time,
-            close
-        FROM stock_data_daily
-        WHERE ticker = $1
-        AND time BETWEEN '{}'
-        AND '{}'
-        ORDER BY time ASC
-        "#,
-        start_date, execution_date
-    );
-
-    // Removed date parameters since they're interpolated in the query
-    let rows = client.query(&query, &[&ticker]).await?;
-
-    if rows.len() < (period + 1) as usize {
-        return Err(DatabaseError::InsufficientData(format!(
-            "Found {} data points but need {} for {}-period RSI calculation for {}",
-            rows.len(),
-            period + 1,
-            period,
-            ticker
-        )));
-    }
+    let closes = load_closes(client, ticker, execution_date, period + 1).await?;
+    let rsi = indicators::rsi(&closes, period as usize)?;
 
-    let prices: Vec<f64> = rows.iter().map(|row| row.get("close")).collect();
-
-    // Calculate gains and losses
-    let (gains, losses): (Vec<f64>, Vec<f64>) = prices
-        .windows(2)
-        .map(|window| {
-            let change = window[1] - window[0];
-            if change > 0.0 {
-                (change, 0.0)
-            } else {
-                (0.0, change.abs())
-            }
-        })
-        .unzip();
-
-    let period_idx = period as usize;
-    let avg_gain = gains[..period_idx].iter().sum::<f64>() / period as f64;
-    let avg_loss = losses[..period_idx].iter().sum::<f64>() / period as f64;
-
-    // Added logging before RSI calculation
     tracing::debug!(
         %ticker,
-        %avg_gain,
-        %avg_loss,
-        "Calculated average gains and losses for RSI"
-    );
-
-    let rsi = match (avg_gain, avg_loss) {
-        (g, l) if l == 0.0 && g == 0.0 => Ok(50.0),
-        (_, l) if l == 0.0 => Ok(100.0),
-        (g, _) if g == 0.0 => Ok(0.0),
-        (g, l) => {
-            let rs = g / l;
-            let rsi = 100.0 - (100.0 / (1.0 + rs));
-            if !rsi.is_finite() || rsi < 0.0 || rsi > 100.0 {
-                Err(DatabaseError::InvalidCalculation(format!(
-                    "RSI calculation for {} resulted in invalid value: {}",
-                    ticker, rsi
-                )))
-            } else {
-                Ok(rsi)
-            }
-        }
-    }?;
-
-    // Added final debug log with calculation results
-    tracing::debug!(
-        %ticker,
-        %start_date,
         %execution_date,
         %period,
         %rsi,
@@ -904,233 +998,122 @@ pub async fn get_rsi(
     Ok(rsi)
 }
 
-/// Calculates the standard deviation of prices for a given stock between two dates.
+/// Calculates the standard deviation of closing prices over the last `period` bars.
 ///
 /// # Arguments
-/// * `pool` - Database connection pool
+/// * `client` - Database client
 /// * `ticker` - Stock ticker symbol
-/// * `start_date` - Start of the calculation period
-/// * `end_date` - End of the calculation period
+/// * `execution_date` - The end date for the calculation
+/// * `period` - Number of bars to include
 ///
 /// # Returns
 /// * `Result<f64, DatabaseError>` - Standard deviation in dollars
 ///
 /// # Examples
-/// ```
-/// let std_dev = calculate_price_std_dev(
-///     &pool,
-///     "AAPL".to_string(),
-///     start_date,
-///     end_date
-/// ).await?;
+/// ```ignore
+/// let std_dev = get_price_std_dev(&client, "AAPL", "2024-12-31T16:00:00.000000Z", 20).await?;
 /// println!("Price standard deviation: ${:.2}", std_dev);
 /// ```
-///
-/// #[derive(Debug)]
-struct PriceStdDevResult {
-    time: DateTime<Utc>,
-    close: f64,
-}
 pub async fn get_price_std_dev(
     client: &Client,
     ticker: &str,
-    execution_date: &str,
+    execution_date: ExecutionDate,
     period: i64,
 ) -> Result<f64, DatabaseError> {
-    validate_ticker(&ticker)?;
+    validate_ticker(ticker)?;
     validate_period(period, "Moving average period")?;
 
-    let start_date = get_start_date(client, &ticker, &execution_date, period).await?;
-
-    let query = format!(
-        r#"
-        SELECT
-            time,
-            close
-        FROM stock_data_daily
-        WHERE ticker = $1
-        AND time BETWEEN '{}'
-        AND '{}'
-        ORDER BY time ASC
-        "#,
-        start_date, execution_date
-    );
-
-    let rows = client.query(&query, &[&ticker]).await?;
-
-    if rows.len() < 2 {
-        return Err(DatabaseError::InsufficientData(
-            "Need at least 2 price points to calculate standard deviation".to_string(),
-        ));
-    }
-
-    let prices: Vec<f64> = rows.iter().map(|row| row.get("close")).collect();
-    let mean = prices.iter().sum::<f64>() / prices.len() as f64;
-
-    let variance = prices
-        .iter()
-        .map(|price| {
-            let diff = price - mean;
-            diff * diff
-        })
-        .sum::<f64>()
-        / (prices.len() - 1) as f64;
-
-    let std_dev = variance.sqrt();
-
-    if !std_dev.is_finite() {
-        return Err(DatabaseError::InvalidCalculation(
-            "Standard deviation calculation resulted in invalid value".to_string(),
-        ));
-    }
-
-    Ok(std_dev)
+    let closes = load_closes(client, ticker, execution_date, period).await?;
+    indicators::price_std_dev(&closes)
 }
 
 /// Calculates the standard deviation of returns over a specified period.
 ///
 /// # Arguments
-/// * `pool` - Database connection pool
+/// * `client` - Database client
 /// * `ticker` - Stock ticker symbol
 /// * `execution_date` - The end date for the calculation
 /// * `period` - Number of days to calculate the return standard deviation
 ///
 /// # Returns
 /// * `Result<f64, DatabaseError>` - Standard deviation of returns in percentage
-#[derive(Debug)]
-struct StdDevPriceResult {
-    close: f64,
-}
-
 pub async fn get_returns_std_dev(
     client: &Client,
-    ticker: &str, // Changed from &String to &str
-    execution_date: &str,
+    ticker: &str,
+    execution_date: ExecutionDate,
     period: i64,
 ) -> Result<f64, DatabaseError> {
-    // Input validation
     validate_ticker(ticker)?;
     validate_period(period, "Return std dev period")?;
 
-    // Get the start date
-    let start_date = get_start_date(client, ticker, execution_date, period).await?;
-
-    tracing::debug!("Starting returns standard deviation calculation");
-
-    // Modified query to use string interpolation for dates with single quotes for QuestDB
-    let query = format!(
-        r#"
-        SELECT
-            time,
-            close
-        FROM stock_data_daily
-        WHERE ticker = $1
-        AND time BETWEEN '{}'
-        AND '{}'
-        ORDER BY time ASC
-        "#,
-        start_date, execution_date
-    );
-
-    // Fetch all prices for the period
-    // Removed date parameters since they're interpolated in the query
-    let rows = client.query(&query, &[&ticker]).await?;
-
-    // Check if we have enough data points
-    if rows.len() < 2 {
-        return Err(DatabaseError::InsufficientData(format!(
-            "Need at least 2 price points to calculate return standard deviation for {}",
-            ticker
-        )));
-    }
-
-    // Extract close prices
-    let prices: Vec<f64> = rows.iter().map(|row| row.get("close")).collect();
-
-    // Calculate daily returns with validation
-    let mut daily_returns = Vec::with_capacity(prices.len() - 1);
-    for i in 1..prices.len() {
-        let previous_close = prices[i - 1];
-        let current_close = prices[i];
-
-        if previous_close == 0.0 {
-            return Err(DatabaseError::InvalidCalculation(format!(
-                "Invalid price data for {}: zero price encountered",
-                ticker
-            )));
-        }
-
-        let daily_return = (current_close - previous_close) / previous_close * 100.0;
-
-        if !daily_return.is_finite() {
-            return Err(DatabaseError::InvalidCalculation(format!(
-                "Return calculation for {} resulted in invalid value",
-                ticker
-            )));
-        }
-
-        daily_returns.push(daily_return);
-    }
-
-    // Calculate mean return
-    let mean_return = daily_returns.iter().sum::<f64>() / daily_returns.len() as f64;
+    let closes = load_closes(client, ticker, execution_date, period).await?;
+    let std_dev = indicators::returns_std_dev(&closes)?;
 
     tracing::debug!(
         %ticker,
-        %mean_return,
-        returns_count = daily_returns.len(),
-        "Calculated mean return"
-    );
-
-    // Calculate variance (sum of squared deviations from mean)
-    let variance = daily_returns
-        .iter()
-        .map(|return_value| {
-            let diff = return_value - mean_return;
-            diff * diff
-        })
-        .sum::<f64>()
-        / (daily_returns.len() - 1) as f64;
-
-    // Calculate standard deviation
-    let std_dev = variance.sqrt();
-
-    // Validate final result
-    if !std_dev.is_finite() {
-        return Err(DatabaseError::InvalidCalculation(format!(
-            "Standard deviation calculation for {} resulted in invalid value",
-            ticker
-        )));
-    }
-
-    // Added final debug log with calculation results
-    tracing::debug!(
-        %ticker,
-        %start_date,
         %execution_date,
         %period,
         %std_dev,
-        %mean_return,
-        %variance,
         "Returns standard deviation calculation completed"
     );
 
     Ok(std_dev)
 }
 
-use deadpool_postgres::PoolError;
+/// Returns the close of the last NASDAQ trading day on or before `date`
+pub async fn get_last_market_day(
+    client: &Client,
+    date: NaiveDate,
+) -> Result<ExecutionDate, DatabaseError> {
+    tracing::debug!("Checking market day for date: {}", date);
+
+    let query = r#"
+        SELECT Date, Date_of_Previous_Trading_Day, Is_Holiday, Is_Weekend
+        FROM nasdaq_closed_days
+        WHERE Date = $1
+        "#;
+
+    let day_start = date.and_hms_opt(0, 0, 0).unwrap();
+    let row = client.query_opt(query, &[&day_start]).await?;
+
+    let market_day = match row {
+        Some(row) => {
+            let is_holiday: bool = row.get("Is_Holiday");
+            let is_weekend: bool = row.get("Is_Weekend");
+
+            if !is_holiday && !is_weekend {
+                date
+            } else {
+                let prev_day: Option<NaiveDateTime> = row.get("Date_of_Previous_Trading_Day");
+                prev_day
+                    .unwrap_or_else(|| date.and_hms_opt(16, 0, 0).unwrap())
+                    .date()
+            }
+        }
+        None => date,
+    };
+
+    Ok(ExecutionDate::at_close(market_day, Exchange::Nasdaq))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use chrono::TimeZone;
-    use deadpool_postgres::{Config, Runtime};
-    use tokio_postgres::NoTls;
+    use crate::config::DatabaseConfig;
+
+    async fn setup_test_client() -> Result<Client, DatabaseError> {
+        let pool = DatabaseConfig::from_env()?.create_pool()?;
+        Ok(pool.get().await?)
+    }
 
     #[test]
     fn test_validate_ticker() {
         assert!(validate_ticker("AAPL").is_ok());
+        assert!(validate_ticker("BRK.B").is_ok());
+        assert!(validate_ticker("SAP.DE").is_ok());
+        assert!(validate_ticker("MATIC-USDT").is_ok());
         assert!(validate_ticker("").is_err());
-        assert!(validate_ticker("TOOLONG").is_err());
+        assert!(validate_ticker("TOOLONGTICKER").is_err());
         assert!(validate_ticker("aapl").is_err()); // lowercase should fail
     }
 
@@ -1138,24 +1121,48 @@ mod tests {
     fn test_validate_period() {
         assert!(validate_period(14, "Test").is_ok());
         assert!(validate_period(0, "Test").is_err());
-        assert!(validate_period(101, "Test").is_err());
+        assert!(validate_period(261, "Test").is_err());
+    }
+
+    #[test]
+    fn test_parse_timestamp_accepts_canonical_forms() {
+        let bar = parse_timestamp("2024-12-31T16:00:00.000000Z").unwrap();
+        assert_eq!(format_timestamp(bar), "2024-12-31T16:00:00.000000Z");
+        assert_eq!(parse_timestamp("2024-12-31T11:00:00-05:00").unwrap(), bar);
+        assert_eq!(
+            format_timestamp(parse_timestamp("2024-12-31").unwrap()),
+            "2024-12-31T00:00:00.000000Z"
+        );
     }
 
     #[test]
-    fn test_validate_date_range() {
-        let start = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
-        let end = Utc.with_ymd_and_hms(2020, 12, 31, 0, 0, 0).unwrap();
-        assert!(validate_date_range(start, end).is_ok());
-        assert!(validate_date_range(end, start).is_err());
+    fn test_parse_timestamp_rejects_hostile_input() {
+        for hostile in [
+            "2024-12-31T16:00:00.000000Z' OR '1'='1",
+            "2024-12-31'; DROP TABLE stock_data_daily; --",
+            "' UNION SELECT * FROM nasdaq_closed_days --",
+            "2024-12-31T16:00:00Z\0",
+            "now()",
+            "",
+        ] {
+            assert!(
+                matches!(
+                    parse_timestamp(hostile),
+                    Err(DatabaseError::InvalidInput(_))
+                ),
+                "accepted {:?}",
+                hostile
+            );
+        }
     }
 
     #[tokio::test]
+    #[ignore = "requires a running QuestDB instance"]
     async fn test_get_current_price() -> Result<(), DatabaseError> {
         let client = setup_test_client().await?;
-        let execution_date = "2015-01-01".to_string();
+        let execution_date: ExecutionDate = "2015-01-01T16:00:00.000000Z".parse()?;
 
-        let current_price =
-            get_current_price(&client, &"AAPL".to_string(), &execution_date).await?;
+        let current_price = get_current_price(&client, "AAPL", execution_date).await?;
         assert!(current_price.close > 0.0);
         assert_eq!(current_price.ticker, "AAPL");
 
@@ -1163,11 +1170,12 @@ mod tests {
     }
 
     #[tokio::test]
+    #[ignore = "requires a running QuestDB instance"]
     async fn test_get_sma() -> Result<(), DatabaseError> {
         let client = setup_test_client().await?;
-        let execution_date = "2020-01-01".to_string();
+        let execution_date: ExecutionDate = "2020-01-01T16:00:00.000000Z".parse()?;
 
-        let sma = get_sma(&client, &"AAPL".to_string(), &execution_date, 20).await?;
+        let sma = get_sma(&client, "AAPL", execution_date, 20).await?;
         assert!(sma.is_finite());
         assert!(sma > 0.0);
 
@@ -1175,11 +1183,12 @@ mod tests {
     }
 
     #[tokio::test]
+    #[ignore = "requires a running QuestDB instance"]
     async fn test_get_ema() -> Result<(), DatabaseError> {
         let client = setup_test_client().await?;
-        let execution_date = "2020-01-01".to_string();
+        let execution_date: ExecutionDate = "2020-01-01T16:00:00.000000Z".parse()?;
 
-        let ema = get_ema(&client, &"AAPL".to_string(), &execution_date, 20).await?;
+        let ema = get_ema(&client, "AAPL", execution_date, 20).await?;
         assert!(ema.is_finite());
         assert!(ema > 0.0);
 
@@ -1187,35 +1196,56 @@ mod tests {
     }
 
     #[tokio::test]
+    #[ignore = "requires a running QuestDB instance"]
+    async fn test_get_price_history_batch() -> Result<(), DatabaseError> {
+        let client = setup_test_client().await?;
+        let start: ExecutionDate = "2019-12-01T16:00:00.000000Z".parse()?;
+        let end: ExecutionDate = "2020-01-01T16:00:00.000000Z".parse()?;
+        let tickers = ["AAPL", "MSFT"].map(String::from);
+
+        let series = get_price_history_batch(&client, &tickers, start, end).await?;
+        for ticker in &tickers {
+            let expected = get_price_history(&client, ticker, start, end).await?;
+            assert_eq!(series[ticker].len(), expected.len());
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running QuestDB instance"]
     async fn test_get_rsi() -> Result<(), DatabaseError> {
         let client = setup_test_client().await?;
-        let execution_date = "2020-01-01".to_string();
+        let execution_date: ExecutionDate = "2020-01-01T16:00:00.000000Z".parse()?;
 
-        let rsi = get_rsi(&client, &"AAPL".to_string(), &execution_date, 14).await?;
-        assert!(rsi >= 0.0 && rsi <= 100.0);
+        let rsi = get_rsi(&client, "AAPL", execution_date, 14).await?;
+        assert!((0.0..=100.0).contains(&rsi));
 
         Ok(())
     }
 
     #[tokio::test]
+    #[ignore = "requires a running QuestDB instance"]
     async fn test_invalid_ticker() -> Result<(), DatabaseError> {
         let client = setup_test_client().await?;
-        let execution_date = "2020-01-01".to_string();
+        let execution_date: ExecutionDate = "2020-01-01T16:00:00.000000Z".parse()?;
 
-        let result = get_current_price(&client, &"INVALID".to_string(), &execution_date).await;
+        let result = get_current_price(&client, "INVALID", execution_date).await;
         assert!(matches!(result, Err(DatabaseError::InsufficientData(_))));
 
         Ok(())
     }
 
     #[tokio::test]
+    #[ignore = "requires a running QuestDB instance"]
     async fn test_future_date() -> Result<(), DatabaseError> {
         let client = setup_test_client().await?;
-        let future_date = (Utc::now() + chrono::Duration::days(365))
-            .format("%Y-%m-%d")
-            .to_string();
+        let future_date = ExecutionDate::at_close(
+            (Utc::now() + chrono::Duration::days(365)).date_naive(),
+            Exchange::Nasdaq,
+        );
 
-        let result = get_current_price(&client, &"AAPL".to_string(), &future_date).await;
+        let result = get_current_price(&client, "AAPL", future_date).await;
         assert!(matches!(result, Err(DatabaseError::InsufficientData(_))));
 
         Ok(())
@@ -0,0 +1,448 @@
+//! Synthetic leveraged and inverse series.
+//! A derived ticker has no prices of its own: its closes are rebuilt from an underlying's
+//! daily returns, multiplied by the leverage and reduced by the fund's running costs, as
+//! a fund rebalancing to its target exposure every session would have traded.
+//! Like adjusted prices, derived closes are anchored at the underlying's close on the
+//! last bar of each window. The underlying's returns are at least split adjusted, since a
+//! fund's exposure does not change when the shares it holds split.
+
+use crate::market::adjustments::{adjust_points, CorporateAction, PriceAdjustment};
+use crate::market::database_functions::{Bar, DatabaseError, PricePoint};
+use crate::market::exchange::Exchange;
+use crate::market::execution_date::ExecutionDate;
+use crate::market::market_cap::SharesOutstanding;
+use crate::market::price_store::{default_window, lookback_rows, PriceStore};
+use crate::market::provider::{BoxFuture, FunctionValues, MarketDataProvider};
+use crate::market::security_master::SecurityMaster;
+use crate::market::universe::MembershipChange;
+use crate::portfolio::blocks::models::FunctionDefinition;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Sessions per year the annual costs are spread over
+const TRADING_DAYS_PER_YEAR: f64 = 252.0;
+
+/// Daily-rebalanced fund tracking a multiple of an underlying's daily returns
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DerivedSeries {
+    pub underlying: String,
+    /// Multiple of the underlying's daily return, e.g. 3.0 for 3x or -1.0 for inverse
+    pub leverage: f64,
+    /// Annual expense ratio, e.g. 0.0095 for 0.95%
+    #[serde(default)]
+    pub expense_ratio: f64,
+    /// Annual rate paid on the exposure beyond the fund's assets: the borrowed part of a
+    /// leveraged fund, the whole short position of an inverse one
+    #[serde(default)]
+    pub borrow_cost: f64,
+}
+
+impl DerivedSeries {
+    /// Costs charged per session, as a fraction of the fund's value
+    pub fn daily_cost(&self) -> f64 {
+        let financed = if self.leverage < 0.0 {
+            -self.leverage
+        } else {
+            (self.leverage - 1.0).max(0.0)
+        };
+        (self.expense_ratio + self.borrow_cost * financed) / TRADING_DAYS_PER_YEAR
+    }
+
+    /// Closes of the fund on the bars of `underlying`, anchored at its last close
+    pub fn derive(&self, underlying: &[PricePoint]) -> Result<Vec<PricePoint>, DatabaseError> {
+        let Some(last) = underlying.last() else {
+            return Ok(Vec::new());
+        };
+
+        let cost = self.daily_cost();
+        let mut close = last.close;
+        let mut derived = Vec::with_capacity(underlying.len());
+        derived.push(last.clone());
+
+        // Walk back from the anchor, undoing one session's net return per bar
+        for pair in underlying.windows(2).rev() {
+            let (previous, next) = (&pair[0], &pair[1]);
+            let growth = 1.0 + self.leverage * (next.close / previous.close - 1.0) - cost;
+            if !growth.is_finite() || growth <= 0.0 {
+                return Err(DatabaseError::InvalidCalculation(format!(
+                    "{}x {} loses everything on {}",
+                    self.leverage, self.underlying, next.time
+                )));
+            }
+            close /= growth;
+            derived.push(PricePoint {
+                time: previous.time,
+                close,
+            });
+        }
+
+        derived.reverse();
+        Ok(derived)
+    }
+}
+
+/// `MarketDataProvider` that serves derived tickers from their underlying and delegates
+/// everything else to `source`
+pub struct DerivedProvider {
+    source: Arc<dyn MarketDataProvider>,
+    series: HashMap<String, DerivedSeries>,
+}
+
+impl DerivedProvider {
+    pub fn new(
+        source: Arc<dyn MarketDataProvider>,
+        series: impl IntoIterator<Item = (String, DerivedSeries)>,
+    ) -> Self {
+        Self {
+            source,
+            series: series.into_iter().collect(),
+        }
+    }
+
+    /// Closes of `series` on the bars of `underlying`, at least split adjusted
+    async fn derive(
+        &self,
+        series: &DerivedSeries,
+        underlying: Vec<PricePoint>,
+        adjustment: PriceAdjustment,
+        exchange: Exchange,
+    ) -> Result<Vec<PricePoint>, DatabaseError> {
+        let adjustment = match adjustment {
+            PriceAdjustment::Raw => PriceAdjustment::SplitAdjusted,
+            adjustment => adjustment,
+        };
+        let underlying = adjust_points(
+            self.source.as_ref(),
+            &series.underlying,
+            underlying,
+            adjustment,
+            exchange,
+        )
+        .await?;
+        series.derive(&underlying)
+    }
+
+    fn no_bars(&self, ticker: &str, series: &DerivedSeries) -> DatabaseError {
+        DatabaseError::InvalidInput(format!(
+            "{} is derived from {} and has no bars",
+            ticker, series.underlying
+        ))
+    }
+}
+
+impl MarketDataProvider for DerivedProvider {
+    fn get_price_series<'a>(
+        &'a self,
+        ticker: &'a str,
+        execution_date: ExecutionDate,
+        trading_days: i64,
+    ) -> BoxFuture<'a, Result<Vec<PricePoint>, DatabaseError>> {
+        let Some(series) = self.series.get(ticker) else {
+            return self
+                .source
+                .get_price_series(ticker, execution_date, trading_days);
+        };
+
+        Box::pin(async move {
+            let underlying = self
+                .source
+                .get_price_series(&series.underlying, execution_date, trading_days)
+                .await?;
+            self.derive(
+                series,
+                underlying,
+                PriceAdjustment::Raw,
+                execution_date.exchange(),
+            )
+            .await
+        })
+    }
+
+    fn get_price_history<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: ExecutionDate,
+        end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<PricePoint>, DatabaseError>> {
+        let Some(series) = self.series.get(ticker) else {
+            return self.source.get_price_history(ticker, start_date, end_date);
+        };
+
+        Box::pin(async move {
+            let underlying = self
+                .source
+                .get_price_history(&series.underlying, start_date, end_date)
+                .await?;
+            self.derive(
+                series,
+                underlying,
+                PriceAdjustment::Raw,
+                end_date.exchange(),
+            )
+            .await
+        })
+    }
+
+    fn get_bar_series<'a>(
+        &'a self,
+        ticker: &'a str,
+        execution_date: ExecutionDate,
+        trading_days: i64,
+    ) -> BoxFuture<'a, Result<Vec<Bar>, DatabaseError>> {
+        match self.series.get(ticker) {
+            Some(series) => {
+                let error = self.no_bars(ticker, series);
+                Box::pin(async move { Err(error) })
+            }
+            None => self
+                .source
+                .get_bar_series(ticker, execution_date, trading_days),
+        }
+    }
+
+    fn get_bar_history<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: ExecutionDate,
+        end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<Bar>, DatabaseError>> {
+        match self.series.get(ticker) {
+            Some(series) => {
+                let error = self.no_bars(ticker, series);
+                Box::pin(async move { Err(error) })
+            }
+            None => self.source.get_bar_history(ticker, start_date, end_date),
+        }
+    }
+
+    fn get_corporate_actions<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: ExecutionDate,
+        end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<CorporateAction>, DatabaseError>> {
+        // The underlying's splits and dividends are already part of the derived returns
+        if self.series.contains_key(ticker) {
+            return Box::pin(async { Ok(Vec::new()) });
+        }
+        self.source
+            .get_corporate_actions(ticker, start_date, end_date)
+    }
+
+    fn get_shares_outstanding<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: ExecutionDate,
+        end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<SharesOutstanding>, DatabaseError>> {
+        // A synthetic series has no shares of its own
+        if self.series.contains_key(ticker) {
+            return Box::pin(async { Ok(Vec::new()) });
+        }
+        self.source
+            .get_shares_outstanding(ticker, start_date, end_date)
+    }
+
+    fn get_index_membership<'a>(
+        &'a self,
+        universe: &'a str,
+        end_date: NaiveDate,
+    ) -> BoxFuture<'a, Result<Vec<MembershipChange>, DatabaseError>> {
+        self.source.get_index_membership(universe, end_date)
+    }
+
+    fn get_security_master(&self) -> BoxFuture<'_, Result<Arc<SecurityMaster>, DatabaseError>> {
+        self.source.get_security_master()
+    }
+
+    fn get_last_market_day(
+        &self,
+        date: NaiveDate,
+    ) -> BoxFuture<'_, Result<ExecutionDate, DatabaseError>> {
+        self.source.get_last_market_day(date)
+    }
+
+    fn evaluate_function<'a>(
+        &'a self,
+        function: &'a FunctionDefinition,
+        execution_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<f64, DatabaseError>> {
+        let Some(series) = self.series.get(&function.asset) else {
+            return self.source.evaluate_function(function, execution_date);
+        };
+
+        Box::pin(async move {
+            let window = function
+                .window_of_days
+                .unwrap_or_else(|| default_window(&function.function_name));
+            let rows = lookback_rows(&function.function_name, window as usize);
+            let underlying = self
+                .source
+                .get_price_series(&series.underlying, execution_date, rows as i64)
+                .await?;
+            // Total return evaluations reinvest the underlying's dividends
+            let derived = self
+                .derive(
+                    series,
+                    underlying,
+                    function.price_adjustment.unwrap_or_default(),
+                    execution_date.exchange(),
+                )
+                .await?;
+
+            let mut store = PriceStore::new();
+            store.insert(&function.asset, derived.into_iter().collect());
+            store.evaluate(function, execution_date)
+        })
+    }
+
+    /// Batches the tickers with prices of their own; derived ones are evaluated singly
+    fn evaluate_function_batch<'a>(
+        &'a self,
+        function: &'a FunctionDefinition,
+        tickers: &'a [String],
+        execution_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<FunctionValues, DatabaseError>> {
+        Box::pin(async move {
+            let (derived, stored): (Vec<String>, Vec<String>) = tickers
+                .iter()
+                .cloned()
+                .partition(|ticker| self.series.contains_key(ticker));
+            let mut values = if stored.is_empty() {
+                FunctionValues::with_capacity(derived.len())
+            } else {
+                self.source
+                    .evaluate_function_batch(function, &stored, execution_date)
+                    .await?
+            };
+            for ticker in derived {
+                let value = self
+                    .evaluate_function(&function.for_asset(&ticker), execution_date)
+                    .await;
+                values.insert(ticker, value);
+            }
+            Ok(values)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market::price_store::PriceSeries;
+    use crate::portfolio::blocks::models::FunctionName;
+
+    fn underlying() -> PriceStore {
+        let mut series = PriceSeries::new();
+        let start = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        for (day, close) in start.iter_days().zip([100.0, 110.0, 99.0, 104.0, 104.0]) {
+            series
+                .push(Exchange::Nasdaq.bar_timestamp(day), close)
+                .unwrap();
+        }
+        let mut store = PriceStore::new();
+        store.insert("QQQ", series);
+        store
+    }
+
+    fn closes(points: &[PricePoint]) -> Vec<f64> {
+        points.iter().map(|point| point.close).collect()
+    }
+
+    #[tokio::test]
+    async fn test_leveraged_and_inverse_series() {
+        let leveraged = DerivedSeries {
+            underlying: "QQQ".to_string(),
+            leverage: 3.0,
+            expense_ratio: 0.0,
+            borrow_cost: 0.0,
+        };
+        let inverse = DerivedSeries {
+            leverage: -1.0,
+            ..leveraged.clone()
+        };
+        let provider = DerivedProvider::new(
+            Arc::new(underlying()),
+            [
+                ("QQQ3X".to_string(), leveraged),
+                ("QQQINV".to_string(), inverse),
+            ],
+        );
+        let execution_date = ExecutionDate::at_close(
+            NaiveDate::from_ymd_opt(2024, 1, 5).unwrap(),
+            Exchange::Nasdaq,
+        );
+
+        // +30%, -30%, +15.15% and 0%, ending at the underlying's last close
+        let rebound = 104.0 / 99.0 - 1.0;
+        let points = provider
+            .get_price_series("QQQ3X", execution_date, 5)
+            .await
+            .unwrap();
+        let third = 104.0 / (1.0 + 3.0 * rebound);
+        let expected = [third / 0.7 / 1.3, third / 0.7, third, 104.0, 104.0];
+        for (close, expected) in closes(&points).into_iter().zip(expected) {
+            assert!((close - expected).abs() < 1e-9);
+        }
+
+        let function = |asset: &str| FunctionDefinition {
+            function_name: FunctionName::CumulativeReturn,
+            window_of_days: Some(3),
+            asset: asset.to_string(),
+            price_adjustment: None,
+        };
+        let leveraged = provider
+            .evaluate_function(&function("QQQ3X"), execution_date)
+            .await
+            .unwrap();
+        let inverse = provider
+            .evaluate_function(&function("QQQINV"), execution_date)
+            .await
+            .unwrap();
+        let underlying = underlying()
+            .evaluate(&function("QQQ"), execution_date)
+            .unwrap();
+        // The window holds a single non-zero return, so it scales with the leverage
+        assert!((leveraged - 3.0 * underlying).abs() < 1e-9);
+        assert!((inverse + underlying).abs() < 1e-9);
+
+        // Derived tickers have no bars or corporate actions of their own
+        assert!(provider
+            .get_bar_series("QQQ3X", execution_date, 5)
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_costs_drag_the_series() {
+        let series = DerivedSeries {
+            underlying: "SPY".to_string(),
+            leverage: 2.0,
+            expense_ratio: 0.01,
+            borrow_cost: 0.05,
+        };
+        // 1% fees plus 5% on the borrowed half of the exposure
+        assert!((series.daily_cost() - 0.06 / 252.0).abs() < 1e-12);
+        let inverse = DerivedSeries {
+            leverage: -2.0,
+            ..series.clone()
+        };
+        assert!((inverse.daily_cost() - 0.11 / 252.0).abs() < 1e-12);
+
+        // A flat underlying loses the daily cost every session
+        let time =
+            |day| Exchange::Nyse.bar_timestamp(NaiveDate::from_ymd_opt(2024, 1, day).unwrap());
+        let flat: Vec<PricePoint> = (2..=4)
+            .map(|day| PricePoint {
+                time: time(day),
+                close: 50.0,
+            })
+            .collect();
+        let derived = series.derive(&flat).unwrap();
+        let cost = series.daily_cost();
+        assert!((derived[0].close - 50.0 / (1.0 - cost).powi(2)).abs() < 1e-9);
+        assert_eq!(derived[2].close, 50.0);
+    }
+}
@@ -0,0 +1,175 @@
+//! Exchanges the engine can execute against and their trading sessions.
+//! Each exchange knows its timezone and local session close, so an execution date can be
+//! turned into the real UTC instant of the close. The timestamp a daily bar carries in
+//! `stock_data_daily` does not depend on the exchange: every bar is stamped with its
+//! session date at [`BAR_TIME`], which is what the CSV loader and the ingestion write.
+
+use crate::market::database_functions::DatabaseError;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Time of day every daily bar is stamped with, whatever its exchange: the NASDAQ close
+/// written as UTC, as in `2024-07-01T16:00:00Z`
+pub const BAR_TIME: NaiveTime = match NaiveTime::from_hms_opt(16, 0, 0) {
+    Some(time) => time,
+    None => panic!("invalid bar time"),
+};
+
+/// Listing exchange of an asset, as written in `Asset.exchange`
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Exchange {
+    #[default]
+    Nasdaq,
+    Nyse,
+    Xetra,
+    /// 24/7 crypto markets, cut into UTC days
+    Crypto,
+}
+
+impl Exchange {
+    /// Timezone the exchange's sessions are scheduled in
+    pub fn timezone(&self) -> Tz {
+        match self {
+            Exchange::Nasdaq | Exchange::Nyse => chrono_tz::America::New_York,
+            Exchange::Xetra => chrono_tz::Europe::Berlin,
+            Exchange::Crypto => chrono_tz::UTC,
+        }
+    }
+
+    /// Local wall-clock time at which the regular session closes
+    pub fn session_close(&self) -> NaiveTime {
+        match self {
+            Exchange::Nasdaq | Exchange::Nyse => NaiveTime::from_hms_opt(16, 0, 0).unwrap(),
+            Exchange::Xetra => NaiveTime::from_hms_opt(17, 30, 0).unwrap(),
+            Exchange::Crypto => NaiveTime::from_hms_opt(23, 59, 59).unwrap(),
+        }
+    }
+
+    /// UTC instant at which the `date` session closes, e.g. 21:00Z in winter and
+    /// 20:00Z in summer for New York
+    pub fn close_instant(&self, date: NaiveDate) -> DateTime<Utc> {
+        let local_close = date.and_time(self.session_close());
+        let timezone = self.timezone();
+        timezone
+            .from_local_datetime(&local_close)
+            .earliest()
+            // A close inside a DST gap does not exist locally; read it as standard time
+            .unwrap_or_else(|| timezone.from_utc_datetime(&local_close))
+            .with_timezone(&Utc)
+    }
+
+    /// Timestamp of the daily bar for `date`. Bars of every exchange are stamped with
+    /// their session date at [`BAR_TIME`], not with the session close, so a bar written
+    /// without knowing its listing is found by the lookups of any exchange.
+    pub fn bar_timestamp(&self, date: NaiveDate) -> NaiveDateTime {
+        date.and_time(BAR_TIME)
+    }
+
+    /// Local calendar date on the exchange at `instant`
+    pub fn trading_date(&self, instant: DateTime<Utc>) -> NaiveDate {
+        instant.with_timezone(&self.timezone()).date_naive()
+    }
+}
+
+impl fmt::Display for Exchange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Exchange::Nasdaq => write!(f, "NASDAQ"),
+            Exchange::Nyse => write!(f, "NYSE"),
+            Exchange::Xetra => write!(f, "XETRA"),
+            Exchange::Crypto => write!(f, "CRYPTO"),
+        }
+    }
+}
+
+impl FromStr for Exchange {
+    type Err = DatabaseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_uppercase().as_str() {
+            "NASDAQ" => Ok(Exchange::Nasdaq),
+            "NYSE" => Ok(Exchange::Nyse),
+            "XETRA" => Ok(Exchange::Xetra),
+            "CRYPTO" => Ok(Exchange::Crypto),
+            other => Err(DatabaseError::InvalidInput(format!(
+                "Unsupported exchange: {}",
+                other
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn test_close_instant_follows_new_york_dst() {
+        let nasdaq = Exchange::Nasdaq;
+        assert_eq!(
+            nasdaq.close_instant(date(2024, 12, 31)).to_rfc3339(),
+            "2024-12-31T21:00:00+00:00"
+        );
+        assert_eq!(
+            nasdaq.close_instant(date(2024, 7, 1)).to_rfc3339(),
+            "2024-07-01T20:00:00+00:00"
+        );
+        // First trading day after the March switch to daylight time
+        assert_eq!(
+            nasdaq.close_instant(date(2024, 3, 11)).to_rfc3339(),
+            "2024-03-11T20:00:00+00:00"
+        );
+    }
+
+    #[test]
+    fn test_bar_timestamp_keeps_storage_convention() {
+        assert_eq!(
+            Exchange::Nyse.bar_timestamp(date(2024, 7, 1)).to_string(),
+            "2024-07-01 16:00:00"
+        );
+    }
+
+    #[test]
+    fn test_trading_date_uses_local_calendar() {
+        // 02:00Z on Jan 1st is still New Year's Eve in New York
+        let instant = Utc.with_ymd_and_hms(2025, 1, 1, 2, 0, 0).unwrap();
+        assert_eq!(Exchange::Nasdaq.trading_date(instant), date(2024, 12, 31));
+    }
+
+    #[test]
+    fn test_parses_exchange_names() {
+        for exchange in [
+            Exchange::Nasdaq,
+            Exchange::Nyse,
+            Exchange::Xetra,
+            Exchange::Crypto,
+        ] {
+            assert_eq!(exchange.to_string().parse::<Exchange>().unwrap(), exchange);
+        }
+        assert_eq!("xetra".parse::<Exchange>().unwrap(), Exchange::Xetra);
+        assert!("LSE".parse::<Exchange>().is_err());
+
+        // Frankfurt closes at 17:30 local time, 16:30Z in winter
+        assert_eq!(
+            Exchange::Xetra
+                .close_instant(date(2024, 12, 30))
+                .to_rfc3339(),
+            "2024-12-30T16:30:00+00:00"
+        );
+        // Its bars are stamped like every other exchange's
+        assert_eq!(
+            Exchange::Xetra.bar_timestamp(date(2024, 12, 30)),
+            Exchange::Nasdaq.bar_timestamp(date(2024, 12, 30))
+        );
+    }
+}
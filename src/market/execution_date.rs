@@ -0,0 +1,138 @@
+//! Typed execution timestamps.
+//! An `ExecutionDate` is the close of one exchange session: the instant at which a strategy
+//! is evaluated. It renders as the UTC close instant and converts to the bar timestamp
+//! used by `stock_data_daily` for lookups.
+
+use crate::market::database_functions::{format_timestamp, parse_timestamp, DatabaseError};
+use crate::market::exchange::Exchange;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// Session close at which a strategy is executed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ExecutionDate {
+    date: NaiveDate,
+    exchange: Exchange,
+}
+
+impl ExecutionDate {
+    /// Close of the `date` session on `exchange`
+    pub fn at_close(date: NaiveDate, exchange: Exchange) -> Self {
+        Self { date, exchange }
+    }
+
+    /// Execution date of the daily bar stamped `timestamp` on `exchange`
+    pub fn from_bar_timestamp(timestamp: NaiveDateTime, exchange: Exchange) -> Self {
+        Self::at_close(timestamp.date(), exchange)
+    }
+
+    /// Trading day this execution belongs to, in the exchange's local calendar
+    pub fn date(&self) -> NaiveDate {
+        self.date
+    }
+
+    pub fn exchange(&self) -> Exchange {
+        self.exchange
+    }
+
+    /// Real UTC instant of the session close
+    pub fn close_instant(&self) -> DateTime<Utc> {
+        self.exchange.close_instant(self.date)
+    }
+
+    /// Timestamp of this session's bar in `stock_data_daily`, used for all price lookups
+    pub fn bar_timestamp(&self) -> NaiveDateTime {
+        self.exchange.bar_timestamp(self.date)
+    }
+}
+
+impl fmt::Display for ExecutionDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&format_timestamp(self.close_instant().naive_utc()))
+    }
+}
+
+impl FromStr for ExecutionDate {
+    type Err = DatabaseError;
+
+    /// Parses a NASDAQ session from a bare `YYYY-MM-DD` date or any RFC 3339 timestamp,
+    /// which is mapped to the New York calendar date it falls on. Both the close instant
+    /// (`2024-12-31T21:00:00Z`) and the bar timestamp (`2024-12-31T16:00:00Z`) parse to
+    /// the same session.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let exchange = Exchange::default();
+        if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+            return Ok(Self::at_close(date, exchange));
+        }
+
+        let instant = parse_timestamp(s)?.and_utc();
+        Ok(Self::at_close(exchange.trading_date(instant), exchange))
+    }
+}
+
+impl Serialize for ExecutionDate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ExecutionDate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_renders_utc_close_instant() {
+        let date = NaiveDate::from_ymd_opt(2024, 12, 31).unwrap();
+        let execution_date = ExecutionDate::at_close(date, Exchange::Nasdaq);
+        assert_eq!(execution_date.to_string(), "2024-12-31T21:00:00.000000Z");
+        assert_eq!(
+            execution_date.bar_timestamp(),
+            date.and_hms_opt(16, 0, 0).unwrap()
+        );
+
+        let json = serde_json::to_string(&execution_date).unwrap();
+        assert_eq!(json, "\"2024-12-31T21:00:00.000000Z\"");
+        assert_eq!(
+            serde_json::from_str::<ExecutionDate>(&json).unwrap(),
+            execution_date
+        );
+    }
+
+    #[test]
+    fn test_parses_instants_into_sessions() {
+        let session = ExecutionDate::at_close(
+            NaiveDate::from_ymd_opt(2024, 7, 1).unwrap(),
+            Exchange::Nasdaq,
+        );
+        for input in [
+            "2024-07-01",
+            "2024-07-01T20:00:00Z",
+            "2024-07-01T16:00:00.000000Z",
+            "2024-07-01T16:00:00-04:00",
+        ] {
+            assert_eq!(
+                input.parse::<ExecutionDate>().unwrap(),
+                session,
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_rejects_malformed_input() {
+        assert!("2024-12-31T16:00:00Z' OR '1'='1"
+            .parse::<ExecutionDate>()
+            .is_err());
+        assert!(serde_json::from_str::<ExecutionDate>("\"yesterday\"").is_err());
+    }
+}
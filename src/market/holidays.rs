@@ -0,0 +1,479 @@
+//! Rule-based US exchange holiday calendar.
+//! Generates the NYSE/NASDAQ closed days for any range of years from the exchange's
+//! holiday rules plus a list of one-off closures, and writes them in the
+//! `nasdaq_closed_days` schema, either as CSV or straight into QuestDB.
+
+use crate::market::calendar::TradingCalendar;
+use crate::market::database_functions::DatabaseError;
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Weekday};
+use deadpool_postgres::Client;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+
+/// First year Juneteenth closed the markets
+const JUNETEENTH_FIRST_YEAR: i32 = 2022;
+
+/// First year Martin Luther King, Jr. Day closed the markets
+const MLK_DAY_FIRST_YEAR: i32 = 1998;
+
+/// Last presidential election day the markets closed for
+const ELECTION_DAY_LAST_YEAR: i32 = 1980;
+
+/// Unscheduled full-day closures
+const SPECIAL_CLOSURES: &[((i32, u32, u32), &str)] = &[
+    ((1985, 9, 27), "Hurricane Gloria"),
+    ((1994, 4, 27), "Funeral of President Richard Nixon"),
+    ((2001, 9, 11), "September 11 Attacks"),
+    ((2001, 9, 12), "September 11 Attacks"),
+    ((2001, 9, 13), "September 11 Attacks"),
+    ((2001, 9, 14), "September 11 Attacks"),
+    (
+        (2004, 6, 11),
+        "National Day of Mourning for President Ronald Reagan",
+    ),
+    (
+        (2007, 1, 2),
+        "National Day of Mourning for President Gerald Ford",
+    ),
+    ((2012, 10, 29), "Hurricane Sandy"),
+    ((2012, 10, 30), "Hurricane Sandy"),
+    (
+        (2018, 12, 5),
+        "National Day of Mourning for President George H. W. Bush",
+    ),
+    (
+        (2025, 1, 9),
+        "National Day of Mourning for President Jimmy Carter",
+    ),
+];
+
+const CSV_HEADER: &str = "Date,Year,Month,Day of Month,Weekday,Is Weekend,Is Holiday,\
+Holiday Name,Season,Days Until Next Trading Day,Date of Next Trading Day,\
+Is Start of Month,Is Last Day of Month";
+
+/// Easter Sunday of `year` (anonymous Gregorian computus)
+pub fn easter_sunday(year: i32) -> NaiveDate {
+    let a = year % 19;
+    let b = year / 100;
+    let c = year % 100;
+    let d = b / 4;
+    let e = b % 4;
+    let f = (b + 8) / 25;
+    let g = (b - f + 1) / 3;
+    let h = (19 * a + b - d - g + 15) % 30;
+    let i = c / 4;
+    let k = c % 4;
+    let l = (32 + 2 * e + 2 * i - h - k) % 7;
+    let m = (a + 11 * h + 22 * l) / 451;
+    let month = (h + l - 7 * m + 114) / 31;
+    let day = (h + l - 7 * m + 114) % 31 + 1;
+
+    NaiveDate::from_ymd_opt(year, month as u32, day as u32).unwrap()
+}
+
+/// `n`th (1-based) `weekday` of a month
+fn nth_weekday(year: i32, month: u32, weekday: Weekday, n: u8) -> NaiveDate {
+    NaiveDate::from_weekday_of_month_opt(year, month, weekday, n).unwrap()
+}
+
+fn last_weekday(year: i32, month: u32, weekday: Weekday) -> NaiveDate {
+    NaiveDate::from_weekday_of_month_opt(year, month, weekday, 5)
+        .unwrap_or_else(|| nth_weekday(year, month, weekday, 4))
+}
+
+/// Day a fixed-date holiday is observed: Saturdays move to Friday, Sundays to Monday
+fn observed(date: NaiveDate) -> (NaiveDate, bool) {
+    match date.weekday() {
+        Weekday::Sat => (date - Duration::days(1), true),
+        Weekday::Sun => (date + Duration::days(1), true),
+        _ => (date, false),
+    }
+}
+
+fn push_fixed(holidays: &mut Vec<(NaiveDate, String)>, date: NaiveDate, name: &str) {
+    let (day, shifted) = observed(date);
+    let name = if shifted {
+        format!("{} (observed)", name)
+    } else {
+        name.to_string()
+    };
+    holidays.push((day, name));
+}
+
+/// Full-day NYSE/NASDAQ closures in `year` other than weekends, oldest first
+pub fn us_exchange_holidays(year: i32) -> Vec<(NaiveDate, String)> {
+    let date = |month, day| NaiveDate::from_ymd_opt(year, month, day).unwrap();
+    let mut holidays = Vec::new();
+
+    // A New Year's Day on Saturday is not observed on the last day of the old year
+    let new_years_day = date(1, 1);
+    if new_years_day.weekday() != Weekday::Sat {
+        push_fixed(&mut holidays, new_years_day, "New Year's Day");
+    }
+    if year >= MLK_DAY_FIRST_YEAR {
+        holidays.push((
+            nth_weekday(year, 1, Weekday::Mon, 3),
+            "Martin Luther King, Jr. Day".to_string(),
+        ));
+    }
+    holidays.push((
+        nth_weekday(year, 2, Weekday::Mon, 3),
+        "Washington's Birthday".to_string(),
+    ));
+    holidays.push((
+        easter_sunday(year) - Duration::days(2),
+        "Good Friday".to_string(),
+    ));
+    holidays.push((
+        last_weekday(year, 5, Weekday::Mon),
+        "Memorial Day".to_string(),
+    ));
+    if year >= JUNETEENTH_FIRST_YEAR {
+        push_fixed(
+            &mut holidays,
+            date(6, 19),
+            "Juneteenth National Independence Day",
+        );
+    }
+    push_fixed(&mut holidays, date(7, 4), "Independence Day");
+    holidays.push((
+        nth_weekday(year, 9, Weekday::Mon, 1),
+        "Labor Day".to_string(),
+    ));
+    if year <= ELECTION_DAY_LAST_YEAR && year % 4 == 0 {
+        // Tuesday after the first Monday of November
+        holidays.push((
+            nth_weekday(year, 11, Weekday::Mon, 1) + Duration::days(1),
+            "Presidential Election Day".to_string(),
+        ));
+    }
+    holidays.push((
+        nth_weekday(year, 11, Weekday::Thu, 4),
+        "Thanksgiving Day".to_string(),
+    ));
+    push_fixed(&mut holidays, date(12, 25), "Christmas Day");
+
+    holidays.extend(
+        SPECIAL_CLOSURES
+            .iter()
+            .filter(|((closure_year, _, _), _)| *closure_year == year)
+            .map(|((_, month, day), name)| (date(*month, *day), name.to_string())),
+    );
+
+    holidays.sort();
+    holidays
+}
+
+/// NYSE/NASDAQ trading calendar for `first_year..=last_year` generated from the rules
+pub fn us_exchange_calendar(
+    first_year: i32,
+    last_year: i32,
+) -> Result<TradingCalendar, DatabaseError> {
+    let first_day =
+        NaiveDate::from_ymd_opt(first_year, 1, 1).ok_or(DatabaseError::InvalidDateRange)?;
+    let last_day =
+        NaiveDate::from_ymd_opt(last_year, 12, 31).ok_or(DatabaseError::InvalidDateRange)?;
+    if first_day > last_day {
+        return Err(DatabaseError::InvalidDateRange);
+    }
+
+    let holidays: BTreeMap<NaiveDate, String> = (first_year..=last_year)
+        .flat_map(us_exchange_holidays)
+        .collect();
+    let weekends = first_day
+        .iter_days()
+        .take_while(|day| *day <= last_day)
+        .filter(|day| matches!(day.weekday(), Weekday::Sat | Weekday::Sun));
+    let closed_days: BTreeSet<NaiveDate> = weekends.chain(holidays.keys().copied()).collect();
+
+    TradingCalendar::new(first_day, last_day, closed_days, holidays)
+}
+
+/// One row of the `nasdaq_closed_days` table
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClosedDay {
+    pub date: NaiveDate,
+    pub is_weekend: bool,
+    pub is_holiday: bool,
+    /// Holiday name, or "Weekend"
+    pub holiday_name: String,
+    pub previous_trading_day: NaiveDate,
+    pub next_trading_day: NaiveDate,
+}
+
+impl ClosedDay {
+    pub fn season(&self) -> &'static str {
+        match self.date.month() {
+            12 | 1 | 2 => "Winter",
+            3..=5 => "Spring",
+            6..=8 => "Summer",
+            _ => "Fall",
+        }
+    }
+
+    pub fn days_until_next_trading_day(&self) -> i64 {
+        (self.next_trading_day - self.date).num_days()
+    }
+
+    pub fn is_start_of_month(&self) -> bool {
+        self.date.day() == 1
+    }
+
+    pub fn is_last_day_of_month(&self) -> bool {
+        self.date
+            .succ_opt()
+            .is_none_or(|next| next.month() != self.date.month())
+    }
+
+    /// Row in the `nasdaq_closed_days.csv` column order
+    fn csv_row(&self) -> String {
+        let flag = |value: bool| if value { "True" } else { "False" };
+        let holiday_name = if self.holiday_name.contains(',') {
+            format!("\"{}\"", self.holiday_name)
+        } else {
+            self.holiday_name.clone()
+        };
+
+        format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            self.date.format("%Y-%m-%d"),
+            self.date.year(),
+            self.date.format("%B"),
+            self.date.day(),
+            self.date.format("%A"),
+            flag(self.is_weekend),
+            flag(self.is_holiday),
+            holiday_name,
+            self.season(),
+            self.days_until_next_trading_day(),
+            self.next_trading_day.format("%Y-%m-%d"),
+            flag(self.is_start_of_month()),
+            flag(self.is_last_day_of_month()),
+        )
+    }
+}
+
+/// Every weekend and holiday in `first_year..=last_year`, oldest first
+pub fn closed_days(first_year: i32, last_year: i32) -> Result<Vec<ClosedDay>, DatabaseError> {
+    // One extra year on each side so neighbouring trading days at the edges are known
+    let calendar = us_exchange_calendar(first_year - 1, last_year + 1)?;
+    let first_day =
+        NaiveDate::from_ymd_opt(first_year, 1, 1).ok_or(DatabaseError::InvalidDateRange)?;
+    let last_day =
+        NaiveDate::from_ymd_opt(last_year, 12, 31).ok_or(DatabaseError::InvalidDateRange)?;
+
+    let mut rows = Vec::new();
+    for date in first_day.iter_days().take_while(|day| *day <= last_day) {
+        if calendar.is_trading_day(date)? {
+            continue;
+        }
+
+        let holiday_name = calendar.holiday_name(date);
+        rows.push(ClosedDay {
+            date,
+            is_weekend: matches!(date.weekday(), Weekday::Sat | Weekday::Sun),
+            is_holiday: holiday_name.is_some(),
+            holiday_name: holiday_name.unwrap_or("Weekend").to_string(),
+            previous_trading_day: calendar.previous_trading_day(date)?,
+            next_trading_day: calendar.next_trading_day(date)?,
+        });
+    }
+    Ok(rows)
+}
+
+/// Writes `rows` in the `nasdaq_closed_days.csv` format
+pub fn write_csv(rows: &[ClosedDay], mut writer: impl Write) -> Result<(), DatabaseError> {
+    writeln!(writer, "{}", CSV_HEADER)?;
+    for row in rows {
+        writeln!(writer, "{}", row.csv_row())?;
+    }
+    Ok(())
+}
+
+fn midnight(date: NaiveDate) -> NaiveDateTime {
+    date.and_hms_opt(0, 0, 0).unwrap()
+}
+
+/// Creates `nasdaq_closed_days` if needed and upserts `rows` into it, keyed by `Date`.
+/// Returns the number of rows written.
+pub async fn upsert_closed_days(client: &Client, rows: &[ClosedDay]) -> Result<u64, DatabaseError> {
+    client
+        .batch_execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS nasdaq_closed_days (
+                Date TIMESTAMP,
+                Year INT,
+                Month SYMBOL,
+                Day_of_Month INT,
+                Weekday SYMBOL,
+                Is_Weekend BOOLEAN,
+                Is_Holiday BOOLEAN,
+                Holiday_Name SYMBOL,
+                Season SYMBOL,
+                Days_Until_Next_Trading_Day INT,
+                Date_of_Next_Trading_Day TIMESTAMP,
+                Date_of_Previous_Trading_Day TIMESTAMP,
+                Is_Start_of_Month BOOLEAN,
+                Is_Last_Day_of_Month BOOLEAN
+            ) TIMESTAMP(Date) PARTITION BY YEAR WAL;
+            ALTER TABLE nasdaq_closed_days DEDUP ENABLE UPSERT KEYS(Date);
+            "#,
+        )
+        .await?;
+
+    let statement = client
+        .prepare(
+            r#"
+            INSERT INTO nasdaq_closed_days (
+                Date, Year, Month, Day_of_Month, Weekday, Is_Weekend, Is_Holiday,
+                Holiday_Name, Season, Days_Until_Next_Trading_Day, Date_of_Next_Trading_Day,
+                Date_of_Previous_Trading_Day, Is_Start_of_Month, Is_Last_Day_of_Month
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
+            "#,
+        )
+        .await?;
+
+    let mut written = 0;
+    for row in rows {
+        let month = row.date.format("%B").to_string();
+        let weekday = row.date.format("%A").to_string();
+        written += client
+            .execute(
+                &statement,
+                &[
+                    &midnight(row.date),
+                    &row.date.year(),
+                    &month,
+                    &(row.date.day() as i32),
+                    &weekday,
+                    &row.is_weekend,
+                    &row.is_holiday,
+                    &row.holiday_name,
+                    &row.season(),
+                    &(row.days_until_next_trading_day() as i32),
+                    &midnight(row.next_trading_day),
+                    &midnight(row.previous_trading_day),
+                    &row.is_start_of_month(),
+                    &row.is_last_day_of_month(),
+                ],
+            )
+            .await?;
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn test_easter_sunday() {
+        assert_eq!(easter_sunday(1981), date(1981, 4, 19));
+        assert_eq!(easter_sunday(2000), date(2000, 4, 23));
+        assert_eq!(easter_sunday(2024), date(2024, 3, 31));
+        assert_eq!(easter_sunday(2038), date(2038, 4, 25));
+    }
+
+    #[test]
+    fn test_observed_and_dated_rules() {
+        let holidays: BTreeMap<NaiveDate, String> =
+            (2020..=2028).flat_map(us_exchange_holidays).collect();
+        let name = |day| holidays.get(&day).map(String::as_str);
+
+        // Independence Day on a Saturday closes the Friday before
+        assert_eq!(name(date(2020, 7, 3)), Some("Independence Day (observed)"));
+        // Juneteenth is only a market holiday from 2022
+        assert_eq!(name(date(2021, 6, 18)), None);
+        assert_eq!(
+            name(date(2022, 6, 20)),
+            Some("Juneteenth National Independence Day (observed)")
+        );
+        assert_eq!(name(date(2024, 3, 29)), Some("Good Friday"));
+        assert_eq!(name(date(2023, 1, 2)), Some("New Year's Day (observed)"));
+        // New Year's Day 2022 and 2028 fall on a Saturday and are not made up
+        assert_eq!(name(date(2021, 12, 31)), None);
+        assert_eq!(name(date(2027, 12, 31)), None);
+        assert_eq!(name(date(2027, 12, 24)), Some("Christmas Day (observed)"));
+        assert_eq!(
+            name(date(2025, 1, 9)),
+            Some("National Day of Mourning for President Jimmy Carter")
+        );
+    }
+
+    fn existing_csv() -> String {
+        let path =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("Python fetch data/nasdaq_closed_days.csv");
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn test_matches_existing_closed_days() {
+        let generated = us_exchange_calendar(1980, 2050).unwrap();
+        let existing = TradingCalendar::nasdaq();
+        assert_eq!(generated.range(), existing.range());
+
+        let (first_day, last_day) = generated.range();
+        let mismatches: Vec<NaiveDate> = first_day
+            .iter_days()
+            .take_while(|day| *day <= last_day)
+            .filter(|day| {
+                generated.is_trading_day(*day).unwrap() != existing.is_trading_day(*day).unwrap()
+            })
+            .collect();
+
+        // The CSV closes New Year's Eve 2027, but the NYSE does not make up a New Year's
+        // Day that falls on a Saturday
+        assert_eq!(mismatches, vec![date(2027, 12, 31)]);
+    }
+
+    #[test]
+    fn test_csv_rows_match_existing_format() {
+        let rows = closed_days(1980, 2050).unwrap();
+        let mut generated = Vec::new();
+        write_csv(&rows, &mut generated).unwrap();
+        let generated = String::from_utf8(generated).unwrap();
+
+        let existing = existing_csv();
+        assert_eq!(generated.lines().next(), existing.lines().next(), "header");
+
+        // Holiday names differ between sources, so compare the weekend rows verbatim
+        let weekend_rows: HashMap<&str, &str> = generated
+            .lines()
+            .filter(|line| line.contains(",True,False,Weekend,"))
+            .map(|line| (&line[..10], line))
+            .collect();
+        let mut compared = 0;
+        for line in existing.lines().skip(1) {
+            // The script did not look past 2050 and points the last row at a Sunday
+            if !line.contains(",True,False,Weekend,") || line.starts_with("2050-12-31") {
+                continue;
+            }
+            assert_eq!(
+                weekend_rows.get(&line[..10]),
+                Some(&line),
+                "{}",
+                &line[..10]
+            );
+            compared += 1;
+        }
+        assert!(compared > 7000);
+
+        // Output parses back into the same calendar
+        let reparsed = TradingCalendar::parse(&generated, "generated").unwrap();
+        assert_eq!(
+            reparsed
+                .count_trading_days(date(1980, 1, 1), date(2050, 12, 31))
+                .unwrap(),
+            us_exchange_calendar(1980, 2050)
+                .unwrap()
+                .count_trading_days(date(1980, 1, 1), date(2050, 12, 31))
+                .unwrap()
+        );
+    }
+}
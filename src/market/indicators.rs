@@ -0,0 +1,361 @@
+//! Indicator math over close-price series.
+//! All functions take closes ordered oldest first and never touch the database,
+//! so every backend evaluates `FunctionName`s the same way.
+
+use crate::market::database_functions::DatabaseError;
+
+/// Location and size of the largest peak-to-trough decline in a series
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaxDrawdown {
+    pub percentage: f64,
+    pub value: f64,
+    pub peak_price: f64,
+    pub trough_price: f64,
+    pub peak_index: usize,
+    pub trough_index: usize,
+}
+
+fn ensure_finite(value: f64, name: &str) -> Result<f64, DatabaseError> {
+    if value.is_finite() {
+        Ok(value)
+    } else {
+        Err(DatabaseError::InvalidCalculation(format!(
+            "{} calculation resulted in invalid value",
+            name
+        )))
+    }
+}
+
+/// Simple moving average of the last `period` closes (fewer if the series is shorter)
+pub fn sma(closes: &[f64], period: usize) -> Result<f64, DatabaseError> {
+    if closes.is_empty() || period == 0 {
+        return Err(DatabaseError::InsufficientData(
+            "Need at least 1 data point for SMA".to_string(),
+        ));
+    }
+
+    let window = &closes[closes.len().saturating_sub(period)..];
+    ensure_finite(window.iter().sum::<f64>() / window.len() as f64, "SMA")
+}
+
+/// Exponential moving average seeded with the SMA of the first `period` closes.
+/// Non-positive closes are bad rows and skipped, as the SQL `close > 0` filter did.
+pub fn ema(closes: &[f64], period: usize) -> Result<f64, DatabaseError> {
+    let closes: Vec<f64> = closes
+        .iter()
+        .copied()
+        .filter(|close| *close > 0.0)
+        .collect();
+    if period == 0 || closes.len() < period {
+        return Err(DatabaseError::InsufficientData(format!(
+            "Need at least {} data points for EMA",
+            period
+        )));
+    }
+
+    let initial_sma = closes[..period].iter().sum::<f64>() / period as f64;
+    let smoothing = 2.0;
+    let multiplier = smoothing / (period as f64 + 1.0);
+
+    let ema = closes[period..].iter().fold(initial_sma, |ema, price| {
+        price * multiplier + ema * (1.0 - multiplier)
+    });
+
+    ensure_finite(ema, "EMA")
+}
+
+/// Percentage change from the first to the last close
+pub fn cumulative_return(closes: &[f64]) -> Result<f64, DatabaseError> {
+    let (first, last) = match (closes.first(), closes.last()) {
+        (Some(first), Some(last)) => (*first, *last),
+        _ => {
+            return Err(DatabaseError::InsufficientData(
+                "Need at least 1 data point for cumulative return".to_string(),
+            ))
+        }
+    };
+
+    ensure_finite((last - first) / first * 100.0, "Cumulative return")
+}
+
+/// Day-over-day percentage returns
+pub fn daily_returns(closes: &[f64]) -> Result<Vec<f64>, DatabaseError> {
+    closes
+        .windows(2)
+        .map(|window| {
+            let previous_close = window[0];
+            let current_close = window[1];
+
+            if previous_close == 0.0 {
+                return Err(DatabaseError::InvalidCalculation(
+                    "Invalid price data: zero price encountered".to_string(),
+                ));
+            }
+
+            ensure_finite(
+                (current_close - previous_close) / previous_close * 100.0,
+                "Return",
+            )
+        })
+        .collect()
+}
+
+/// Average of the last `period` daily returns, in percent
+pub fn ma_of_returns(closes: &[f64], period: usize) -> Result<f64, DatabaseError> {
+    if closes.len() < 2 {
+        return Err(DatabaseError::InsufficientData(
+            "Need at least 2 price points to calculate returns".to_string(),
+        ));
+    }
+
+    let returns = daily_returns(closes)?;
+    if let Some(suspicious) = returns.iter().find(|r| r.abs() > 100.0) {
+        return Err(DatabaseError::InsufficientData(format!(
+            "Suspicious return value detected: {}%",
+            suspicious
+        )));
+    }
+
+    if period == 0 || returns.len() < period {
+        return Err(DatabaseError::InsufficientData(format!(
+            "Need at least {} data points for {}-day MA of returns",
+            period, period
+        )));
+    }
+
+    let window = &returns[returns.len() - period..];
+    ensure_finite(window.iter().sum::<f64>() / period as f64, "MA of returns")
+}
+
+/// Relative strength index from simple averages of the first `period` gains and losses
+pub fn rsi(closes: &[f64], period: usize) -> Result<f64, DatabaseError> {
+    if period == 0 || closes.len() < period + 1 {
+        return Err(DatabaseError::InsufficientData(format!(
+            "Found {} data points but need {} for {}-period RSI calculation",
+            closes.len(),
+            period + 1,
+            period
+        )));
+    }
+
+    let (gains, losses): (Vec<f64>, Vec<f64>) = closes
+        .windows(2)
+        .map(|window| {
+            let change = window[1] - window[0];
+            if change > 0.0 {
+                (change, 0.0)
+            } else {
+                (0.0, change.abs())
+            }
+        })
+        .unzip();
+
+    let avg_gain = gains[..period].iter().sum::<f64>() / period as f64;
+    let avg_loss = losses[..period].iter().sum::<f64>() / period as f64;
+
+    match (avg_gain, avg_loss) {
+        (g, l) if l == 0.0 && g == 0.0 => Ok(50.0),
+        (_, 0.0) => Ok(100.0),
+        (0.0, _) => Ok(0.0),
+        (g, l) => {
+            let rsi = 100.0 - (100.0 / (1.0 + g / l));
+            if !rsi.is_finite() || !(0.0..=100.0).contains(&rsi) {
+                Err(DatabaseError::InvalidCalculation(format!(
+                    "RSI calculation resulted in invalid value: {}",
+                    rsi
+                )))
+            } else {
+                Ok(rsi)
+            }
+        }
+    }
+}
+
+/// Sample standard deviation of `values`
+fn sample_std_dev(values: &[f64], name: &str) -> Result<f64, DatabaseError> {
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    let variance = values
+        .iter()
+        .map(|value| {
+            let diff = value - mean;
+            diff * diff
+        })
+        .sum::<f64>()
+        / (values.len() - 1) as f64;
+
+    ensure_finite(variance.sqrt(), name)
+}
+
+/// Sample standard deviation of closes, in price units
+pub fn price_std_dev(closes: &[f64]) -> Result<f64, DatabaseError> {
+    if closes.len() < 2 {
+        return Err(DatabaseError::InsufficientData(
+            "Need at least 2 price points to calculate standard deviation".to_string(),
+        ));
+    }
+
+    sample_std_dev(closes, "Standard deviation")
+}
+
+/// Sample standard deviation of daily returns, in percent
+pub fn returns_std_dev(closes: &[f64]) -> Result<f64, DatabaseError> {
+    if closes.len() < 2 {
+        return Err(DatabaseError::InsufficientData(
+            "Need at least 2 price points to calculate return standard deviation".to_string(),
+        ));
+    }
+
+    sample_std_dev(&daily_returns(closes)?, "Returns standard deviation")
+}
+
+/// Largest decline from a running peak, in percent of that peak
+pub fn max_drawdown(closes: &[f64]) -> Result<MaxDrawdown, DatabaseError> {
+    if closes.len() < 2 {
+        return Err(DatabaseError::InsufficientData(
+            "Need at least 2 data points for drawdown calculation".to_string(),
+        ));
+    }
+
+    let mut result = MaxDrawdown {
+        percentage: 0.0,
+        value: 0.0,
+        peak_price: 0.0,
+        trough_price: 0.0,
+        peak_index: 0,
+        trough_index: 0,
+    };
+    let mut peak_price = f64::NEG_INFINITY;
+    let mut peak_index = 0;
+
+    for (index, &price) in closes.iter().enumerate() {
+        if price > peak_price {
+            peak_price = price;
+            peak_index = index;
+        }
+
+        let drawdown = (peak_price - price) / peak_price * 100.0;
+        if drawdown > result.percentage {
+            result = MaxDrawdown {
+                percentage: drawdown,
+                value: peak_price - price,
+                peak_price,
+                trough_price: price,
+                peak_index,
+                trough_index: index,
+            };
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLOSES: [f64; 6] = [10.0, 11.0, 12.0, 9.0, 10.0, 13.0];
+
+    #[test]
+    fn test_sma_uses_last_period_closes() {
+        assert_eq!(sma(&CLOSES, 3).unwrap(), (9.0 + 10.0 + 13.0) / 3.0);
+        assert_eq!(sma(&CLOSES, 10).unwrap(), 65.0 / 6.0);
+        assert!(sma(&[], 3).is_err());
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn test_ema_reference_value() {
+        // Seed 11 from the first three closes, then alpha 0.5: 10, 10, 11.5
+        assert_close(ema(&CLOSES, 3).unwrap(), 11.5);
+        // A single window degenerates to the SMA
+        assert_close(ema(&CLOSES[..3], 3).unwrap(), 11.0);
+        assert!(matches!(
+            ema(&CLOSES, 7),
+            Err(DatabaseError::InsufficientData(_))
+        ));
+        // The zero close is skipped: seed 11 from 10 and 12, then 13 at alpha 2/3
+        assert_close(ema(&[10.0, 0.0, 12.0, 13.0], 2).unwrap(), 37.0 / 3.0);
+    }
+
+    #[test]
+    fn test_cumulative_return() {
+        assert_close(cumulative_return(&CLOSES).unwrap(), 30.0);
+        assert_close(cumulative_return(&[50.0, 40.0]).unwrap(), -20.0);
+    }
+
+    #[test]
+    fn test_daily_returns_reference_values() {
+        let returns = daily_returns(&CLOSES).unwrap();
+        let expected = [10.0, 100.0 / 11.0, -25.0, 100.0 / 9.0, 30.0];
+        assert_eq!(returns.len(), expected.len());
+        for (actual, expected) in returns.iter().zip(expected) {
+            assert_close(*actual, expected);
+        }
+
+        assert!(matches!(
+            daily_returns(&[0.0, 1.0]),
+            Err(DatabaseError::InvalidCalculation(_))
+        ));
+    }
+
+    #[test]
+    fn test_ma_of_returns_reference_value() {
+        assert_close(
+            ma_of_returns(&CLOSES, 2).unwrap(),
+            (100.0 / 9.0 + 30.0) / 2.0,
+        );
+        assert!(ma_of_returns(&CLOSES, 6).is_err());
+        assert!(matches!(
+            ma_of_returns(&[1.0, 3.0, 3.0], 2),
+            Err(DatabaseError::InsufficientData(_))
+        ));
+    }
+
+    #[test]
+    fn test_rsi_reference_values() {
+        // Changes +1 +1 -3 +1 +3: average gain 1.2, average loss 0.6, RS 2
+        assert_close(rsi(&CLOSES, 5).unwrap(), 100.0 - 100.0 / 3.0);
+        assert_eq!(rsi(&[5.0, 5.0, 5.0], 2).unwrap(), 50.0);
+        assert_eq!(rsi(&[1.0, 2.0, 3.0], 2).unwrap(), 100.0);
+        assert_eq!(rsi(&[3.0, 2.0, 1.0], 2).unwrap(), 0.0);
+        assert!(rsi(&CLOSES, 6).is_err());
+    }
+
+    #[test]
+    fn test_std_dev_reference_values() {
+        // Squared deviations from 5 sum to 32 over 7 degrees of freedom
+        let prices = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+        assert_close(price_std_dev(&prices).unwrap(), (32.0_f64 / 7.0).sqrt());
+
+        // Returns +10% and -10% around a zero mean
+        assert_close(
+            returns_std_dev(&[100.0, 110.0, 99.0]).unwrap(),
+            200.0_f64.sqrt(),
+        );
+
+        assert!(price_std_dev(&[1.0]).is_err());
+        assert!(returns_std_dev(&[1.0]).is_err());
+    }
+
+    #[test]
+    fn test_max_drawdown_tracks_peak_and_trough() {
+        let drawdown = max_drawdown(&CLOSES).unwrap();
+        assert_eq!(drawdown.percentage, 25.0);
+        assert_eq!(drawdown.value, 3.0);
+        assert_eq!(drawdown.peak_price, 12.0);
+        assert_eq!(drawdown.trough_price, 9.0);
+        assert_eq!(drawdown.peak_index, 2);
+        assert_eq!(drawdown.trough_index, 3);
+
+        let rising = max_drawdown(&[1.0, 2.0, 3.0]).unwrap();
+        assert_eq!(rising.percentage, 0.0);
+    }
+}
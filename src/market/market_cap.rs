@@ -0,0 +1,115 @@
+//! Point-in-time market capitalisation.
+//! Market cap is the latest reported share count in effect on a session times that
+//! session's traded close; neither is adjusted, so splits cancel out.
+
+use crate::market::adjustments::PriceAdjustment;
+use crate::market::database_functions::DatabaseError;
+use crate::market::execution_date::ExecutionDate;
+use crate::market::provider::MarketDataProvider;
+use crate::portfolio::blocks::models::{FunctionDefinition, FunctionName};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+/// Share count of a ticker in effect from the bar stamped `time` on
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SharesOutstanding {
+    pub time: NaiveDateTime,
+    pub shares: f64,
+}
+
+/// Records of `records` (sorted by time) in effect between `start` and `end`: the last
+/// one at or before `start` followed by every change up to `end`
+pub fn shares_between(
+    records: &[SharesOutstanding],
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+) -> &[SharesOutstanding] {
+    let first = records
+        .partition_point(|record| record.time <= start)
+        .saturating_sub(1);
+    let last = records.partition_point(|record| record.time <= end);
+    &records[first.min(last)..last]
+}
+
+/// Market cap of `ticker` at the close of `execution_date`
+pub async fn market_cap(
+    market_data: &dyn MarketDataProvider,
+    ticker: &str,
+    execution_date: ExecutionDate,
+) -> Result<f64, DatabaseError> {
+    let shares = market_data
+        .get_shares_outstanding(ticker, execution_date, execution_date)
+        .await?
+        .last()
+        .map(|record| record.shares)
+        .ok_or_else(|| {
+            DatabaseError::InsufficientData(format!(
+                "No shares outstanding for {} as of {}",
+                ticker, execution_date
+            ))
+        })?;
+
+    let close = market_data
+        .evaluate_function(
+            &FunctionDefinition {
+                function_name: FunctionName::CurrentPrice,
+                window_of_days: None,
+                asset: ticker.to_string(),
+                price_adjustment: Some(PriceAdjustment::Raw),
+            },
+            execution_date,
+        )
+        .await?;
+
+    let market_cap = shares * close;
+    if market_cap.is_finite() && market_cap > 0.0 {
+        Ok(market_cap)
+    } else {
+        Err(DatabaseError::InvalidCalculation(format!(
+            "Invalid market cap for {}: {} shares at {}",
+            ticker, shares, close
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market::price_store::{bar_timestamp, PriceSeries, PriceStore};
+    use chrono::NaiveDate;
+
+    fn time(month: u32, day: u32) -> NaiveDateTime {
+        bar_timestamp(NaiveDate::from_ymd_opt(2024, month, day).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_market_cap_uses_shares_in_effect() {
+        let mut series = PriceSeries::new();
+        for (day, close) in [(2, 100.0), (3, 50.0), (4, 52.0)] {
+            series.push(time(1, day), close).unwrap();
+        }
+        // A 2-for-1 split on the 3rd doubles the share count
+        series.set_shares_outstanding(vec![
+            SharesOutstanding {
+                time: time(1, 3),
+                shares: 2_000.0,
+            },
+            SharesOutstanding {
+                time: time(1, 1),
+                shares: 1_000.0,
+            },
+        ]);
+        let mut store = PriceStore::new();
+        store.insert("AAPL", series);
+
+        let on = |day| ExecutionDate::from_bar_timestamp(time(1, day), Default::default());
+        assert_eq!(market_cap(&store, "AAPL", on(2)).await.unwrap(), 100_000.0);
+        assert_eq!(market_cap(&store, "AAPL", on(3)).await.unwrap(), 100_000.0);
+        assert_eq!(market_cap(&store, "AAPL", on(4)).await.unwrap(), 104_000.0);
+
+        let records = store.get("AAPL").unwrap().shares_outstanding();
+        assert_eq!(shares_between(records, time(1, 2), time(1, 4)).len(), 2);
+        let before = time(1, 1) - chrono::Duration::days(1);
+        assert!(shares_between(records, before, before).is_empty());
+    }
+}
@@ -0,0 +1,144 @@
+//! Missing-data handling for price lookups.
+//! Decides what happens when an asset has no bar on the session it is evaluated on, and
+//! records every asset the decision affected so runs can report them.
+
+use crate::market::database_functions::DatabaseError;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/// What to do when an asset has no bar on its session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(tag = "policy", rename_all = "snake_case")]
+pub enum MissingDataPolicy {
+    /// Fail the lookup
+    #[default]
+    Error,
+    /// Use the latest bar if at most `max_days` sessions of the asset's exchange are missing
+    ForwardFill { max_days: u32 },
+    /// Use the latest bar however old it is
+    LastAvailable,
+    /// Leave the asset out of filters and weightings; also applies when the asset does
+    /// not have enough history for a function's window
+    ExcludeAsset,
+}
+
+impl fmt::Display for MissingDataPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MissingDataPolicy::Error => write!(f, "error"),
+            MissingDataPolicy::ForwardFill { max_days } => write!(f, "forward_fill:{}", max_days),
+            MissingDataPolicy::LastAvailable => write!(f, "last_available"),
+            MissingDataPolicy::ExcludeAsset => write!(f, "exclude_asset"),
+        }
+    }
+}
+
+impl FromStr for MissingDataPolicy {
+    type Err = DatabaseError;
+
+    /// Parses `error`, `forward_fill:<days>`, `last_available` or `exclude_asset`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        match s.split_once(':') {
+            Some(("forward_fill", days)) => {
+                let max_days = days.trim().parse().map_err(|_| {
+                    DatabaseError::InvalidInput(format!("Invalid forward fill days: {}", days))
+                })?;
+                Ok(MissingDataPolicy::ForwardFill { max_days })
+            }
+            None if s == "error" => Ok(MissingDataPolicy::Error),
+            None if s == "last_available" => Ok(MissingDataPolicy::LastAvailable),
+            None if s == "exclude_asset" => Ok(MissingDataPolicy::ExcludeAsset),
+            _ => Err(DatabaseError::InvalidInput(format!(
+                "Unsupported missing data policy: {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// How an asset's missing data was handled
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum MissingDataAction {
+    /// Evaluated on the bar of an earlier session
+    Filled { from: NaiveDate },
+    /// Left out of the filter or weighting that evaluated it
+    Excluded,
+    /// History before the asset's first bar was spliced from the proxy's returns
+    Proxied { proxy: String },
+}
+
+/// Asset whose bar was missing on the session it was evaluated on
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MissingDataEvent {
+    pub ticker: String,
+    pub session: NaiveDate,
+    #[serde(flatten)]
+    pub action: MissingDataAction,
+}
+
+/// Missing-data events of a run, grouped by strategy execution date
+#[derive(Debug, Default)]
+pub struct MissingDataLog {
+    events: Mutex<BTreeMap<NaiveDate, BTreeMap<String, MissingDataEvent>>>,
+}
+
+impl MissingDataLog {
+    /// Records `event` for the execution on `execution_date`, once per ticker
+    pub fn record(&self, execution_date: NaiveDate, event: MissingDataEvent) {
+        tracing::warn!(
+            ticker = %event.ticker,
+            session = %event.session,
+            action = ?event.action,
+            "Missing data"
+        );
+        self.events
+            .lock()
+            .unwrap()
+            .entry(execution_date)
+            .or_default()
+            .entry(event.ticker.clone())
+            .or_insert(event);
+    }
+
+    /// Removes and returns the events of the execution on `execution_date`, by ticker
+    pub fn take(&self, execution_date: NaiveDate) -> Vec<MissingDataEvent> {
+        self.events
+            .lock()
+            .unwrap()
+            .remove(&execution_date)
+            .map(|events| events.into_values().collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_policies() {
+        for policy in [
+            MissingDataPolicy::Error,
+            MissingDataPolicy::ForwardFill { max_days: 3 },
+            MissingDataPolicy::LastAvailable,
+            MissingDataPolicy::ExcludeAsset,
+        ] {
+            assert_eq!(
+                policy.to_string().parse::<MissingDataPolicy>().unwrap(),
+                policy
+            );
+        }
+        assert!("forward_fill:soon".parse::<MissingDataPolicy>().is_err());
+        assert!("skip".parse::<MissingDataPolicy>().is_err());
+
+        let policy: MissingDataPolicy =
+            serde_json::from_str(r#"{"policy": "forward_fill", "max_days": 2}"#).unwrap();
+        assert_eq!(policy, MissingDataPolicy::ForwardFill { max_days: 2 });
+    }
+}
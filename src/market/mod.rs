@@ -1 +1,21 @@
+pub mod adjustments;
+pub mod audit;
+pub mod calendar;
+pub mod csv_prices;
 pub mod database_functions;
+pub mod derived;
+pub mod exchange;
+pub mod execution_date;
+pub mod holidays;
+pub mod indicators;
+pub mod market_cap;
+pub mod missing_data;
+pub mod preloaded;
+pub mod price_store;
+pub mod provider;
+pub mod proxies;
+pub mod resilience;
+pub mod security_master;
+pub mod sessions;
+pub mod symbols;
+pub mod universe;
@@ -0,0 +1,570 @@
+//! Backtest-wide price cache.
+//! Loads each ticker's close history once for a whole execution span and evaluates
+//! indicators from memory, so a backtest issues one query per ticker instead of one
+//! query per (function, ticker, execution date).
+
+use crate::market::adjustments::CorporateAction;
+use crate::market::database_functions::{Bar, DatabaseError, PricePoint};
+use crate::market::exchange::Exchange;
+use crate::market::execution_date::ExecutionDate;
+use crate::market::market_cap::SharesOutstanding;
+use crate::market::price_store::{PriceSeries, PriceStore};
+use crate::market::provider::{BoxFuture, FunctionValues, MarketDataProvider};
+use crate::market::security_master::SecurityMaster;
+use crate::market::universe::MembershipChange;
+use crate::portfolio::blocks::models::FunctionDefinition;
+use chrono::NaiveDate;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio::task::JoinSet;
+use tracing::{debug, info, warn};
+
+/// Longest lookback `get_price_series` accepts ("Trading days" limit)
+const MAX_LOOKBACK_ROWS: usize = 500;
+
+/// A ticker to preload and what to load for it
+#[derive(Debug, Clone, PartialEq)]
+pub struct TickerPreload {
+    pub ticker: String,
+    /// Bars needed at every execution date
+    pub rows: usize,
+    /// Exchange whose sessions the bars are stamped on
+    pub exchange: Exchange,
+    /// Whether splits and dividends are loaded for adjusted evaluations
+    pub corporate_actions: bool,
+    /// Whether share counts are loaded for market-cap weighting
+    pub shares_outstanding: bool,
+    /// Whether full OHLCV bars are loaded instead of closes
+    pub bars: bool,
+}
+
+/// Finds the oldest bar needed so that `rows` bars are available at `first_execution`,
+/// then loads everything from there up to `last_execution`, on `exchange`'s sessions
+async fn load_ticker(
+    source: Arc<dyn MarketDataProvider>,
+    preload: TickerPreload,
+    first_execution: ExecutionDate,
+    last_execution: ExecutionDate,
+) -> Result<(String, PriceSeries), DatabaseError> {
+    let TickerPreload {
+        ticker,
+        rows,
+        exchange,
+        corporate_actions,
+        shares_outstanding,
+        bars,
+    } = preload;
+    let rows = rows.clamp(1, MAX_LOOKBACK_ROWS) as i64;
+    let first_execution = ExecutionDate::at_close(first_execution.date(), exchange);
+    let last_execution = ExecutionDate::at_close(last_execution.date(), exchange);
+
+    let start = match source
+        .get_price_series(&ticker, first_execution, rows)
+        .await
+    {
+        Ok(points) => points
+            .first()
+            .map(|point| ExecutionDate::from_bar_timestamp(point.time, exchange))
+            .unwrap_or(first_execution),
+        // Ticker has no bars before the span yet, its history starts inside it
+        Err(DatabaseError::InsufficientData(_)) => first_execution,
+        Err(e) => return Err(e),
+    };
+
+    let mut series: PriceSeries = if bars {
+        source
+            .get_bar_history(&ticker, start, last_execution)
+            .await?
+            .into_iter()
+            .collect()
+    } else {
+        source
+            .get_price_history(&ticker, start, last_execution)
+            .await?
+            .into_iter()
+            .collect()
+    };
+
+    if corporate_actions {
+        let actions: Vec<CorporateAction> = source
+            .get_corporate_actions(&ticker, start, last_execution)
+            .await?;
+        series.set_corporate_actions(actions);
+    }
+
+    if shares_outstanding {
+        let shares: Vec<SharesOutstanding> = source
+            .get_shares_outstanding(&ticker, start, last_execution)
+            .await?;
+        series.set_shares_outstanding(shares);
+    }
+
+    Ok((ticker, series))
+}
+
+/// `MarketDataProvider` that answers indicator evaluations from series preloaded out of
+/// another provider and delegates everything else to it
+pub struct PreloadedProvider {
+    store: PriceStore,
+    /// Bars loaded per ticker before the first execution date
+    lookbacks: HashMap<String, usize>,
+    /// Tickers whose share counts were preloaded
+    shares_loaded: HashSet<String>,
+    /// Membership changes of the strategy's universes up to the last execution date
+    memberships: HashMap<String, Vec<MembershipChange>>,
+    security_master: Option<Arc<SecurityMaster>>,
+    source: Arc<dyn MarketDataProvider>,
+    first_execution: ExecutionDate,
+    last_execution: ExecutionDate,
+}
+
+impl PreloadedProvider {
+    /// Preloads every entry for execution dates between `first_date` and `last_date`.
+    /// Tickers that fail to load are left to `source`.
+    pub async fn load(
+        source: Arc<dyn MarketDataProvider>,
+        preloads: impl IntoIterator<Item = TickerPreload>,
+        first_date: NaiveDate,
+        last_date: NaiveDate,
+    ) -> Result<Self, DatabaseError> {
+        let first_execution = source.get_last_market_day(first_date).await?;
+        let last_execution = source.get_last_market_day(last_date).await?;
+
+        let mut join_set = JoinSet::new();
+        let mut lookbacks = HashMap::new();
+        let mut shares_loaded = HashSet::new();
+        for preload in preloads {
+            lookbacks.insert(preload.ticker.clone(), preload.rows.min(MAX_LOOKBACK_ROWS));
+            if preload.shares_outstanding {
+                shares_loaded.insert(preload.ticker.clone());
+            }
+            join_set.spawn(load_ticker(
+                Arc::clone(&source),
+                preload,
+                first_execution,
+                last_execution,
+            ));
+        }
+
+        let mut store = PriceStore::new();
+        while let Some(result) = join_set.join_next().await {
+            match result {
+                Ok(Ok((ticker, series))) => {
+                    debug!(
+                        %ticker,
+                        points = series.len(),
+                        actions = series.corporate_actions().len(),
+                        "Preloaded price history"
+                    );
+                    store.insert(&ticker, series);
+                }
+                Ok(Err(e)) => warn!("Failed to preload price history: {}", e),
+                Err(e) => warn!("Preload task failed: {}", e),
+            }
+        }
+
+        info!(
+            "Preloaded {} tickers from {} to {}",
+            store.tickers().count(),
+            first_execution,
+            last_execution
+        );
+
+        lookbacks.retain(|ticker, _| store.get(ticker).is_some());
+        shares_loaded.retain(|ticker| store.get(ticker).is_some());
+        Ok(Self {
+            store,
+            lookbacks,
+            shares_loaded,
+            memberships: HashMap::new(),
+            security_master: None,
+            first_execution,
+            last_execution,
+            source,
+        })
+    }
+
+    /// Answers membership lookups of `universe` up to the last execution date from
+    /// `changes`, every change of the universe dated on or before it
+    pub fn with_index_membership(mut self, universe: &str, changes: Vec<MembershipChange>) -> Self {
+        self.memberships.insert(universe.to_string(), changes);
+        self
+    }
+
+    /// Answers security master lookups from `security_master`
+    pub fn with_security_master(mut self, security_master: Arc<SecurityMaster>) -> Self {
+        self.security_master = Some(security_master);
+        self
+    }
+
+    pub fn store(&self) -> &PriceStore {
+        &self.store
+    }
+
+    /// Whether lookups for `ticker` at `execution_date` can be answered from the preloaded series
+    fn covers(&self, ticker: &str, execution_date: ExecutionDate) -> bool {
+        self.store.get(ticker).is_some()
+            && (self.first_execution.date()..=self.last_execution.date())
+                .contains(&execution_date.date())
+    }
+}
+
+impl MarketDataProvider for PreloadedProvider {
+    fn get_price_series<'a>(
+        &'a self,
+        ticker: &'a str,
+        execution_date: ExecutionDate,
+        trading_days: i64,
+    ) -> BoxFuture<'a, Result<Vec<PricePoint>, DatabaseError>> {
+        if !self.covers(ticker, execution_date) {
+            return self
+                .source
+                .get_price_series(ticker, execution_date, trading_days);
+        }
+
+        Box::pin(async move {
+            // The preloaded history is contiguous, so a full window from it is exact. So is
+            // a shorter one within the planned lookback: the ticker has no older bars.
+            let points = self
+                .store
+                .get_price_series(ticker, execution_date, trading_days)
+                .await?;
+            let planned = self.lookbacks.get(ticker).copied().unwrap_or_default() as i64;
+            if points.len() as i64 == trading_days || trading_days <= planned {
+                Ok(points)
+            } else {
+                self.source
+                    .get_price_series(ticker, execution_date, trading_days)
+                    .await
+            }
+        })
+    }
+
+    fn get_price_history<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: ExecutionDate,
+        end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<PricePoint>, DatabaseError>> {
+        self.source.get_price_history(ticker, start_date, end_date)
+    }
+
+    fn get_bar_series<'a>(
+        &'a self,
+        ticker: &'a str,
+        execution_date: ExecutionDate,
+        trading_days: i64,
+    ) -> BoxFuture<'a, Result<Vec<Bar>, DatabaseError>> {
+        let preloaded_bars = self
+            .store
+            .get(ticker)
+            .is_some_and(|series| series.has_bars());
+        if !preloaded_bars || !self.covers(ticker, execution_date) {
+            return self
+                .source
+                .get_bar_series(ticker, execution_date, trading_days);
+        }
+
+        Box::pin(async move {
+            // Same as closes: full windows and windows within the plan are exact
+            let bars = self
+                .store
+                .get_bar_series(ticker, execution_date, trading_days)
+                .await?;
+            let planned = self.lookbacks.get(ticker).copied().unwrap_or_default() as i64;
+            if bars.len() as i64 == trading_days || trading_days <= planned {
+                Ok(bars)
+            } else {
+                self.source
+                    .get_bar_series(ticker, execution_date, trading_days)
+                    .await
+            }
+        })
+    }
+
+    fn get_bar_history<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: ExecutionDate,
+        end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<Bar>, DatabaseError>> {
+        self.source.get_bar_history(ticker, start_date, end_date)
+    }
+
+    fn get_corporate_actions<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: ExecutionDate,
+        end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<CorporateAction>, DatabaseError>> {
+        self.source
+            .get_corporate_actions(ticker, start_date, end_date)
+    }
+
+    fn get_shares_outstanding<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: ExecutionDate,
+        end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<SharesOutstanding>, DatabaseError>> {
+        if self.shares_loaded.contains(ticker)
+            && self.covers(ticker, start_date)
+            && self.covers(ticker, end_date)
+        {
+            self.store
+                .get_shares_outstanding(ticker, start_date, end_date)
+        } else {
+            self.source
+                .get_shares_outstanding(ticker, start_date, end_date)
+        }
+    }
+
+    fn get_index_membership<'a>(
+        &'a self,
+        universe: &'a str,
+        end_date: NaiveDate,
+    ) -> BoxFuture<'a, Result<Vec<MembershipChange>, DatabaseError>> {
+        match self.memberships.get(universe) {
+            Some(changes) if end_date <= self.last_execution.date() => Box::pin(async move {
+                Ok(changes
+                    .iter()
+                    .take_while(|change| change.date <= end_date)
+                    .cloned()
+                    .collect())
+            }),
+            _ => self.source.get_index_membership(universe, end_date),
+        }
+    }
+
+    fn get_security_master(&self) -> BoxFuture<'_, Result<Arc<SecurityMaster>, DatabaseError>> {
+        match &self.security_master {
+            Some(security_master) => {
+                let security_master = Arc::clone(security_master);
+                Box::pin(async move { Ok(security_master) })
+            }
+            None => self.source.get_security_master(),
+        }
+    }
+
+    fn get_last_market_day(
+        &self,
+        date: NaiveDate,
+    ) -> BoxFuture<'_, Result<ExecutionDate, DatabaseError>> {
+        self.source.get_last_market_day(date)
+    }
+
+    fn evaluate_function<'a>(
+        &'a self,
+        function: &'a FunctionDefinition,
+        execution_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<f64, DatabaseError>> {
+        if self.covers(&function.asset, execution_date) {
+            Box::pin(async move { self.store.evaluate(function, execution_date) })
+        } else {
+            self.source.evaluate_function(function, execution_date)
+        }
+    }
+
+    /// Evaluates covered tickers from memory and batches the rest to the source
+    fn evaluate_function_batch<'a>(
+        &'a self,
+        function: &'a FunctionDefinition,
+        tickers: &'a [String],
+        execution_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<FunctionValues, DatabaseError>> {
+        Box::pin(async move {
+            let (covered, uncovered): (Vec<String>, Vec<String>) = tickers
+                .iter()
+                .cloned()
+                .partition(|ticker| self.covers(ticker, execution_date));
+            let mut values = if uncovered.is_empty() {
+                FunctionValues::with_capacity(covered.len())
+            } else {
+                self.source
+                    .evaluate_function_batch(function, &uncovered, execution_date)
+                    .await?
+            };
+            for ticker in covered {
+                let value = self
+                    .store
+                    .evaluate(&function.for_asset(&ticker), execution_date);
+                values.insert(ticker, value);
+            }
+            Ok(values)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market::exchange::Exchange;
+    use crate::market::price_store::{bar_timestamp, PriceSeries};
+    use crate::portfolio::blocks::models::FunctionName;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Wraps a store and counts the calls the preloaded provider makes into it
+    struct CountingProvider {
+        inner: PriceStore,
+        history_calls: AtomicUsize,
+        evaluations: AtomicUsize,
+    }
+
+    impl MarketDataProvider for CountingProvider {
+        fn get_price_series<'a>(
+            &'a self,
+            ticker: &'a str,
+            execution_date: ExecutionDate,
+            trading_days: i64,
+        ) -> BoxFuture<'a, Result<Vec<PricePoint>, DatabaseError>> {
+            self.inner
+                .get_price_series(ticker, execution_date, trading_days)
+        }
+
+        fn get_price_history<'a>(
+            &'a self,
+            ticker: &'a str,
+            start_date: ExecutionDate,
+            end_date: ExecutionDate,
+        ) -> BoxFuture<'a, Result<Vec<PricePoint>, DatabaseError>> {
+            self.history_calls.fetch_add(1, Ordering::SeqCst);
+            self.inner.get_price_history(ticker, start_date, end_date)
+        }
+
+        fn get_last_market_day(
+            &self,
+            date: NaiveDate,
+        ) -> BoxFuture<'_, Result<ExecutionDate, DatabaseError>> {
+            self.inner.get_last_market_day(date)
+        }
+
+        fn evaluate_function<'a>(
+            &'a self,
+            function: &'a FunctionDefinition,
+            execution_date: ExecutionDate,
+        ) -> BoxFuture<'a, Result<f64, DatabaseError>> {
+            self.evaluations.fetch_add(1, Ordering::SeqCst);
+            self.inner.evaluate_function(function, execution_date)
+        }
+    }
+
+    fn source() -> Arc<CountingProvider> {
+        let mut inner = PriceStore::new();
+        for (ticker, offset) in [("AAPL", 0.0), ("MSFT", 50.0)] {
+            let mut series = PriceSeries::new();
+            let start = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+            for (day, date) in start.iter_days().take(120).enumerate() {
+                let close = offset + 100.0 + (day as f64 * 0.7).sin() * 5.0;
+                series.push(bar_timestamp(date), close).unwrap();
+            }
+            inner.insert(ticker, series);
+        }
+
+        Arc::new(CountingProvider {
+            inner,
+            history_calls: AtomicUsize::new(0),
+            evaluations: AtomicUsize::new(0),
+        })
+    }
+
+    fn preload(ticker: &str, rows: usize) -> TickerPreload {
+        TickerPreload {
+            ticker: ticker.to_string(),
+            rows,
+            exchange: Exchange::Nasdaq,
+            corporate_actions: false,
+            shares_outstanding: false,
+            bars: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_preloaded_evaluations_do_not_hit_the_source() {
+        let source = source();
+        let preloaded = PreloadedProvider::load(
+            source.clone(),
+            [preload("AAPL", 21), preload("MSFT", 21)],
+            NaiveDate::from_ymd_opt(2024, 2, 1).unwrap(),
+            NaiveDate::from_ymd_opt(2024, 4, 1).unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(source.history_calls.load(Ordering::SeqCst), 2);
+
+        for date in [
+            NaiveDate::from_ymd_opt(2024, 2, 1).unwrap(),
+            NaiveDate::from_ymd_opt(2024, 3, 1).unwrap(),
+            NaiveDate::from_ymd_opt(2024, 4, 1).unwrap(),
+        ] {
+            let execution_date = ExecutionDate::at_close(date, Exchange::Nasdaq);
+            for ticker in ["AAPL", "MSFT"] {
+                for function_name in [
+                    FunctionName::RelativeStrengthIndex,
+                    FunctionName::MovingAverageOfReturns,
+                    FunctionName::SimpleMovingAverage,
+                ] {
+                    let function = FunctionDefinition {
+                        function_name,
+                        window_of_days: Some(20),
+                        asset: ticker.to_string(),
+                        price_adjustment: None,
+                    };
+                    let expected = source.inner.evaluate(&function, execution_date).unwrap();
+                    let actual = preloaded
+                        .evaluate_function(&function, execution_date)
+                        .await
+                        .unwrap();
+                    assert_eq!(actual, expected);
+                }
+            }
+        }
+
+        assert_eq!(source.evaluations.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_unplanned_ticker_falls_back_to_source() {
+        let source = source();
+        let preloaded = PreloadedProvider::load(
+            source.clone(),
+            [preload("AAPL", 20)],
+            NaiveDate::from_ymd_opt(2024, 2, 1).unwrap(),
+            NaiveDate::from_ymd_opt(2024, 3, 1).unwrap(),
+        )
+        .await
+        .unwrap();
+
+        let function = FunctionDefinition {
+            function_name: FunctionName::CurrentPrice,
+            window_of_days: None,
+            asset: "MSFT".to_string(),
+            price_adjustment: None,
+        };
+        let execution_date = "2024-02-15T16:00:00.000000Z".parse().unwrap();
+        preloaded
+            .evaluate_function(&function, execution_date)
+            .await
+            .unwrap();
+        assert_eq!(source.evaluations.load(Ordering::SeqCst), 1);
+
+        // A batch answers the preloaded ticker from memory and sends the others on
+        let tickers = ["AAPL", "MSFT", "NVDA"].map(String::from);
+        let mut values = preloaded
+            .evaluate_function_batch(&function, &tickers, execution_date)
+            .await
+            .unwrap();
+        assert_eq!(source.evaluations.load(Ordering::SeqCst), 3);
+        assert_eq!(
+            values.remove("AAPL").unwrap().unwrap(),
+            source
+                .inner
+                .evaluate(&function.for_asset("AAPL"), execution_date)
+                .unwrap()
+        );
+        assert!(values.remove("MSFT").unwrap().is_ok());
+        assert!(matches!(
+            values.remove("NVDA").unwrap(),
+            Err(DatabaseError::InsufficientData(_))
+        ));
+    }
+}
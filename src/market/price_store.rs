@@ -0,0 +1,714 @@
+//! In-memory daily price store.
+//! Holds one columnar close series per ticker, optionally with full OHLCV bars, and
+//! answers every `FunctionName` without a database, which makes it usable for offline
+//! backtests and tests. Splits and dividends stored alongside a series are used to
+//! evaluate functions on adjusted prices, share counts to compute market caps. Index
+//! membership is kept per universe.
+
+use crate::market::adjustments::{adjust_closes, CorporateAction, PriceAdjustment};
+use crate::market::calendar::TradingCalendar;
+use crate::market::database_functions::{
+    validate_period, validate_ticker, Bar, DatabaseError, PricePoint,
+};
+use crate::market::exchange::Exchange;
+use crate::market::execution_date::ExecutionDate;
+use crate::market::indicators;
+use crate::market::market_cap::{shares_between, SharesOutstanding};
+use crate::market::provider::{BoxFuture, MarketDataProvider};
+use crate::market::security_master::SecurityMaster;
+use crate::market::universe::MembershipChange;
+use crate::portfolio::blocks::models::{FunctionDefinition, FunctionName};
+use chrono::{NaiveDate, NaiveDateTime};
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Timestamp of the daily bar for `date` on any exchange, matching how
+/// `stock_data_daily` stamps bars
+pub fn bar_timestamp(date: NaiveDate) -> NaiveDateTime {
+    Exchange::Nasdaq.bar_timestamp(date)
+}
+
+/// Open, high, low and volume columns of a series that holds full bars
+#[derive(Debug, Clone, Default)]
+struct BarColumns {
+    opens: Vec<f64>,
+    highs: Vec<f64>,
+    lows: Vec<f64>,
+    volumes: Vec<i64>,
+}
+
+/// Prices for a single ticker, stored as parallel columns ordered by time. A series
+/// holds either closes only or full OHLCV bars.
+#[derive(Debug, Clone, Default)]
+pub struct PriceSeries {
+    times: Vec<NaiveDateTime>,
+    closes: Vec<f64>,
+    bars: Option<BarColumns>,
+    actions: Vec<CorporateAction>,
+    shares: Vec<SharesOutstanding>,
+}
+
+impl PriceSeries {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn check_order(&self, time: NaiveDateTime) -> Result<(), DatabaseError> {
+        match self.times.last() {
+            Some(last) if time <= *last => Err(DatabaseError::InvalidInput(format!(
+                "Bars must be in increasing time order ({} after {})",
+                time, last
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Appends a close; closes must be pushed in strictly increasing time order
+    pub fn push(&mut self, time: NaiveDateTime, close: f64) -> Result<(), DatabaseError> {
+        if self.bars.is_some() {
+            return Err(DatabaseError::InvalidInput(
+                "Series holds full bars; push a Bar instead".to_string(),
+            ));
+        }
+        self.check_order(time)?;
+        self.times.push(time);
+        self.closes.push(close);
+        Ok(())
+    }
+
+    /// Appends a full bar; bars must be pushed in strictly increasing time order
+    pub fn push_bar(&mut self, bar: Bar) -> Result<(), DatabaseError> {
+        if self.bars.is_none() && !self.is_empty() {
+            return Err(DatabaseError::InvalidInput(
+                "Series holds closes only; push a close instead".to_string(),
+            ));
+        }
+        self.check_order(bar.time)?;
+        self.times.push(bar.time);
+        self.closes.push(bar.close);
+
+        let columns = self.bars.get_or_insert_with(BarColumns::default);
+        columns.opens.push(bar.open);
+        columns.highs.push(bar.high);
+        columns.lows.push(bar.low);
+        columns.volumes.push(bar.volume);
+        Ok(())
+    }
+
+    /// Whether the series holds open, high, low and volume besides the close
+    pub fn has_bars(&self) -> bool {
+        self.bars.is_some()
+    }
+
+    /// Full bar at `index`, if the series holds full bars
+    pub fn bar(&self, index: usize) -> Option<Bar> {
+        let columns = self.bars.as_ref()?;
+        Some(Bar {
+            time: *self.times.get(index)?,
+            open: columns.opens[index],
+            high: columns.highs[index],
+            low: columns.lows[index],
+            close: self.closes[index],
+            volume: columns.volumes[index],
+        })
+    }
+
+    /// Full bars in `range`, if the series holds full bars
+    pub fn bars(&self, range: Range<usize>) -> Option<Vec<Bar>> {
+        self.bars.as_ref()?;
+        range.map(|index| self.bar(index)).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.times.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.times.is_empty()
+    }
+
+    pub fn times(&self) -> &[NaiveDateTime] {
+        &self.times
+    }
+
+    pub fn closes(&self) -> &[f64] {
+        &self.closes
+    }
+
+    /// Index range of the last `rows` bars at or before `as_of`
+    pub fn window_ending_at(&self, as_of: NaiveDateTime, rows: usize) -> Range<usize> {
+        let end = self.times.partition_point(|time| *time <= as_of);
+        end.saturating_sub(rows)..end
+    }
+
+    /// Index range of the bars between `start` and `end` inclusive
+    pub fn range_between(&self, start: NaiveDateTime, end: NaiveDateTime) -> Range<usize> {
+        self.times.partition_point(|time| *time < start)
+            ..self.times.partition_point(|time| *time <= end)
+    }
+
+    /// Replaces the splits and dividends used for adjusted closes
+    pub fn set_corporate_actions(&mut self, mut actions: Vec<CorporateAction>) {
+        actions.sort_by_key(|action| action.time);
+        self.actions = actions;
+    }
+
+    pub fn corporate_actions(&self) -> &[CorporateAction] {
+        &self.actions
+    }
+
+    /// Stores the share counts reported for the ticker, in any order
+    pub fn set_shares_outstanding(&mut self, mut shares: Vec<SharesOutstanding>) {
+        shares.sort_by_key(|record| record.time);
+        self.shares = shares;
+    }
+
+    pub fn shares_outstanding(&self) -> &[SharesOutstanding] {
+        &self.shares
+    }
+
+    /// Closes in `range`, adjusted relative to the last bar of the range
+    pub fn adjusted_closes(
+        &self,
+        range: Range<usize>,
+        adjustment: PriceAdjustment,
+    ) -> Cow<'_, [f64]> {
+        adjust_closes(&self.times, &self.closes, &self.actions, range, adjustment)
+    }
+
+    /// Close of the bar stamped exactly at `time`
+    pub fn close_at(&self, time: NaiveDateTime) -> Option<f64> {
+        self.times
+            .binary_search(&time)
+            .ok()
+            .map(|index| self.closes[index])
+    }
+}
+
+impl FromIterator<PricePoint> for PriceSeries {
+    fn from_iter<I: IntoIterator<Item = PricePoint>>(iter: I) -> Self {
+        let mut points: Vec<PricePoint> = iter.into_iter().collect();
+        points.sort_by_key(|point| point.time);
+        points.dedup_by_key(|point| point.time);
+
+        Self {
+            times: points.iter().map(|point| point.time).collect(),
+            closes: points.iter().map(|point| point.close).collect(),
+            bars: None,
+            actions: Vec::new(),
+            shares: Vec::new(),
+        }
+    }
+}
+
+impl FromIterator<Bar> for PriceSeries {
+    fn from_iter<I: IntoIterator<Item = Bar>>(iter: I) -> Self {
+        let mut bars: Vec<Bar> = iter.into_iter().collect();
+        bars.sort_by_key(|bar| bar.time);
+        bars.dedup_by_key(|bar| bar.time);
+
+        Self {
+            times: bars.iter().map(|bar| bar.time).collect(),
+            closes: bars.iter().map(|bar| bar.close).collect(),
+            bars: Some(BarColumns {
+                opens: bars.iter().map(|bar| bar.open).collect(),
+                highs: bars.iter().map(|bar| bar.high).collect(),
+                lows: bars.iter().map(|bar| bar.low).collect(),
+                volumes: bars.iter().map(|bar| bar.volume).collect(),
+            }),
+            actions: Vec::new(),
+            shares: Vec::new(),
+        }
+    }
+}
+
+/// Number of bars, ending at the execution date, that a function reads
+pub fn lookback_rows(function_name: &FunctionName, period: usize) -> usize {
+    match function_name {
+        FunctionName::CurrentPrice => 1,
+        FunctionName::MovingAverageOfReturns | FunctionName::RelativeStrengthIndex => period + 1,
+        _ => period,
+    }
+}
+
+/// Window used when a function definition does not specify one
+pub fn default_window(function_name: &FunctionName) -> u32 {
+    match function_name {
+        FunctionName::RelativeStrengthIndex => 14,
+        _ => 20,
+    }
+}
+
+fn period_context(function_name: &FunctionName) -> &'static str {
+    match function_name {
+        FunctionName::CurrentPrice => "Trading days",
+        FunctionName::CumulativeReturn => "Return period",
+        FunctionName::SimpleMovingAverage => "SMA period",
+        FunctionName::ExponentialMovingAverage => "EMA period",
+        FunctionName::MovingAverageOfReturns => "Moving average period",
+        FunctionName::RelativeStrengthIndex => "RSI period",
+        FunctionName::PriceStandardDeviation => "Moving average period",
+        FunctionName::ReturnsStandardDeviation => "Return std dev period",
+        FunctionName::MaxDrawdown => "Drawdown period",
+    }
+}
+
+fn no_bars(ticker: &str) -> DatabaseError {
+    DatabaseError::InsufficientData(format!("No OHLCV bars loaded for {}", ticker))
+}
+
+/// `MarketDataProvider` that serves prices and indicators from memory
+#[derive(Debug, Clone, Default)]
+pub struct PriceStore {
+    series: HashMap<String, PriceSeries>,
+    trading_days: BTreeSet<NaiveDate>,
+    calendar: Option<Arc<TradingCalendar>>,
+    /// Membership changes by universe, ordered by date
+    memberships: HashMap<String, Vec<MembershipChange>>,
+    security_master: Option<Arc<SecurityMaster>>,
+}
+
+impl PriceStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answers market day lookups from `calendar` instead of the loaded bars
+    pub fn with_calendar(mut self, calendar: Arc<TradingCalendar>) -> Self {
+        self.calendar = Some(calendar);
+        self
+    }
+
+    /// Answers security master lookups from `security_master`
+    pub fn with_security_master(mut self, security_master: Arc<SecurityMaster>) -> Self {
+        self.security_master = Some(security_master);
+        self
+    }
+
+    /// Adds or replaces the series for `ticker`
+    pub fn insert(&mut self, ticker: &str, series: PriceSeries) {
+        self.trading_days
+            .extend(series.times().iter().map(|time| time.date()));
+        self.series.insert(ticker.to_string(), series);
+    }
+
+    pub fn get(&self, ticker: &str) -> Option<&PriceSeries> {
+        self.series.get(ticker)
+    }
+
+    /// Adds or replaces the membership changes of `universe`, in any order
+    pub fn set_index_membership(&mut self, universe: &str, mut changes: Vec<MembershipChange>) {
+        changes.sort_by_key(|change| change.date);
+        self.memberships.insert(universe.to_string(), changes);
+    }
+
+    pub fn tickers(&self) -> impl Iterator<Item = &str> {
+        self.series.keys().map(String::as_str)
+    }
+
+    fn series_for(&self, ticker: &str) -> Result<&PriceSeries, DatabaseError> {
+        validate_ticker(ticker)?;
+        self.series.get(ticker).ok_or_else(|| {
+            DatabaseError::InsufficientData(format!("No price data loaded for {}", ticker))
+        })
+    }
+
+    /// Closes of the last `rows` bars of `ticker` at or before `as_of`, adjusted as of `as_of`
+    fn closes_ending_at(
+        &self,
+        ticker: &str,
+        as_of: NaiveDateTime,
+        rows: usize,
+        adjustment: PriceAdjustment,
+    ) -> Result<Cow<'_, [f64]>, DatabaseError> {
+        let series = self.series_for(ticker)?;
+        let range = series.window_ending_at(as_of, rows);
+        if range.is_empty() {
+            return Err(DatabaseError::InsufficientData(format!(
+                "No data found for ticker {} before {}",
+                ticker, as_of
+            )));
+        }
+        Ok(series.adjusted_closes(range, adjustment))
+    }
+
+    /// Evaluates `function` synchronously against the stored series
+    pub fn evaluate(
+        &self,
+        function: &FunctionDefinition,
+        execution_date: ExecutionDate,
+    ) -> Result<f64, DatabaseError> {
+        let ticker = function.asset.as_str();
+        let as_of = execution_date.bar_timestamp();
+
+        if function.function_name == FunctionName::CurrentPrice {
+            return self.series_for(ticker)?.close_at(as_of).ok_or_else(|| {
+                DatabaseError::InsufficientData(format!(
+                    "No price data found for {} at {}",
+                    ticker, execution_date
+                ))
+            });
+        }
+
+        let window = function
+            .window_of_days
+            .unwrap_or_else(|| default_window(&function.function_name));
+        validate_period(window as i64, period_context(&function.function_name))?;
+        let period = window as usize;
+
+        let adjustment = function.price_adjustment.unwrap_or_default();
+        let closes = self.closes_ending_at(
+            ticker,
+            as_of,
+            lookback_rows(&function.function_name, period),
+            adjustment,
+        )?;
+        let closes = closes.as_ref();
+
+        let value = match function.function_name {
+            FunctionName::CurrentPrice => unreachable!("handled above"),
+            FunctionName::CumulativeReturn => indicators::cumulative_return(closes),
+            FunctionName::SimpleMovingAverage => indicators::sma(closes, period),
+            FunctionName::ExponentialMovingAverage => indicators::ema(closes, period),
+            FunctionName::MovingAverageOfReturns => indicators::ma_of_returns(closes, period),
+            FunctionName::RelativeStrengthIndex => indicators::rsi(closes, period),
+            FunctionName::PriceStandardDeviation => indicators::price_std_dev(closes),
+            FunctionName::ReturnsStandardDeviation => indicators::returns_std_dev(closes),
+            FunctionName::MaxDrawdown => {
+                indicators::max_drawdown(closes).map(|drawdown| drawdown.percentage)
+            }
+        }?;
+
+        tracing::debug!(
+            %ticker,
+            %execution_date,
+            function = %function.function_name,
+            %adjustment,
+            %value,
+            "Evaluated function from price store"
+        );
+
+        Ok(value)
+    }
+}
+
+impl MarketDataProvider for PriceStore {
+    fn get_price_series<'a>(
+        &'a self,
+        ticker: &'a str,
+        execution_date: ExecutionDate,
+        trading_days: i64,
+    ) -> BoxFuture<'a, Result<Vec<PricePoint>, DatabaseError>> {
+        Box::pin(async move {
+            validate_period(trading_days, "Trading days")?;
+            let as_of = execution_date.bar_timestamp();
+            let series = self.series_for(ticker)?;
+            let range = series.window_ending_at(as_of, trading_days as usize);
+
+            Ok(series.times()[range.clone()]
+                .iter()
+                .zip(&series.closes()[range])
+                .map(|(time, close)| PricePoint {
+                    time: *time,
+                    close: *close,
+                })
+                .collect())
+        })
+    }
+
+    fn get_price_history<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: ExecutionDate,
+        end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<PricePoint>, DatabaseError>> {
+        Box::pin(async move {
+            let start = start_date.bar_timestamp();
+            let end = end_date.bar_timestamp();
+            let series = self.series_for(ticker)?;
+            let range = series.range_between(start, end);
+
+            Ok(series.times()[range.clone()]
+                .iter()
+                .zip(&series.closes()[range])
+                .map(|(time, close)| PricePoint {
+                    time: *time,
+                    close: *close,
+                })
+                .collect())
+        })
+    }
+
+    fn get_bar_series<'a>(
+        &'a self,
+        ticker: &'a str,
+        execution_date: ExecutionDate,
+        trading_days: i64,
+    ) -> BoxFuture<'a, Result<Vec<Bar>, DatabaseError>> {
+        Box::pin(async move {
+            validate_period(trading_days, "Trading days")?;
+            let series = self.series_for(ticker)?;
+            let range =
+                series.window_ending_at(execution_date.bar_timestamp(), trading_days as usize);
+            series.bars(range).ok_or_else(|| no_bars(ticker))
+        })
+    }
+
+    fn get_bar_history<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: ExecutionDate,
+        end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<Bar>, DatabaseError>> {
+        Box::pin(async move {
+            let series = self.series_for(ticker)?;
+            let range = series.range_between(start_date.bar_timestamp(), end_date.bar_timestamp());
+            series.bars(range).ok_or_else(|| no_bars(ticker))
+        })
+    }
+
+    fn get_corporate_actions<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: ExecutionDate,
+        end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<CorporateAction>, DatabaseError>> {
+        Box::pin(async move {
+            let start = start_date.bar_timestamp();
+            let end = end_date.bar_timestamp();
+            Ok(self
+                .series_for(ticker)?
+                .corporate_actions()
+                .iter()
+                .filter(|action| action.time >= start && action.time <= end)
+                .copied()
+                .collect())
+        })
+    }
+
+    fn get_shares_outstanding<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: ExecutionDate,
+        end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<SharesOutstanding>, DatabaseError>> {
+        Box::pin(async move {
+            let records = self.series_for(ticker)?.shares_outstanding();
+            Ok(shares_between(
+                records,
+                start_date.bar_timestamp(),
+                end_date.bar_timestamp(),
+            )
+            .to_vec())
+        })
+    }
+
+    fn get_index_membership<'a>(
+        &'a self,
+        universe: &'a str,
+        end_date: NaiveDate,
+    ) -> BoxFuture<'a, Result<Vec<MembershipChange>, DatabaseError>> {
+        Box::pin(async move {
+            let changes = self
+                .memberships
+                .get(universe)
+                .map_or(&[][..], Vec::as_slice);
+            Ok(changes
+                .iter()
+                .take_while(|change| change.date <= end_date)
+                .cloned()
+                .collect())
+        })
+    }
+
+    fn get_security_master(&self) -> BoxFuture<'_, Result<Arc<SecurityMaster>, DatabaseError>> {
+        Box::pin(async move {
+            self.security_master.clone().ok_or_else(|| {
+                DatabaseError::InsufficientData("No security master loaded".to_string())
+            })
+        })
+    }
+
+    fn get_last_market_day(
+        &self,
+        date: NaiveDate,
+    ) -> BoxFuture<'_, Result<ExecutionDate, DatabaseError>> {
+        Box::pin(async move {
+            if let Some(calendar) = self.calendar.as_ref().filter(|c| c.covers(date)) {
+                let market_day = calendar.trading_day_on_or_before(date)?;
+                return Ok(ExecutionDate::at_close(market_day, Exchange::Nasdaq));
+            }
+
+            let market_day = self
+                .trading_days
+                .range(..=date)
+                .next_back()
+                .ok_or_else(|| {
+                    DatabaseError::InsufficientData(format!("No trading day on or before {}", date))
+                })?;
+
+            Ok(ExecutionDate::at_close(*market_day, Exchange::Nasdaq))
+        })
+    }
+
+    fn evaluate_function<'a>(
+        &'a self,
+        function: &'a FunctionDefinition,
+        execution_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<f64, DatabaseError>> {
+        Box::pin(async move { self.evaluate(function, execution_date) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bar_time(date: &str) -> NaiveDateTime {
+        bar_timestamp(NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap())
+    }
+
+    fn test_store() -> PriceStore {
+        let closes = [
+            ("2024-01-02", 100.0),
+            ("2024-01-03", 102.0),
+            ("2024-01-04", 101.0),
+            ("2024-01-05", 105.0),
+            ("2024-01-08", 104.0),
+        ];
+        let mut series = PriceSeries::new();
+        for (date, close) in closes {
+            series.push(bar_time(date), close).unwrap();
+        }
+
+        let mut store = PriceStore::new();
+        store.insert("QQQ", series);
+        store
+    }
+
+    fn function(function_name: FunctionName, window_of_days: Option<u32>) -> FunctionDefinition {
+        FunctionDefinition {
+            function_name,
+            window_of_days,
+            asset: "QQQ".to_string(),
+            price_adjustment: None,
+        }
+    }
+
+    #[test]
+    fn test_series_rejects_out_of_order_bars() {
+        let mut series = PriceSeries::new();
+        series.push(bar_time("2024-01-03"), 1.0).unwrap();
+        assert!(series.push(bar_time("2024-01-02"), 1.0).is_err());
+    }
+
+    #[test]
+    fn test_evaluate_ignores_bars_after_execution_date() {
+        let store = test_store();
+        let sma = store
+            .evaluate(
+                &function(FunctionName::SimpleMovingAverage, Some(2)),
+                "2024-01-04T16:00:00.000000Z".parse().unwrap(),
+            )
+            .unwrap();
+        assert_eq!(sma, 101.5);
+
+        let price = store
+            .evaluate(
+                &function(FunctionName::CurrentPrice, None),
+                "2024-01-08T16:00:00.000000Z".parse().unwrap(),
+            )
+            .unwrap();
+        assert_eq!(price, 104.0);
+    }
+
+    #[test]
+    fn test_current_price_requires_exact_bar() {
+        let store = test_store();
+        let result = store.evaluate(
+            &function(FunctionName::CurrentPrice, None),
+            "2024-01-06T16:00:00.000000Z".parse().unwrap(),
+        );
+        assert!(matches!(result, Err(DatabaseError::InsufficientData(_))));
+    }
+
+    #[tokio::test]
+    async fn test_last_market_day_skips_weekend() {
+        let store = test_store();
+        let saturday = NaiveDate::from_ymd_opt(2024, 1, 6).unwrap();
+        assert_eq!(
+            store
+                .get_last_market_day(saturday)
+                .await
+                .unwrap()
+                .to_string(),
+            "2024-01-05T21:00:00.000000Z"
+        );
+    }
+
+    #[test]
+    fn test_series_holds_closes_or_bars() {
+        let day = |d| bar_timestamp(NaiveDate::from_ymd_opt(2024, 1, d).unwrap());
+        let bar = |d| Bar {
+            time: day(d),
+            open: 10.0,
+            high: 12.0,
+            low: 9.0,
+            close: 11.0,
+            volume: 1_000,
+        };
+
+        let mut bars = PriceSeries::new();
+        bars.push_bar(bar(2)).unwrap();
+        bars.push_bar(bar(3)).unwrap();
+        assert!(bars.push(day(4), 11.5).is_err());
+        assert!(bars.push_bar(bar(3)).is_err());
+        assert_eq!(bars.closes(), [11.0, 11.0]);
+        assert_eq!(bars.bars(0..2), Some(vec![bar(2), bar(3)]));
+
+        let mut closes = PriceSeries::new();
+        closes.push(day(2), 11.0).unwrap();
+        assert!(closes.push_bar(bar(3)).is_err());
+        assert!(!closes.has_bars());
+        assert_eq!(closes.bars(0..0), None);
+    }
+
+    #[test]
+    fn test_adjusted_evaluation_applies_splits() {
+        let mut store = test_store();
+        let mut series = store.get("QQQ").unwrap().clone();
+        // 2-for-1 split effective January 5th
+        series.set_corporate_actions(vec![CorporateAction {
+            time: bar_time("2024-01-05"),
+            split_factor: 2.0,
+            dividend: 0.0,
+        }]);
+        store.insert("QQQ", series);
+
+        let execution_date = "2024-01-08T16:00:00.000000Z".parse().unwrap();
+        let mut cumulative_return = function(FunctionName::CumulativeReturn, Some(5));
+        let raw = store.evaluate(&cumulative_return, execution_date).unwrap();
+        assert_eq!(raw, 4.0);
+
+        cumulative_return.price_adjustment = Some(PriceAdjustment::SplitAdjusted);
+        let adjusted = store.evaluate(&cumulative_return, execution_date).unwrap();
+        assert_eq!(adjusted, 108.0);
+    }
+
+    #[tokio::test]
+    async fn test_last_market_day_uses_calendar() {
+        // Bars alone cannot tell that December 31st 2024 was a trading day
+        let store = test_store().with_calendar(TradingCalendar::nasdaq());
+        let new_years_day = NaiveDate::from_ymd_opt(2025, 1, 1).unwrap();
+        assert_eq!(
+            store
+                .get_last_market_day(new_years_day)
+                .await
+                .unwrap()
+                .date(),
+            NaiveDate::from_ymd_opt(2024, 12, 31).unwrap()
+        );
+    }
+}
@@ -0,0 +1,609 @@
+//! Market data access for the strategy engine.
+//! The executor only talks to a `MarketDataProvider`, so the QuestDB backend can be
+//! swapped for any other source of prices, calendars and indicator values.
+
+use crate::market::adjustments::{CorporateAction, PriceAdjustment};
+use crate::market::database_functions::{self, Bar, DatabaseError, PricePoint};
+use crate::market::execution_date::ExecutionDate;
+use crate::market::market_cap::SharesOutstanding;
+use crate::market::price_store::{default_window, lookback_rows, PriceSeries, PriceStore};
+use crate::market::security_master::SecurityMaster;
+use crate::market::universe::MembershipChange;
+use crate::portfolio::blocks::models::{FunctionDefinition, FunctionName};
+use chrono::{Days, NaiveDate};
+use deadpool_postgres::Pool;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Value of one function per ticker, or why it could not be evaluated for that ticker
+pub type FunctionValues = HashMap<String, Result<f64, DatabaseError>>;
+
+/// Result of `values` for each of `tickers`, in order and once per listing. Values and
+/// exclusions repeat for a ticker listed twice; any other error is moved out on its
+/// first listing, since it ends the evaluation.
+pub fn in_ticker_order(
+    mut values: FunctionValues,
+    tickers: &[String],
+) -> Vec<(String, Result<f64, DatabaseError>)> {
+    tickers
+        .iter()
+        .map(|ticker| {
+            let result = match values.get(ticker) {
+                Some(Ok(value)) => Ok(*value),
+                Some(Err(DatabaseError::AssetExcluded(excluded))) => {
+                    Err(DatabaseError::AssetExcluded(excluded.clone()))
+                }
+                _ => values.remove(ticker).unwrap_or_else(|| {
+                    Err(DatabaseError::InvalidCalculation(format!(
+                        "No value evaluated for {}",
+                        ticker
+                    )))
+                }),
+            };
+            (ticker.clone(), result)
+        })
+        .collect()
+}
+
+/// Calendar days loaded per bar a batched evaluation reads: weekends and holidays
+/// included, with room to spare. Tickers still short of bars are evaluated on their own.
+const BATCH_DAYS_PER_ROW: f64 = 1.5;
+
+/// Source of everything the strategy engine needs to know about the market
+pub trait MarketDataProvider: Send + Sync {
+    /// Returns the last `trading_days` closes for `ticker` up to and including
+    /// `execution_date`, oldest first
+    fn get_price_series<'a>(
+        &'a self,
+        ticker: &'a str,
+        execution_date: ExecutionDate,
+        trading_days: i64,
+    ) -> BoxFuture<'a, Result<Vec<PricePoint>, DatabaseError>>;
+
+    /// Returns every close for `ticker` between `start_date` and `end_date` inclusive, oldest first
+    fn get_price_history<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: ExecutionDate,
+        end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<PricePoint>, DatabaseError>>;
+
+    /// Returns the last `trading_days` OHLCV bars for `ticker` up to and including
+    /// `execution_date`, oldest first. Providers that only hold closes report
+    /// `InsufficientData`.
+    fn get_bar_series<'a>(
+        &'a self,
+        ticker: &'a str,
+        _execution_date: ExecutionDate,
+        _trading_days: i64,
+    ) -> BoxFuture<'a, Result<Vec<Bar>, DatabaseError>> {
+        Box::pin(async move {
+            Err(DatabaseError::InsufficientData(format!(
+                "No OHLCV bars for {}",
+                ticker
+            )))
+        })
+    }
+
+    /// Returns every OHLCV bar for `ticker` between `start_date` and `end_date` inclusive,
+    /// oldest first
+    fn get_bar_history<'a>(
+        &'a self,
+        ticker: &'a str,
+        _start_date: ExecutionDate,
+        _end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<Bar>, DatabaseError>> {
+        Box::pin(async move {
+            Err(DatabaseError::InsufficientData(format!(
+                "No OHLCV bars for {}",
+                ticker
+            )))
+        })
+    }
+
+    /// Returns the splits and dividends of `ticker` taking effect between `start_date`
+    /// and `end_date` inclusive, oldest first. Providers without corporate action data
+    /// report none, which leaves adjusted prices equal to raw prices.
+    fn get_corporate_actions<'a>(
+        &'a self,
+        _ticker: &'a str,
+        _start_date: ExecutionDate,
+        _end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<CorporateAction>, DatabaseError>> {
+        Box::pin(async move { Ok(Vec::new()) })
+    }
+
+    /// Returns the share counts of `ticker` in effect between `start_date` and `end_date`:
+    /// the last one reported at or before `start_date`, then every change up to
+    /// `end_date`, oldest first. Providers without share data report none.
+    fn get_shares_outstanding<'a>(
+        &'a self,
+        _ticker: &'a str,
+        _start_date: ExecutionDate,
+        _end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<SharesOutstanding>, DatabaseError>> {
+        Box::pin(async move { Ok(Vec::new()) })
+    }
+
+    /// Returns every addition to and removal from `universe` dated on or before
+    /// `end_date`, oldest first. Providers without membership data report none.
+    fn get_index_membership<'a>(
+        &'a self,
+        _universe: &'a str,
+        _end_date: NaiveDate,
+    ) -> BoxFuture<'a, Result<Vec<MembershipChange>, DatabaseError>> {
+        Box::pin(async move { Ok(Vec::new()) })
+    }
+
+    /// Returns the reference data of every known security. Providers without a
+    /// security master report `InsufficientData`.
+    fn get_security_master(&self) -> BoxFuture<'_, Result<Arc<SecurityMaster>, DatabaseError>> {
+        Box::pin(async move {
+            Err(DatabaseError::InsufficientData(
+                "No security master".to_string(),
+            ))
+        })
+    }
+
+    /// Returns the close of the last trading day on or before `date`
+    fn get_last_market_day(
+        &self,
+        date: NaiveDate,
+    ) -> BoxFuture<'_, Result<ExecutionDate, DatabaseError>>;
+
+    /// Evaluates an indicator function for its asset as of `execution_date`
+    fn evaluate_function<'a>(
+        &'a self,
+        function: &'a FunctionDefinition,
+        execution_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<f64, DatabaseError>>;
+
+    /// Evaluates `function` for each of `tickers` in place of its asset. Every ticker
+    /// gets its own result, so one without data does not fail the others; the batch only
+    /// fails as a whole when the data cannot be read at all. Evaluates the tickers one by
+    /// one unless the provider can load them together.
+    fn evaluate_function_batch<'a>(
+        &'a self,
+        function: &'a FunctionDefinition,
+        tickers: &'a [String],
+        execution_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<FunctionValues, DatabaseError>> {
+        Box::pin(async move {
+            let mut values = FunctionValues::with_capacity(tickers.len());
+            for ticker in tickers {
+                let function = function.for_asset(ticker);
+                let value = self.evaluate_function(&function, execution_date).await;
+                values.insert(ticker.clone(), value);
+            }
+            Ok(values)
+        })
+    }
+}
+
+/// `MarketDataProvider` backed by the `stock_data_daily` and `nasdaq_closed_days` tables
+#[derive(Clone)]
+pub struct QuestDbProvider {
+    pool: Pool,
+}
+
+impl QuestDbProvider {
+    pub fn new(pool: Pool) -> Self {
+        Self { pool }
+    }
+
+    pub fn pool(&self) -> &Pool {
+        &self.pool
+    }
+
+    /// Evaluates `function` on adjusted closes. The SQL indicators only know raw closes,
+    /// so the function's window and the corporate actions inside it are loaded into a
+    /// `PriceStore` and evaluated there.
+    async fn evaluate_adjusted(
+        &self,
+        function: &FunctionDefinition,
+        execution_date: ExecutionDate,
+    ) -> Result<f64, DatabaseError> {
+        let client = self.pool.get().await?;
+        let ticker = function.asset.as_str();
+        let window = function
+            .window_of_days
+            .unwrap_or_else(|| default_window(&function.function_name));
+        let rows = lookback_rows(&function.function_name, window as usize);
+
+        let points =
+            database_functions::get_price_series(&client, ticker, execution_date, rows as i64)
+                .await?;
+        let first = match points.first() {
+            Some(point) => ExecutionDate::from_bar_timestamp(point.time, execution_date.exchange()),
+            None => {
+                return Err(DatabaseError::InsufficientData(format!(
+                    "No data found for ticker {} before {}",
+                    ticker, execution_date
+                )))
+            }
+        };
+        let actions =
+            database_functions::get_corporate_actions(&client, ticker, first, execution_date)
+                .await?;
+
+        let mut series: PriceSeries = points.into_iter().collect();
+        series.set_corporate_actions(actions);
+        let mut store = PriceStore::new();
+        store.insert(ticker, series);
+        store.evaluate(function, execution_date)
+    }
+
+    /// Loads the window `function` reads for all `tickers` with one query for closes and
+    /// one for corporate actions. Returns the loaded series and the tickers left out.
+    async fn load_batch(
+        &self,
+        function: &FunctionDefinition,
+        tickers: &[String],
+        execution_date: ExecutionDate,
+    ) -> Result<(PriceStore, Vec<String>), DatabaseError> {
+        if tickers.is_empty() {
+            return Ok((PriceStore::new(), Vec::new()));
+        }
+        let window = function
+            .window_of_days
+            .unwrap_or_else(|| default_window(&function.function_name));
+        let rows = lookback_rows(&function.function_name, window as usize);
+        let days = (rows as f64 * BATCH_DAYS_PER_ROW).ceil() as u64 + 10;
+        let start = execution_date
+            .date()
+            .checked_sub_days(Days::new(days))
+            .ok_or(DatabaseError::InvalidDateRange)?;
+        let start = ExecutionDate::at_close(start, execution_date.exchange());
+
+        let client = self.pool.get().await?;
+        let mut history =
+            database_functions::get_price_history_batch(&client, tickers, start, execution_date)
+                .await?;
+        let mut actions = if function.price_adjustment.unwrap_or_default() != PriceAdjustment::Raw {
+            database_functions::get_corporate_actions_batch(&client, tickers, start, execution_date)
+                .await?
+        } else {
+            HashMap::new()
+        };
+
+        // Tickers with fewer bars in the range are left to a query of their own, which
+        // looks further back
+        let mut store = PriceStore::new();
+        let mut short = Vec::new();
+        for ticker in tickers {
+            let points = history.remove(ticker).unwrap_or_default();
+            if points.is_empty() || points.len() < rows {
+                short.push(ticker.clone());
+                continue;
+            }
+            let points = &points[points.len() - rows..];
+            let first = points[0].time;
+            let mut series: PriceSeries = points.iter().cloned().collect();
+            let mut ticker_actions = actions.remove(ticker).unwrap_or_default();
+            ticker_actions.retain(|action| action.time >= first);
+            series.set_corporate_actions(ticker_actions);
+            store.insert(ticker, series);
+        }
+        Ok((store, short))
+    }
+}
+
+impl MarketDataProvider for QuestDbProvider {
+    fn get_price_series<'a>(
+        &'a self,
+        ticker: &'a str,
+        execution_date: ExecutionDate,
+        trading_days: i64,
+    ) -> BoxFuture<'a, Result<Vec<PricePoint>, DatabaseError>> {
+        Box::pin(async move {
+            let client = self.pool.get().await?;
+            database_functions::get_price_series(&client, ticker, execution_date, trading_days)
+                .await
+        })
+    }
+
+    fn get_price_history<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: ExecutionDate,
+        end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<PricePoint>, DatabaseError>> {
+        Box::pin(async move {
+            let client = self.pool.get().await?;
+            database_functions::get_price_history(&client, ticker, start_date, end_date).await
+        })
+    }
+
+    fn get_bar_series<'a>(
+        &'a self,
+        ticker: &'a str,
+        execution_date: ExecutionDate,
+        trading_days: i64,
+    ) -> BoxFuture<'a, Result<Vec<Bar>, DatabaseError>> {
+        Box::pin(async move {
+            let client = self.pool.get().await?;
+            database_functions::get_bar_series(&client, ticker, execution_date, trading_days).await
+        })
+    }
+
+    fn get_bar_history<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: ExecutionDate,
+        end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<Bar>, DatabaseError>> {
+        Box::pin(async move {
+            let client = self.pool.get().await?;
+            database_functions::get_bar_history(&client, ticker, start_date, end_date).await
+        })
+    }
+
+    fn get_corporate_actions<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: ExecutionDate,
+        end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<CorporateAction>, DatabaseError>> {
+        Box::pin(async move {
+            let client = self.pool.get().await?;
+            database_functions::get_corporate_actions(&client, ticker, start_date, end_date).await
+        })
+    }
+
+    fn get_shares_outstanding<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: ExecutionDate,
+        end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<SharesOutstanding>, DatabaseError>> {
+        Box::pin(async move {
+            let client = self.pool.get().await?;
+            database_functions::get_shares_outstanding(&client, ticker, start_date, end_date).await
+        })
+    }
+
+    fn get_index_membership<'a>(
+        &'a self,
+        universe: &'a str,
+        end_date: NaiveDate,
+    ) -> BoxFuture<'a, Result<Vec<MembershipChange>, DatabaseError>> {
+        Box::pin(async move {
+            let client = self.pool.get().await?;
+            database_functions::get_index_membership(&client, universe, end_date).await
+        })
+    }
+
+    fn get_security_master(&self) -> BoxFuture<'_, Result<Arc<SecurityMaster>, DatabaseError>> {
+        Box::pin(async move { Ok(Arc::new(SecurityMaster::from_database(&self.pool).await?)) })
+    }
+
+    fn get_last_market_day(
+        &self,
+        date: NaiveDate,
+    ) -> BoxFuture<'_, Result<ExecutionDate, DatabaseError>> {
+        Box::pin(async move {
+            let client = self.pool.get().await?;
+            database_functions::get_last_market_day(&client, date).await
+        })
+    }
+
+    /// Evaluates on closes loaded for all tickers at once, the way preloaded runs do,
+    /// instead of one SQL indicator query per ticker
+    fn evaluate_function_batch<'a>(
+        &'a self,
+        function: &'a FunctionDefinition,
+        tickers: &'a [String],
+        execution_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<FunctionValues, DatabaseError>> {
+        Box::pin(async move {
+            let (valid, mut single): (Vec<String>, Vec<String>) = tickers
+                .iter()
+                .cloned()
+                .partition(|ticker| database_functions::validate_ticker(ticker).is_ok());
+            let (store, short) = self.load_batch(function, &valid, execution_date).await?;
+            single.extend(short);
+
+            let mut values = FunctionValues::with_capacity(tickers.len());
+            for ticker in &valid {
+                if store.get(ticker).is_some() {
+                    let value = store.evaluate(&function.for_asset(ticker), execution_date);
+                    values.insert(ticker.clone(), value);
+                }
+            }
+            for ticker in single {
+                let value = self
+                    .evaluate_function(&function.for_asset(&ticker), execution_date)
+                    .await;
+                values.insert(ticker, value);
+            }
+            Ok(values)
+        })
+    }
+
+    fn evaluate_function<'a>(
+        &'a self,
+        function: &'a FunctionDefinition,
+        execution_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<f64, DatabaseError>> {
+        Box::pin(async move {
+            if function.price_adjustment.unwrap_or_default() != PriceAdjustment::Raw {
+                return self.evaluate_adjusted(function, execution_date).await;
+            }
+
+            let client = self.pool.get().await?;
+            let ticker = function.asset.as_str();
+
+            match function.function_name {
+                FunctionName::CurrentPrice => {
+                    let price =
+                        database_functions::get_current_price(&client, ticker, execution_date)
+                            .await?;
+
+                    Ok(price.close)
+                }
+                FunctionName::CumulativeReturn => {
+                    database_functions::get_cumulative_return(
+                        &client,
+                        ticker,
+                        execution_date,
+                        function.window_of_days.unwrap_or(20) as i64,
+                    )
+                    .await
+                }
+                FunctionName::SimpleMovingAverage => {
+                    database_functions::get_sma(
+                        &client,
+                        ticker,
+                        execution_date,
+                        function.window_of_days.unwrap_or(20) as i64,
+                    )
+                    .await
+                }
+                FunctionName::ExponentialMovingAverage => {
+                    database_functions::get_ema(
+                        &client,
+                        ticker,
+                        execution_date,
+                        function.window_of_days.unwrap_or(20) as i64,
+                    )
+                    .await
+                }
+                FunctionName::MovingAverageOfReturns => {
+                    database_functions::get_ma_of_returns(
+                        &client,
+                        ticker,
+                        execution_date,
+                        function.window_of_days.unwrap_or(20) as i64,
+                    )
+                    .await
+                }
+                FunctionName::RelativeStrengthIndex => {
+                    database_functions::get_rsi(
+                        &client,
+                        ticker,
+                        execution_date,
+                        function.window_of_days.unwrap_or(14) as i64,
+                    )
+                    .await
+                }
+                FunctionName::PriceStandardDeviation => {
+                    database_functions::get_price_std_dev(
+                        &client,
+                        ticker,
+                        execution_date,
+                        function.window_of_days.unwrap_or(20) as i64,
+                    )
+                    .await
+                }
+                FunctionName::ReturnsStandardDeviation => {
+                    database_functions::get_returns_std_dev(
+                        &client,
+                        ticker,
+                        execution_date,
+                        function.window_of_days.unwrap_or(20) as i64,
+                    )
+                    .await
+                }
+                FunctionName::MaxDrawdown => {
+                    let result = database_functions::get_max_drawdown(
+                        &client,
+                        ticker,
+                        execution_date,
+                        function.window_of_days.unwrap_or(20) as i64,
+                    )
+                    .await?;
+
+                    Ok(result.max_drawdown_percentage) // Note we use the percentage field
+                }
+            }
+        })
+    }
+}
+
+/// In-memory provider with fixed indicator values, used to run the engine in unit tests
+#[cfg(test)]
+pub(crate) mod mock {
+    use super::*;
+    use crate::market::exchange::Exchange;
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    pub(crate) struct FixedValueProvider {
+        values: HashMap<(String, String), f64>,
+    }
+
+    impl FixedValueProvider {
+        /// Registers the value returned for `function_name` evaluated on `ticker`
+        pub(crate) fn with_value(
+            mut self,
+            ticker: &str,
+            function_name: FunctionName,
+            value: f64,
+        ) -> Self {
+            self.values
+                .insert((ticker.to_string(), function_name.to_string()), value);
+            self
+        }
+    }
+
+    impl MarketDataProvider for FixedValueProvider {
+        /// Tickers with a registered value have a bar on every day
+        fn get_price_series<'a>(
+            &'a self,
+            ticker: &'a str,
+            execution_date: ExecutionDate,
+            _trading_days: i64,
+        ) -> BoxFuture<'a, Result<Vec<PricePoint>, DatabaseError>> {
+            Box::pin(async move {
+                if self.values.keys().any(|(asset, _)| asset == ticker) {
+                    return Ok(vec![PricePoint {
+                        time: execution_date.bar_timestamp(),
+                        close: 1.0,
+                    }]);
+                }
+                Err(DatabaseError::InsufficientData(format!(
+                    "No price series for {}",
+                    ticker
+                )))
+            })
+        }
+
+        fn get_price_history<'a>(
+            &'a self,
+            ticker: &'a str,
+            _start_date: ExecutionDate,
+            end_date: ExecutionDate,
+        ) -> BoxFuture<'a, Result<Vec<PricePoint>, DatabaseError>> {
+            self.get_price_series(ticker, end_date, 1)
+        }
+
+        fn get_last_market_day(
+            &self,
+            date: NaiveDate,
+        ) -> BoxFuture<'_, Result<ExecutionDate, DatabaseError>> {
+            Box::pin(async move { Ok(ExecutionDate::at_close(date, Exchange::Nasdaq)) })
+        }
+
+        fn evaluate_function<'a>(
+            &'a self,
+            function: &'a FunctionDefinition,
+            _execution_date: ExecutionDate,
+        ) -> BoxFuture<'a, Result<f64, DatabaseError>> {
+            Box::pin(async move {
+                self.values
+                    .get(&(function.asset.clone(), function.function_name.to_string()))
+                    .copied()
+                    .ok_or_else(|| {
+                        DatabaseError::InsufficientData(format!(
+                            "No {} value for {}",
+                            function.function_name, function.asset
+                        ))
+                    })
+            })
+        }
+    }
+}
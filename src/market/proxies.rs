@@ -0,0 +1,123 @@
+//! Proxy series for assets with short history.
+//! Before an asset's first bar its closes are rebuilt from a proxy's daily returns,
+//! optionally scaled, and chained onto the first real close so indicators see one
+//! continuous series.
+
+use crate::market::database_functions::{DatabaseError, PricePoint};
+
+/// Extends `real` backwards with `proxy`'s returns, multiplied by `scale`.
+///
+/// Proxy bars after the first real bar are ignored. The proxy bar on (or closest before)
+/// the first real day links the two series. Without real bars the series is anchored at
+/// the proxy's last close.
+pub fn splice(
+    proxy: &[PricePoint],
+    real: &[PricePoint],
+    scale: f64,
+) -> Result<Vec<PricePoint>, DatabaseError> {
+    let inception = real.first().map(|point| point.time);
+    let proxy: Vec<&PricePoint> = proxy
+        .iter()
+        .filter(|point| inception.is_none_or(|time| point.time <= time))
+        .collect();
+    let Some(anchor) = proxy.last() else {
+        return Ok(real.to_vec());
+    };
+
+    let mut close = real.first().map_or(anchor.close, |point| point.close);
+    let mut synthetic = Vec::with_capacity(proxy.len() + real.len());
+    if Some(anchor.time) != inception {
+        synthetic.push(PricePoint {
+            time: anchor.time,
+            close,
+        });
+    }
+
+    // Walk back from the anchor, undoing one scaled proxy return per bar
+    for pair in proxy.windows(2).rev() {
+        let (previous, next) = (pair[0], pair[1]);
+        let growth = 1.0 + scale * (next.close / previous.close - 1.0);
+        if !growth.is_finite() || growth <= 0.0 {
+            return Err(DatabaseError::InvalidCalculation(format!(
+                "Proxy return on {} scaled by {} wipes out the series",
+                next.time, scale
+            )));
+        }
+        close /= growth;
+        synthetic.push(PricePoint {
+            time: previous.time,
+            close,
+        });
+    }
+
+    synthetic.reverse();
+    synthetic.extend(real.iter().cloned());
+    Ok(synthetic)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveDate, NaiveDateTime};
+
+    fn day(day: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_hms_opt(16, 0, 0)
+            .unwrap()
+    }
+
+    fn points(closes: &[(u32, f64)]) -> Vec<PricePoint> {
+        closes
+            .iter()
+            .map(|(d, close)| PricePoint {
+                time: day(*d),
+                close: *close,
+            })
+            .collect()
+    }
+
+    fn closes(points: &[PricePoint]) -> Vec<(u32, f64)> {
+        points
+            .iter()
+            .map(|point| {
+                (
+                    point.time.date().format("%d").to_string().parse().unwrap(),
+                    point.close,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_splices_scaled_proxy_returns() {
+        let proxy = points(&[(2, 100.0), (3, 110.0), (4, 99.0), (5, 120.0)]);
+        let real = points(&[(4, 30.0), (5, 33.0)]);
+
+        // Tripled returns: +30% into the 3rd, -30% into the 4th
+        let spliced = splice(&proxy, &real, 3.0).unwrap();
+        let expected = [(2, 30.0 / 0.7 / 1.3), (3, 30.0 / 0.7), (4, 30.0), (5, 33.0)];
+        for ((day, close), (expected_day, expected_close)) in
+            closes(&spliced).into_iter().zip(expected)
+        {
+            assert_eq!(day, expected_day);
+            assert!((close - expected_close).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_unlisted_asset_follows_the_proxy() {
+        let proxy = points(&[(2, 100.0), (3, 110.0)]);
+        let spliced = splice(&proxy, &[], 1.0).unwrap();
+        for (actual, expected) in closes(&spliced).into_iter().zip([(2, 100.0), (3, 110.0)]) {
+            assert_eq!(actual.0, expected.0);
+            assert!((actual.1 - expected.1).abs() < 1e-9);
+        }
+
+        // A scaled return of -100% or worse cannot be chained
+        assert!(matches!(
+            splice(&proxy, &[], -10.0),
+            Err(DatabaseError::InvalidCalculation(_))
+        ));
+    }
+}
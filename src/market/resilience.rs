@@ -0,0 +1,495 @@
+//! Retries, timeouts and circuit breaking for market data requests.
+//! A `ResilientProvider` sits directly on top of the database provider. Every request
+//! gets a deadline, and transient failures (see [`DatabaseError::is_transient`]) are
+//! retried with exponential backoff. Consecutive transient failures open a circuit
+//! breaker: while it is open, requests fail at once with `CircuitOpen` instead of each
+//! waiting out its own timeouts, and the run stops on the first of them.
+
+use crate::config::{CircuitBreakerConfig, DatabaseConfig, RetryConfig};
+use crate::market::adjustments::CorporateAction;
+use crate::market::database_functions::{Bar, DatabaseError, PricePoint};
+use crate::market::execution_date::ExecutionDate;
+use crate::market::market_cap::SharesOutstanding;
+use crate::market::provider::{BoxFuture, FunctionValues, MarketDataProvider};
+use crate::market::security_master::SecurityMaster;
+use crate::market::universe::MembershipChange;
+use crate::portfolio::blocks::models::FunctionDefinition;
+use chrono::NaiveDate;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Counts consecutive transient failures and rejects requests for a while once there
+/// are too many. After that while the next request goes through: a success closes the
+/// circuit, another transient failure opens it again.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    /// Fails with `CircuitOpen` while the circuit is open
+    pub fn check(&self) -> Result<(), DatabaseError> {
+        let state = self.state.lock().unwrap();
+        match state.open_until {
+            Some(until) if Instant::now() < until => Err(DatabaseError::CircuitOpen(format!(
+                "{} consecutive failures, retrying in {:?}",
+                state.failures,
+                until - Instant::now()
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.check().is_err()
+    }
+
+    /// Records that the database answered, even if with an error of the request
+    pub fn record_response(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.failures = 0;
+        state.open_until = None;
+    }
+
+    /// Records a transient failure, opening the circuit at the threshold
+    pub fn record_failure(&self) {
+        if self.config.failure_threshold == 0 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.failures = state.failures.saturating_add(1);
+        if state.failures >= self.config.failure_threshold {
+            if state.open_until.is_none() {
+                tracing::warn!(
+                    "Opening the database circuit after {} consecutive failures",
+                    state.failures
+                );
+            }
+            state.open_until = Some(Instant::now() + Duration::from_millis(self.config.open_ms));
+        }
+    }
+}
+
+/// `MarketDataProvider` that bounds, retries and circuit-breaks every request to `source`
+pub struct ResilientProvider {
+    source: Arc<dyn MarketDataProvider>,
+    query_timeout: Duration,
+    retry: RetryConfig,
+    breaker: CircuitBreaker,
+}
+
+impl ResilientProvider {
+    /// Wraps `source` with the default timeout, retries and circuit breaker
+    pub fn new(source: Arc<dyn MarketDataProvider>) -> Self {
+        Self::from_config(source, &DatabaseConfig::default())
+    }
+
+    /// Wraps `source` with the timeout, retries and circuit breaker of `config`
+    pub fn from_config(source: Arc<dyn MarketDataProvider>, config: &DatabaseConfig) -> Self {
+        Self {
+            source,
+            query_timeout: Duration::from_millis(config.query_timeout_ms),
+            retry: config.retry,
+            breaker: CircuitBreaker::new(config.circuit_breaker),
+        }
+    }
+
+    pub fn with_query_timeout(mut self, query_timeout: Duration) -> Self {
+        self.query_timeout = query_timeout;
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryConfig) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_circuit_breaker(mut self, config: CircuitBreakerConfig) -> Self {
+        self.breaker = CircuitBreaker::new(config);
+        self
+    }
+
+    pub fn circuit_breaker(&self) -> &CircuitBreaker {
+        &self.breaker
+    }
+
+    /// Sends the request built by `request` until it succeeds, fails permanently or
+    /// runs out of attempts
+    async fn call<'a, T>(
+        &'a self,
+        operation: &str,
+        subject: &str,
+        request: impl Fn() -> BoxFuture<'a, Result<T, DatabaseError>>,
+    ) -> Result<T, DatabaseError> {
+        let attempts = self.retry.max_attempts.max(1);
+        let mut backoff = Duration::from_millis(self.retry.initial_backoff_ms);
+        let mut attempt = 1;
+        loop {
+            self.breaker.check()?;
+            let result = match tokio::time::timeout(self.query_timeout, request()).await {
+                Ok(result) => result,
+                Err(_) => Err(DatabaseError::Timeout(format!(
+                    "{} for {} after {:?}",
+                    operation, subject, self.query_timeout
+                ))),
+            };
+
+            match result {
+                Err(e) if e.is_transient() => {
+                    self.breaker.record_failure();
+                    if attempt >= attempts {
+                        return Err(e);
+                    }
+                    tracing::warn!(
+                        "{} for {} failed (attempt {} of {}), retrying in {:?}: {}",
+                        operation,
+                        subject,
+                        attempt,
+                        attempts,
+                        backoff,
+                        e
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(Duration::from_millis(self.retry.max_backoff_ms));
+                    attempt += 1;
+                }
+                result => {
+                    self.breaker.record_response();
+                    return result;
+                }
+            }
+        }
+    }
+}
+
+impl MarketDataProvider for ResilientProvider {
+    fn get_price_series<'a>(
+        &'a self,
+        ticker: &'a str,
+        execution_date: ExecutionDate,
+        trading_days: i64,
+    ) -> BoxFuture<'a, Result<Vec<PricePoint>, DatabaseError>> {
+        Box::pin(self.call("Price series", ticker, move || {
+            self.source
+                .get_price_series(ticker, execution_date, trading_days)
+        }))
+    }
+
+    fn get_price_history<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: ExecutionDate,
+        end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<PricePoint>, DatabaseError>> {
+        Box::pin(self.call("Price history", ticker, move || {
+            self.source.get_price_history(ticker, start_date, end_date)
+        }))
+    }
+
+    fn get_bar_series<'a>(
+        &'a self,
+        ticker: &'a str,
+        execution_date: ExecutionDate,
+        trading_days: i64,
+    ) -> BoxFuture<'a, Result<Vec<Bar>, DatabaseError>> {
+        Box::pin(self.call("Bar series", ticker, move || {
+            self.source
+                .get_bar_series(ticker, execution_date, trading_days)
+        }))
+    }
+
+    fn get_bar_history<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: ExecutionDate,
+        end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<Bar>, DatabaseError>> {
+        Box::pin(self.call("Bar history", ticker, move || {
+            self.source.get_bar_history(ticker, start_date, end_date)
+        }))
+    }
+
+    fn get_corporate_actions<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: ExecutionDate,
+        end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<CorporateAction>, DatabaseError>> {
+        Box::pin(self.call("Corporate actions", ticker, move || {
+            self.source
+                .get_corporate_actions(ticker, start_date, end_date)
+        }))
+    }
+
+    fn get_shares_outstanding<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: ExecutionDate,
+        end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<SharesOutstanding>, DatabaseError>> {
+        Box::pin(self.call("Shares outstanding", ticker, move || {
+            self.source
+                .get_shares_outstanding(ticker, start_date, end_date)
+        }))
+    }
+
+    fn get_index_membership<'a>(
+        &'a self,
+        universe: &'a str,
+        end_date: NaiveDate,
+    ) -> BoxFuture<'a, Result<Vec<MembershipChange>, DatabaseError>> {
+        Box::pin(self.call("Index membership", universe, move || {
+            self.source.get_index_membership(universe, end_date)
+        }))
+    }
+
+    fn get_security_master(&self) -> BoxFuture<'_, Result<Arc<SecurityMaster>, DatabaseError>> {
+        Box::pin(self.call("Security master", "all securities", move || {
+            self.source.get_security_master()
+        }))
+    }
+
+    fn get_last_market_day(
+        &self,
+        date: NaiveDate,
+    ) -> BoxFuture<'_, Result<ExecutionDate, DatabaseError>> {
+        Box::pin(async move {
+            self.call("Last market day", &date.to_string(), move || {
+                self.source.get_last_market_day(date)
+            })
+            .await
+        })
+    }
+
+    fn evaluate_function<'a>(
+        &'a self,
+        function: &'a FunctionDefinition,
+        execution_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<f64, DatabaseError>> {
+        Box::pin(self.call("Function", &function.asset, move || {
+            self.source.evaluate_function(function, execution_date)
+        }))
+    }
+
+    fn evaluate_function_batch<'a>(
+        &'a self,
+        function: &'a FunctionDefinition,
+        tickers: &'a [String],
+        execution_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<FunctionValues, DatabaseError>> {
+        Box::pin(async move {
+            let subject = format!("{} tickers", tickers.len());
+            self.call("Function batch", &subject, move || {
+                self.source
+                    .evaluate_function_batch(function, tickers, execution_date)
+            })
+            .await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market::provider::mock::FixedValueProvider;
+    use crate::portfolio::blocks::models::FunctionName;
+    use std::io::{Error, ErrorKind};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Provider whose first `failures` requests fail with `error`
+    struct FlakyProvider {
+        source: FixedValueProvider,
+        failures: usize,
+        error: fn() -> DatabaseError,
+        calls: AtomicUsize,
+    }
+
+    impl MarketDataProvider for FlakyProvider {
+        fn get_price_series<'a>(
+            &'a self,
+            ticker: &'a str,
+            execution_date: ExecutionDate,
+            trading_days: i64,
+        ) -> BoxFuture<'a, Result<Vec<PricePoint>, DatabaseError>> {
+            self.source
+                .get_price_series(ticker, execution_date, trading_days)
+        }
+
+        fn get_price_history<'a>(
+            &'a self,
+            ticker: &'a str,
+            start_date: ExecutionDate,
+            end_date: ExecutionDate,
+        ) -> BoxFuture<'a, Result<Vec<PricePoint>, DatabaseError>> {
+            self.source.get_price_history(ticker, start_date, end_date)
+        }
+
+        fn get_last_market_day(
+            &self,
+            date: NaiveDate,
+        ) -> BoxFuture<'_, Result<ExecutionDate, DatabaseError>> {
+            self.source.get_last_market_day(date)
+        }
+
+        fn evaluate_function<'a>(
+            &'a self,
+            function: &'a FunctionDefinition,
+            execution_date: ExecutionDate,
+        ) -> BoxFuture<'a, Result<f64, DatabaseError>> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+                let error = (self.error)();
+                return Box::pin(async move { Err(error) });
+            }
+            self.source.evaluate_function(function, execution_date)
+        }
+    }
+
+    fn provider(failures: usize, error: fn() -> DatabaseError) -> Arc<FlakyProvider> {
+        Arc::new(FlakyProvider {
+            source: FixedValueProvider::default().with_value(
+                "AAPL",
+                FunctionName::CurrentPrice,
+                190.0,
+            ),
+            failures,
+            error,
+            calls: AtomicUsize::new(0),
+        })
+    }
+
+    fn function() -> FunctionDefinition {
+        FunctionDefinition {
+            function_name: FunctionName::CurrentPrice,
+            window_of_days: None,
+            asset: "AAPL".to_string(),
+            price_adjustment: None,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retries_transient_errors_and_opens_the_circuit() {
+        let date: ExecutionDate = "2024-06-03".parse().unwrap();
+        let function = function();
+        let refused = || DatabaseError::IoError(Error::from(ErrorKind::ConnectionRefused));
+        assert!(refused().is_transient());
+        assert!(DatabaseError::Timeout("query".into()).is_transient());
+        assert!(!DatabaseError::InvalidTicker.is_transient());
+        assert!(!DatabaseError::CircuitOpen("down".into()).is_transient());
+
+        // Two refused connections are retried away
+        let flaky = provider(2, refused);
+        let resilient = ResilientProvider::new(flaky.clone());
+        assert_eq!(
+            resilient.evaluate_function(&function, date).await.unwrap(),
+            190.0
+        );
+        assert_eq!(flaky.calls.load(Ordering::SeqCst), 3);
+
+        // Permanent errors are not
+        let invalid = provider(1, || DatabaseError::InvalidTicker);
+        let resilient = ResilientProvider::new(invalid.clone());
+        assert!(resilient.evaluate_function(&function, date).await.is_err());
+        assert_eq!(invalid.calls.load(Ordering::SeqCst), 1);
+
+        // A database that stays down opens the circuit after the threshold, and then
+        // requests fail without reaching it until the circuit lets one through again
+        let down = provider(usize::MAX, refused);
+        let resilient = ResilientProvider::new(down.clone())
+            .with_retry(RetryConfig {
+                max_attempts: 2,
+                initial_backoff_ms: 100,
+                max_backoff_ms: 100,
+            })
+            .with_circuit_breaker(CircuitBreakerConfig {
+                failure_threshold: 3,
+                open_ms: 1_000,
+            });
+        assert!(matches!(
+            resilient.evaluate_function(&function, date).await,
+            Err(DatabaseError::IoError(_))
+        ));
+        assert!(matches!(
+            resilient.evaluate_function(&function, date).await,
+            Err(DatabaseError::CircuitOpen(_))
+        ));
+        assert_eq!(down.calls.load(Ordering::SeqCst), 3);
+        assert!(resilient.circuit_breaker().is_open());
+
+        tokio::time::advance(Duration::from_millis(1_000)).await;
+        assert!(!resilient.circuit_breaker().is_open());
+        assert!(resilient.evaluate_function(&function, date).await.is_err());
+        assert_eq!(down.calls.load(Ordering::SeqCst), 4);
+        assert!(resilient.circuit_breaker().is_open());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_slow_requests_time_out() {
+        struct SlowProvider(FixedValueProvider);
+
+        impl MarketDataProvider for SlowProvider {
+            fn get_price_series<'a>(
+                &'a self,
+                ticker: &'a str,
+                execution_date: ExecutionDate,
+                trading_days: i64,
+            ) -> BoxFuture<'a, Result<Vec<PricePoint>, DatabaseError>> {
+                self.0
+                    .get_price_series(ticker, execution_date, trading_days)
+            }
+
+            fn get_price_history<'a>(
+                &'a self,
+                ticker: &'a str,
+                start_date: ExecutionDate,
+                end_date: ExecutionDate,
+            ) -> BoxFuture<'a, Result<Vec<PricePoint>, DatabaseError>> {
+                self.0.get_price_history(ticker, start_date, end_date)
+            }
+
+            fn get_last_market_day(
+                &self,
+                date: NaiveDate,
+            ) -> BoxFuture<'_, Result<ExecutionDate, DatabaseError>> {
+                Box::pin(async move {
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                    self.0.get_last_market_day(date).await
+                })
+            }
+
+            fn evaluate_function<'a>(
+                &'a self,
+                function: &'a FunctionDefinition,
+                execution_date: ExecutionDate,
+            ) -> BoxFuture<'a, Result<f64, DatabaseError>> {
+                self.0.evaluate_function(function, execution_date)
+            }
+        }
+
+        let resilient =
+            ResilientProvider::new(Arc::new(SlowProvider(FixedValueProvider::default())))
+                .with_query_timeout(Duration::from_secs(1));
+        let date = NaiveDate::from_ymd_opt(2024, 6, 3).unwrap();
+        let started = Instant::now();
+        assert!(matches!(
+            resilient.get_last_market_day(date).await,
+            Err(DatabaseError::Timeout(_))
+        ));
+        // Three attempts of a second with 200 ms and 400 ms between them
+        assert_eq!(started.elapsed(), Duration::from_millis(3_600));
+    }
+}
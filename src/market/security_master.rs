@@ -0,0 +1,230 @@
+//! Security master.
+//! Reference data for every ticker a strategy may hold: where it is listed, what it is
+//! and when it traded. Loaded from the `security_master` table or a CSV file and used to
+//! check strategies before a run.
+
+use crate::market::calendar::{parse_flag, split_row};
+use crate::market::database_functions::{self, DatabaseError};
+use chrono::NaiveDate;
+use deadpool_postgres::Pool;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Broad kind of instrument
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AssetClass {
+    Equity,
+    Etf,
+    Index,
+    Crypto,
+    Other,
+}
+
+impl fmt::Display for AssetClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssetClass::Equity => write!(f, "equity"),
+            AssetClass::Etf => write!(f, "etf"),
+            AssetClass::Index => write!(f, "index"),
+            AssetClass::Crypto => write!(f, "crypto"),
+            AssetClass::Other => write!(f, "other"),
+        }
+    }
+}
+
+impl FromStr for AssetClass {
+    type Err = DatabaseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "equity" | "stock" => Ok(AssetClass::Equity),
+            "etf" => Ok(AssetClass::Etf),
+            "index" => Ok(AssetClass::Index),
+            "crypto" => Ok(AssetClass::Crypto),
+            "other" => Ok(AssetClass::Other),
+            other => Err(DatabaseError::InvalidInput(format!(
+                "Unsupported asset class: {}",
+                other
+            ))),
+        }
+    }
+}
+
+/// Reference data of one ticker
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Security {
+    pub ticker: String,
+    pub name: String,
+    /// Exchange of the primary listing, e.g. `NASDAQ`
+    pub exchange: String,
+    pub asset_class: AssetClass,
+    pub sector: Option<String>,
+    pub currency: String,
+    pub first_trading_date: Option<NaiveDate>,
+    pub last_trading_date: Option<NaiveDate>,
+    pub delisted: bool,
+}
+
+/// Every known security, by ticker
+#[derive(Debug, Clone, Default)]
+pub struct SecurityMaster {
+    securities: HashMap<String, Security>,
+}
+
+impl SecurityMaster {
+    pub fn new(securities: impl IntoIterator<Item = Security>) -> Self {
+        Self {
+            securities: securities
+                .into_iter()
+                .map(|security| (security.ticker.clone(), security))
+                .collect(),
+        }
+    }
+
+    /// Loads the `security_master` table
+    pub async fn from_database(pool: &Pool) -> Result<Self, DatabaseError> {
+        let client = pool.get().await?;
+        Ok(Self::new(
+            database_functions::get_security_master(&client).await?,
+        ))
+    }
+
+    /// Loads a security master in the format described at [`SecurityMaster::parse`]
+    pub fn from_csv(path: impl AsRef<Path>) -> Result<Self, DatabaseError> {
+        let path = path.as_ref();
+        Self::parse(&fs::read_to_string(path)?, &path.display().to_string())
+    }
+
+    /// Parses rows with `ticker`, `name`, `exchange`, `asset_class`, `sector`, `currency`,
+    /// `first_trading_date`, `last_trading_date` and `delisted` columns. Sector and dates
+    /// may be empty.
+    pub fn parse(contents: &str, source: &str) -> Result<Self, DatabaseError> {
+        let mut lines = contents.lines();
+        let header = split_row(lines.next().ok_or_else(|| {
+            DatabaseError::InvalidInput(format!("Empty security master {}", source))
+        })?);
+        let column = |name: &str| {
+            header
+                .iter()
+                .position(|column| column.trim() == name)
+                .ok_or_else(|| {
+                    DatabaseError::InvalidInput(format!("Missing column '{}' in {}", name, source))
+                })
+        };
+        let ticker_index = column("ticker")?;
+        let name_index = column("name")?;
+        let exchange_index = column("exchange")?;
+        let class_index = column("asset_class")?;
+        let sector_index = column("sector")?;
+        let currency_index = column("currency")?;
+        let first_index = column("first_trading_date")?;
+        let last_index = column("last_trading_date")?;
+        let delisted_index = column("delisted")?;
+
+        let mut securities = Vec::new();
+        for (line_number, line) in lines.enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let fields = split_row(line);
+            let invalid_row = |reason: &str| {
+                DatabaseError::InvalidInput(format!("{}:{}: {}", source, line_number + 2, reason))
+            };
+            let field = |index: usize| {
+                fields
+                    .get(index)
+                    .map(|field| field.trim())
+                    .ok_or_else(|| invalid_row("missing column"))
+            };
+            let optional = |index: usize| {
+                field(index).map(|value| (!value.is_empty()).then(|| value.to_string()))
+            };
+            let date = |index: usize| {
+                optional(index)?
+                    .map(|value| {
+                        NaiveDate::parse_from_str(&value, "%Y-%m-%d")
+                            .map_err(|_| invalid_row("invalid date"))
+                    })
+                    .transpose()
+            };
+
+            let ticker = field(ticker_index)?;
+            if ticker.is_empty() {
+                return Err(invalid_row("missing ticker"));
+            }
+            securities.push(Security {
+                ticker: ticker.to_string(),
+                name: field(name_index)?.to_string(),
+                exchange: field(exchange_index)?.to_string(),
+                asset_class: field(class_index)?
+                    .parse()
+                    .map_err(|_| invalid_row("invalid asset class"))?,
+                sector: optional(sector_index)?,
+                currency: field(currency_index)?.to_string(),
+                first_trading_date: date(first_index)?,
+                last_trading_date: date(last_index)?,
+                delisted: parse_flag(field(delisted_index)?)
+                    .ok_or_else(|| invalid_row("invalid flag"))?,
+            });
+        }
+
+        Ok(Self::new(securities))
+    }
+
+    pub fn get(&self, ticker: &str) -> Option<&Security> {
+        self.securities.get(ticker)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Security> {
+        self.securities.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.securities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.securities.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_security_master() {
+        let master = SecurityMaster::parse(
+            "ticker,name,exchange,asset_class,sector,currency,first_trading_date,last_trading_date,delisted\n\
+             AAPL,Apple Inc.,NASDAQ,equity,Technology,USD,1980-12-12,,False\n\
+             TWTR,\"Twitter, Inc.\",NYSE,equity,Communication Services,USD,2013-11-07,2022-10-27,True\n\
+             SPY,SPDR S&P 500 ETF Trust,NYSE,etf,,USD,,,False\n",
+            "securities.csv",
+        )
+        .unwrap();
+
+        assert_eq!(master.len(), 3);
+        let twitter = master.get("TWTR").unwrap();
+        assert_eq!(twitter.name, "Twitter, Inc.");
+        assert!(twitter.delisted);
+        assert_eq!(
+            twitter.last_trading_date,
+            NaiveDate::from_ymd_opt(2022, 10, 27)
+        );
+        assert_eq!(master.get("SPY").unwrap().sector, None);
+        assert!(master.get("MSFT").is_none());
+
+        let result = SecurityMaster::parse(
+            "ticker,name,exchange,asset_class,sector,currency,first_trading_date,last_trading_date,delisted\n\
+             AAPL,Apple Inc.,NASDAQ,warrant,Technology,USD,,,False\n",
+            "securities.csv",
+        );
+        assert!(matches!(result, Err(DatabaseError::InvalidInput(_))));
+    }
+}
@@ -0,0 +1,897 @@
+//! Exchange-aware scheduling for strategies.
+//! Every asset is evaluated on the session of the exchange it is listed on, while the
+//! strategy itself executes on days on which all of its exchanges are open. On a day
+//! when an asset's exchange is closed its latest earlier close is used. When an asset
+//! has no bar on a session its exchange was open, the run's `MissingDataPolicy` decides.
+//! Assets with a proxy are evaluated on the proxy's returns before their first bar.
+
+use crate::market::adjustments::{adjust_points, CorporateAction, PriceAdjustment};
+use crate::market::calendar::CalendarRegistry;
+use crate::market::database_functions::{Bar, DatabaseError, PricePoint};
+use crate::market::exchange::Exchange;
+use crate::market::execution_date::ExecutionDate;
+use crate::market::market_cap::SharesOutstanding;
+use crate::market::missing_data::{
+    MissingDataAction, MissingDataEvent, MissingDataLog, MissingDataPolicy,
+};
+use crate::market::price_store::{default_window, lookback_rows, PriceSeries, PriceStore};
+use crate::market::provider::{BoxFuture, FunctionValues, MarketDataProvider};
+use crate::market::proxies::splice;
+use crate::market::security_master::SecurityMaster;
+use crate::market::universe::MembershipChange;
+use crate::portfolio::blocks::models::{AssetProxy, FunctionDefinition};
+use chrono::NaiveDate;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
+
+/// `MarketDataProvider` that moves every request onto the session of the asset's exchange
+pub struct SessionProvider {
+    source: Arc<dyn MarketDataProvider>,
+    calendars: Arc<CalendarRegistry>,
+    exchanges: HashMap<String, Exchange>,
+    schedule: BTreeSet<Exchange>,
+    policy: MissingDataPolicy,
+    log: Arc<MissingDataLog>,
+    proxies: HashMap<String, AssetProxy>,
+}
+
+impl SessionProvider {
+    /// Wraps `source` for a strategy whose tickers are listed on `exchanges`. Tickers
+    /// without an entry are treated as listed on the default exchange (NASDAQ).
+    pub fn new(
+        source: Arc<dyn MarketDataProvider>,
+        calendars: Arc<CalendarRegistry>,
+        exchanges: impl IntoIterator<Item = (String, Exchange)>,
+    ) -> Result<Self, DatabaseError> {
+        let exchanges: HashMap<String, Exchange> = exchanges.into_iter().collect();
+        let mut schedule: BTreeSet<Exchange> = exchanges.values().copied().collect();
+        if schedule.is_empty() {
+            schedule.insert(Exchange::default());
+        }
+
+        // Fail before the run rather than on the first execution date
+        for exchange in schedule.iter().chain([&Exchange::default()]) {
+            calendars.get(*exchange)?;
+        }
+
+        Ok(Self {
+            source,
+            calendars,
+            exchanges,
+            schedule,
+            policy: MissingDataPolicy::default(),
+            log: Arc::default(),
+            proxies: HashMap::new(),
+        })
+    }
+
+    /// Handles assets without a bar on their session with `policy`, recording every
+    /// affected asset in `log`
+    pub fn with_missing_data(
+        mut self,
+        policy: MissingDataPolicy,
+        log: Arc<MissingDataLog>,
+    ) -> Self {
+        self.policy = policy;
+        self.log = log;
+        self
+    }
+
+    /// Evaluates each ticker on its proxy's returns before the ticker's first bar.
+    /// Proxies are evaluated on the sessions of the ticker's exchange.
+    pub fn with_proxies(mut self, proxies: impl IntoIterator<Item = (String, AssetProxy)>) -> Self {
+        self.proxies = proxies.into_iter().collect();
+        self
+    }
+
+    /// Exchanges whose calendars decide on which days the strategy executes
+    pub fn schedule(&self) -> &BTreeSet<Exchange> {
+        &self.schedule
+    }
+
+    pub fn exchange(&self, ticker: &str) -> Exchange {
+        self.exchanges.get(ticker).copied().unwrap_or_default()
+    }
+
+    /// Latest session of `ticker`'s exchange on or before the day of `execution_date`
+    pub fn session(
+        &self,
+        ticker: &str,
+        execution_date: ExecutionDate,
+    ) -> Result<ExecutionDate, DatabaseError> {
+        self.calendars
+            .session_on_or_before(self.exchange(ticker), execution_date.date())
+    }
+
+    /// Session whose bar `ticker` is evaluated on for the strategy execution on
+    /// `execution_date`, after applying the missing-data policy
+    async fn available_session(
+        &self,
+        ticker: &str,
+        execution_date: ExecutionDate,
+    ) -> Result<ExecutionDate, DatabaseError> {
+        let session = self.session(ticker, execution_date)?;
+        let last_bar = self
+            .price_series(ticker, session, 1)
+            .await?
+            .last()
+            .map(|point| point.time.date());
+        if last_bar == Some(session.date()) {
+            return Ok(session);
+        }
+
+        let exchange = self.exchange(ticker);
+        let missing = |reason: String| {
+            DatabaseError::InsufficientData(format!(
+                "No bar for {} on {} ({}, missing data policy {})",
+                ticker,
+                session.date(),
+                reason,
+                self.policy
+            ))
+        };
+        let reason = match last_bar {
+            Some(date) => format!("last bar on {}", date),
+            None => "no earlier bars".to_string(),
+        };
+
+        let filled = match (self.policy, last_bar) {
+            (MissingDataPolicy::ForwardFill { max_days }, Some(date)) => {
+                // Sessions after the last bar up to and including the current one
+                let calendar = self.calendars.get(exchange)?;
+                let missed = date
+                    .succ_opt()
+                    .and_then(|next| calendar.count_trading_days(next, session.date()).ok());
+                match missed {
+                    Some(missed) if missed <= max_days as usize => date,
+                    _ => return Err(missing(reason)),
+                }
+            }
+            (MissingDataPolicy::LastAvailable, Some(date)) => date,
+            (MissingDataPolicy::ExcludeAsset, _) => {
+                return Err(self.exclude(ticker, session, execution_date))
+            }
+            _ => return Err(missing(reason)),
+        };
+
+        self.log.record(
+            execution_date.date(),
+            MissingDataEvent {
+                ticker: ticker.to_string(),
+                session: session.date(),
+                action: MissingDataAction::Filled { from: filled },
+            },
+        );
+        Ok(ExecutionDate::at_close(filled, exchange))
+    }
+
+    /// Last `rows` closes of `ticker` up to `session`; none if it has no bars yet
+    async fn price_series(
+        &self,
+        ticker: &str,
+        session: ExecutionDate,
+        rows: usize,
+    ) -> Result<Vec<PricePoint>, DatabaseError> {
+        match self
+            .source
+            .get_price_series(ticker, session, rows as i64)
+            .await
+        {
+            Err(DatabaseError::InsufficientData(_)) => Ok(Vec::new()),
+            result => result,
+        }
+    }
+
+    async fn evaluate_on_session(
+        &self,
+        function: &FunctionDefinition,
+        execution_date: ExecutionDate,
+    ) -> Result<f64, DatabaseError> {
+        let session = self
+            .available_session(&function.asset, execution_date)
+            .await?;
+        self.source.evaluate_function(function, session).await
+    }
+
+    /// Evaluates `function` on its asset's bars, preceded by the proxy's returns when the
+    /// asset has fewer bars than the function reads
+    async fn evaluate_with_proxy(
+        &self,
+        function: &FunctionDefinition,
+        proxy: &AssetProxy,
+        execution_date: ExecutionDate,
+    ) -> Result<f64, DatabaseError> {
+        let ticker = function.asset.as_str();
+        let exchange = self.exchange(ticker);
+        let window = function
+            .window_of_days
+            .unwrap_or_else(|| default_window(&function.function_name));
+        let rows = lookback_rows(&function.function_name, window as usize);
+
+        let listed = !self
+            .price_series(ticker, self.session(ticker, execution_date)?, 1)
+            .await?
+            .is_empty();
+        let (session, real, proxy_points) = if listed {
+            let session = self.available_session(ticker, execution_date).await?;
+            let real = self.price_series(ticker, session, rows).await?;
+            if real.len() >= rows {
+                return self.source.evaluate_function(function, session).await;
+            }
+            // One proxy bar overlaps the first real bar to link the two
+            let inception = ExecutionDate::from_bar_timestamp(real[0].time, exchange);
+            let proxy_points = self
+                .price_series(&proxy.ticker, inception, rows - real.len() + 1)
+                .await?;
+            (session, real, proxy_points)
+        } else {
+            let session = self
+                .available_session(&proxy.ticker, execution_date)
+                .await?;
+            let proxy_points = self.price_series(&proxy.ticker, session, rows).await?;
+            (session, Vec::new(), proxy_points)
+        };
+
+        let adjustment = function.price_adjustment.unwrap_or_default();
+        let proxy_points = adjust_points(
+            self.source.as_ref(),
+            &proxy.ticker,
+            proxy_points,
+            adjustment,
+            exchange,
+        )
+        .await?;
+        let mut series: PriceSeries = splice(&proxy_points, &real, proxy.scale)?
+            .into_iter()
+            .collect();
+        if adjustment != PriceAdjustment::Raw && !real.is_empty() {
+            let first = ExecutionDate::from_bar_timestamp(real[0].time, exchange);
+            series.set_corporate_actions(
+                self.source
+                    .get_corporate_actions(ticker, first, session)
+                    .await?,
+            );
+        }
+
+        self.log.record(
+            execution_date.date(),
+            MissingDataEvent {
+                ticker: ticker.to_string(),
+                session: session.date(),
+                action: MissingDataAction::Proxied {
+                    proxy: proxy.ticker.clone(),
+                },
+            },
+        );
+
+        let as_of = match series.times().last() {
+            Some(time) => ExecutionDate::from_bar_timestamp(*time, exchange),
+            None => session,
+        };
+        let mut store = PriceStore::new();
+        store.insert(ticker, series);
+        store.evaluate(function, as_of)
+    }
+
+    fn exclude(
+        &self,
+        ticker: &str,
+        session: ExecutionDate,
+        execution_date: ExecutionDate,
+    ) -> DatabaseError {
+        self.log.record(
+            execution_date.date(),
+            MissingDataEvent {
+                ticker: ticker.to_string(),
+                session: session.date(),
+                action: MissingDataAction::Excluded,
+            },
+        );
+        DatabaseError::AssetExcluded(ticker.to_string())
+    }
+
+    /// Not enough history for the window counts as missing data as well
+    fn exclude_insufficient(
+        &self,
+        ticker: &str,
+        execution_date: ExecutionDate,
+        result: Result<f64, DatabaseError>,
+    ) -> Result<f64, DatabaseError> {
+        match result {
+            Err(DatabaseError::InsufficientData(_))
+                if self.policy == MissingDataPolicy::ExcludeAsset =>
+            {
+                let session = self.session(ticker, execution_date)?;
+                Err(self.exclude(ticker, session, execution_date))
+            }
+            result => result,
+        }
+    }
+
+    /// `date` as a bound on `ticker`'s bars, whether or not its exchange is open that day
+    fn bound(&self, ticker: &str, date: ExecutionDate) -> ExecutionDate {
+        ExecutionDate::at_close(date.date(), self.exchange(ticker))
+    }
+}
+
+impl MarketDataProvider for SessionProvider {
+    fn get_price_series<'a>(
+        &'a self,
+        ticker: &'a str,
+        execution_date: ExecutionDate,
+        trading_days: i64,
+    ) -> BoxFuture<'a, Result<Vec<PricePoint>, DatabaseError>> {
+        self.source
+            .get_price_series(ticker, self.bound(ticker, execution_date), trading_days)
+    }
+
+    fn get_price_history<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: ExecutionDate,
+        end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<PricePoint>, DatabaseError>> {
+        self.source.get_price_history(
+            ticker,
+            self.bound(ticker, start_date),
+            self.bound(ticker, end_date),
+        )
+    }
+
+    fn get_bar_series<'a>(
+        &'a self,
+        ticker: &'a str,
+        execution_date: ExecutionDate,
+        trading_days: i64,
+    ) -> BoxFuture<'a, Result<Vec<Bar>, DatabaseError>> {
+        self.source
+            .get_bar_series(ticker, self.bound(ticker, execution_date), trading_days)
+    }
+
+    fn get_bar_history<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: ExecutionDate,
+        end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<Bar>, DatabaseError>> {
+        self.source.get_bar_history(
+            ticker,
+            self.bound(ticker, start_date),
+            self.bound(ticker, end_date),
+        )
+    }
+
+    fn get_corporate_actions<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: ExecutionDate,
+        end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<CorporateAction>, DatabaseError>> {
+        self.source.get_corporate_actions(
+            ticker,
+            self.bound(ticker, start_date),
+            self.bound(ticker, end_date),
+        )
+    }
+
+    fn get_shares_outstanding<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: ExecutionDate,
+        end_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<Vec<SharesOutstanding>, DatabaseError>> {
+        self.source.get_shares_outstanding(
+            ticker,
+            self.bound(ticker, start_date),
+            self.bound(ticker, end_date),
+        )
+    }
+
+    fn get_index_membership<'a>(
+        &'a self,
+        universe: &'a str,
+        end_date: NaiveDate,
+    ) -> BoxFuture<'a, Result<Vec<MembershipChange>, DatabaseError>> {
+        self.source.get_index_membership(universe, end_date)
+    }
+
+    fn get_security_master(&self) -> BoxFuture<'_, Result<Arc<SecurityMaster>, DatabaseError>> {
+        self.source.get_security_master()
+    }
+
+    fn get_last_market_day(
+        &self,
+        date: NaiveDate,
+    ) -> BoxFuture<'_, Result<ExecutionDate, DatabaseError>> {
+        Box::pin(async move {
+            self.calendars
+                .common_session_on_or_before(&self.schedule, date)
+        })
+    }
+
+    fn evaluate_function<'a>(
+        &'a self,
+        function: &'a FunctionDefinition,
+        execution_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<f64, DatabaseError>> {
+        Box::pin(async move {
+            let ticker = function.asset.as_str();
+            let result = match self.proxies.get(ticker) {
+                Some(proxy) => {
+                    self.evaluate_with_proxy(function, proxy, execution_date)
+                        .await
+                }
+                None => self.evaluate_on_session(function, execution_date).await,
+            };
+            self.exclude_insufficient(ticker, execution_date, result)
+        })
+    }
+
+    /// Resolves each ticker's session first, then hands the source one batch per
+    /// session. Proxied tickers are evaluated one by one.
+    fn evaluate_function_batch<'a>(
+        &'a self,
+        function: &'a FunctionDefinition,
+        tickers: &'a [String],
+        execution_date: ExecutionDate,
+    ) -> BoxFuture<'a, Result<FunctionValues, DatabaseError>> {
+        Box::pin(async move {
+            let mut values = FunctionValues::with_capacity(tickers.len());
+            let mut batches: BTreeMap<ExecutionDate, Vec<String>> = BTreeMap::new();
+            let mut seen = HashSet::new();
+            for ticker in tickers.iter().filter(|ticker| seen.insert(*ticker)) {
+                if self.proxies.contains_key(ticker) {
+                    let value = self
+                        .evaluate_function(&function.for_asset(ticker), execution_date)
+                        .await;
+                    values.insert(ticker.clone(), value);
+                    continue;
+                }
+                match self.available_session(ticker, execution_date).await {
+                    Ok(session) => batches.entry(session).or_default().push(ticker.clone()),
+                    Err(e) => {
+                        let value = self.exclude_insufficient(ticker, execution_date, Err(e));
+                        values.insert(ticker.clone(), value);
+                    }
+                }
+            }
+
+            for (session, batch) in batches {
+                let mut results = self
+                    .source
+                    .evaluate_function_batch(function, &batch, session)
+                    .await?;
+                for ticker in batch {
+                    let result = results.remove(&ticker).unwrap_or_else(|| {
+                        Err(DatabaseError::InvalidCalculation(format!(
+                            "No value evaluated for {}",
+                            ticker
+                        )))
+                    });
+                    let value = self.exclude_insufficient(&ticker, execution_date, result);
+                    values.insert(ticker, value);
+                }
+            }
+            Ok(values)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market::calendar::TradingCalendar;
+    use crate::portfolio::blocks::models::FunctionName;
+    use chrono::Datelike;
+    use std::sync::Mutex;
+
+    /// Records the session each evaluation and batch was asked for. Tickers in
+    /// `last_bars` stop trading after the given day, all others have a bar every day.
+    #[derive(Default)]
+    struct RecordingProvider {
+        sessions: Mutex<Vec<(String, ExecutionDate)>>,
+        batches: Mutex<Vec<(Vec<String>, ExecutionDate)>>,
+        last_bars: HashMap<String, NaiveDate>,
+    }
+
+    impl MarketDataProvider for RecordingProvider {
+        fn get_price_series<'a>(
+            &'a self,
+            ticker: &'a str,
+            execution_date: ExecutionDate,
+            _trading_days: i64,
+        ) -> BoxFuture<'a, Result<Vec<PricePoint>, DatabaseError>> {
+            Box::pin(async move {
+                let date = match self.last_bars.get(ticker) {
+                    Some(last) => execution_date.date().min(*last),
+                    None => execution_date.date(),
+                };
+                Ok(vec![PricePoint {
+                    time: execution_date.exchange().bar_timestamp(date),
+                    close: 1.0,
+                }])
+            })
+        }
+
+        fn get_price_history<'a>(
+            &'a self,
+            ticker: &'a str,
+            _start_date: ExecutionDate,
+            end_date: ExecutionDate,
+        ) -> BoxFuture<'a, Result<Vec<PricePoint>, DatabaseError>> {
+            self.get_price_series(ticker, end_date, 1)
+        }
+
+        fn get_last_market_day(
+            &self,
+            date: NaiveDate,
+        ) -> BoxFuture<'_, Result<ExecutionDate, DatabaseError>> {
+            Box::pin(async move { Ok(ExecutionDate::at_close(date, Exchange::Nasdaq)) })
+        }
+
+        fn evaluate_function<'a>(
+            &'a self,
+            function: &'a FunctionDefinition,
+            execution_date: ExecutionDate,
+        ) -> BoxFuture<'a, Result<f64, DatabaseError>> {
+            Box::pin(async move {
+                self.sessions
+                    .lock()
+                    .unwrap()
+                    .push((function.asset.clone(), execution_date));
+                Ok(1.0)
+            })
+        }
+
+        fn evaluate_function_batch<'a>(
+            &'a self,
+            _function: &'a FunctionDefinition,
+            tickers: &'a [String],
+            execution_date: ExecutionDate,
+        ) -> BoxFuture<'a, Result<FunctionValues, DatabaseError>> {
+            Box::pin(async move {
+                self.batches
+                    .lock()
+                    .unwrap()
+                    .push((tickers.to_vec(), execution_date));
+                Ok(tickers
+                    .iter()
+                    .map(|ticker| (ticker.clone(), Ok(1.0)))
+                    .collect())
+            })
+        }
+    }
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn current_price(ticker: &str) -> FunctionDefinition {
+        FunctionDefinition {
+            function_name: FunctionName::CurrentPrice,
+            window_of_days: None,
+            asset: ticker.to_string(),
+            price_adjustment: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_assets_evaluate_on_their_own_exchange() {
+        // Frankfurt is closed on New Year's Eve, New York is not
+        let xetra = TradingCalendar::parse(
+            "Date,Is Weekend,Is Holiday,Holiday Name\n2024-12-31,False,True,New Year's Eve\n",
+            "XETRA.csv",
+        )
+        .unwrap();
+        let mut calendars = CalendarRegistry::builtin();
+        calendars.register(Exchange::Xetra, Arc::new(xetra));
+
+        let source = Arc::new(RecordingProvider::default());
+        let provider = SessionProvider::new(
+            source.clone(),
+            Arc::new(calendars),
+            [
+                ("SPY".to_string(), Exchange::Nyse),
+                ("SAP".to_string(), Exchange::Xetra),
+                ("BTC-USD".to_string(), Exchange::Crypto),
+            ],
+        )
+        .unwrap();
+
+        // Mixed strategies only execute when every exchange is open
+        let execution_date = provider
+            .get_last_market_day(date(2024, 12, 31))
+            .await
+            .unwrap();
+        assert_eq!(execution_date.date(), date(2024, 12, 30));
+
+        let late = ExecutionDate::at_close(date(2024, 12, 31), Exchange::Nyse);
+        for ticker in ["SPY", "SAP", "BTC-USD", "QQQ"] {
+            provider
+                .evaluate_function(&current_price(ticker), late)
+                .await
+                .unwrap();
+        }
+
+        let sessions = source.sessions.lock().unwrap().clone();
+        assert_eq!(
+            sessions,
+            vec![
+                ("SPY".to_string(), late),
+                (
+                    "SAP".to_string(),
+                    ExecutionDate::at_close(date(2024, 12, 30), Exchange::Xetra)
+                ),
+                (
+                    "BTC-USD".to_string(),
+                    ExecutionDate::at_close(date(2024, 12, 31), Exchange::Crypto)
+                ),
+                (
+                    "QQQ".to_string(),
+                    ExecutionDate::at_close(date(2024, 12, 31), Exchange::Nasdaq)
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_batch_reaches_the_source_once_per_session() {
+        let source = Arc::new(RecordingProvider {
+            last_bars: HashMap::from([("TSLA".to_string(), date(2024, 12, 20))]),
+            ..Default::default()
+        });
+        let log = Arc::new(MissingDataLog::default());
+        let provider =
+            SessionProvider::new(source.clone(), Arc::new(CalendarRegistry::builtin()), [])
+                .unwrap()
+                .with_missing_data(MissingDataPolicy::ExcludeAsset, Arc::clone(&log));
+        let execution_date = ExecutionDate::at_close(date(2024, 12, 31), Exchange::Nasdaq);
+
+        let tickers = ["AAPL", "TSLA", "MSFT", "AAPL"].map(String::from);
+        let values = provider
+            .evaluate_function_batch(&current_price(""), &tickers, execution_date)
+            .await
+            .unwrap();
+        assert!(matches!(values["AAPL"], Ok(1.0)));
+        assert!(matches!(values["MSFT"], Ok(1.0)));
+        assert!(matches!(
+            values["TSLA"],
+            Err(DatabaseError::AssetExcluded(_))
+        ));
+
+        // The tickers with a bar on the session go out together, without single evaluations
+        assert_eq!(
+            *source.batches.lock().unwrap(),
+            vec![(vec!["AAPL".to_string(), "MSFT".to_string()], execution_date)]
+        );
+        assert!(source.sessions.lock().unwrap().is_empty());
+        assert_eq!(log.take(date(2024, 12, 31)).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_csv_prices_evaluate_on_every_exchange() {
+        let dir = std::env::temp_dir().join(format!("session_prices_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let prices = [
+            ("SAP", [("2024-12-27", 234.0), ("2024-12-30", 236.5)]),
+            ("SPY", [("2024-12-30", 588.2), ("2024-12-31", 586.1)]),
+            (
+                "BTC-USD",
+                [("2024-12-30", 92_700.0), ("2024-12-31", 93_400.0)],
+            ),
+        ];
+        for (ticker, closes) in &prices {
+            let rows: String = closes
+                .iter()
+                .map(|(day, close)| format!("{},{}\n", day, close))
+                .collect();
+            std::fs::write(
+                dir.join(format!("{}prices.csv", ticker)),
+                format!("date,close\n{}", rows),
+            )
+            .unwrap();
+        }
+        let store = PriceStore::from_csv_dir(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let xetra = TradingCalendar::parse(
+            "Date,Is Weekend,Is Holiday,Holiday Name\n2024-12-31,False,True,New Year's Eve\n",
+            "XETRA.csv",
+        )
+        .unwrap();
+        let mut calendars = CalendarRegistry::builtin();
+        calendars.register(Exchange::Xetra, Arc::new(xetra));
+        let provider = SessionProvider::new(
+            Arc::new(store.unwrap()),
+            Arc::new(calendars),
+            [
+                ("SAP".to_string(), Exchange::Xetra),
+                ("SPY".to_string(), Exchange::Nyse),
+                ("BTC-USD".to_string(), Exchange::Crypto),
+            ],
+        )
+        .unwrap();
+
+        // Bars are stamped the same on every exchange, so each asset finds the close of
+        // its own session
+        let late = ExecutionDate::at_close(date(2024, 12, 31), Exchange::Nyse);
+        for (ticker, expected) in [("SAP", 236.5), ("SPY", 586.1), ("BTC-USD", 93_400.0)] {
+            let price = provider
+                .evaluate_function(&current_price(ticker), late)
+                .await
+                .unwrap();
+            assert_eq!(price, expected, "{}", ticker);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_missing_data_policies() {
+        let source = Arc::new(RecordingProvider {
+            last_bars: HashMap::from([
+                ("AAPL".to_string(), date(2024, 12, 27)),
+                ("TSLA".to_string(), date(2024, 12, 20)),
+            ]),
+            ..Default::default()
+        });
+        let provider = |policy: MissingDataPolicy, log: &Arc<MissingDataLog>| {
+            SessionProvider::new(source.clone(), Arc::new(CalendarRegistry::builtin()), [])
+                .unwrap()
+                .with_missing_data(policy, Arc::clone(log))
+        };
+        let execution_date = ExecutionDate::at_close(date(2024, 12, 31), Exchange::Nasdaq);
+        let log = Arc::new(MissingDataLog::default());
+
+        // AAPL misses the 30th and 31st, TSLA the six sessions since the 20th
+        let strict = provider(MissingDataPolicy::Error, &log);
+        assert!(matches!(
+            strict
+                .evaluate_function(&current_price("AAPL"), execution_date)
+                .await,
+            Err(DatabaseError::InsufficientData(_))
+        ));
+
+        let fill = provider(MissingDataPolicy::ForwardFill { max_days: 2 }, &log);
+        fill.evaluate_function(&current_price("AAPL"), execution_date)
+            .await
+            .unwrap();
+        assert!(fill
+            .evaluate_function(&current_price("TSLA"), execution_date)
+            .await
+            .is_err());
+
+        let last = provider(MissingDataPolicy::LastAvailable, &log);
+        last.evaluate_function(&current_price("TSLA"), execution_date)
+            .await
+            .unwrap();
+        let sessions = source.sessions.lock().unwrap().clone();
+        assert_eq!(
+            sessions,
+            vec![
+                (
+                    "AAPL".to_string(),
+                    ExecutionDate::at_close(date(2024, 12, 27), Exchange::Nasdaq)
+                ),
+                (
+                    "TSLA".to_string(),
+                    ExecutionDate::at_close(date(2024, 12, 20), Exchange::Nasdaq)
+                ),
+            ]
+        );
+
+        let exclude = provider(MissingDataPolicy::ExcludeAsset, &log);
+        assert!(matches!(
+            exclude
+                .evaluate_function(&current_price("AAPL"), execution_date)
+                .await,
+            Err(DatabaseError::AssetExcluded(_))
+        ));
+
+        // Only the first outcome per ticker and execution date is kept
+        assert_eq!(
+            log.take(execution_date.date()),
+            vec![
+                MissingDataEvent {
+                    ticker: "AAPL".to_string(),
+                    session: date(2024, 12, 31),
+                    action: MissingDataAction::Filled {
+                        from: date(2024, 12, 27)
+                    },
+                },
+                MissingDataEvent {
+                    ticker: "TSLA".to_string(),
+                    session: date(2024, 12, 31),
+                    action: MissingDataAction::Filled {
+                        from: date(2024, 12, 20)
+                    },
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_proxy_fills_history_before_inception() {
+        // QQQ gains 1% a session, TQQQ three times that from its first bar on the 22nd
+        let sessions: Vec<NaiveDate> = date(2024, 1, 2)
+            .iter_days()
+            .take_while(|day| *day <= date(2024, 1, 31))
+            .filter(|day| day.weekday().number_from_monday() <= 5 && *day != date(2024, 1, 15))
+            .collect();
+        let series = |growth: f64, from: NaiveDate| -> PriceSeries {
+            let mut series = PriceSeries::new();
+            for (i, day) in sessions.iter().filter(|day| **day >= from).enumerate() {
+                series
+                    .push(
+                        Exchange::Nasdaq.bar_timestamp(*day),
+                        30.0 * growth.powi(i as i32),
+                    )
+                    .unwrap();
+            }
+            series
+        };
+        let mut store = PriceStore::new();
+        store.insert("QQQ", series(1.01, sessions[0]));
+        store.insert("TQQQ", series(1.03, date(2024, 1, 22)));
+        let mut reference = PriceStore::new();
+        reference.insert("TQQQ", series(1.03, sessions[0]));
+
+        let log = Arc::new(MissingDataLog::default());
+        let provider = SessionProvider::new(
+            Arc::new(store),
+            Arc::new(CalendarRegistry::builtin()),
+            [("TQQQ".to_string(), Exchange::Nasdaq)],
+        )
+        .unwrap()
+        .with_missing_data(MissingDataPolicy::Error, Arc::clone(&log))
+        .with_proxies([(
+            "TQQQ".to_string(),
+            AssetProxy {
+                ticker: "QQQ".to_string(),
+                scale: 3.0,
+            },
+        )]);
+        let function = FunctionDefinition {
+            function_name: FunctionName::CumulativeReturn,
+            window_of_days: Some(5),
+            asset: "TQQQ".to_string(),
+            price_adjustment: None,
+        };
+
+        // Before listing, on the inception day and once TQQQ has a full window of its own
+        for day in [date(2024, 1, 19), date(2024, 1, 24), date(2024, 1, 30)] {
+            let execution_date = ExecutionDate::at_close(day, Exchange::Nasdaq);
+            let actual = provider
+                .evaluate_function(&function, execution_date)
+                .await
+                .unwrap();
+            let expected = reference.evaluate(&function, execution_date).unwrap();
+            assert!((actual - expected).abs() < 1e-9);
+        }
+
+        let proxied = |day: NaiveDate| MissingDataEvent {
+            ticker: "TQQQ".to_string(),
+            session: day,
+            action: MissingDataAction::Proxied {
+                proxy: "QQQ".to_string(),
+            },
+        };
+        assert_eq!(
+            log.take(date(2024, 1, 19)),
+            vec![proxied(date(2024, 1, 19))]
+        );
+        assert_eq!(
+            log.take(date(2024, 1, 24)),
+            vec![proxied(date(2024, 1, 24))]
+        );
+        assert!(log.take(date(2024, 1, 30)).is_empty());
+    }
+
+    #[test]
+    fn test_rejects_exchange_without_calendar() {
+        let result = SessionProvider::new(
+            Arc::new(RecordingProvider::default()),
+            Arc::new(CalendarRegistry::builtin()),
+            [("SAP".to_string(), Exchange::Xetra)],
+        );
+        assert!(matches!(result, Err(DatabaseError::InvalidInput(_))));
+    }
+}
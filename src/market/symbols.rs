@@ -0,0 +1,228 @@
+//! Ticker symbol rules.
+//! What a valid symbol looks like depends on where it trades: US share classes use a dot
+//! (`BRK.B`), Xetra listings carry a venue suffix (`SAP.DE`) and crypto assets are quoted
+//! as pairs (`BTC-USD`). The rules are installed once per process and shared by block
+//! validation and the data layer, so both accept and reject the same symbols.
+
+use crate::market::database_functions::DatabaseError;
+use crate::market::exchange::Exchange;
+use crate::market::security_master::AssetClass;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, OnceLock, RwLock};
+
+/// Shape of the symbols of one exchange or asset class
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SymbolRule {
+    /// Longest symbol, separators included
+    pub max_len: usize,
+    /// Characters allowed between letters and digits, e.g. `.` in `BRK.B`
+    #[serde(default)]
+    pub separators: String,
+    #[serde(default)]
+    pub allow_lowercase: bool,
+    /// Symbol must be a `BASE-QUOTE` pair such as `BTC-USD`
+    #[serde(default)]
+    pub pair: bool,
+}
+
+impl SymbolRule {
+    pub fn accepts(&self, symbol: &str) -> bool {
+        if symbol.is_empty() || symbol.len() > self.max_len {
+            return false;
+        }
+
+        let mut previous_separator = true;
+        for c in symbol.chars() {
+            let separator = self.separators.contains(c);
+            let valid = c.is_ascii_uppercase()
+                || c.is_ascii_digit()
+                || (self.allow_lowercase && c.is_ascii_lowercase())
+                || separator;
+            // Separators cannot lead, trail or follow each other
+            if !valid || (separator && previous_separator) {
+                return false;
+            }
+            previous_separator = separator;
+        }
+        if previous_separator {
+            return false;
+        }
+
+        !self.pair || symbol.matches('-').count() == 1
+    }
+}
+
+/// Symbol rules by asset class and exchange, with a default for everything else
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SymbolRules {
+    pub default: SymbolRule,
+    #[serde(default)]
+    pub exchanges: HashMap<Exchange, SymbolRule>,
+    #[serde(default)]
+    pub asset_classes: HashMap<AssetClass, SymbolRule>,
+}
+
+impl Default for SymbolRules {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl SymbolRules {
+    /// US-style symbols by default, `SAP.DE`-style symbols on Xetra and pairs for crypto
+    pub fn builtin() -> Self {
+        let crypto = SymbolRule {
+            max_len: 20,
+            separators: "-".to_string(),
+            allow_lowercase: false,
+            pair: true,
+        };
+        Self {
+            default: SymbolRule {
+                max_len: 10,
+                separators: ".-".to_string(),
+                allow_lowercase: false,
+                pair: false,
+            },
+            exchanges: HashMap::from([
+                (
+                    Exchange::Xetra,
+                    SymbolRule {
+                        max_len: 12,
+                        separators: ".".to_string(),
+                        allow_lowercase: false,
+                        pair: false,
+                    },
+                ),
+                (Exchange::Crypto, crypto.clone()),
+            ]),
+            asset_classes: HashMap::from([(AssetClass::Crypto, crypto)]),
+        }
+    }
+
+    /// Loads rules from JSON such as
+    /// `{"default": {"max_len": 10, "separators": ".-"}, "exchanges": {"XETRA": {...}}}`
+    pub fn from_json_file(path: impl AsRef<Path>) -> Result<Self, DatabaseError> {
+        let path = path.as_ref();
+        serde_json::from_str(&fs::read_to_string(path)?).map_err(|e| {
+            DatabaseError::InvalidInput(format!("Invalid symbol rules {}: {}", path.display(), e))
+        })
+    }
+
+    /// Rule for a symbol of `asset_class` listed on `exchange`; the asset class wins
+    pub fn rule(&self, exchange: Option<Exchange>, asset_class: Option<AssetClass>) -> &SymbolRule {
+        asset_class
+            .and_then(|asset_class| self.asset_classes.get(&asset_class))
+            .or_else(|| exchange.and_then(|exchange| self.exchanges.get(&exchange)))
+            .unwrap_or(&self.default)
+    }
+
+    /// Checks `symbol` against the rule of its exchange and asset class
+    pub fn validate(
+        &self,
+        symbol: &str,
+        exchange: Option<Exchange>,
+        asset_class: Option<AssetClass>,
+    ) -> Result<(), DatabaseError> {
+        if self.rule(exchange, asset_class).accepts(symbol) {
+            Ok(())
+        } else {
+            Err(DatabaseError::InvalidTicker)
+        }
+    }
+
+    /// Checks a symbol whose exchange is unknown: any rule may accept it
+    pub fn validate_any(&self, symbol: &str) -> Result<(), DatabaseError> {
+        let accepted = self.default.accepts(symbol)
+            || self.exchanges.values().any(|rule| rule.accepts(symbol))
+            || self.asset_classes.values().any(|rule| rule.accepts(symbol));
+        if accepted {
+            Ok(())
+        } else {
+            Err(DatabaseError::InvalidTicker)
+        }
+    }
+}
+
+fn installed() -> &'static RwLock<Arc<SymbolRules>> {
+    static RULES: OnceLock<RwLock<Arc<SymbolRules>>> = OnceLock::new();
+    RULES.get_or_init(|| RwLock::new(Arc::new(SymbolRules::builtin())))
+}
+
+/// Rules in effect for this process, the built-in ones unless others were installed
+pub fn symbol_rules() -> Arc<SymbolRules> {
+    Arc::clone(&installed().read().unwrap())
+}
+
+/// Replaces the rules used by block validation and the data layer
+pub fn install_symbol_rules(rules: SymbolRules) {
+    *installed().write().unwrap() = Arc::new(rules);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_rules_per_exchange() {
+        let rules = SymbolRules::builtin();
+        for (symbol, exchange) in [
+            ("AAPL", Exchange::Nasdaq),
+            ("BRK.B", Exchange::Nyse),
+            ("BF-B", Exchange::Nyse),
+            ("SAP.DE", Exchange::Xetra),
+            ("BTC-USD", Exchange::Crypto),
+            ("MATIC-USDT", Exchange::Crypto),
+        ] {
+            assert!(
+                rules.validate(symbol, Some(exchange), None).is_ok(),
+                "{}",
+                symbol
+            );
+        }
+        for (symbol, exchange) in [
+            ("", Exchange::Nasdaq),
+            ("aapl", Exchange::Nasdaq),
+            ("TOOLONGTICKER", Exchange::Nasdaq),
+            (".B", Exchange::Nyse),
+            ("BRK..B", Exchange::Nyse),
+            ("SAP-DE", Exchange::Xetra),
+            ("BTC", Exchange::Crypto),
+            ("BTC-USD-PERP", Exchange::Crypto),
+        ] {
+            assert!(
+                rules.validate(symbol, Some(exchange), None).is_err(),
+                "{}",
+                symbol
+            );
+        }
+
+        // The asset class rule wins over the exchange's
+        assert!(rules
+            .validate("ETH-USD", Some(Exchange::Nasdaq), Some(AssetClass::Crypto))
+            .is_ok());
+        assert!(rules.validate_any("BTC-USDT").is_ok());
+        assert!(rules.validate_any("TOOLONGTICKER").is_err());
+    }
+
+    #[test]
+    fn test_rules_from_json() {
+        let rules: SymbolRules = serde_json::from_str(
+            r#"{
+                "default": { "max_len": 6 },
+                "exchanges": { "CRYPTO": { "max_len": 12, "separators": "-", "allow_lowercase": true, "pair": true } }
+            }"#,
+        )
+        .unwrap();
+        assert!(rules.validate("BRK.B", Some(Exchange::Nyse), None).is_err());
+        assert!(rules
+            .validate("btc-usd", Some(Exchange::Crypto), None)
+            .is_ok());
+        assert!(rules
+            .validate("AAPL", None, Some(AssetClass::Equity))
+            .is_ok());
+    }
+}
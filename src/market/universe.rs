@@ -0,0 +1,188 @@
+//! Point-in-time index membership.
+//! A universe such as `NASDAQ100` is stored as the dated additions and removals of its
+//! constituents, so a backtest only sees the tickers that were members on each execution
+//! date instead of today's list.
+
+use crate::market::calendar::split_row;
+use crate::market::database_functions::DatabaseError;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Whether a ticker joined or left a universe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MembershipAction {
+    Added,
+    Removed,
+}
+
+impl FromStr for MembershipAction {
+    type Err = DatabaseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "added" | "add" => Ok(MembershipAction::Added),
+            "removed" | "remove" => Ok(MembershipAction::Removed),
+            other => Err(DatabaseError::InvalidInput(format!(
+                "Unsupported membership action: {}",
+                other
+            ))),
+        }
+    }
+}
+
+/// A ticker joining or leaving a universe, effective from the session on `date`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MembershipChange {
+    pub ticker: String,
+    pub date: NaiveDate,
+    pub action: MembershipAction,
+}
+
+/// Members of a universe on `date`, sorted, given its changes ordered by date
+pub fn members_on(changes: &[MembershipChange], date: NaiveDate) -> Vec<String> {
+    let mut members = BTreeSet::new();
+    for change in changes.iter().take_while(|change| change.date <= date) {
+        match change.action {
+            MembershipAction::Added => members.insert(change.ticker.as_str()),
+            MembershipAction::Removed => members.remove(change.ticker.as_str()),
+        };
+    }
+    members.into_iter().map(str::to_string).collect()
+}
+
+/// Every ticker that is a member at some point between `start` and `end`
+pub fn members_between(
+    changes: &[MembershipChange],
+    start: NaiveDate,
+    end: NaiveDate,
+) -> BTreeSet<String> {
+    let mut members: BTreeSet<String> = members_on(changes, start).into_iter().collect();
+    members.extend(
+        changes
+            .iter()
+            .filter(|change| {
+                change.action == MembershipAction::Added
+                    && change.date > start
+                    && change.date <= end
+            })
+            .map(|change| change.ticker.clone()),
+    );
+    members
+}
+
+/// File in a price directory holding the membership changes of its universes
+pub const INDEX_MEMBERSHIP_FILE: &str = "index_membership.csv";
+
+/// Parses rows with `universe`, `ticker`, `date` and `action` (`added` or `removed`)
+/// columns into the changes of every universe, ordered by date
+pub fn parse_index_membership(
+    contents: &str,
+    source: &str,
+) -> Result<HashMap<String, Vec<MembershipChange>>, DatabaseError> {
+    let mut lines = contents.lines();
+    let header = split_row(lines.next().ok_or_else(|| {
+        DatabaseError::InvalidInput(format!("Empty index membership {}", source))
+    })?);
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|column| column.trim() == name)
+            .ok_or_else(|| {
+                DatabaseError::InvalidInput(format!("Missing column '{}' in {}", name, source))
+            })
+    };
+    let universe_index = column("universe")?;
+    let ticker_index = column("ticker")?;
+    let date_index = column("date")?;
+    let action_index = column("action")?;
+
+    let mut universes: HashMap<String, Vec<MembershipChange>> = HashMap::new();
+    for (line_number, line) in lines.enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let fields = split_row(line);
+        let invalid_row = |reason: &str| {
+            DatabaseError::InvalidInput(format!("{}:{}: {}", source, line_number + 2, reason))
+        };
+        let field = |index: usize| {
+            fields
+                .get(index)
+                .map(|field| field.trim())
+                .ok_or_else(|| invalid_row("missing column"))
+        };
+
+        let universe = field(universe_index)?;
+        let ticker = field(ticker_index)?;
+        if universe.is_empty() || ticker.is_empty() {
+            return Err(invalid_row("missing universe or ticker"));
+        }
+        universes
+            .entry(universe.to_string())
+            .or_default()
+            .push(MembershipChange {
+                ticker: ticker.to_string(),
+                date: NaiveDate::parse_from_str(field(date_index)?, "%Y-%m-%d")
+                    .map_err(|_| invalid_row("invalid date"))?,
+                action: field(action_index)?
+                    .parse()
+                    .map_err(|_| invalid_row("invalid action"))?,
+            });
+    }
+
+    // Stable, so same-day changes keep their file order
+    for changes in universes.values_mut() {
+        changes.sort_by_key(|change| change.date);
+    }
+    Ok(universes)
+}
+
+/// Loads a file in the format described at [`parse_index_membership`]
+pub fn load_index_membership(
+    path: impl AsRef<Path>,
+) -> Result<HashMap<String, Vec<MembershipChange>>, DatabaseError> {
+    let path = path.as_ref();
+    parse_index_membership(&fs::read_to_string(path)?, &path.display().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_members_follow_additions_and_removals() {
+        let universes = parse_index_membership(
+            "universe,ticker,date,action\n\
+             NASDAQ100,AAPL,2010-01-04,added\n\
+             NASDAQ100,MSFT,2010-01-04,added\n\
+             NASDAQ100,TSLA,2013-07-15,added\n\
+             NASDAQ100,MSFT,2012-03-19,removed\n\
+             DOW30,AAPL,2015-03-19,added\n",
+            "index_membership.csv",
+        )
+        .unwrap();
+        let nasdaq = &universes["NASDAQ100"];
+        let date = |year, month, day| NaiveDate::from_ymd_opt(year, month, day).unwrap();
+
+        assert!(members_on(nasdaq, date(2009, 12, 31)).is_empty());
+        assert_eq!(members_on(nasdaq, date(2010, 1, 4)), ["AAPL", "MSFT"]);
+        assert_eq!(members_on(nasdaq, date(2012, 3, 19)), ["AAPL"]);
+        assert_eq!(members_on(nasdaq, date(2020, 1, 2)), ["AAPL", "TSLA"]);
+
+        // MSFT left before the span, TSLA joined during it
+        let members = members_between(nasdaq, date(2013, 1, 2), date(2014, 1, 2));
+        assert_eq!(members.into_iter().collect::<Vec<_>>(), ["AAPL", "TSLA"]);
+
+        let result = parse_index_membership(
+            "universe,ticker,date,action\nNASDAQ100,AAPL,2010-01-04,joined\n",
+            "index_membership.csv",
+        );
+        assert!(matches!(result, Err(DatabaseError::InvalidInput(_))));
+    }
+}
@@ -1,2 +1,4 @@
 pub mod filter;
 pub mod models;
+pub mod screen;
+pub mod universe;
@@ -1,6 +1,9 @@
 //! Models for the investment portfolio block system.
 //! This module contains all data structures and their serialization logic.
 
+use crate::market::adjustments::PriceAdjustment;
+use crate::market::derived::DerivedSeries;
+use crate::market::security_master::AssetClass;
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
@@ -23,6 +26,8 @@ pub enum BlockType {
     Condition,
     Filter,
     Asset,
+    Universe,
+    Screen,
 }
 
 impl fmt::Display for BlockType {
@@ -33,6 +38,8 @@ impl fmt::Display for BlockType {
             BlockType::Condition => write!(f, "Condition"),
             BlockType::Filter => write!(f, "Filter"),
             BlockType::Asset => write!(f, "Asset"),
+            BlockType::Universe => write!(f, "Universe"),
+            BlockType::Screen => write!(f, "Screen"),
         }
     }
 }
@@ -53,6 +60,9 @@ pub enum BlockAttributes {
         values: Vec<f64>,
         #[serde(skip_serializing_if = "Option::is_none")]
         window_of_trading_days: Option<u32>,
+        /// Largest fraction of the block a single market-cap weighted asset may take
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_weight: Option<f64>,
     },
     Condition {
         function: FunctionDefinition,
@@ -67,7 +77,60 @@ pub enum BlockAttributes {
         ticker: String,
         company_name: String,
         exchange: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        proxy: Option<AssetProxy>,
+        /// Synthetic series built from another ticker instead of stored prices
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        derived: Option<DerivedSeries>,
     },
+    /// Assets that pass `screen` on each execution date
+    Screen {
+        screen: ScreenCriteria,
+    },
+    /// Point-in-time constituents of a named universe, e.g. `NASDAQ100`
+    Universe {
+        universe: String,
+        /// Exchange the constituents are evaluated on
+        exchange: String,
+    },
+}
+
+/// Metadata and liquidity an asset needs on an execution date to pass a `Screen` block
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ScreenCriteria {
+    /// Candidates are the members of this universe; every listed security when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub universe: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exchanges: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sectors: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub asset_classes: Vec<AssetClass>,
+    /// Lowest close on the execution date
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_price: Option<f64>,
+    /// Lowest average of close times volume over `dollar_volume_window` bars
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_average_dollar_volume: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dollar_volume_window: Option<u32>,
+    /// Fewest bars of history up to the execution date
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_history_days: Option<u32>,
+}
+
+/// Series whose returns stand in for an asset's before its first bar
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AssetProxy {
+    pub ticker: String,
+    /// Multiplier applied to the proxy's daily returns, e.g. 3.0 for a 3x leveraged fund
+    #[serde(default = "default_proxy_scale")]
+    pub scale: f64,
+}
+
+fn default_proxy_scale() -> f64 {
+    1.0
 }
 
 /// Types of weight calculations available
@@ -110,6 +173,19 @@ pub struct FunctionDefinition {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub window_of_days: Option<u32>,
     pub asset: String,
+    /// Prices the function is computed from; the run's default when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price_adjustment: Option<PriceAdjustment>,
+}
+
+impl FunctionDefinition {
+    /// The same function evaluated on `asset`
+    pub fn for_asset(&self, asset: &str) -> Self {
+        Self {
+            asset: asset.to_string(),
+            ..self.clone()
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -164,6 +240,9 @@ pub enum CompareToValue {
 pub struct SortFunction {
     pub function_name: FunctionName,
     pub window_of_days: u32,
+    /// Prices the function is computed from; the run's default when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price_adjustment: Option<PriceAdjustment>,
 }
 
 /// Selection configuration for filters
@@ -240,7 +319,7 @@ mod tests {
             },
             "operator": ">",
             "compare_to": {
-                "type": "fixed",
+                "type": "fixed_value",
                 "value": 150.0
             },
             "children": []
@@ -266,6 +345,7 @@ mod tests {
             ticker,
             company_name,
             exchange,
+            ..
         } = &block.attributes
         {
             assert_eq!(ticker, "AAPL");
@@ -0,0 +1,203 @@
+//! Screening of assets by reference data and liquidity.
+//! A `Screen` block stands for the assets that pass its criteria on each execution date:
+//! candidates come from a universe or from the security master, are narrowed by
+//! exchange, sector and asset class, then by the price, dollar volume and history they
+//! have up to that date.
+
+use crate::market::database_functions::DatabaseError;
+use crate::market::exchange::Exchange;
+use crate::market::execution_date::ExecutionDate;
+use crate::market::provider::MarketDataProvider;
+use crate::market::security_master::Security;
+use crate::portfolio::blocks::models::ScreenCriteria;
+use crate::portfolio::blocks::universe::universe_members;
+use chrono::NaiveDate;
+use std::sync::Arc;
+use tracing::debug;
+
+/// Bars averaged for `min_average_dollar_volume` when no window is given
+pub const DEFAULT_DOLLAR_VOLUME_WINDOW: u32 = 20;
+
+impl ScreenCriteria {
+    /// Whether candidates are narrowed by security master data
+    pub fn has_metadata_criteria(&self) -> bool {
+        !self.exchanges.is_empty() || !self.sectors.is_empty() || !self.asset_classes.is_empty()
+    }
+
+    /// Whether candidates are read from the security master
+    pub fn needs_security_master(&self) -> bool {
+        self.universe.is_none() || self.has_metadata_criteria()
+    }
+
+    /// Whether `security` is listed on one of the exchanges, in one of the sectors and
+    /// of one of the asset classes of the screen; empty lists accept everything
+    pub fn matches_metadata(&self, security: &Security) -> bool {
+        let listed_on = |names: &[String], value: &str| {
+            names.is_empty()
+                || names
+                    .iter()
+                    .any(|name| name.trim().eq_ignore_ascii_case(value.trim()))
+        };
+        listed_on(&self.exchanges, &security.exchange)
+            && (self.sectors.is_empty()
+                || security
+                    .sector
+                    .as_deref()
+                    .is_some_and(|sector| listed_on(&self.sectors, sector)))
+            && (self.asset_classes.is_empty() || self.asset_classes.contains(&security.asset_class))
+    }
+
+    /// Whether the screen reads OHLCV bars rather than closes
+    pub fn needs_bars(&self) -> bool {
+        self.min_average_dollar_volume.is_some()
+    }
+
+    fn dollar_volume_window(&self) -> usize {
+        self.dollar_volume_window
+            .unwrap_or(DEFAULT_DOLLAR_VOLUME_WINDOW) as usize
+    }
+
+    /// Bars the liquidity criteria read per execution date
+    pub fn lookback_rows(&self) -> usize {
+        let history = self.min_history_days.unwrap_or(0) as usize;
+        let dollar_volume = if self.needs_bars() {
+            self.dollar_volume_window()
+        } else {
+            0
+        };
+        history.max(dollar_volume).max(1)
+    }
+}
+
+/// Whether `security` traded at some point between `start` and `end`
+pub fn listed_between(security: &Security, start: NaiveDate, end: NaiveDate) -> bool {
+    security.first_trading_date.is_none_or(|first| first <= end)
+        && security.last_trading_date.is_none_or(|last| last >= start)
+}
+
+/// Exchange `security` is evaluated on; unsupported listings use the default
+pub fn listing_exchange(security: &Security) -> Exchange {
+    security.exchange.parse().unwrap_or_default()
+}
+
+/// Tickers passing `criteria` on `execution_date` with the exchange they are listed
+/// on, sorted by ticker
+pub async fn screen_assets(
+    market_data: &Arc<dyn MarketDataProvider>,
+    criteria: &ScreenCriteria,
+    execution_date: ExecutionDate,
+) -> Result<Vec<(String, Exchange)>, DatabaseError> {
+    let candidates: Vec<(String, Exchange)> = match &criteria.universe {
+        Some(universe) => {
+            let security_master = if criteria.has_metadata_criteria() {
+                Some(market_data.get_security_master().await?)
+            } else {
+                None
+            };
+            universe_members(market_data, universe, execution_date)
+                .await?
+                .into_iter()
+                .filter_map(|ticker| match &security_master {
+                    Some(master) => {
+                        let security = master.get(&ticker)?;
+                        criteria
+                            .matches_metadata(security)
+                            .then(|| (ticker, listing_exchange(security)))
+                    }
+                    None => Some((ticker, execution_date.exchange())),
+                })
+                .collect()
+        }
+        None => {
+            let date = execution_date.date();
+            let master = market_data.get_security_master().await?;
+            let mut candidates: Vec<_> = master
+                .iter()
+                .filter(|security| {
+                    listed_between(security, date, date) && criteria.matches_metadata(security)
+                })
+                .map(|security| (security.ticker.clone(), listing_exchange(security)))
+                .collect();
+            candidates.sort();
+            candidates
+        }
+    };
+
+    let mut passed = Vec::with_capacity(candidates.len());
+    for (ticker, exchange) in candidates {
+        if passes_liquidity(market_data, criteria, &ticker, execution_date).await? {
+            passed.push((ticker, exchange));
+        }
+    }
+    debug!(
+        "Screen passed {} assets on {}",
+        passed.len(),
+        execution_date
+    );
+    Ok(passed)
+}
+
+/// Whether `ticker` has the price, dollar volume and history `criteria` asks for as of
+/// `execution_date`. Tickers without enough data fail the screen.
+async fn passes_liquidity(
+    market_data: &Arc<dyn MarketDataProvider>,
+    criteria: &ScreenCriteria,
+    ticker: &str,
+    execution_date: ExecutionDate,
+) -> Result<bool, DatabaseError> {
+    let rows = criteria.lookback_rows();
+    let (closes, dollar_volumes): (Vec<f64>, Vec<f64>) = if criteria.needs_bars() {
+        match market_data
+            .get_bar_series(ticker, execution_date, rows as i64)
+            .await
+        {
+            Ok(bars) => bars
+                .iter()
+                .map(|bar| (bar.close, bar.close * bar.volume as f64))
+                .unzip(),
+            Err(DatabaseError::InsufficientData(_)) | Err(DatabaseError::AssetExcluded(_)) => {
+                return Ok(false)
+            }
+            Err(e) => return Err(e),
+        }
+    } else {
+        match market_data
+            .get_price_series(ticker, execution_date, rows as i64)
+            .await
+        {
+            Ok(points) => (points.iter().map(|point| point.close).collect(), Vec::new()),
+            Err(DatabaseError::InsufficientData(_)) | Err(DatabaseError::AssetExcluded(_)) => {
+                return Ok(false)
+            }
+            Err(e) => return Err(e),
+        }
+    };
+
+    let Some(&close) = closes.last() else {
+        return Ok(false);
+    };
+    if criteria
+        .min_history_days
+        .is_some_and(|days| closes.len() < days as usize)
+    {
+        return Ok(false);
+    }
+    if criteria
+        .min_price
+        .is_some_and(|min_price| close < min_price)
+    {
+        return Ok(false);
+    }
+    if let Some(min_dollar_volume) = criteria.min_average_dollar_volume {
+        let window = criteria.dollar_volume_window();
+        if dollar_volumes.len() < window {
+            return Ok(false);
+        }
+        let recent = &dollar_volumes[dollar_volumes.len() - window..];
+        let average = recent.iter().sum::<f64>() / window as f64;
+        if average < min_dollar_volume {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
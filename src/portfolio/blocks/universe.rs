@@ -0,0 +1,112 @@
+//! Expansion of `Universe` and `Screen` blocks into the assets they stand for on an
+//! execution date: the members of a universe, or the assets passing a screen.
+
+use crate::market::database_functions::DatabaseError;
+use crate::market::execution_date::ExecutionDate;
+use crate::market::provider::MarketDataProvider;
+use crate::market::universe::members_on;
+use crate::portfolio::blocks::models::{Block, BlockAttributes, BlockType};
+use crate::portfolio::blocks::screen::screen_assets;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::sync::Arc;
+use tracing::debug;
+
+/// Tickers that are members of `universe` on the session of `execution_date`, sorted
+pub async fn universe_members(
+    market_data: &Arc<dyn MarketDataProvider>,
+    universe: &str,
+    execution_date: ExecutionDate,
+) -> Result<Vec<String>, DatabaseError> {
+    let changes = market_data
+        .get_index_membership(universe, execution_date.date())
+        .await?;
+    let members = members_on(&changes, execution_date.date());
+    debug!(
+        "Universe {} has {} members on {}",
+        universe,
+        members.len(),
+        execution_date
+    );
+    Ok(members)
+}
+
+/// Tickers `attributes` stands for on `execution_date` with the exchange they are
+/// evaluated on, if it is a `Universe` or `Screen` block
+pub async fn block_members(
+    market_data: &Arc<dyn MarketDataProvider>,
+    attributes: &BlockAttributes,
+    execution_date: ExecutionDate,
+) -> Result<Option<Vec<(String, String)>>, DatabaseError> {
+    match attributes {
+        BlockAttributes::Universe { universe, exchange } => Ok(Some(
+            universe_members(market_data, universe, execution_date)
+                .await?
+                .into_iter()
+                .map(|ticker| (ticker, exchange.clone()))
+                .collect(),
+        )),
+        BlockAttributes::Screen { screen } => Ok(Some(
+            screen_assets(market_data, screen, execution_date)
+                .await?
+                .into_iter()
+                .map(|(ticker, exchange)| (ticker, exchange.to_string()))
+                .collect(),
+        )),
+        _ => Ok(None),
+    }
+}
+
+/// `blocks` with every `Universe` and `Screen` block replaced by an `Asset` block per
+/// member on `execution_date`. Members already listed as an asset are not repeated.
+pub async fn expand_universes<'a>(
+    blocks: &'a [Block],
+    market_data: &Arc<dyn MarketDataProvider>,
+    execution_date: ExecutionDate,
+) -> Result<Cow<'a, [Block]>, DatabaseError> {
+    if !blocks.iter().any(|block| {
+        matches!(
+            block.attributes,
+            BlockAttributes::Universe { .. } | BlockAttributes::Screen { .. }
+        )
+    }) {
+        return Ok(Cow::Borrowed(blocks));
+    }
+
+    let mut tickers: HashSet<String> = blocks
+        .iter()
+        .filter_map(|block| match &block.attributes {
+            BlockAttributes::Asset { ticker, .. } => Some(ticker.clone()),
+            _ => None,
+        })
+        .collect();
+
+    let mut expanded = Vec::with_capacity(blocks.len());
+    for block in blocks {
+        let Some(members) = block_members(market_data, &block.attributes, execution_date).await?
+        else {
+            expanded.push(block.clone());
+            continue;
+        };
+        for (ticker, exchange) in members {
+            if tickers.insert(ticker.clone()) {
+                expanded.push(member_asset(ticker, exchange));
+            }
+        }
+    }
+    Ok(Cow::Owned(expanded))
+}
+
+fn member_asset(ticker: String, exchange: String) -> Block {
+    Block {
+        blocktype: BlockType::Asset,
+        attributes: BlockAttributes::Asset {
+            company_name: ticker.clone(),
+            ticker,
+            exchange,
+            proxy: None,
+            derived: None,
+        },
+        children: None,
+    }
+}
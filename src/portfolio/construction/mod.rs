@@ -1 +1,2 @@
-pub mod validate_json;
\ No newline at end of file
+pub mod validate_json;
+pub mod validate_securities;
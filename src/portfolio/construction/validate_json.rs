@@ -1,11 +1,17 @@
 //! Investment portfolio block system validator.
 //! Implements validation rules for all block types and their configurations.
 
+use crate::market::exchange::Exchange;
+use crate::market::symbols::symbol_rules;
 use crate::portfolio::blocks::models::{
-    Block, BlockAttributes, BlockType, WeightType, AllocationType, CompareToValue, FunctionDefinition, FunctionName,
+    AllocationType, Block, BlockAttributes, BlockType, CompareToValue, FunctionDefinition,
+    FunctionName, WeightType,
 };
 use thiserror::Error;
 
+/// Longest lookback a screen may ask for, the data layer's limit
+const MAX_SCREEN_WINDOW: u32 = 500;
+
 /// Custom error types for validation failures
 #[derive(Error, Debug, PartialEq)]
 pub enum ValidationError {
@@ -24,6 +30,12 @@ pub enum ValidationError {
     #[error("Asset block validation failed: {0}")]
     AssetError(AssetError),
 
+    #[error("Universe block validation failed: {0}")]
+    UniverseError(UniverseError),
+
+    #[error("Screen block validation failed: {0}")]
+    ScreenError(ScreenError),
+
     #[error("Block type mismatch: expected {expected}, got {found}")]
     BlockTypeMismatch { expected: String, found: String },
 }
@@ -59,6 +71,9 @@ pub enum WeightError {
     #[error("Missing values array for specified weights")]
     MissingValues,
 
+    #[error("max_weight must be greater than 0 and at most 1 (found {0})")]
+    InvalidMaxWeight(f64),
+
     #[error("Invalid configuration: {0}")]
     InvalidConfiguration(String),
 }
@@ -85,7 +100,7 @@ pub enum FilterError {
     #[error("Invalid sort function: {0}")]
     InvalidSortFunction(String),
 
-    #[error("Child at position {0} must be an Asset block")]
+    #[error("Child at position {0} must be an Asset, Universe or Screen block")]
     NonAssetChild(usize),
 
     #[error("Missing sort function configuration")]
@@ -109,6 +124,50 @@ pub enum AssetError {
 
     #[error("Missing exchange")]
     MissingExchange,
+
+    #[error("Unsupported exchange: {0}")]
+    UnsupportedExchange(String),
+
+    #[error("Invalid ticker symbol for {exchange}: {ticker}")]
+    InvalidTicker { ticker: String, exchange: String },
+
+    #[error("Invalid proxy: {0}")]
+    InvalidProxy(String),
+
+    #[error("Invalid derived series: {0}")]
+    InvalidDerivedSeries(String),
+}
+
+/// Universe block specific errors
+#[derive(Debug, Error, PartialEq)]
+pub enum UniverseError {
+    #[error("Universe blocks cannot have children")]
+    HasChildren,
+
+    #[error("Missing universe name")]
+    MissingUniverse,
+
+    #[error("Unsupported exchange: {0}")]
+    UnsupportedExchange(String),
+}
+
+/// Screen block specific errors
+#[derive(Debug, Error, PartialEq)]
+pub enum ScreenError {
+    #[error("Screen blocks cannot have children")]
+    HasChildren,
+
+    #[error("Missing universe name")]
+    MissingUniverse,
+
+    #[error("Unsupported exchange: {0}")]
+    UnsupportedExchange(String),
+
+    #[error("{name} must be positive (found {value})")]
+    NonPositive { name: String, value: f64 },
+
+    #[error("{name} must be between 1 and {max} trading days (found {value})")]
+    InvalidWindow { name: String, value: u32, max: u32 },
 }
 
 /// Validation trait for block structures
@@ -153,6 +212,10 @@ impl Validate for Block {
             }
             (BlockType::Filter, BlockAttributes::Filter { .. }) => validate_filter_block(self)?,
             (BlockType::Asset, BlockAttributes::Asset { .. }) => validate_asset_block(self)?,
+            (BlockType::Universe, BlockAttributes::Universe { .. }) => {
+                validate_universe_block(self)?
+            }
+            (BlockType::Screen, BlockAttributes::Screen { .. }) => validate_screen_block(self)?,
             _ => {
                 return Err(ValidationError::BlockTypeMismatch {
                     expected: self.blocktype.to_string(),
@@ -176,7 +239,7 @@ fn validate_group_block(block: &Block) -> Result<(), ValidationError> {
         let children = block
             .children
             .as_ref()
-            .ok_or_else(|| ValidationError::GroupError(GroupError::NoChildren))?;
+            .ok_or(ValidationError::GroupError(GroupError::NoChildren))?;
 
         if children.is_empty() {
             return Err(ValidationError::GroupError(GroupError::NoChildren));
@@ -206,8 +269,24 @@ fn validate_weight_block(block: &Block) -> Result<(), ValidationError> {
         allocation_type,
         values,
         window_of_trading_days,
+        max_weight,
     } = &block.attributes
     {
+        if let Some(max_weight) = *max_weight {
+            if *weight_type != WeightType::MarketCap {
+                return Err(ValidationError::WeightError(
+                    WeightError::InvalidConfiguration(
+                        "max_weight only applies to market_cap weights".into(),
+                    ),
+                ));
+            }
+            if !(max_weight > 0.0 && max_weight <= 1.0) {
+                return Err(ValidationError::WeightError(WeightError::InvalidMaxWeight(
+                    max_weight,
+                )));
+            }
+        }
+
         match weight_type {
             WeightType::Specified => {
                 // Validate allocation type is present
@@ -241,21 +320,17 @@ fn validate_weight_block(block: &Block) -> Result<(), ValidationError> {
                     }
                 }
             }
-            WeightType::Equal => {
-                if !values.is_empty() {
-                    return Err(ValidationError::WeightError(
-                        WeightError::InvalidConfiguration(
-                            "Equal weights should not have values specified".into(),
-                        ),
-                    ));
-                }
+            WeightType::Equal if !values.is_empty() => {
+                return Err(ValidationError::WeightError(
+                    WeightError::InvalidConfiguration(
+                        "Equal weights should not have values specified".into(),
+                    ),
+                ));
             }
-            WeightType::InverseVolatility => {
-                if window_of_trading_days.is_none() {
-                    return Err(ValidationError::WeightError(
-                        WeightError::MissingVolatilityWindow,
-                    ));
-                }
+            WeightType::InverseVolatility if window_of_trading_days.is_none() => {
+                return Err(ValidationError::WeightError(
+                    WeightError::MissingVolatilityWindow,
+                ));
             }
             _ => {}
         }
@@ -281,19 +356,20 @@ fn validate_condition_block(block: &Block) -> Result<(), ValidationError> {
     } = &block.attributes
     {
         // Validate function configuration
-        validate_function_definition(function).map_err(|e| ValidationError::ConditionError(e))?;
+        validate_function_definition(function).map_err(ValidationError::ConditionError)?;
 
         // Validate compare_to function if present
         if let CompareToValue::Function { function } = compare_to {
-            validate_function_definition(function)
-                .map_err(|e| ValidationError::ConditionError(e))?;
+            validate_function_definition(function).map_err(ValidationError::ConditionError)?;
         }
 
         // Validate child count
         let children = block
             .children
             .as_ref()
-            .ok_or_else(|| ValidationError::ConditionError(ConditionError::InvalidChildCount(0)))?;
+            .ok_or(ValidationError::ConditionError(
+                ConditionError::InvalidChildCount(0),
+            ))?;
 
         if children.len() != 2 {
             return Err(ValidationError::ConditionError(
@@ -313,11 +389,7 @@ fn validate_condition_block(block: &Block) -> Result<(), ValidationError> {
 }
 
 fn validate_filter_block(block: &Block) -> Result<(), ValidationError> {
-    if let BlockAttributes::Filter {
-        sort_function,
-        select,
-    } = &block.attributes
-    {
+    if let BlockAttributes::Filter { sort_function, .. } = &block.attributes {
         // Validiere sort_function
         if !sort_function.function_name.requires_window_of_days() {
             return Err(ValidationError::FilterError(
@@ -326,18 +398,20 @@ fn validate_filter_block(block: &Block) -> Result<(), ValidationError> {
                 ),
             ));
         }
-        // Validate sort_function configuration
-        validate_function_definition(&FunctionDefinition {
-            function_name: sort_function.function_name.clone(),
-            window_of_days: Some(sort_function.window_of_days),
-            asset: "".to_string(), // Sort function doesn't require asset
-        })
-        .map_err(|e| ValidationError::ConditionError(e))?;
-
-        // Validate children are all Asset blocks
+        // Validate sort_function configuration (sort functions don't carry an asset)
+        validate_window_of_days(
+            &sort_function.function_name,
+            Some(sort_function.window_of_days),
+        )
+        .map_err(ValidationError::ConditionError)?;
+
+        // Validate children are all Asset or Universe blocks
         if let Some(children) = &block.children {
             for (index, child) in children.iter().enumerate() {
-                if child.blocktype != BlockType::Asset {
+                if !matches!(
+                    child.blocktype,
+                    BlockType::Asset | BlockType::Universe | BlockType::Screen
+                ) {
                     return Err(ValidationError::FilterError(FilterError::NonAssetChild(
                         index,
                     )));
@@ -352,11 +426,88 @@ fn validate_filter_block(block: &Block) -> Result<(), ValidationError> {
     }
 }
 
+fn validate_universe_block(block: &Block) -> Result<(), ValidationError> {
+    if let BlockAttributes::Universe { universe, exchange } = &block.attributes {
+        if block.children.is_some() {
+            return Err(ValidationError::UniverseError(UniverseError::HasChildren));
+        }
+        if universe.trim().is_empty() {
+            return Err(ValidationError::UniverseError(
+                UniverseError::MissingUniverse,
+            ));
+        }
+        if exchange.parse::<Exchange>().is_err() {
+            return Err(ValidationError::UniverseError(
+                UniverseError::UnsupportedExchange(exchange.clone()),
+            ));
+        }
+
+        Ok(())
+    } else {
+        unreachable!("Block type mismatch should have been caught earlier")
+    }
+}
+
+fn validate_screen_block(block: &Block) -> Result<(), ValidationError> {
+    if let BlockAttributes::Screen { screen } = &block.attributes {
+        let error = |error| Err(ValidationError::ScreenError(error));
+        if block.children.is_some() {
+            return error(ScreenError::HasChildren);
+        }
+        if screen
+            .universe
+            .as_ref()
+            .is_some_and(|universe| universe.trim().is_empty())
+        {
+            return error(ScreenError::MissingUniverse);
+        }
+        if let Some(exchange) = screen
+            .exchanges
+            .iter()
+            .find(|exchange| exchange.parse::<Exchange>().is_err())
+        {
+            return error(ScreenError::UnsupportedExchange(exchange.clone()));
+        }
+        for (name, value) in [
+            ("min_price", screen.min_price),
+            (
+                "min_average_dollar_volume",
+                screen.min_average_dollar_volume,
+            ),
+        ] {
+            if let Some(value) = value.filter(|value| !(value.is_finite() && *value > 0.0)) {
+                return error(ScreenError::NonPositive {
+                    name: name.to_string(),
+                    value,
+                });
+            }
+        }
+        for (name, value) in [
+            ("dollar_volume_window", screen.dollar_volume_window),
+            ("min_history_days", screen.min_history_days),
+        ] {
+            if let Some(value) = value.filter(|value| !(1..=MAX_SCREEN_WINDOW).contains(value)) {
+                return error(ScreenError::InvalidWindow {
+                    name: name.to_string(),
+                    value,
+                    max: MAX_SCREEN_WINDOW,
+                });
+            }
+        }
+
+        Ok(())
+    } else {
+        unreachable!("Block type mismatch should have been caught earlier")
+    }
+}
+
 fn validate_asset_block(block: &Block) -> Result<(), ValidationError> {
     if let BlockAttributes::Asset {
         ticker,
         company_name,
         exchange,
+        proxy,
+        derived,
     } = &block.attributes
     {
         // Validate no children
@@ -374,6 +525,70 @@ fn validate_asset_block(block: &Block) -> Result<(), ValidationError> {
         if exchange.trim().is_empty() {
             return Err(ValidationError::AssetError(AssetError::MissingExchange));
         }
+        let Ok(listing) = exchange.parse::<Exchange>() else {
+            return Err(ValidationError::AssetError(
+                AssetError::UnsupportedExchange(exchange.clone()),
+            ));
+        };
+        // Proxies trade on the asset's exchange as well
+        let symbol_rules = symbol_rules();
+        for symbol in std::iter::once(ticker).chain(proxy.as_ref().map(|proxy| &proxy.ticker)) {
+            if symbol_rules.validate(symbol, Some(listing), None).is_err() {
+                return Err(ValidationError::AssetError(AssetError::InvalidTicker {
+                    ticker: symbol.clone(),
+                    exchange: exchange.clone(),
+                }));
+            }
+        }
+        if let Some(proxy) = proxy {
+            if proxy.ticker.trim().is_empty() || proxy.ticker == *ticker {
+                return Err(ValidationError::AssetError(AssetError::InvalidProxy(
+                    format!("{} cannot proxy {}", proxy.ticker, ticker),
+                )));
+            }
+            if !proxy.scale.is_finite() || proxy.scale == 0.0 {
+                return Err(ValidationError::AssetError(AssetError::InvalidProxy(
+                    format!("scale must be a non-zero number, got {}", proxy.scale),
+                )));
+            }
+        }
+        if let Some(derived) = derived {
+            let invalid = |message: String| {
+                Err(ValidationError::AssetError(
+                    AssetError::InvalidDerivedSeries(message),
+                ))
+            };
+            if derived.underlying == *ticker
+                || symbol_rules
+                    .validate(&derived.underlying, Some(listing), None)
+                    .is_err()
+            {
+                return invalid(format!(
+                    "{} cannot be derived from {}",
+                    ticker, derived.underlying
+                ));
+            }
+            if !derived.leverage.is_finite() || derived.leverage == 0.0 {
+                return invalid(format!(
+                    "leverage must be a non-zero number, got {}",
+                    derived.leverage
+                ));
+            }
+            for (name, cost) in [
+                ("expense_ratio", derived.expense_ratio),
+                ("borrow_cost", derived.borrow_cost),
+            ] {
+                if !cost.is_finite() || !(0.0..1.0).contains(&cost) {
+                    return invalid(format!("{} must be between 0 and 1, got {}", name, cost));
+                }
+            }
+            if proxy.is_some() {
+                return invalid(format!(
+                    "{} cannot have both a proxy and a derived series",
+                    ticker
+                ));
+            }
+        }
 
         Ok(())
     } else {
@@ -388,23 +603,32 @@ fn validate_function_definition(function: &FunctionDefinition) -> Result<(), Con
             "Asset cannot be empty".to_string(),
         ));
     }
+    if symbol_rules().validate_any(&function.asset).is_err() {
+        return Err(ConditionError::FunctionError(format!(
+            "Invalid ticker symbol: {}",
+            function.asset
+        )));
+    }
 
+    validate_window_of_days(&function.function_name, function.window_of_days)
+}
+
+fn validate_window_of_days(
+    function_name: &FunctionName,
+    window_of_days: Option<u32>,
+) -> Result<(), ConditionError> {
     // Validate window_of_days based on function type
-    if function.function_name.requires_window_of_days() {
-        match function.window_of_days {
-            None => {
-                return Err(ConditionError::MissingWindowDays(
-                    function.function_name.to_string(),
-                ))
-            }
-            Some(days) if days == 0 => {
+    if function_name.requires_window_of_days() {
+        match window_of_days {
+            None => return Err(ConditionError::MissingWindowDays(function_name.to_string())),
+            Some(0) => {
                 return Err(ConditionError::FunctionError(
                     "Window of days must be greater than 0".to_string(),
                 ))
             }
             Some(days) => {
                 // Different limits for different functions
-                let max_days = match function.function_name {
+                let max_days = match function_name {
                     FunctionName::ExponentialMovingAverage => 500, // Increased limit for EMA
                     _ => 252, // Default limit for other functions
                 };
@@ -412,12 +636,12 @@ fn validate_function_definition(function: &FunctionDefinition) -> Result<(), Con
                 if days > max_days {
                     return Err(ConditionError::FunctionError(format!(
                         "Window of days cannot exceed {} for {}",
-                        max_days, function.function_name
+                        max_days, function_name
                     )));
                 }
             } //  _ => {}
         }
-    } else if function.window_of_days.is_some() {
+    } else if window_of_days.is_some() {
         return Err(ConditionError::InvalidWindowDays);
     }
 
@@ -516,6 +740,51 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_validate_market_cap_max_weight() {
+        let mut weight = json!({
+            "blocktype": "Weight",
+            "type": "market_cap",
+            "max_weight": 0.5,
+            "children": [
+                {
+                    "blocktype": "Asset",
+                    "ticker": "AAPL",
+                    "company_name": "Apple Inc.",
+                    "exchange": "NASDAQ"
+                },
+                {
+                    "blocktype": "Asset",
+                    "ticker": "MSFT",
+                    "company_name": "Microsoft Corporation",
+                    "exchange": "NASDAQ"
+                }
+            ]
+        });
+        let block: Block = serde_json::from_value(weight.clone()).unwrap();
+        assert!(block.validate().is_ok());
+
+        weight["max_weight"] = json!(1.5);
+        let block: Block = serde_json::from_value(weight.clone()).unwrap();
+        assert!(matches!(
+            block.validate(),
+            Err(ValidationError::WeightError(WeightError::InvalidMaxWeight(
+                _
+            )))
+        ));
+
+        // Only market-cap weights can be limited
+        weight["max_weight"] = json!(0.5);
+        weight["type"] = json!("equal");
+        let block: Block = serde_json::from_value(weight).unwrap();
+        assert!(matches!(
+            block.validate(),
+            Err(ValidationError::WeightError(
+                WeightError::InvalidConfiguration(_)
+            ))
+        ));
+    }
+
     #[test]
     fn test_validate_condition_block() {
         // Valid condition block
@@ -527,7 +796,7 @@ mod tests {
             },
             "operator": ">",
             "compare_to": {
-                "type": "fixed",
+                "type": "fixed_value",
                 "value": 150.0
             },
             "children": [
@@ -558,7 +827,7 @@ mod tests {
             },
             "operator": ">",
             "compare_to": {
-                "type": "fixed",
+                "type": "fixed_value",
                 "value": 150.0
             },
             "children": [
@@ -680,6 +949,107 @@ mod tests {
             block.validate(),
             Err(ValidationError::AssetError(AssetError::MissingTicker))
         ));
+
+        // Invalid asset (exchange without a calendar)
+        let invalid_asset = json!({
+            "blocktype": "Asset",
+            "ticker": "VOD",
+            "company_name": "Vodafone Group",
+            "exchange": "LSE"
+        });
+
+        let block: Block = serde_json::from_value(invalid_asset).unwrap();
+        assert_eq!(
+            block.validate(),
+            Err(ValidationError::AssetError(
+                AssetError::UnsupportedExchange("LSE".to_string())
+            ))
+        );
+
+        // Symbols follow the rules of the asset's exchange
+        for (ticker, exchange, valid) in [
+            ("BRK.B", "NYSE", true),
+            ("SAP.DE", "XETRA", true),
+            ("BTC-USD", "CRYPTO", true),
+            ("SAP-DE", "XETRA", false),
+            ("BTC", "CRYPTO", false),
+            ("brk.b", "NYSE", false),
+        ] {
+            let block: Block = serde_json::from_value(json!({
+                "blocktype": "Asset",
+                "ticker": ticker,
+                "company_name": "Listed Company",
+                "exchange": exchange
+            }))
+            .unwrap();
+            if valid {
+                assert!(block.validate().is_ok(), "{}", ticker);
+            } else {
+                assert_eq!(
+                    block.validate(),
+                    Err(ValidationError::AssetError(AssetError::InvalidTicker {
+                        ticker: ticker.to_string(),
+                        exchange: exchange.to_string(),
+                    }))
+                );
+            }
+        }
+
+        // Proxies need another ticker and a usable scale
+        let mut proxied_asset = json!({
+            "blocktype": "Asset",
+            "ticker": "TQQQ",
+            "company_name": "ProShares UltraPro QQQ",
+            "exchange": "NASDAQ",
+            "proxy": { "ticker": "QQQ", "scale": 3.0 }
+        });
+        let block: Block = serde_json::from_value(proxied_asset.clone()).unwrap();
+        assert!(block.validate().is_ok());
+
+        proxied_asset["proxy"] = json!({ "ticker": "QQQ", "scale": 0.0 });
+        let block: Block = serde_json::from_value(proxied_asset.clone()).unwrap();
+        assert!(matches!(
+            block.validate(),
+            Err(ValidationError::AssetError(AssetError::InvalidProxy(_)))
+        ));
+
+        proxied_asset["proxy"] = json!({ "ticker": "TQQQ" });
+        let block: Block = serde_json::from_value(proxied_asset).unwrap();
+        assert!(matches!(
+            block.validate(),
+            Err(ValidationError::AssetError(AssetError::InvalidProxy(_)))
+        ));
+
+        // Derived series need another underlying, a leverage and costs given as fractions
+        let mut derived_asset = json!({
+            "blocktype": "Asset",
+            "ticker": "SPY3X",
+            "company_name": "Synthetic 3x S&P 500",
+            "exchange": "NYSE",
+            "derived": {
+                "underlying": "SPY",
+                "leverage": 3.0,
+                "expense_ratio": 0.0091,
+                "borrow_cost": 0.05
+            }
+        });
+        let block: Block = serde_json::from_value(derived_asset.clone()).unwrap();
+        assert!(block.validate().is_ok());
+
+        for derived in [
+            json!({ "underlying": "SPY", "leverage": 0.0 }),
+            json!({ "underlying": "SPY3X", "leverage": 3.0 }),
+            json!({ "underlying": "SPY", "leverage": -1.0, "expense_ratio": 95.0 }),
+        ] {
+            derived_asset["derived"] = derived;
+            let block: Block = serde_json::from_value(derived_asset.clone()).unwrap();
+            assert!(matches!(
+                block.validate(),
+                Err(ValidationError::AssetError(
+                    AssetError::InvalidDerivedSeries(_)
+                ))
+            ));
+        }
     }
 
     #[test]
@@ -689,6 +1059,7 @@ mod tests {
             function_name: FunctionName::CurrentPrice,
             window_of_days: Some(10),
             asset: "AAPL".to_string(),
+            price_adjustment: None,
         };
         assert!(matches!(
             validate_function_definition(&invalid_current_price),
@@ -700,6 +1071,7 @@ mod tests {
             function_name: FunctionName::CumulativeReturn,
             window_of_days: None,
             asset: "AAPL".to_string(),
+            price_adjustment: None,
         };
         assert!(matches!(
             validate_function_definition(&invalid_cumulative_return),
@@ -711,6 +1083,7 @@ mod tests {
             function_name: FunctionName::CurrentPrice,
             window_of_days: None,
             asset: "AAPL".to_string(),
+            price_adjustment: None,
         };
         assert!(validate_function_definition(&valid_current_price).is_ok());
 
@@ -719,6 +1092,7 @@ mod tests {
             function_name: FunctionName::CumulativeReturn,
             window_of_days: Some(10),
             asset: "AAPL".to_string(),
+            price_adjustment: None,
         };
         assert!(validate_function_definition(&valid_cumulative_return).is_ok());
     }
@@ -0,0 +1,270 @@
+//! Checks a strategy's tickers against the security master before a run.
+//! Structural validation (`validate_json`) accepts any well-formed ticker; this flags
+//! tickers the master does not know, `Asset` blocks whose exchange or company name
+//! disagree with it and assets delisted before the end of the run.
+
+use crate::market::security_master::SecurityMaster;
+use crate::portfolio::blocks::models::{Block, BlockAttributes, CompareToValue};
+use chrono::NaiveDate;
+use std::collections::HashSet;
+use thiserror::Error;
+
+/// Disagreement between a strategy and the security master
+#[derive(Debug, Error, PartialEq)]
+pub enum SecurityIssue {
+    #[error("{0} is not in the security master")]
+    UnknownTicker(String),
+
+    #[error("{ticker} is declared on {declared} but listed on {listed}")]
+    ExchangeMismatch {
+        ticker: String,
+        declared: String,
+        listed: String,
+    },
+
+    #[error("{ticker} is declared as '{declared}' but listed as '{listed}'")]
+    NameMismatch {
+        ticker: String,
+        declared: String,
+        listed: String,
+    },
+
+    #[error("{ticker} was delisted (last traded {})", last_trading_date.map_or("on an unknown date".to_string(), |date| date.to_string()))]
+    Delisted {
+        ticker: String,
+        last_trading_date: Option<NaiveDate>,
+    },
+}
+
+impl SecurityIssue {
+    /// Whether the run should not start; names are only reported since they are often
+    /// spelled differently
+    pub fn is_error(&self) -> bool {
+        !matches!(self, SecurityIssue::NameMismatch { .. })
+    }
+}
+
+/// Every issue of the tickers `strategy` holds or evaluates, for a run ending on
+/// `last_date`. Derived assets are checked through their underlying.
+pub fn validate_securities(
+    strategy: &Block,
+    master: &SecurityMaster,
+    last_date: NaiveDate,
+) -> Vec<SecurityIssue> {
+    let mut checker = Checker {
+        master,
+        last_date,
+        derived: HashSet::new(),
+        issues: Vec::new(),
+    };
+    collect_derived(strategy, &mut checker.derived);
+    checker.visit(strategy);
+    checker.issues
+}
+
+fn collect_derived(block: &Block, derived: &mut HashSet<String>) {
+    if let BlockAttributes::Asset {
+        ticker,
+        derived: Some(_),
+        ..
+    } = &block.attributes
+    {
+        derived.insert(ticker.clone());
+    }
+    for child in block.children.as_deref().unwrap_or_default() {
+        collect_derived(child, derived);
+    }
+}
+
+struct Checker<'a> {
+    master: &'a SecurityMaster,
+    last_date: NaiveDate,
+    /// Tickers built from another ticker, which have no entry of their own
+    derived: HashSet<String>,
+    issues: Vec<SecurityIssue>,
+}
+
+impl Checker<'_> {
+    fn report(&mut self, issue: SecurityIssue) {
+        if !self.issues.contains(&issue) {
+            self.issues.push(issue);
+        }
+    }
+
+    /// Flags unknown and delisted tickers
+    fn check_ticker(&mut self, ticker: &str) {
+        if self.derived.contains(ticker) {
+            return;
+        }
+        let Some(security) = self.master.get(ticker) else {
+            self.report(SecurityIssue::UnknownTicker(ticker.to_string()));
+            return;
+        };
+
+        let delisted_in_run = security
+            .last_trading_date
+            .is_none_or(|last| last < self.last_date);
+        if security.delisted && delisted_in_run {
+            self.report(SecurityIssue::Delisted {
+                ticker: ticker.to_string(),
+                last_trading_date: security.last_trading_date,
+            });
+        }
+    }
+
+    fn visit(&mut self, block: &Block) {
+        match &block.attributes {
+            BlockAttributes::Asset {
+                ticker,
+                company_name,
+                exchange,
+                proxy,
+                derived,
+            } => {
+                if let Some(proxy) = proxy {
+                    self.check_ticker(&proxy.ticker);
+                }
+                if let Some(derived) = derived {
+                    self.check_ticker(&derived.underlying);
+                } else {
+                    self.check_asset(ticker, company_name, exchange);
+                }
+            }
+            BlockAttributes::Condition {
+                function,
+                compare_to,
+                ..
+            } => {
+                self.check_ticker(&function.asset);
+                if let CompareToValue::Function { function } = compare_to {
+                    self.check_ticker(&function.asset);
+                }
+            }
+            _ => {}
+        }
+
+        for child in block.children.as_deref().unwrap_or_default() {
+            self.visit(child);
+        }
+    }
+
+    fn check_asset(&mut self, ticker: &str, company_name: &str, exchange: &str) {
+        self.check_ticker(ticker);
+        let Some(security) = self.master.get(ticker) else {
+            return;
+        };
+
+        if !security
+            .exchange
+            .trim()
+            .eq_ignore_ascii_case(exchange.trim())
+        {
+            self.report(SecurityIssue::ExchangeMismatch {
+                ticker: ticker.to_string(),
+                declared: exchange.to_string(),
+                listed: security.exchange.clone(),
+            });
+        }
+        if normalized_name(&security.name) != normalized_name(company_name) {
+            self.report(SecurityIssue::NameMismatch {
+                ticker: ticker.to_string(),
+                declared: company_name.to_string(),
+                listed: security.name.clone(),
+            });
+        }
+    }
+}
+
+/// Company name without case, punctuation or spacing differences
+fn normalized_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn master() -> SecurityMaster {
+        SecurityMaster::parse(
+            "ticker,name,exchange,asset_class,sector,currency,first_trading_date,last_trading_date,delisted\n\
+             AAPL,Apple Inc.,NASDAQ,equity,Technology,USD,1980-12-12,,False\n\
+             TWTR,\"Twitter, Inc.\",NYSE,equity,Communication Services,USD,2013-11-07,2022-10-27,True\n\
+             SPY,SPDR S&P 500 ETF Trust,NYSE,etf,,USD,1993-01-29,,False\n",
+            "securities.csv",
+        )
+        .unwrap()
+    }
+
+    fn asset(ticker: &str, company_name: &str, exchange: &str) -> serde_json::Value {
+        json!({
+            "blocktype": "Asset",
+            "ticker": ticker,
+            "company_name": company_name,
+            "exchange": exchange
+        })
+    }
+
+    #[test]
+    fn test_flags_strategy_securities() {
+        let mut synthetic = asset("SPY3X", "Synthetic 3x S&P 500", "NYSE");
+        synthetic["derived"] = json!({ "underlying": "SPY", "leverage": 3.0 });
+        let strategy: Block = serde_json::from_value(json!({
+            "blocktype": "Condition",
+            "function": { "function_name": "current_price", "asset": "QQQ" },
+            "operator": ">",
+            "compare_to": { "type": "fixed_value", "value": 100.0 },
+            "children": [
+                {
+                    "blocktype": "Group",
+                    "name": "Stocks",
+                    "children": [
+                        asset("AAPL", "APPLE INC", "NYSE"),
+                        asset("TWTR", "Twitter Inc", "NYSE"),
+                        asset("MSFT", "Microsoft Corporation", "NASDAQ")
+                    ]
+                },
+                synthetic
+            ]
+        }))
+        .unwrap();
+
+        let date = |year, month, day| NaiveDate::from_ymd_opt(year, month, day).unwrap();
+        let issues = validate_securities(&strategy, &master(), date(2025, 1, 1));
+        assert_eq!(
+            issues,
+            vec![
+                SecurityIssue::UnknownTicker("QQQ".to_string()),
+                SecurityIssue::ExchangeMismatch {
+                    ticker: "AAPL".to_string(),
+                    declared: "NYSE".to_string(),
+                    listed: "NASDAQ".to_string(),
+                },
+                SecurityIssue::Delisted {
+                    ticker: "TWTR".to_string(),
+                    last_trading_date: Some(date(2022, 10, 27)),
+                },
+                SecurityIssue::UnknownTicker("MSFT".to_string()),
+            ]
+        );
+
+        // Twitter was still listed in a run ending before its delisting
+        let issues = validate_securities(&strategy, &master(), date(2020, 1, 1));
+        assert!(!issues
+            .iter()
+            .any(|issue| matches!(issue, SecurityIssue::Delisted { .. })));
+    }
+
+    #[test]
+    fn test_name_mismatches_are_warnings() {
+        let strategy: Block =
+            serde_json::from_value(asset("AAPL", "Apple Computer", "NASDAQ")).unwrap();
+        let date = |year| NaiveDate::from_ymd_opt(year, 1, 1).unwrap();
+        let issues = validate_securities(&strategy, &master(), date(2025));
+        assert_eq!(issues.len(), 1);
+        assert!(!issues[0].is_error());
+    }
+}
@@ -1,3 +1,5 @@
+pub mod options;
+pub mod preload;
 pub mod sequential_execution;
 pub mod strategy_executor;
 pub mod time_based_execution;
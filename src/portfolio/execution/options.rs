@@ -0,0 +1,44 @@
+//! Run-wide settings for strategy execution.
+
+use crate::market::adjustments::PriceAdjustment;
+use crate::market::calendar::CalendarRegistry;
+use crate::market::missing_data::MissingDataPolicy;
+use std::sync::Arc;
+
+/// Settings shared by every execution date of a run
+#[derive(Debug, Clone)]
+pub struct ExecutionOptions {
+    /// Trading calendars the strategy's exchanges are scheduled on
+    pub calendars: Arc<CalendarRegistry>,
+    /// Prices used by functions that do not set their own `price_adjustment`
+    pub price_adjustment: PriceAdjustment,
+    /// What to do when an asset has no bar on the session it is evaluated on
+    pub missing_data: MissingDataPolicy,
+}
+
+impl Default for ExecutionOptions {
+    fn default() -> Self {
+        Self {
+            calendars: Arc::new(CalendarRegistry::builtin()),
+            price_adjustment: PriceAdjustment::Raw,
+            missing_data: MissingDataPolicy::Error,
+        }
+    }
+}
+
+impl ExecutionOptions {
+    pub fn with_calendars(mut self, calendars: Arc<CalendarRegistry>) -> Self {
+        self.calendars = calendars;
+        self
+    }
+
+    pub fn with_price_adjustment(mut self, price_adjustment: PriceAdjustment) -> Self {
+        self.price_adjustment = price_adjustment;
+        self
+    }
+
+    pub fn with_missing_data(mut self, missing_data: MissingDataPolicy) -> Self {
+        self.missing_data = missing_data;
+        self
+    }
+}
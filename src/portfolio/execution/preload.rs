@@ -0,0 +1,857 @@
+//! Preload planning for backtests.
+//! Walks a strategy's block tree once, collects every ticker it evaluates together with
+//! the longest lookback it needs, and loads those series up front. The exchange of every
+//! `Asset` block decides which sessions the strategy is evaluated on. Proxies of assets
+//! are loaded with the same lookback as the asset they stand in for, and derived series
+//! are planned as their underlying. Assets under market-cap weights also load their
+//! share counts. Universes and screens are planned once for every candidate they may
+//! stand for during the run, which are only known after the universe's membership or
+//! the security master has been loaded.
+
+use crate::market::adjustments::{AdjustedProvider, PriceAdjustment};
+use crate::market::database_functions::DatabaseError;
+use crate::market::derived::{DerivedProvider, DerivedSeries};
+use crate::market::exchange::Exchange;
+use crate::market::missing_data::MissingDataLog;
+use crate::market::preloaded::{PreloadedProvider, TickerPreload};
+use crate::market::price_store::{default_window, lookback_rows};
+use crate::market::provider::MarketDataProvider;
+use crate::market::security_master::SecurityMaster;
+use crate::market::sessions::SessionProvider;
+use crate::market::universe::members_between;
+use crate::portfolio::blocks::models::{
+    AssetProxy, Block, BlockAttributes, CompareToValue, FunctionDefinition, FunctionName,
+    ScreenCriteria, WeightType,
+};
+use crate::portfolio::blocks::screen::{listed_between, listing_exchange};
+use crate::portfolio::execution::options::ExecutionOptions;
+use chrono::NaiveDate;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+/// Default inverse volatility window, kept in line with the strategy executor
+const DEFAULT_VOLATILITY_WINDOW: u32 = 252;
+
+/// Every ticker a strategy reads prices for, with the bars it needs per execution date
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PreloadPlan {
+    lookbacks: BTreeMap<String, usize>,
+    exchanges: BTreeMap<String, Exchange>,
+    /// Tickers with a function that asks for adjusted prices
+    adjusted: BTreeSet<String>,
+    /// Tickers with a function that uses the run's default prices
+    unspecified: BTreeSet<String>,
+    /// Proxy of every asset that declares one
+    proxies: BTreeMap<String, AssetProxy>,
+    /// Assets built from another ticker's returns
+    derived: BTreeMap<String, DerivedSeries>,
+    /// Tickers weighted by market cap
+    market_cap: BTreeSet<String>,
+    /// What the strategy evaluates for the members of each universe
+    universes: BTreeMap<String, MemberPlan>,
+    /// What the strategy evaluates for the assets passing each screen
+    screens: Vec<(ScreenCriteria, MemberPlan)>,
+    /// Tickers whose OHLCV bars are read, not only their closes
+    bars: BTreeSet<String>,
+}
+
+/// Exchange and functions shared by every member of a universe or screen
+#[derive(Debug, Clone, Default, PartialEq)]
+struct MemberPlan {
+    exchange: Option<Exchange>,
+    functions: Vec<(FunctionName, Option<u32>, Option<PriceAdjustment>)>,
+    market_cap: bool,
+}
+
+impl PreloadPlan {
+    /// Fails when an `Asset` block names an unsupported exchange, when the same ticker
+    /// is listed on two different exchanges, declares two different proxies or derived
+    /// series, or is derived from another derived series
+    pub fn from_strategy(strategy: &Block) -> Result<Self, DatabaseError> {
+        let mut plan = Self::default();
+        plan.visit(strategy)?;
+        plan.add_proxies()?;
+        plan.add_derived()?;
+        Ok(plan)
+    }
+
+    /// Bars needed for `ticker`, if the strategy evaluates it at all
+    pub fn lookback(&self, ticker: &str) -> Option<usize> {
+        self.lookbacks.get(ticker).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lookbacks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lookbacks.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.lookbacks
+            .iter()
+            .map(|(ticker, rows)| (ticker.as_str(), *rows))
+    }
+
+    /// Exchange `ticker` is listed on; tickers without an `Asset` block use the default
+    pub fn exchange(&self, ticker: &str) -> Exchange {
+        self.exchanges.get(ticker).copied().unwrap_or_default()
+    }
+
+    /// Whether evaluating `ticker` needs its splits and dividends when functions without
+    /// their own `price_adjustment` use `default`
+    pub fn needs_corporate_actions(&self, ticker: &str, default: PriceAdjustment) -> bool {
+        self.adjusted.contains(ticker)
+            || (default != PriceAdjustment::Raw && self.unspecified.contains(ticker))
+    }
+
+    /// Whether `ticker` is weighted by market cap and needs its share counts
+    pub fn needs_shares_outstanding(&self, ticker: &str) -> bool {
+        self.market_cap.contains(ticker)
+    }
+
+    /// Every universe the strategy draws assets from
+    pub fn universes(&self) -> impl Iterator<Item = &str> {
+        self.universes.keys().map(String::as_str)
+    }
+
+    /// Plans `members` of `universe` like assets listed in its place. Fails when a
+    /// member is also listed on another exchange.
+    pub fn add_universe_members(
+        &mut self,
+        universe: &str,
+        members: impl IntoIterator<Item = String>,
+    ) -> Result<(), DatabaseError> {
+        let Some(plan) = self.universes.get(universe).cloned() else {
+            return Ok(());
+        };
+        let exchange = plan.exchange.unwrap_or_default();
+        for member in members {
+            self.add_member(&plan, &member, Some(exchange))?;
+        }
+        Ok(())
+    }
+
+    /// Every screen the strategy draws assets from
+    pub fn screens(&self) -> impl Iterator<Item = &ScreenCriteria> {
+        self.screens.iter().map(|(screen, _)| screen)
+    }
+
+    /// Plans `candidates` of `screen` like assets listed in its place, with the bars its
+    /// liquidity criteria read. Candidates without an exchange use the default sessions.
+    pub fn add_screen_candidates(
+        &mut self,
+        screen: &ScreenCriteria,
+        candidates: impl IntoIterator<Item = (String, Option<Exchange>)>,
+    ) -> Result<(), DatabaseError> {
+        let Some((_, plan)) = self
+            .screens
+            .iter()
+            .find(|(criteria, _)| criteria == screen)
+            .cloned()
+        else {
+            return Ok(());
+        };
+        let rows = screen.lookback_rows();
+        for (ticker, exchange) in candidates {
+            self.add_member(&plan, &ticker, exchange)?;
+            let entry = self.lookbacks.entry(ticker.clone()).or_insert(0);
+            *entry = (*entry).max(rows);
+            if screen.needs_bars() {
+                self.bars.insert(ticker);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `ticker` is screened on OHLCV bars
+    pub fn needs_bars(&self, ticker: &str) -> bool {
+        self.bars.contains(ticker)
+    }
+
+    fn add_member(
+        &mut self,
+        plan: &MemberPlan,
+        ticker: &str,
+        exchange: Option<Exchange>,
+    ) -> Result<(), DatabaseError> {
+        if let Some(exchange) = exchange {
+            self.add_exchange(ticker, exchange)?;
+        }
+        for (function_name, window_of_days, price_adjustment) in &plan.functions {
+            self.add(ticker, function_name, *window_of_days, *price_adjustment);
+        }
+        if plan.market_cap {
+            self.market_cap.insert(ticker.to_string());
+        }
+        Ok(())
+    }
+
+    /// Exchange of every ticker held through an `Asset` block
+    pub fn exchanges(&self) -> impl Iterator<Item = (&str, Exchange)> {
+        self.exchanges
+            .iter()
+            .map(|(ticker, exchange)| (ticker.as_str(), *exchange))
+    }
+
+    /// Proxy of every asset that declares one
+    pub fn proxies(&self) -> impl Iterator<Item = (&str, &AssetProxy)> {
+        self.proxies
+            .iter()
+            .map(|(ticker, proxy)| (ticker.as_str(), proxy))
+    }
+
+    /// Every derived asset with the series it is built from
+    pub fn derived(&self) -> impl Iterator<Item = (&str, &DerivedSeries)> {
+        self.derived
+            .iter()
+            .map(|(ticker, series)| (ticker.as_str(), series))
+    }
+
+    fn add_proxy(&mut self, ticker: &str, proxy: &AssetProxy) -> Result<(), DatabaseError> {
+        match self.proxies.insert(ticker.to_string(), proxy.clone()) {
+            Some(previous) if previous != *proxy => Err(DatabaseError::InvalidInput(format!(
+                "{} has both {} and {} as proxy",
+                ticker, previous.ticker, proxy.ticker
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Plans every proxy like the asset it stands in for: same lookback, same prices,
+    /// evaluated on the asset's sessions
+    fn add_proxies(&mut self) -> Result<(), DatabaseError> {
+        let proxies: Vec<(String, String)> = self
+            .proxies
+            .iter()
+            .map(|(ticker, proxy)| (ticker.clone(), proxy.ticker.clone()))
+            .collect();
+
+        for (ticker, proxy) in proxies {
+            if let Some(rows) = self.lookback(&ticker) {
+                let entry = self.lookbacks.entry(proxy.clone()).or_insert(0);
+                *entry = (*entry).max(rows);
+            }
+            let exchange = self.exchange(&ticker);
+            self.add_exchange(&proxy, exchange)?;
+            if self.adjusted.contains(&ticker) {
+                self.adjusted.insert(proxy.clone());
+            }
+            if self.unspecified.contains(&ticker) {
+                self.unspecified.insert(proxy);
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces every derived asset by its underlying, which is loaded instead with the
+    /// same lookback and prices, on the derived asset's sessions
+    fn add_derived(&mut self) -> Result<(), DatabaseError> {
+        let derived: Vec<(String, String)> = self
+            .derived
+            .iter()
+            .map(|(ticker, series)| (ticker.clone(), series.underlying.clone()))
+            .collect();
+
+        for (ticker, underlying) in derived {
+            if self.derived.contains_key(&underlying) {
+                return Err(DatabaseError::InvalidInput(format!(
+                    "{} is derived from {}, which is derived itself",
+                    ticker, underlying
+                )));
+            }
+            // A synthetic series has no shares to load
+            self.market_cap.remove(&ticker);
+            if let Some(rows) = self.lookbacks.remove(&ticker) {
+                let entry = self.lookbacks.entry(underlying.clone()).or_insert(0);
+                *entry = (*entry).max(rows);
+            }
+            let exchange = self.exchange(&ticker);
+            self.add_exchange(&underlying, exchange)?;
+            if self.adjusted.remove(&ticker) {
+                self.adjusted.insert(underlying.clone());
+            }
+            if self.unspecified.remove(&ticker) {
+                self.unspecified.insert(underlying);
+            }
+        }
+        Ok(())
+    }
+
+    fn add_exchange(&mut self, ticker: &str, exchange: Exchange) -> Result<(), DatabaseError> {
+        match self.exchanges.insert(ticker.to_string(), exchange) {
+            Some(previous) if previous != exchange => Err(DatabaseError::InvalidInput(format!(
+                "{} is listed on both {} and {}",
+                ticker, previous, exchange
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn add(
+        &mut self,
+        ticker: &str,
+        function_name: &FunctionName,
+        window_of_days: Option<u32>,
+        price_adjustment: Option<PriceAdjustment>,
+    ) {
+        let period = window_of_days.unwrap_or_else(|| default_window(function_name)) as usize;
+        let rows = lookback_rows(function_name, period);
+        let entry = self.lookbacks.entry(ticker.to_string()).or_insert(0);
+        *entry = (*entry).max(rows);
+
+        match price_adjustment {
+            None => self.unspecified.insert(ticker.to_string()),
+            Some(PriceAdjustment::Raw) => false,
+            Some(_) => self.adjusted.insert(ticker.to_string()),
+        };
+    }
+
+    /// Plan shared by the members of a `Universe` or `Screen` block
+    fn member_plan(&mut self, attributes: &BlockAttributes) -> Option<&mut MemberPlan> {
+        match attributes {
+            BlockAttributes::Universe { universe, .. } => {
+                Some(self.universes.entry(universe.clone()).or_default())
+            }
+            BlockAttributes::Screen { screen } => {
+                let index = match self
+                    .screens
+                    .iter()
+                    .position(|(criteria, _)| criteria == screen)
+                {
+                    Some(index) => index,
+                    None => {
+                        self.screens.push((screen.clone(), MemberPlan::default()));
+                        self.screens.len() - 1
+                    }
+                };
+                Some(&mut self.screens[index].1)
+            }
+            _ => None,
+        }
+    }
+
+    fn add_to_members(
+        &mut self,
+        attributes: &BlockAttributes,
+        function_name: &FunctionName,
+        window_of_days: Option<u32>,
+        price_adjustment: Option<PriceAdjustment>,
+    ) {
+        if let Some(plan) = self.member_plan(attributes) {
+            plan.functions
+                .push((function_name.clone(), window_of_days, price_adjustment));
+        }
+    }
+
+    fn add_function(&mut self, function: &FunctionDefinition) {
+        self.add(
+            &function.asset,
+            &function.function_name,
+            function.window_of_days,
+            function.price_adjustment,
+        );
+    }
+
+    fn visit(&mut self, block: &Block) -> Result<(), DatabaseError> {
+        let children = block.children.as_deref().unwrap_or_default();
+
+        match &block.attributes {
+            BlockAttributes::Asset {
+                ticker,
+                exchange,
+                proxy,
+                derived,
+                ..
+            } => {
+                self.add_exchange(ticker, exchange.parse()?)?;
+                if let Some(proxy) = proxy {
+                    self.add_proxy(ticker, proxy)?;
+                }
+                if let Some(series) = derived {
+                    match self.derived.insert(ticker.clone(), series.clone()) {
+                        Some(previous) if previous != *series => {
+                            return Err(DatabaseError::InvalidInput(format!(
+                                "{} is derived from both {} and {}",
+                                ticker, previous.underlying, series.underlying
+                            )))
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            BlockAttributes::Condition {
+                function,
+                compare_to,
+                ..
+            } => {
+                self.add_function(function);
+                if let CompareToValue::Function { function } = compare_to {
+                    self.add_function(function);
+                }
+            }
+            BlockAttributes::Universe { universe, exchange } => {
+                let exchange: Exchange = exchange.parse()?;
+                let plan = self.universes.entry(universe.clone()).or_default();
+                match plan.exchange.replace(exchange) {
+                    Some(previous) if previous != exchange => {
+                        return Err(DatabaseError::InvalidInput(format!(
+                            "Universe {} is evaluated on both {} and {}",
+                            universe, previous, exchange
+                        )))
+                    }
+                    _ => {}
+                }
+            }
+            BlockAttributes::Screen { .. } => {
+                self.member_plan(&block.attributes);
+            }
+            BlockAttributes::Filter { sort_function, .. } => {
+                for child in children {
+                    match &child.attributes {
+                        BlockAttributes::Asset { ticker, .. } => self.add(
+                            ticker,
+                            &sort_function.function_name,
+                            Some(sort_function.window_of_days),
+                            sort_function.price_adjustment,
+                        ),
+                        attributes => self.add_to_members(
+                            attributes,
+                            &sort_function.function_name,
+                            Some(sort_function.window_of_days),
+                            sort_function.price_adjustment,
+                        ),
+                    }
+                }
+            }
+            BlockAttributes::Weight {
+                weight_type: WeightType::InverseVolatility,
+                window_of_trading_days,
+                ..
+            } => {
+                let window = window_of_trading_days.unwrap_or(DEFAULT_VOLATILITY_WINDOW);
+                let mut tickers = Vec::new();
+                let mut members = Vec::new();
+                collect_asset_tickers(children, &mut tickers, &mut members);
+                for ticker in tickers {
+                    self.add(
+                        ticker,
+                        &FunctionName::ReturnsStandardDeviation,
+                        Some(window),
+                        None,
+                    );
+                }
+                for attributes in members {
+                    self.add_to_members(
+                        attributes,
+                        &FunctionName::ReturnsStandardDeviation,
+                        Some(window),
+                        None,
+                    );
+                }
+            }
+            BlockAttributes::Weight {
+                weight_type: WeightType::MarketCap,
+                ..
+            } => {
+                // Market caps are priced at the traded close
+                let mut tickers = Vec::new();
+                let mut members = Vec::new();
+                collect_asset_tickers(children, &mut tickers, &mut members);
+                for ticker in tickers {
+                    self.add(
+                        ticker,
+                        &FunctionName::CurrentPrice,
+                        None,
+                        Some(PriceAdjustment::Raw),
+                    );
+                    self.market_cap.insert(ticker.to_string());
+                }
+                for attributes in members {
+                    if let Some(plan) = self.member_plan(attributes) {
+                        plan.functions.push((
+                            FunctionName::CurrentPrice,
+                            None,
+                            Some(PriceAdjustment::Raw),
+                        ));
+                        plan.market_cap = true;
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        for child in children {
+            self.visit(child)?;
+        }
+        Ok(())
+    }
+}
+
+/// Tickers of the `Asset` blocks and attributes of the `Universe` and `Screen` blocks
+/// in `blocks`
+fn collect_asset_tickers<'a>(
+    blocks: &'a [Block],
+    tickers: &mut Vec<&'a str>,
+    members: &mut Vec<&'a BlockAttributes>,
+) {
+    for block in blocks {
+        match &block.attributes {
+            BlockAttributes::Asset { ticker, .. } => tickers.push(ticker),
+            attributes @ (BlockAttributes::Universe { .. } | BlockAttributes::Screen { .. }) => {
+                members.push(attributes)
+            }
+            _ => {}
+        }
+        if let Some(children) = &block.children {
+            collect_asset_tickers(children, tickers, members);
+        }
+    }
+}
+
+/// Loads everything `strategy` evaluates between `first_date` and `last_date` once and
+/// returns a provider that serves those evaluations from memory, scheduled on the
+/// calendars of the strategy's exchanges and priced with the run's default adjustment.
+/// Derived assets are built from their preloaded underlying, universes and screens
+/// expand to every candidate between the two dates.
+/// Assets affected by the missing-data policy are recorded in `missing_data`.
+pub async fn preload_market_data(
+    market_data: &Arc<dyn MarketDataProvider>,
+    options: &ExecutionOptions,
+    missing_data: &Arc<MissingDataLog>,
+    strategy: &Block,
+    first_date: NaiveDate,
+    last_date: NaiveDate,
+) -> Result<Arc<dyn MarketDataProvider>, DatabaseError> {
+    let mut plan = PreloadPlan::from_strategy(strategy)?;
+    let mut memberships = Vec::new();
+    let universes: Vec<String> = plan.universes().map(str::to_string).collect();
+    for universe in universes {
+        let changes = market_data
+            .get_index_membership(&universe, last_date)
+            .await?;
+        plan.add_universe_members(&universe, members_between(&changes, first_date, last_date))?;
+        memberships.push((universe, changes));
+    }
+
+    let mut security_master: Option<Arc<SecurityMaster>> = None;
+    let screens: Vec<ScreenCriteria> = plan.screens().cloned().collect();
+    for screen in screens {
+        let master = if screen.needs_security_master() {
+            if security_master.is_none() {
+                security_master = Some(market_data.get_security_master().await?);
+            }
+            security_master.clone()
+        } else {
+            None
+        };
+
+        let candidates: Vec<(String, Option<Exchange>)> = match (&screen.universe, master) {
+            (Some(universe), master) => {
+                let changes = market_data
+                    .get_index_membership(universe, last_date)
+                    .await?;
+                let members = members_between(&changes, first_date, last_date);
+                memberships.push((universe.clone(), changes));
+                members
+                    .into_iter()
+                    .filter_map(|ticker| match &master {
+                        Some(master) => {
+                            let security = master.get(&ticker)?;
+                            screen
+                                .matches_metadata(security)
+                                .then(|| (ticker, Some(listing_exchange(security))))
+                        }
+                        None => Some((ticker, None)),
+                    })
+                    .collect()
+            }
+            (None, Some(master)) => master
+                .iter()
+                .filter(|security| {
+                    listed_between(security, first_date, last_date)
+                        && screen.matches_metadata(security)
+                })
+                .map(|security| (security.ticker.clone(), Some(listing_exchange(security))))
+                .collect(),
+            (None, None) => Vec::new(),
+        };
+        plan.add_screen_candidates(&screen, candidates)?;
+    }
+
+    let source = if plan.is_empty() {
+        Arc::clone(market_data)
+    } else {
+        let preloads = plan.iter().map(|(ticker, rows)| TickerPreload {
+            ticker: ticker.to_string(),
+            rows,
+            exchange: plan.exchange(ticker),
+            corporate_actions: plan.needs_corporate_actions(ticker, options.price_adjustment),
+            shares_outstanding: plan.needs_shares_outstanding(ticker),
+            bars: plan.needs_bars(ticker),
+        });
+        let preloaded =
+            PreloadedProvider::load(Arc::clone(market_data), preloads, first_date, last_date)
+                .await?;
+        let mut preloaded = memberships
+            .into_iter()
+            .fold(preloaded, |preloaded, (universe, changes)| {
+                preloaded.with_index_membership(&universe, changes)
+            });
+        if let Some(security_master) = security_master {
+            preloaded = preloaded.with_security_master(security_master);
+        }
+        Arc::new(preloaded)
+    };
+    let source: Arc<dyn MarketDataProvider> = if plan.derived.is_empty() {
+        source
+    } else {
+        let derived = plan
+            .derived()
+            .map(|(ticker, series)| (ticker.to_string(), series.clone()));
+        Arc::new(DerivedProvider::new(source, derived))
+    };
+
+    let exchanges = plan
+        .exchanges()
+        .map(|(ticker, exchange)| (ticker.to_string(), exchange));
+    let sessions = SessionProvider::new(source, Arc::clone(&options.calendars), exchanges)?
+        .with_missing_data(options.missing_data, Arc::clone(missing_data))
+        .with_proxies(
+            plan.proxies()
+                .map(|(ticker, proxy)| (ticker.to_string(), proxy.clone())),
+        );
+
+    if options.price_adjustment == PriceAdjustment::Raw {
+        Ok(Arc::new(sessions))
+    } else {
+        Ok(Arc::new(AdjustedProvider::new(
+            Arc::new(sessions),
+            options.price_adjustment,
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn asset(ticker: &str) -> serde_json::Value {
+        json!({
+            "blocktype": "Asset",
+            "ticker": ticker,
+            "company_name": format!("{} Inc.", ticker),
+            "exchange": "NASDAQ"
+        })
+    }
+
+    #[test]
+    fn test_plan_collects_max_lookback_per_ticker() {
+        let strategy: Block = serde_json::from_value(json!({
+            "blocktype": "Group",
+            "name": "Plan",
+            "children": [{
+                "blocktype": "Condition",
+                "function": {
+                    "function_name": "current_price",
+                    "asset": "SPY"
+                },
+                "operator": ">",
+                "compare_to": {
+                    "type": "function",
+                    "function": {
+                        "function_name": "simple_moving_average",
+                        "window_of_days": 200,
+                        "asset": "SPY",
+                        "price_adjustment": "total_return"
+                    }
+                },
+                "children": [
+                    {
+                        "blocktype": "Filter",
+                        "sort_function": {
+                            "function_name": "relative_strength_index",
+                            "window_of_days": 10
+                        },
+                        "select": { "option": "Top", "amount": 1 },
+                        "children": [asset("AAPL"), asset("MSFT")]
+                    },
+                    {
+                        "blocktype": "Weight",
+                        "type": "inverse_volatility",
+                        "window_of_trading_days": 60,
+                        "children": [asset("TLT"), asset("AAPL")]
+                    }
+                ]
+            }]
+        }))
+        .unwrap();
+
+        let plan = PreloadPlan::from_strategy(&strategy).unwrap();
+        assert_eq!(plan.len(), 4);
+        assert_eq!(plan.lookback("SPY"), Some(200));
+        assert_eq!(plan.lookback("MSFT"), Some(11));
+        assert_eq!(plan.lookback("AAPL"), Some(60));
+        assert_eq!(plan.lookback("TLT"), Some(60));
+        assert_eq!(plan.lookback("BIL"), None);
+        assert_eq!(plan.exchange("AAPL"), Exchange::Nasdaq);
+
+        // Only adjusted evaluations need splits and dividends
+        assert!(plan.needs_corporate_actions("SPY", PriceAdjustment::Raw));
+        assert!(!plan.needs_corporate_actions("TLT", PriceAdjustment::Raw));
+        assert!(plan.needs_corporate_actions("TLT", PriceAdjustment::TotalReturn));
+    }
+
+    #[test]
+    fn test_plan_loads_proxies_like_their_asset() {
+        let mut tqqq = asset("TQQQ");
+        tqqq["proxy"] = json!({ "ticker": "QQQ", "scale": 3.0 });
+        let strategy: Block = serde_json::from_value(json!({
+            "blocktype": "Filter",
+            "sort_function": {
+                "function_name": "cumulative_return",
+                "window_of_days": 60,
+                "price_adjustment": "total_return"
+            },
+            "select": { "option": "Top", "amount": 1 },
+            "children": [tqqq, asset("SOXL")]
+        }))
+        .unwrap();
+
+        let plan = PreloadPlan::from_strategy(&strategy).unwrap();
+        assert_eq!(plan.lookback("QQQ"), Some(60));
+        assert_eq!(plan.exchange("QQQ"), Exchange::Nasdaq);
+        assert!(plan.needs_corporate_actions("QQQ", PriceAdjustment::Raw));
+        assert_eq!(
+            plan.proxies().collect::<Vec<_>>(),
+            vec![(
+                "TQQQ",
+                &AssetProxy {
+                    ticker: "QQQ".to_string(),
+                    scale: 3.0
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn test_plan_loads_underlying_of_derived_assets() {
+        let mut synthetic = asset("QQQ3X");
+        synthetic["derived"] = json!({ "underlying": "QQQ", "leverage": 3.0 });
+        let mut tqqq = asset("TQQQ");
+        tqqq["proxy"] = json!({ "ticker": "QQQ3X" });
+        let strategy: Block = serde_json::from_value(json!({
+            "blocktype": "Weight",
+            "type": "inverse_volatility",
+            "window_of_trading_days": 90,
+            "children": [synthetic, tqqq]
+        }))
+        .unwrap();
+
+        // The proxy's lookback ends up on the underlying of the derived proxy
+        let plan = PreloadPlan::from_strategy(&strategy).unwrap();
+        assert_eq!(plan.lookback("QQQ3X"), None);
+        assert_eq!(plan.lookback("QQQ"), Some(90));
+        assert_eq!(plan.exchange("QQQ"), Exchange::Nasdaq);
+        assert_eq!(plan.derived().count(), 1);
+    }
+
+    #[test]
+    fn test_plan_loads_shares_for_market_cap_weights() {
+        let mut synthetic = asset("QQQ3X");
+        synthetic["derived"] = json!({ "underlying": "QQQ", "leverage": 3.0 });
+        let strategy: Block = serde_json::from_value(json!({
+            "blocktype": "Weight",
+            "type": "market_cap",
+            "max_weight": 0.5,
+            "children": [asset("AAPL"), asset("MSFT"), synthetic]
+        }))
+        .unwrap();
+
+        let plan = PreloadPlan::from_strategy(&strategy).unwrap();
+        assert_eq!(plan.lookback("AAPL"), Some(1));
+        assert!(plan.needs_shares_outstanding("AAPL"));
+        assert!(plan.needs_shares_outstanding("MSFT"));
+        assert!(!plan.needs_shares_outstanding("QQQ3X"));
+        assert!(!plan.needs_shares_outstanding("QQQ"));
+        assert!(!plan.needs_corporate_actions("AAPL", PriceAdjustment::TotalReturn));
+    }
+
+    #[test]
+    fn test_plan_expands_universe_members() {
+        let strategy: Block = serde_json::from_value(json!({
+            "blocktype": "Filter",
+            "sort_function": {
+                "function_name": "cumulative_return",
+                "window_of_days": 120
+            },
+            "select": { "option": "Top", "amount": 3 },
+            "children": [
+                { "blocktype": "Universe", "universe": "NASDAQ100", "exchange": "NASDAQ" },
+                asset("TLT")
+            ]
+        }))
+        .unwrap();
+
+        let mut plan = PreloadPlan::from_strategy(&strategy).unwrap();
+        assert_eq!(plan.universes().collect::<Vec<_>>(), ["NASDAQ100"]);
+        assert_eq!(plan.lookback("TLT"), Some(120));
+        assert_eq!(plan.lookback("AAPL"), None);
+
+        plan.add_universe_members("NASDAQ100", ["AAPL".to_string(), "TSLA".to_string()])
+            .unwrap();
+        assert_eq!(plan.lookback("AAPL"), Some(120));
+        assert_eq!(plan.lookback("TSLA"), Some(120));
+        assert_eq!(plan.exchange("TSLA"), Exchange::Nasdaq);
+    }
+
+    #[test]
+    fn test_plan_screens_candidates_on_bars() {
+        let strategy: Block = serde_json::from_value(json!({
+            "blocktype": "Weight",
+            "type": "inverse_volatility",
+            "window_of_trading_days": 20,
+            "children": [{
+                "blocktype": "Screen",
+                "screen": {
+                    "exchanges": ["NYSE"],
+                    "min_average_dollar_volume": 1000000.0,
+                    "dollar_volume_window": 60
+                }
+            }]
+        }))
+        .unwrap();
+
+        let mut plan = PreloadPlan::from_strategy(&strategy).unwrap();
+        let screens: Vec<_> = plan.screens().cloned().collect();
+        assert_eq!(screens.len(), 1);
+        assert_eq!(plan.lookback("XOM"), None);
+
+        plan.add_screen_candidates(&screens[0], [("XOM".to_string(), Some(Exchange::Nyse))])
+            .unwrap();
+        // The dollar volume window is longer than the volatility window
+        assert_eq!(plan.lookback("XOM"), Some(60));
+        assert_eq!(plan.exchange("XOM"), Exchange::Nyse);
+        assert!(plan.needs_bars("XOM"));
+        assert!(!plan.needs_bars("AAPL"));
+    }
+
+    #[test]
+    fn test_plan_rejects_conflicting_exchanges() {
+        let mut listed_twice = asset("SAP");
+        listed_twice["exchange"] = json!("XETRA");
+        let strategy: Block = serde_json::from_value(json!({
+            "blocktype": "Group",
+            "name": "Conflict",
+            "children": [asset("SAP"), listed_twice]
+        }))
+        .unwrap();
+
+        assert!(matches!(
+            PreloadPlan::from_strategy(&strategy),
+            Err(DatabaseError::InvalidInput(_))
+        ));
+    }
+}
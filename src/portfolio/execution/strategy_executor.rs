@@ -1,24 +1,26 @@
-use crate::market::database_functions::{self, DatabaseError};
+use crate::market::database_functions::DatabaseError;
+use crate::market::execution_date::ExecutionDate;
+use crate::market::market_cap::market_cap;
+use crate::market::provider::{in_ticker_order, BoxFuture, MarketDataProvider};
 use crate::portfolio::blocks::filter::apply_filter;
 use crate::portfolio::blocks::models::{
     Block, BlockAttributes, CompareToValue, ComparisonOperator, FunctionDefinition, FunctionName,
-    SelectOption, WeightType,
+    WeightType,
 };
+use crate::portfolio::blocks::universe::{block_members, expand_universes};
 
-use deadpool_postgres::Pool; // Import Pool and Client from deadpool-postgres
-use std::future::Future;
-use std::pin::Pin;
-use tracing::{debug, info};
+use std::sync::Arc;
+use tracing::{debug, warn};
 
 #[derive(Debug, Clone)]
 pub struct Allocation {
     pub ticker: String,
     pub weight: f64,
-    pub date: String,
+    pub date: ExecutionDate,
 }
 
 impl Allocation {
-    pub fn new(ticker: String, weight: f64, date: String) -> Result<Self, DatabaseError> {
+    pub fn new(ticker: String, weight: f64, date: ExecutionDate) -> Result<Self, DatabaseError> {
         if weight.is_finite() && weight >= 0.0 {
             Ok(Self {
                 ticker,
@@ -36,20 +38,18 @@ impl Allocation {
 
 pub async fn execute_strategy(
     block: &Block,
-    pool: &Pool,
-    execution_date: &String,
+    market_data: &Arc<dyn MarketDataProvider>,
+    execution_date: ExecutionDate,
 ) -> Result<Vec<Allocation>, DatabaseError> {
     //info!("Starting strategy execution for date: {}", execution_date);
-    let allocations = execute_block(block, pool, execution_date, 1.0).await?;
+    let allocations = execute_block(block, market_data, execution_date, 1.0).await?;
     normalize_weights(&allocations)
 }
 
-type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
-
 fn execute_block<'a>(
     block: &'a Block,
-    pool: &'a Pool,
-    execution_date: &'a String,
+    market_data: &'a Arc<dyn MarketDataProvider>,
+    execution_date: ExecutionDate,
     parent_weight: f64,
 ) -> BoxFuture<'a, Result<Vec<Allocation>, DatabaseError>> {
     Box::pin(async move {
@@ -57,7 +57,7 @@ fn execute_block<'a>(
             BlockAttributes::Group { name } => {
                 debug!("Executing group: {}", name);
                 if let Some(children) = &block.children {
-                    execute_children(children, pool, execution_date, parent_weight).await
+                    execute_children(children, market_data, execution_date, parent_weight).await
                 } else {
                     Ok(Vec::new())
                 }
@@ -68,16 +68,23 @@ fn execute_block<'a>(
                 compare_to,
             } => {
                 if let Some(children) = &block.children {
-                    let condition_met =
-                        evaluate_condition(function, operator, compare_to, pool, execution_date)
-                            .await?;
+                    let condition_met = evaluate_condition(
+                        function,
+                        operator,
+                        compare_to,
+                        market_data,
+                        execution_date,
+                    )
+                    .await?;
 
                     if condition_met {
                         debug!("Condition met - executing first branch");
-                        execute_block(&children[0], pool, execution_date, parent_weight).await
+                        execute_block(&children[0], market_data, execution_date, parent_weight)
+                            .await
                     } else if children.len() > 1 {
                         debug!("Condition not met - executing second branch");
-                        execute_block(&children[1], pool, execution_date, parent_weight).await
+                        execute_block(&children[1], market_data, execution_date, parent_weight)
+                            .await
                     } else {
                         Ok(Vec::new())
                     }
@@ -94,14 +101,14 @@ fn execute_block<'a>(
                     match weight_type {
                         WeightType::Equal => {
                             let weight = parent_weight / children.len() as f64;
-                            execute_children(children, pool, execution_date, weight).await
+                            execute_children(children, market_data, execution_date, weight).await
                         }
                         WeightType::Specified => {
                             let mut weighted_allocations = Vec::new();
                             for (child, &weight) in children.iter().zip(values.iter()) {
                                 let child_weight = parent_weight * (weight / 100.0);
-                                let mut child_allocations =
-                                    execute_block(child, pool, execution_date, child_weight)
+                                let child_allocations =
+                                    execute_block(child, market_data, execution_date, child_weight)
                                         .await?;
                                 weighted_allocations.extend(child_allocations);
                             }
@@ -122,7 +129,7 @@ fn execute_block<'a>(
                             // Get valid assets after conditions/filters
                             let temp_allocations = execute_children(
                                 children,
-                                pool,
+                                market_data,
                                 execution_date,
                                 1.0, // temporary equal weight for traversal
                             )
@@ -133,53 +140,43 @@ fn execute_block<'a>(
                                 return Ok(vec![Allocation::new(
                                     temp_allocations[0].ticker.clone(),
                                     parent_weight,
-                                    execution_date.clone(),
+                                    execution_date,
                                 )?]);
                             }
 
-                            // Extract unique tickers - using owned strings
+                            // One ticker per allocation, so a ticker reached through two
+                            // branches keeps both of its allocations
                             let tickers: Vec<String> =
                                 temp_allocations.iter().map(|a| a.ticker.clone()).collect();
 
                             // Get period from block attributes, default to 252 trading days (1 year)
                             let period = window_of_trading_days.unwrap_or(252);
 
-                            // Calculate volatilities in parallel using references
-                            let volatility_futures: Vec<_> = tickers
-                                .iter()
-                                .map(|ticker| {
-                                    let pool = pool.clone();
-                                    let exec_date = execution_date.clone();
-                                    let period = period as i64;
-                                    let ticker = ticker.clone();
-                                    tokio::spawn(async move {
-                                        let client = pool.get().await.map_err(|e| {
-                                            DatabaseError::InvalidCalculation(format!(
-                                                "Failed to get database client: {}",
-                                                e
-                                            ))
-                                        })?;
-                                        database_functions::get_returns_std_dev(
-                                            &client, &ticker, &exec_date, period,
-                                        )
-                                        .await
-                                        .map(|vol| (ticker, vol))
-                                    })
-                                })
-                                .collect();
+                            // Calculate all volatilities in one batch
+                            let function = FunctionDefinition {
+                                function_name: FunctionName::ReturnsStandardDeviation,
+                                window_of_days: Some(period),
+                                asset: String::new(),
+                                price_adjustment: None,
+                            };
+                            let volatilities = market_data
+                                .evaluate_function_batch(&function, &tickers, execution_date)
+                                .await?;
 
                             // Collect results and calculate inverse volatilities
                             let mut inverse_vols = Vec::with_capacity(tickers.len());
                             let mut total_inverse_vol = 0.0;
 
                             // Process results and handle errors
-                            for handle in volatility_futures {
-                                let (ticker, vol) = handle.await.map_err(|e| {
-                                    DatabaseError::InvalidCalculation(format!(
-                                        "Failed to calculate volatility: {}",
-                                        e
-                                    ))
-                                })??;
+                            for (ticker, volatility) in in_ticker_order(volatilities, &tickers) {
+                                let vol = match volatility {
+                                    Ok(volatility) => volatility,
+                                    Err(DatabaseError::AssetExcluded(ticker)) => {
+                                        debug!("Asset {} excluded for missing data", ticker);
+                                        continue;
+                                    }
+                                    Err(e) => return Err(e),
+                                };
 
                                 let inverse_vol = 1.0 / vol;
                                 if !inverse_vol.is_finite() || inverse_vol <= 0.0 {
@@ -193,56 +190,92 @@ fn execute_block<'a>(
                                 total_inverse_vol += inverse_vol;
                             }
 
+                            if inverse_vols.is_empty() {
+                                return Ok(Vec::new());
+                            }
+
                             // Create final allocations with normalized weights
                             let allocations = inverse_vols
                                 .into_iter()
                                 .map(|(ticker, inverse_vol)| {
                                     let weight = parent_weight * (inverse_vol / total_inverse_vol);
-                                    Allocation::new(ticker, weight, execution_date.clone())
+                                    Allocation::new(ticker, weight, execution_date)
                                 })
                                 .collect::<Result<Vec<_>, _>>()?;
 
                             Ok(allocations)
                         }
                         WeightType::MarketCap => {
-                            tracing::warn!(
-                                "Market cap weighting is currently using placeholder values. All stocks will be equally weighted."
-                            );
+                            let max_weight = if let BlockAttributes::Weight { max_weight, .. } =
+                                &block.attributes
+                            {
+                                *max_weight
+                            } else {
+                                None
+                            };
 
                             // Get valid assets after conditions/filters
                             let temp_allocations = execute_children(
                                 children,
-                                pool,
+                                market_data,
                                 execution_date,
                                 1.0, // temporary equal weight for traversal
                             )
                             .await?;
 
-                            // Early return for single asset case
-                            if temp_allocations.len() == 1 {
-                                return Ok(vec![Allocation::new(
-                                    temp_allocations[0].ticker.clone(),
-                                    parent_weight,
-                                    execution_date.clone(),
-                                )?]);
+                            // Calculate market caps in parallel
+                            let market_cap_futures: Vec<_> = temp_allocations
+                                .iter()
+                                .map(|allocation| {
+                                    let market_data = Arc::clone(market_data);
+                                    let ticker = allocation.ticker.clone();
+                                    tokio::spawn(async move {
+                                        market_cap(market_data.as_ref(), &ticker, execution_date)
+                                            .await
+                                            .map(|cap| (ticker, cap))
+                                    })
+                                })
+                                .collect();
+
+                            let mut market_caps = Vec::with_capacity(market_cap_futures.len());
+                            for handle in market_cap_futures {
+                                let result = handle.await.map_err(|e| {
+                                    DatabaseError::InvalidCalculation(format!(
+                                        "Failed to calculate market cap: {}",
+                                        e
+                                    ))
+                                })?;
+                                match result {
+                                    Ok(market_cap) => market_caps.push(market_cap),
+                                    Err(DatabaseError::AssetExcluded(ticker)) => {
+                                        debug!("Asset {} excluded for missing data", ticker);
+                                    }
+                                    Err(e) => return Err(e),
+                                }
                             }
 
-                            // Since market_cap returns same value for all stocks,
-                            // we can optimize by just doing equal weighting
-                            let weight = parent_weight / temp_allocations.len() as f64;
-                            let allocations = temp_allocations
+                            if market_caps.is_empty() {
+                                return Ok(Vec::new());
+                            }
+
+                            let total_market_cap: f64 =
+                                market_caps.iter().map(|(_, cap)| cap).sum();
+                            let mut weights: Vec<f64> = market_caps
+                                .iter()
+                                .map(|(_, cap)| cap / total_market_cap)
+                                .collect();
+                            if let Some(max_weight) = max_weight {
+                                cap_weights(&mut weights, max_weight);
+                            }
+
+                            market_caps
                                 .into_iter()
-                                .map(|alloc| {
-                                    Allocation::new(alloc.ticker, weight, execution_date.clone())
+                                .zip(weights)
+                                .map(|((ticker, _), weight)| {
+                                    Allocation::new(ticker, parent_weight * weight, execution_date)
                                 })
-                                .collect::<Result<Vec<_>, _>>()?;
-
-                            Ok(allocations)
+                                .collect::<Result<Vec<_>, _>>()
                         }
-                        _ => Err(DatabaseError::InvalidInput(format!(
-                            "Unsupported weight type: {:?}",
-                            weight_type
-                        ))),
                     }
                 } else {
                     Ok(Vec::new())
@@ -253,11 +286,16 @@ fn execute_block<'a>(
                 select,
             } => {
                 if let Some(children) = &block.children {
+                    let assets = expand_universes(children, market_data, execution_date).await?;
+                    if assets.is_empty() {
+                        warn!("Filter has no assets on {}", execution_date);
+                        return Ok(Vec::new());
+                    }
                     apply_filter(
-                        pool,
+                        market_data,
                         sort_function,
                         select,
-                        children,
+                        &assets,
                         execution_date,
                         parent_weight,
                     )
@@ -266,6 +304,24 @@ fn execute_block<'a>(
                     Ok(Vec::new())
                 }
             }
+            BlockAttributes::Universe { .. } | BlockAttributes::Screen { .. } => {
+                // Members share the block's weight equally
+                let members = block_members(market_data, &block.attributes, execution_date)
+                    .await?
+                    .unwrap_or_default();
+                if members.is_empty() {
+                    warn!(
+                        "{} block has no members on {}",
+                        block.blocktype, execution_date
+                    );
+                    return Ok(Vec::new());
+                }
+                let weight = parent_weight / members.len() as f64;
+                members
+                    .into_iter()
+                    .map(|(ticker, _)| Allocation::new(ticker, weight, execution_date))
+                    .collect()
+            }
             BlockAttributes::Asset { ticker, .. } => {
                 // Special handling for BIL (cash equivalent)
                 if ticker == "BIL" {
@@ -273,13 +329,13 @@ fn execute_block<'a>(
                     Ok(vec![Allocation {
                         ticker: String::from("CASH"),
                         weight: parent_weight,
-                        date: execution_date.clone(),
+                        date: execution_date,
                     }])
                 } else {
                     Ok(vec![Allocation {
                         ticker: ticker.clone(),
                         weight: parent_weight,
-                        date: execution_date.clone(),
+                        date: execution_date,
                     }])
                 }
             }
@@ -289,13 +345,14 @@ fn execute_block<'a>(
 
 async fn execute_children<'a>(
     children: &'a [Block],
-    pool: &'a Pool,
-    execution_date: &'a String,
+    market_data: &'a Arc<dyn MarketDataProvider>,
+    execution_date: ExecutionDate,
     weight: f64,
 ) -> Result<Vec<Allocation>, DatabaseError> {
     let mut all_allocations = Vec::new();
     for child in children {
-        let mut child_allocations = execute_block(child, pool, execution_date, weight).await?;
+        let mut child_allocations =
+            execute_block(child, market_data, execution_date, weight).await?;
         all_allocations.append(&mut child_allocations);
     }
     Ok(all_allocations)
@@ -305,8 +362,8 @@ async fn evaluate_condition(
     function: &FunctionDefinition,
     operator: &ComparisonOperator,
     compare_to: &CompareToValue,
-    pool: &Pool,
-    execution_date: &String,
+    market_data: &Arc<dyn MarketDataProvider>,
+    execution_date: ExecutionDate,
 ) -> Result<bool, DatabaseError> {
     debug!(
         "Starting condition evaluation: {:?} {:?}",
@@ -315,7 +372,9 @@ async fn evaluate_condition(
 
     // First function evaluation
     debug!("Evaluating first function: {:?}", function);
-    let function_value = evaluate_function(function, pool, execution_date).await?;
+    let function_value = market_data
+        .evaluate_function(function, execution_date)
+        .await?;
     debug!("First function value: {}", function_value);
 
     // Second function/value evaluation
@@ -324,7 +383,9 @@ async fn evaluate_condition(
             function: compare_function,
         } => {
             debug!("Evaluating comparison function: {:?}", compare_function);
-            evaluate_function(compare_function, pool, execution_date).await?
+            market_data
+                .evaluate_function(compare_function, execution_date)
+                .await?
         }
         CompareToValue::Fixed { value, .. } => {
             debug!("Using fixed comparison value: {}", value);
@@ -350,129 +411,56 @@ async fn evaluate_condition(
     Ok(result)
 }
 
-async fn evaluate_function(
-    function: &FunctionDefinition,
-    pool: &Pool,
-    execution_date: &String,
-) -> Result<f64, DatabaseError> {
-    debug!("Evaluating function with date: {}", execution_date);
-    //info!("Start eval");
-
-    // Get a client from the pool
-    let client = pool.get().await?;
-
-    match function.function_name {
-        FunctionName::CumulativeReturn => {
-            let result = database_functions::get_cumulative_return(
-                &client, // Pass the client instead of the pool
-                &function.asset,
-                execution_date,
-                function.window_of_days.unwrap_or(20) as i64,
-            )
-            .await?;
-
-            Ok(result)
-        }
-        FunctionName::CurrentPrice => {
-            let price = database_functions::get_current_price(
-                &client, // Pass the client instead of the pool
-                &function.asset,
-                execution_date,
-            )
-            .await?;
-
-            Ok(price.close)
-        }
-        FunctionName::RelativeStrengthIndex => {
-            let rsi = database_functions::get_rsi(
-                &client, // Pass the client instead of the pool
-                &function.asset,
-                execution_date,
-                function.window_of_days.unwrap_or(14) as i64,
-            )
-            .await?;
-
-            Ok(rsi)
-        }
-        FunctionName::SimpleMovingAverage => {
-            let sma = database_functions::get_sma(
-                &client, // Pass the client instead of the pool
-                &function.asset,
-                execution_date,
-                function.window_of_days.unwrap_or(20) as i64,
-            )
-            .await?;
-
-            Ok(sma)
-        }
-        FunctionName::ExponentialMovingAverage => {
-            let ema = database_functions::get_ema(
-                &client, // Pass the client instead of the pool
-                &function.asset,
-                execution_date,
-                function.window_of_days.unwrap_or(20) as i64,
-            )
-            .await?;
-
-            Ok(ema)
-        }
-        // FunctionName::MovingAverageOfPrice => {
-        //     let ma_price = database_functions::get_ma_of_price(
-        //         &client, // Pass the client instead of the pool
-        //         &function.asset,
-        //         execution_date,
-        //         function.window_of_days.unwrap_or(20) as i64,
-        //     )
-        //     .await?;
-        //
-        //     Ok(ma_price)
-        // }
-        FunctionName::MaxDrawdown => {
-            let result = database_functions::get_max_drawdown(
-                &client,
-                &function.asset,
-                execution_date,
-                function.window_of_days.unwrap_or(20) as i64,
-            )
-            .await?;
-
-            Ok(result.max_drawdown_percentage) // Note we use the percentage field
+/// Limits every weight of `weights` (summing to 1) to `max_weight`, handing the excess
+/// to the uncapped weights in proportion to their size until none is above the limit.
+/// When the limit cannot be met by any split, the weights become equal.
+fn cap_weights(weights: &mut [f64], max_weight: f64) {
+    if weights.len() as f64 * max_weight < 1.0 {
+        tracing::warn!(
+            "max_weight {} cannot be met by {} assets, weighting them equally",
+            max_weight,
+            weights.len()
+        );
+        let equal = 1.0 / weights.len() as f64;
+        weights.iter_mut().for_each(|weight| *weight = equal);
+        return;
+    }
+
+    let mut capped = vec![false; weights.len()];
+    loop {
+        let mut newly_capped = false;
+        for (weight, capped) in weights.iter_mut().zip(capped.iter_mut()) {
+            if !*capped && *weight > max_weight {
+                *weight = max_weight;
+                *capped = true;
+                newly_capped = true;
+            }
         }
-        FunctionName::MovingAverageOfReturns => {
-            let ma_returns = database_functions::get_ma_of_returns(
-                &client, // Pass the client instead of the pool
-                &function.asset,
-                execution_date,
-                function.window_of_days.unwrap_or(20) as i64,
-            )
-            .await?;
-
-            Ok(ma_returns)
+        if !newly_capped {
+            return;
         }
-        FunctionName::PriceStandardDeviation => {
-            let price_std = database_functions::get_price_std_dev(
-                &client, // Pass the client instead of the pool
-                &function.asset,
-                execution_date,
-                function.window_of_days.unwrap_or(20) as i64,
-            )
-            .await?;
-
-            Ok(price_std)
+
+        let capped_total = capped.iter().filter(|&&capped| capped).count() as f64 * max_weight;
+        let uncapped_total: f64 = weights
+            .iter()
+            .zip(&capped)
+            .filter(|(_, &capped)| !capped)
+            .map(|(weight, _)| weight)
+            .sum();
+        if uncapped_total <= 0.0 {
+            return;
         }
-        FunctionName::ReturnsStandardDeviation => {
-            let returns_std = database_functions::get_returns_std_dev(
-                &client, // Pass the client instead of the pool
-                &function.asset,
-                execution_date,
-                function.window_of_days.unwrap_or(20) as i64,
-            )
-            .await?;
-
-            Ok(returns_std)
+        let scale = (1.0 - capped_total) / uncapped_total;
+        for (weight, _) in weights
+            .iter_mut()
+            .zip(&capped)
+            .filter(|(_, &capped)| !capped)
+        {
+            *weight *= scale;
         }
     }
 }
+
 fn normalize_weights(allocations: &[Allocation]) -> Result<Vec<Allocation>, DatabaseError> {
     if allocations.is_empty() {
         return Err(DatabaseError::InvalidCalculation(
@@ -494,7 +482,331 @@ fn normalize_weights(allocations: &[Allocation]) -> Result<Vec<Allocation>, Data
         .map(|a| Allocation {
             ticker: a.ticker.clone(),
             weight: a.weight / total_weight,
-            date: a.date.clone(),
+            date: a.date,
         })
         .collect())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market::provider::mock::FixedValueProvider;
+    use serde_json::json;
+
+    fn execution_date() -> ExecutionDate {
+        "2024-12-31T16:00:00.000000Z".parse().unwrap()
+    }
+
+    fn strategy(value: serde_json::Value) -> Block {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn asset(ticker: &str) -> serde_json::Value {
+        json!({
+            "blocktype": "Asset",
+            "ticker": ticker,
+            "company_name": format!("{} Inc.", ticker),
+            "exchange": "NASDAQ"
+        })
+    }
+
+    fn weight_of(allocations: &[Allocation], ticker: &str) -> f64 {
+        allocations
+            .iter()
+            .find(|a| a.ticker == ticker)
+            .map(|a| a.weight)
+            .unwrap_or(0.0)
+    }
+
+    #[tokio::test]
+    async fn test_condition_selects_branch() {
+        let block = strategy(json!({
+            "blocktype": "Condition",
+            "function": {
+                "function_name": "max_drawdown",
+                "window_of_days": 60,
+                "asset": "QQQ"
+            },
+            "operator": ">",
+            "compare_to": { "type": "fixed_value", "value": 15.0 },
+            "children": [asset("BIL"), asset("QQQ")]
+        }));
+
+        let calm: Arc<dyn MarketDataProvider> = Arc::new(FixedValueProvider::default().with_value(
+            "QQQ",
+            FunctionName::MaxDrawdown,
+            4.0,
+        ));
+        let allocations = execute_strategy(&block, &calm, execution_date())
+            .await
+            .unwrap();
+        assert_eq!(allocations.len(), 1);
+        assert_eq!(allocations[0].ticker, "QQQ");
+
+        let stressed: Arc<dyn MarketDataProvider> = Arc::new(
+            FixedValueProvider::default().with_value("QQQ", FunctionName::MaxDrawdown, 22.0),
+        );
+        let allocations = execute_strategy(&block, &stressed, execution_date())
+            .await
+            .unwrap();
+        assert_eq!(allocations[0].ticker, "CASH");
+        assert_eq!(allocations[0].date, execution_date());
+    }
+
+    #[tokio::test]
+    async fn test_inverse_volatility_weights() {
+        let block = strategy(json!({
+            "blocktype": "Weight",
+            "type": "inverse_volatility",
+            "window_of_trading_days": 20,
+            "children": [asset("AAPL"), asset("MSFT")]
+        }));
+
+        let market_data: Arc<dyn MarketDataProvider> = Arc::new(
+            FixedValueProvider::default()
+                .with_value("AAPL", FunctionName::ReturnsStandardDeviation, 1.0)
+                .with_value("MSFT", FunctionName::ReturnsStandardDeviation, 3.0),
+        );
+
+        let allocations = execute_strategy(&block, &market_data, execution_date())
+            .await
+            .unwrap();
+        assert!((weight_of(&allocations, "AAPL") - 0.75).abs() < 1e-12);
+        assert!((weight_of(&allocations, "MSFT") - 0.25).abs() < 1e-12);
+    }
+
+    #[tokio::test]
+    async fn test_inverse_volatility_keeps_duplicate_allocations() {
+        let block = strategy(json!({
+            "blocktype": "Weight",
+            "type": "inverse_volatility",
+            "window_of_trading_days": 20,
+            "children": [asset("AAPL"), asset("AAPL"), asset("MSFT")]
+        }));
+
+        let market_data: Arc<dyn MarketDataProvider> = Arc::new(
+            FixedValueProvider::default()
+                .with_value("AAPL", FunctionName::ReturnsStandardDeviation, 1.0)
+                .with_value("MSFT", FunctionName::ReturnsStandardDeviation, 3.0),
+        );
+
+        // Inverse volatilities 1, 1 and 1/3 out of 7/3
+        let allocations = execute_strategy(&block, &market_data, execution_date())
+            .await
+            .unwrap();
+        let weights: Vec<_> = allocations
+            .iter()
+            .map(|a| (a.ticker.as_str(), a.weight))
+            .collect();
+        assert_eq!(weights.len(), 3);
+        for ((ticker, weight), (expected_ticker, expected)) in
+            weights
+                .into_iter()
+                .zip([("AAPL", 3.0), ("AAPL", 3.0), ("MSFT", 1.0)])
+        {
+            assert_eq!(ticker, expected_ticker);
+            assert!((weight - expected / 7.0).abs() < 1e-12);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_missing_market_data_is_an_error() {
+        let block = strategy(json!({
+            "blocktype": "Condition",
+            "function": { "function_name": "current_price", "asset": "QQQ" },
+            "operator": ">",
+            "compare_to": { "type": "fixed_value", "value": 100.0 },
+            "children": [asset("QQQ"), asset("BIL")]
+        }));
+
+        let market_data: Arc<dyn MarketDataProvider> = Arc::new(FixedValueProvider::default());
+        let result = execute_strategy(&block, &market_data, execution_date()).await;
+        assert!(matches!(result, Err(DatabaseError::InsufficientData(_))));
+    }
+
+    #[tokio::test]
+    async fn test_market_cap_weights_with_limit() {
+        use crate::market::market_cap::SharesOutstanding;
+        use crate::market::price_store::{PriceSeries, PriceStore};
+
+        let time = execution_date().bar_timestamp();
+        let mut store = PriceStore::new();
+        for (ticker, close, shares) in [
+            ("AAPL", 200.0, 6_000.0),
+            ("MSFT", 100.0, 3_000.0),
+            ("NVDA", 50.0, 2_000.0),
+        ] {
+            let mut series = PriceSeries::new();
+            series.push(time, close).unwrap();
+            series.set_shares_outstanding(vec![SharesOutstanding { time, shares }]);
+            store.insert(ticker, series);
+        }
+        let market_data: Arc<dyn MarketDataProvider> = Arc::new(store);
+
+        // Caps of 1.2M, 300k and 100k
+        let mut block = json!({
+            "blocktype": "Weight",
+            "type": "market_cap",
+            "children": [asset("AAPL"), asset("MSFT"), asset("NVDA")]
+        });
+        let allocations =
+            execute_strategy(&strategy(block.clone()), &market_data, execution_date())
+                .await
+                .unwrap();
+        assert!((weight_of(&allocations, "AAPL") - 0.75).abs() < 1e-12);
+        assert!((weight_of(&allocations, "NVDA") - 0.0625).abs() < 1e-12);
+
+        // AAPL's excess goes to the others in proportion to their caps
+        block["max_weight"] = json!(0.5);
+        let allocations = execute_strategy(&strategy(block), &market_data, execution_date())
+            .await
+            .unwrap();
+        assert!((weight_of(&allocations, "AAPL") - 0.5).abs() < 1e-12);
+        assert!((weight_of(&allocations, "MSFT") - 0.375).abs() < 1e-12);
+        assert!((weight_of(&allocations, "NVDA") - 0.125).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_cap_weights_redistributes_until_within_limit() {
+        let mut weights = vec![0.6, 0.3, 0.05, 0.05];
+        cap_weights(&mut weights, 0.35);
+        // The second weight only exceeds the limit after the first redistribution
+        assert_eq!(weights[0], 0.35);
+        assert_eq!(weights[1], 0.35);
+        assert!((weights[2] - 0.15).abs() < 1e-12);
+        assert!((weights[3] - 0.15).abs() < 1e-12);
+
+        // Two assets cannot stay below 40% each
+        let mut weights = vec![0.9, 0.1];
+        cap_weights(&mut weights, 0.4);
+        assert_eq!(weights, vec![0.5, 0.5]);
+    }
+
+    #[tokio::test]
+    async fn test_filter_selects_from_point_in_time_universe() {
+        use crate::market::price_store::{bar_timestamp, PriceSeries, PriceStore};
+        use crate::market::universe::{MembershipAction, MembershipChange};
+        use chrono::NaiveDate;
+
+        let date = |day| NaiveDate::from_ymd_opt(2024, 12, day).unwrap();
+        let mut store = PriceStore::new();
+        for (ticker, first, last) in [("AAPL", 100.0, 110.0), ("NVDA", 100.0, 150.0)] {
+            let mut series = PriceSeries::new();
+            series.push(bar_timestamp(date(30)), first).unwrap();
+            series.push(bar_timestamp(date(31)), last).unwrap();
+            store.insert(ticker, series);
+        }
+        // NVDA left the universe before the 31st
+        let change = |ticker: &str, day, action| MembershipChange {
+            ticker: ticker.to_string(),
+            date: date(day),
+            action,
+        };
+        store.set_index_membership(
+            "NASDAQ100",
+            vec![
+                change("AAPL", 2, MembershipAction::Added),
+                change("NVDA", 2, MembershipAction::Added),
+                change("NVDA", 31, MembershipAction::Removed),
+            ],
+        );
+        let market_data: Arc<dyn MarketDataProvider> = Arc::new(store);
+
+        let block = strategy(json!({
+            "blocktype": "Filter",
+            "sort_function": { "function_name": "cumulative_return", "window_of_days": 1 },
+            "select": { "option": "Top", "amount": 1 },
+            "children": [
+                { "blocktype": "Universe", "universe": "NASDAQ100", "exchange": "NASDAQ" }
+            ]
+        }));
+        let on = |day| ExecutionDate::at_close(date(day), Default::default());
+
+        let allocations = execute_strategy(&block, &market_data, on(31))
+            .await
+            .unwrap();
+        assert_eq!(allocations.len(), 1);
+        assert_eq!(allocations[0].ticker, "AAPL");
+    }
+
+    #[tokio::test]
+    async fn test_screen_selects_by_metadata_and_liquidity() {
+        use crate::market::database_functions::Bar;
+        use crate::market::price_store::{bar_timestamp, PriceSeries, PriceStore};
+        use crate::market::security_master::SecurityMaster;
+        use chrono::NaiveDate;
+
+        let date = |day| NaiveDate::from_ymd_opt(2024, 12, day).unwrap();
+        let mut store = PriceStore::new();
+        for (ticker, close, volume) in [
+            ("AAPL", 250.0, 50_000_000),
+            ("NVDA", 130.0, 200_000_000),
+            ("PENY", 2.0, 900_000_000),
+            ("THIN", 40.0, 1_000),
+            ("XOM", 110.0, 15_000_000),
+        ] {
+            let mut series = PriceSeries::new();
+            for day in [27, 30, 31] {
+                series
+                    .push_bar(Bar {
+                        time: bar_timestamp(date(day)),
+                        open: close,
+                        high: close,
+                        low: close,
+                        close,
+                        volume,
+                    })
+                    .unwrap();
+            }
+            store.insert(ticker, series);
+        }
+        let master = SecurityMaster::parse(
+            "ticker,name,exchange,asset_class,sector,currency,first_trading_date,last_trading_date,delisted\n\
+             AAPL,Apple Inc.,NASDAQ,equity,Technology,USD,1980-12-12,,False\n\
+             NVDA,NVIDIA Corp.,NASDAQ,equity,Technology,USD,1999-01-22,,False\n\
+             PENY,Penny Corp.,NASDAQ,equity,Technology,USD,2020-01-02,,False\n\
+             THIN,Thin Corp.,NASDAQ,equity,Technology,USD,2020-01-02,,False\n\
+             GONE,Gone Corp.,NASDAQ,equity,Technology,USD,2010-01-04,2024-06-28,True\n\
+             XOM,Exxon Mobil Corp.,NYSE,equity,Energy,USD,1970-01-02,,False\n",
+            "securities.csv",
+        )
+        .unwrap();
+        let market_data: Arc<dyn MarketDataProvider> =
+            Arc::new(store.with_security_master(Arc::new(master)));
+
+        // PENY is too cheap, THIN too illiquid, GONE delisted and XOM in another sector
+        let block = strategy(json!({
+            "blocktype": "Screen",
+            "screen": {
+                "sectors": ["Technology"],
+                "min_price": 5.0,
+                "min_average_dollar_volume": 1000000.0,
+                "dollar_volume_window": 3
+            }
+        }));
+        let allocations = execute_strategy(
+            &block,
+            &market_data,
+            ExecutionDate::at_close(date(31), Default::default()),
+        )
+        .await
+        .unwrap();
+        let tickers: Vec<_> = allocations.iter().map(|a| a.ticker.as_str()).collect();
+        assert_eq!(tickers, ["AAPL", "NVDA"]);
+        assert!(allocations.iter().all(|a| a.weight == 0.5));
+
+        // Three bars are not enough history for four
+        let block = strategy(json!({
+            "blocktype": "Screen",
+            "screen": { "sectors": ["Technology"], "min_history_days": 4 }
+        }));
+        let members = block_members(
+            &market_data,
+            &block.attributes,
+            ExecutionDate::at_close(date(31), Default::default()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(members, Some(vec![]));
+    }
+}
@@ -1,5 +1,9 @@
 // use crate::market::database_functions::DatabaseError;
+use crate::market::execution_date::ExecutionDate;
+use crate::market::missing_data::{MissingDataEvent, MissingDataLog};
 // use crate::portfolio::blocks::models::Block;
+use crate::portfolio::execution::options::ExecutionOptions;
+use crate::portfolio::execution::preload::preload_market_data;
 // use crate::portfolio::execution::strategy_executor::{execute_strategy, Allocation};
 // use chrono::{Datelike, Months, NaiveDate, NaiveDateTime, TimeZone, Utc};
 // use deadpool_postgres::{Client, Pool};
@@ -147,16 +151,15 @@
 
 //START OF PARALLIZED VERSION
 
-use crate::market::database_functions_old::DatabaseError;
+use crate::market::database_functions::DatabaseError;
+use crate::market::provider::MarketDataProvider;
 use crate::portfolio::blocks::models::Block;
-use crate::portfolio::execution::strategy_executorOld::{execute_strategy, Allocation};
-use chrono::{Months, NaiveDate, NaiveDateTime, Utc};
-use deadpool_postgres::{Client, Pool};
+use crate::portfolio::execution::strategy_executor::{execute_strategy, Allocation};
+use chrono::{Months, NaiveDate, Utc};
 use std::sync::Arc;
 use sysinfo::System;
 use tokio::sync::{broadcast, Semaphore};
 use tokio::task::JoinSet;
-use tokio::time::{timeout, Duration};
 use tracing::{debug, error, info, warn};
 
 // Constants for execution control
@@ -181,16 +184,18 @@ impl ExecutionFrequency {
 
 #[derive(Debug)]
 pub struct ExecutionResult {
-    pub display_date: String,
-    pub execution_date: String,
+    pub display_date: NaiveDate,
+    pub execution_date: ExecutionDate,
     pub allocations: Vec<Allocation>,
+    /// Assets the missing-data policy filled or excluded on this execution date
+    pub missing_data: Vec<MissingDataEvent>,
 }
 
-#[derive(Debug)]
 struct ExecutionTask {
     date: NaiveDate,
     strategy: Arc<Block>,
-    pool: Arc<Pool>,
+    market_data: Arc<dyn MarketDataProvider>,
+    missing_data: Arc<MissingDataLog>,
 }
 
 impl From<chrono::format::ParseError> for DatabaseError {
@@ -199,68 +204,23 @@ impl From<chrono::format::ParseError> for DatabaseError {
     }
 }
 
-async fn get_last_market_day(client: &Client, date: NaiveDate) -> Result<String, DatabaseError> {
-    let date_str = date.format("%Y-%m-%d").to_string();
-    debug!("Checking market day for date: {}", date_str);
-
-    let query = format!(
-        r#"
-        SELECT Date, Date_of_Previous_Trading_Day, Is_Holiday, Is_Weekend
-        FROM nasdaq_closed_days
-        WHERE Date = '{}'
-        "#,
-        date_str
-    );
-
-    let row = client.query_opt(&query, &[]).await?;
-
-    let market_day = match row {
-        Some(row) => {
-            let is_holiday: bool = row.get("Is_Holiday");
-            let is_weekend: bool = row.get("Is_Weekend");
-
-            if !is_holiday && !is_weekend {
-                date
-            } else {
-                let prev_day: Option<NaiveDateTime> = row.get("Date_of_Previous_Trading_Day");
-                prev_day
-                    .unwrap_or_else(|| date.and_hms_opt(16, 0, 0).unwrap())
-                    .date()
-            }
-        }
-        None => date,
-    };
-
-    Ok(format!(
-        "{}T16:00:00.000000Z",
-        market_day.format("%Y-%m-%d")
-    ))
-}
-
 async fn process_execution_task(
     task: ExecutionTask,
     semaphore: Arc<Semaphore>,
 ) -> Result<ExecutionResult, DatabaseError> {
-    let permit = semaphore.acquire().await.unwrap();
+    let _permit = semaphore.acquire().await.unwrap();
     debug!("Acquired execution permit for date: {}", task.date);
 
-    // Add a timeout for the task
-    match timeout(Duration::from_secs(30), async {
-        let client = task.pool.get().await?;
-        let execution_date = get_last_market_day(&client, task.date).await?;
-        let allocations = execute_strategy(&task.strategy, &task.pool, &execution_date).await?;
-        Ok((execution_date, allocations))
+    // No timeout around the whole date: each database request is bounded by the
+    // resilient provider's query timeout and retry budget
+    let execution_date = task.market_data.get_last_market_day(task.date).await?;
+    let allocations = execute_strategy(&task.strategy, &task.market_data, execution_date).await?;
+    Ok(ExecutionResult {
+        display_date: task.date,
+        execution_date,
+        allocations,
+        missing_data: task.missing_data.take(execution_date.date()),
     })
-    .await
-    {
-        Ok(Ok((execution_date, allocations))) => Ok(ExecutionResult {
-            display_date: task.date.format("%Y-%m-%d").to_string(),
-            execution_date,
-            allocations,
-        }),
-        Ok(Err(e)) => Err(e),
-        Err(_) => Err(DatabaseError::InvalidInput("Task timeout".into())),
-    }
 }
 
 fn generate_execution_dates(
@@ -273,7 +233,7 @@ fn generate_execution_dates(
 
     while current <= end_date {
         dates.push(current);
-        if let Some(next) = current.checked_add_months(Months::new(frequency.months() as u32)) {
+        if let Some(next) = current.checked_add_months(Months::new(frequency.months())) {
             current = next;
         } else {
             break;
@@ -307,7 +267,8 @@ fn get_recommended_batch_size() -> usize {
 }
 
 pub async fn execute_strategy_over_time_span(
-    pool: &Pool,
+    market_data: &Arc<dyn MarketDataProvider>,
+    options: &ExecutionOptions,
     strategy: &Block,
     start_date: &str,
     end_date: Option<&str>,
@@ -330,12 +291,20 @@ pub async fn execute_strategy_over_time_span(
 
     info!("Executing strategy from {} to {}", start, end);
 
-    // Convert pool and strategy to Arc for sharing
-    let pool = Arc::new(pool.clone());
+    // Convert strategy to Arc for sharing
     let strategy = Arc::new(strategy.clone());
 
     // Generate execution dates
     let dates = generate_execution_dates(frequency, start, end);
+    if dates.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // Load every series the strategy reads once for the whole span
+    let missing_data = Arc::new(MissingDataLog::default());
+    let market_data =
+        preload_market_data(market_data, options, &missing_data, &strategy, start, end).await?;
+    let market_data = &market_data;
 
     // Create semaphore for concurrency control
     let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_EXECUTIONS));
@@ -365,7 +334,8 @@ pub async fn execute_strategy_over_time_span(
             let task = ExecutionTask {
                 date: *date,
                 strategy: strategy.clone(),
-                pool: pool.clone(),
+                market_data: Arc::clone(market_data),
+                missing_data: Arc::clone(&missing_data),
             };
             let semaphore = semaphore.clone();
 
@@ -384,6 +354,11 @@ pub async fn execute_strategy_over_time_span(
                         Ok(Ok(execution_result)) => {
                             results.push(execution_result);
                         }
+                        // The database is down, so every other date would fail too
+                        Ok(Err(e @ DatabaseError::CircuitOpen(_))) => {
+                            join_set.abort_all();
+                            return Err(e);
+                        }
                         Ok(Err(e)) => {
                             warn!("Task execution failed: {}", e);
                         }
@@ -398,7 +373,7 @@ pub async fn execute_strategy_over_time_span(
     }
 
     // Sort results by display date
-    results.sort_by(|a, b| a.display_date.cmp(&b.display_date));
+    results.sort_by_key(|result| result.display_date);
 
     Ok(results)
 }
@@ -1,3 +1,3 @@
 pub mod blocks;
+pub mod construction;
 pub mod execution;
-pub mod construction;
\ No newline at end of file